@@ -23,7 +23,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let mut cur_event_num: usize = 0;
 
     // If one of the args is "pub", start as a publisher
-    if let Some(_) = std::env::args().find(|a| a == "pub") {
+    if std::env::args().any(|a| a == "pub") {
         loop {
             match client.publish("peer.heartbeat", None, None, true).await {
                 Ok(pub_id) => println!("\tSent event id {}", pub_id.unwrap()),
@@ -49,7 +49,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
         while cur_event_num < max_events {
             match heartbeat_queue.recv().await {
-                Some((pub_id, args, kwargs)) => {
+                Some((pub_id, args, kwargs, _)) => {
                     println!("\tGot {} (args: {:?}, kwargs: {:?})", pub_id, args, kwargs)
                 }
                 None => println!("Subscription is done"),
@@ -64,6 +64,6 @@ async fn main() -> Result<(), Box<dyn Error>> {
     client.leave_realm().await?;
 
     tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-    client.disconnect().await;
+    client.disconnect().await?;
     Ok(())
 }
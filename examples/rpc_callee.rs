@@ -4,7 +4,8 @@ use std::sync::atomic::{AtomicU64, Ordering};
 use lazy_static::*;
 
 use wamp_async::{
-    Client, ClientConfig, ClientState, SerializerType, WampArgs, WampError, WampKwArgs,
+    Client, ClientConfig, ClientState, RpcEventPump, SerializerType, ShutdownToken, WampArgs,
+    WampError, WampKwArgs,
 };
 
 lazy_static! {
@@ -73,20 +74,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
     // Spawn the event loop
     tokio::spawn(evt_loop);
 
-    // Handle RPC events in separate tasks
-    tokio::spawn(async move {
-        let mut rpc_event_queue = rpc_evt_queue.unwrap();
-        loop {
-            // Wait for an RPC call
-            let rpc_event = match rpc_event_queue.recv().await {
-                Some(e) => e,
-                None => break,
-            };
-
-            // Execute the function call
-            tokio::spawn(rpc_event);
-        }
-    });
+    // Handle up to 32 RPC events at once in separate tasks
+    let shutdown = ShutdownToken::new();
+    let rpc_pump = RpcEventPump::spawn(rpc_evt_queue.unwrap(), 32, shutdown.clone());
 
     println!("Joining realm");
     client.join_realm("realm1").await?;
@@ -113,10 +103,14 @@ async fn main() -> Result<(), Box<dyn Error>> {
     client.unregister(echo_rpc_id).await?;
     client.unregister(strict_echo_rpc_id).await?;
 
+    // Wait for the invocations still running
+    shutdown.shutdown();
+    rpc_pump.join().await;
+
     println!("Leaving realm");
     client.leave_realm().await?;
 
-    client.disconnect().await;
+    client.disconnect().await?;
 
     Ok(())
 }
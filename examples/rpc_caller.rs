@@ -52,7 +52,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let mut keyword_args = WampKwArgs::new();
     keyword_args.insert("key".to_string(), try_into_any_value(&my_struct).unwrap());
 
-    for (send_args, send_kwargs) in vec![
+    for (send_args, send_kwargs) in [
         (None, None),
         (Some(positional_args.clone()), None),
         (None, Some(keyword_args.clone())),
@@ -85,6 +85,6 @@ async fn main() -> Result<(), Box<dyn Error>> {
     println!("Leaving realm");
     client.leave_realm().await?;
 
-    client.disconnect().await;
+    client.disconnect().await?;
     Ok(())
 }
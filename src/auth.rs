@@ -0,0 +1,412 @@
+//! WAMP-CRA (challenge response authentication) helpers
+//!
+//! These are the primitives used by both sides of WAMP-CRA : the router builds a challenge
+//! with [`CraChallenge`] and checks the signature with [`verify_signature`], the client
+//! answers with [`sign_challenge`]. Passwords stored salted are turned into the signing key
+//! with [`derive_key`]. Clients that do not fully trust the router check the challenge with a
+//! [`ChallengePolicy`] before signing it, using [`sign_challenge_with_policy`].
+//!
+//! ```
+//! use wamp_async::auth::{compute_signature, verify_signature, CraChallenge};
+//!
+//! // Router side
+//! let challenge = CraChallenge::new("joe", "user", "static", 1234).to_json();
+//!
+//! // Client side
+//! let signature = compute_signature("secret", &challenge);
+//!
+//! assert!(verify_signature("secret", &challenge, &signature));
+//! ```
+//!
+//! Requires the `auth` feature.
+
+use std::collections::{HashSet, VecDeque};
+use std::convert::TryFrom;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use log::*;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::common::*;
+use crate::error::*;
+use crate::log_targets::AUTH;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Iterations used to derive a key when the challenge does not specify it
+pub const DEFAULT_ITERATIONS: u32 = 1000;
+/// Length in bytes of a derived key when the challenge does not specify it
+pub const DEFAULT_KEY_LEN: usize = 32;
+/// Most iterations accepted to derive a key, so a router cannot make the client spin
+pub const MAX_ITERATIONS: u32 = 5_000_000;
+/// Longest derived key accepted, in bytes
+pub const MAX_KEY_LEN: usize = 64;
+/// Difference accepted between the challenge timestamp and the local clock by default
+pub const DEFAULT_MAX_CLOCK_SKEW: Duration = Duration::from_secs(300);
+/// Number of nonces remembered by default to detect replayed challenges
+pub const DEFAULT_NONCE_HISTORY: usize = 1024;
+
+/// Derives the signing key of a salted secret with PBKDF2-HMAC-SHA256. The key is returned
+/// base64 encoded, which is how it is used to sign challenges.
+///
+/// Fails when `iterations` is not within `1..=MAX_ITERATIONS` or `key_len` is not within
+/// `1..=MAX_KEY_LEN`.
+pub fn derive_key(
+    secret: &str,
+    salt: &str,
+    iterations: u32,
+    key_len: usize,
+) -> Result<String, WampError> {
+    if !(1..=MAX_ITERATIONS).contains(&iterations) {
+        return Err(WampError::InvalidArgument(format!(
+            "Invalid PBKDF2 iterations {}, expected 1 to {}",
+            iterations, MAX_ITERATIONS
+        )));
+    }
+    if !(1..=MAX_KEY_LEN).contains(&key_len) {
+        return Err(WampError::InvalidArgument(format!(
+            "Invalid PBKDF2 key length {}, expected 1 to {}",
+            key_len, MAX_KEY_LEN
+        )));
+    }
+    let mut key = vec![0u8; key_len];
+    pbkdf2::pbkdf2_hmac::<Sha256>(secret.as_bytes(), salt.as_bytes(), iterations, &mut key);
+    Ok(base64::encode(key))
+}
+
+/// Signs `challenge` with HMAC-SHA256, returning the base64 encoded signature
+pub fn compute_signature(key: &str, challenge: &str) -> String {
+    let mut mac = hmac(key);
+    mac.update(challenge.as_bytes());
+    base64::encode(mac.finalize().into_bytes())
+}
+
+/// Checks the signature of `challenge` in constant time
+pub fn verify_signature(key: &str, challenge: &str, signature: &str) -> bool {
+    let signature = match base64::decode(signature) {
+        Ok(s) => s,
+        Err(_) => return false,
+    };
+    let mut mac = hmac(key);
+    mac.update(challenge.as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
+
+fn hmac(key: &str) -> HmacSha256 {
+    HmacSha256::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length")
+}
+
+/// Answers the CHALLENGE of a WAMP-CRA authentication, deriving the key from `secret` when the
+/// router sent a salt
+///
+/// Fails when the `iterations` or `keylen` of the CHALLENGE are out of the bounds accepted by
+/// [`derive_key`]. Negative values are not valid WAMP integers and are rejected when the
+/// CHALLENGE is parsed.
+///
+/// Meant to be called from the challenge handler given to
+/// [`crate::Client::join_realm_with_authentication`] with the `extra` of the CHALLENGE.
+pub fn sign_challenge(
+    secret: &str,
+    extra: &WampDict,
+) -> Result<AuthenticationChallengeResponse, WampError> {
+    let challenge = get_challenge(extra)?;
+    let signature = match extra.get("salt") {
+        Some(Arg::String(salt)) | Some(Arg::Uri(salt)) => {
+            let iterations = get_integer(extra, "iterations")?.unwrap_or(DEFAULT_ITERATIONS as u64);
+            let key_len = get_integer(extra, "keylen")?.unwrap_or(DEFAULT_KEY_LEN as u64);
+            // Out of range values saturate, derive_key rejects them
+            let key = derive_key(
+                secret,
+                salt,
+                u32::try_from(iterations).unwrap_or(u32::MAX),
+                usize::try_from(key_len).unwrap_or(usize::MAX),
+            )?;
+            compute_signature(&key, challenge)
+        }
+        _ => compute_signature(secret, challenge),
+    };
+
+    Ok(AuthenticationChallengeResponse::with_signature(signature))
+}
+
+/// Same as [`sign_challenge`], refusing to sign challenges rejected by `policy`
+pub fn sign_challenge_with_policy(
+    secret: &str,
+    extra: &WampDict,
+    policy: &ChallengePolicy,
+) -> Result<AuthenticationChallengeResponse, WampError> {
+    policy.check(get_challenge(extra)?)?;
+    sign_challenge(secret, extra)
+}
+
+fn get_challenge(extra: &WampDict) -> Result<&String, WampError> {
+    match extra.get("challenge") {
+        Some(Arg::String(c)) | Some(Arg::Uri(c)) => Ok(c),
+        _ => Err(WampError::InvalidArgument(
+            "The CHALLENGE has no challenge string".to_string(),
+        )),
+    }
+}
+
+fn get_integer(extra: &WampDict, key: &str) -> Result<Option<u64>, WampError> {
+    match extra.get(key) {
+        None => Ok(None),
+        Some(Arg::Id(v)) => Ok(Some(std::num::NonZeroU64::from(*v).get())),
+        Some(Arg::Integer(v)) => Ok(Some(*v as u64)),
+        Some(v) => Err(WampError::InvalidArgument(format!(
+            "The CHALLENGE {} is not an integer : {:?}",
+            key, v
+        ))),
+    }
+}
+
+/// Challenge string of a WAMP-CRA authentication, as built by the router
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CraChallenge {
+    pub authid: String,
+    pub authrole: String,
+    /// Always `wampcra`
+    pub authmethod: String,
+    pub authprovider: String,
+    /// Random value making each challenge unique
+    pub nonce: String,
+    /// UTC time the challenge was created at, in ISO 8601
+    pub timestamp: String,
+    pub session: u64,
+}
+
+impl CraChallenge {
+    /// Creates a challenge for the session `session`, with a random nonce and the current time
+    pub fn new<T: Into<String>>(authid: T, authrole: T, authprovider: T, session: u64) -> Self {
+        Self {
+            authid: authid.into(),
+            authrole: authrole.into(),
+            authmethod: "wampcra".to_string(),
+            authprovider: authprovider.into(),
+            nonce: base64::encode(rand::random::<[u8; 16]>()),
+            timestamp: utc_timestamp(SystemTime::now()),
+            session,
+        }
+    }
+
+    /// Returns the challenge string sent in the CHALLENGE message
+    pub fn to_json(&self) -> String {
+        // A struct of strings and integers is always serializable
+        serde_json::to_string(self).expect("Challenge is serializable")
+    }
+
+    /// Parses a challenge string
+    pub fn from_json(challenge: &str) -> Result<Self, WampError> {
+        serde_json::from_str(challenge)
+            .map_err(|e| WampError::InvalidArgument(format!("Invalid WAMP-CRA challenge : {}", e)))
+    }
+}
+
+/// Checks made on a WAMP-CRA challenge before signing it
+///
+/// A malicious or compromised router could replay a challenge it captured to obtain a valid
+/// signature. The policy rejects challenges that :
+/// - are not `wampcra` challenges or are not bound to a session
+/// - were issued for another `authid`, when [`ChallengePolicy::with_authid`] is set
+/// - have a timestamp too far from the local clock
+/// - reuse a nonce of a challenge already checked
+///
+/// Clones share the nonces seen, so the policy can be kept across reconnects.
+///
+/// ```
+/// use wamp_async::auth::{ChallengePolicy, CraChallenge};
+///
+/// let policy = ChallengePolicy::new().with_authid("joe");
+/// let challenge = CraChallenge::new("joe", "user", "static", 1234).to_json();
+///
+/// assert!(policy.check(&challenge).is_ok());
+/// // The same challenge cannot be signed twice
+/// assert!(policy.check(&challenge).is_err());
+/// ```
+#[derive(Clone)]
+pub struct ChallengePolicy {
+    max_clock_skew: Option<Duration>,
+    authid: Option<String>,
+    nonces: Arc<Mutex<NonceHistory>>,
+}
+
+struct NonceHistory {
+    capacity: usize,
+    order: VecDeque<String>,
+    seen: HashSet<String>,
+}
+
+impl NonceHistory {
+    /// Remembers `nonce`, returns false if it was already seen
+    fn insert(&mut self, nonce: &str) -> bool {
+        if !self.seen.insert(nonce.to_string()) {
+            return false;
+        }
+        self.order.push_back(nonce.to_string());
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.seen.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+impl Default for ChallengePolicy {
+    fn default() -> Self {
+        Self {
+            max_clock_skew: Some(DEFAULT_MAX_CLOCK_SKEW),
+            authid: None,
+            nonces: Arc::new(Mutex::new(NonceHistory {
+                capacity: DEFAULT_NONCE_HISTORY,
+                order: VecDeque::new(),
+                seen: HashSet::new(),
+            })),
+        }
+    }
+}
+
+impl ChallengePolicy {
+    /// Creates a policy accepting [`DEFAULT_MAX_CLOCK_SKEW`] and remembering
+    /// [`DEFAULT_NONCE_HISTORY`] nonces
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the difference accepted between the challenge timestamp and the local clock.
+    /// `None` does not check the timestamp.
+    pub fn with_max_clock_skew(mut self, skew: Option<Duration>) -> Self {
+        self.max_clock_skew = skew;
+        self
+    }
+
+    /// Only accepts challenges issued for `authid`
+    pub fn with_authid<T: Into<String>>(mut self, authid: T) -> Self {
+        self.authid = Some(authid.into());
+        self
+    }
+
+    /// Sets the number of nonces remembered to detect replays (at least 1)
+    pub fn with_nonce_history(self, capacity: usize) -> Self {
+        self.nonces.lock().unwrap().capacity = capacity.max(1);
+        self
+    }
+
+    /// Returns the difference accepted between the challenge timestamp and the local clock
+    pub fn get_max_clock_skew(&self) -> Option<Duration> {
+        self.max_clock_skew
+    }
+
+    /// Checks a challenge string, remembering its nonce when it is accepted
+    pub fn check(&self, challenge: &str) -> Result<CraChallenge, WampError> {
+        let challenge = CraChallenge::from_json(challenge)?;
+        let reject = |reason: String| {
+            warn!(target: AUTH, "Refusing to sign the WAMP-CRA challenge : {}", reason);
+            Err(WampError::InvalidArgument(format!(
+                "Refusing to sign the WAMP-CRA challenge : {}",
+                reason
+            )))
+        };
+
+        if challenge.authmethod != "wampcra" {
+            return reject(format!("unexpected authmethod {}", challenge.authmethod));
+        }
+        if challenge.session == 0 {
+            return reject("it is not bound to a session".to_string());
+        }
+        if challenge.nonce.is_empty() {
+            return reject("it has no nonce".to_string());
+        }
+        if let Some(authid) = &self.authid {
+            if challenge.authid != *authid {
+                return reject(format!("it was issued for authid {}", challenge.authid));
+            }
+        }
+
+        if let Some(max_skew) = self.max_clock_skew {
+            let issued = match parse_utc_timestamp(&challenge.timestamp) {
+                Some(t) => t,
+                None => return reject(format!("invalid timestamp {}", challenge.timestamp)),
+            };
+            let skew = match SystemTime::now().duration_since(issued) {
+                Ok(d) => d,
+                Err(e) => e.duration(),
+            };
+            if skew > max_skew {
+                return reject(format!(
+                    "its timestamp {} is {:?} away from the local clock",
+                    challenge.timestamp, skew
+                ));
+            }
+        }
+
+        if !self.nonces.lock().unwrap().insert(&challenge.nonce) {
+            return reject(format!("the nonce {} was already used", challenge.nonce));
+        }
+        Ok(challenge)
+    }
+}
+
+/// Formats a time as `YYYY-MM-DDTHH:MM:SS.mmmZ`
+fn utc_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+
+    // Civil date from the number of days since 1970-01-01
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Parses a time formatted as `YYYY-MM-DDTHH:MM:SS[.fraction]Z`
+fn parse_utc_timestamp(timestamp: &str) -> Option<SystemTime> {
+    let (date, time) = timestamp.strip_suffix('Z')?.split_once('T')?;
+    let mut date = date.splitn(3, '-').map(|v| v.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut time = time.splitn(3, ':').map(|v| v.parse::<u64>().ok());
+    let (hours, minutes, secs) = (time.next()??, time.next()??, time.next()??);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hours > 23
+        || minutes > 59
+        || secs > 60
+        || !fraction.bytes().all(|b| b.is_ascii_digit())
+    {
+        return None;
+    }
+    let fraction = &fraction[..fraction.len().min(9)];
+    let nanos = format!("{:0<9}", fraction).parse::<u32>().ok()?;
+
+    // Number of days since 1970-01-01 of the civil date
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146_097 + doe - 719_468).ok()?;
+
+    let secs = days * 86400 + hours * 3600 + minutes * 60 + secs;
+    Some(UNIX_EPOCH + Duration::new(secs, nanos))
+}
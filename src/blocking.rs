@@ -0,0 +1,136 @@
+//! A blocking client API
+//!
+//! The [`blocking::Client`](Client) wraps the async [`crate::Client`] and drives it on an
+//! internal runtime. It is meant for CLI tools and applications that do not use async and
+//! must not be used from within an async context.
+//!
+//! ```no_run
+//! use wamp_async::blocking::Client;
+//!
+//! let mut client = Client::connect("wss://localhost:8080/ws", None)?;
+//! client.join_realm("realm1")?;
+//! let (args, kwargs) = client.call("peer.echo", Some(vec![12.into()]), None)?;
+//! println!("{:?} {:?}", args, kwargs);
+//!
+//! let (_sub_id, events) = client.subscribe("peer.heartbeat")?;
+//! for (pub_id, args, kwargs, _details) in events {
+//!     println!("{} {:?} {:?}", pub_id, args, kwargs);
+//! }
+//! client.disconnect()?;
+//! # Ok::<(), wamp_async::WampError>(())
+//! ```
+//!
+//! Requires the `blocking` feature.
+
+use tokio::runtime::Runtime;
+
+use crate::client::{ClientConfig, DisconnectSummary};
+use crate::common::*;
+use crate::core::SubscriptionQueue;
+use crate::error::*;
+
+/// A blocking WAMP client. See [`crate::Client`]
+pub struct Client {
+    client: crate::Client<'static>,
+    runtime: Runtime,
+}
+
+impl Client {
+    /// Connects to a WAMP server and starts the event loop. See [`crate::Client::connect`]
+    pub fn connect<T: AsRef<str>>(uri: T, cfg: Option<ClientConfig>) -> Result<Self, WampError> {
+        let runtime = match tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+        {
+            Ok(r) => r,
+            Err(e) => return Err(From::from(format!("Failed to start the runtime : {}", e))),
+        };
+
+        let (client, (evt_loop, rpc_evt_queue)) =
+            runtime.block_on(crate::Client::connect(uri, cfg))?;
+        runtime.spawn(evt_loop);
+        if let Some(mut rpc_evt_queue) = rpc_evt_queue {
+            runtime.spawn(async move {
+                while let Some(rpc_event) = rpc_evt_queue.recv().await {
+                    tokio::spawn(rpc_event);
+                }
+            });
+        }
+
+        Ok(Self { client, runtime })
+    }
+
+    /// Attempts to join a realm. See [`crate::Client::join_realm`]
+    pub fn join_realm<T: Into<String>>(&mut self, realm: T) -> Result<(), WampError> {
+        self.runtime.block_on(self.client.join_realm(realm))
+    }
+
+    /// Leaves the current realm. See [`crate::Client::leave_realm`]
+    pub fn leave_realm(&mut self) -> Result<(), WampError> {
+        self.runtime.block_on(self.client.leave_realm())
+    }
+
+    /// Calls a registered RPC endpoint on the server. See [`crate::Client::call`]
+    pub fn call<T: AsRef<str>>(
+        &self,
+        uri: T,
+        arguments: Option<WampArgs>,
+        arguments_kw: Option<WampKwArgs>,
+    ) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError> {
+        self.runtime
+            .block_on(self.client.call(uri, arguments, arguments_kw))
+    }
+
+    /// Publishes an event on a specific topic. See [`crate::Client::publish`]
+    pub fn publish<T: AsRef<str>>(
+        &self,
+        topic: T,
+        arguments: Option<WampArgs>,
+        arguments_kw: Option<WampKwArgs>,
+        acknowledge: bool,
+    ) -> Result<Option<WampId>, WampError> {
+        self.runtime.block_on(
+            self.client
+                .publish(topic, arguments, arguments_kw, acknowledge),
+        )
+    }
+
+    /// Subscribes to events for the specified topic. See [`crate::Client::subscribe`]
+    ///
+    /// The returned [`Subscription`] is an iterator over the received events.
+    pub fn subscribe<T: AsRef<str>>(&self, topic: T) -> Result<(WampId, Subscription), WampError> {
+        let (sub_id, queue) = self.runtime.block_on(self.client.subscribe(topic))?;
+        Ok((sub_id, Subscription { queue }))
+    }
+
+    /// Unsubscribes to a previously subscribed topic. See [`crate::Client::unsubscribe`]
+    pub fn unsubscribe(&self, sub_id: WampId) -> Result<(), WampError> {
+        self.runtime.block_on(self.client.unsubscribe(sub_id))
+    }
+
+    /// Returns whether we are connected to the server or not
+    pub fn is_connected(&mut self) -> bool {
+        self.client.is_connected()
+    }
+
+    /// Cleanly closes a connection with the server. See [`crate::Client::disconnect`]
+    pub fn disconnect(self) -> Result<DisconnectSummary, WampError> {
+        self.runtime.block_on(self.client.disconnect())
+    }
+}
+
+/// Blocking iterator over the events of a subscription
+///
+/// Iteration ends once the subscription is closed.
+pub struct Subscription {
+    queue: SubscriptionQueue,
+}
+
+impl Iterator for Subscription {
+    type Item = (WampId, Option<WampArgs>, Option<WampKwArgs>, WampDict);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.queue.blocking_recv()
+    }
+}
@@ -1,23 +1,50 @@
+use futures::future::{self, Either};
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, StreamExt};
+use serde::{de::DeserializeOwned, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::future::Future;
-use futures::FutureExt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use log::*;
 use tokio::sync::oneshot;
-use tokio::sync::{
-    mpsc, mpsc::UnboundedReceiver, mpsc::UnboundedSender,
-};
+use tokio::sync::{mpsc, mpsc::UnboundedReceiver, watch};
 use url::*;
 
+use crate::codec::PayloadCodec;
 pub use crate::common::*;
 use crate::core::*;
+use crate::dispatcher::DynamicDispatcher;
 use crate::error::*;
+use crate::extensions::Extensions;
+use crate::invocation::InvocationStream;
+use crate::journal::EventJournal;
+use crate::log_targets::CLIENT;
+use crate::manifest::*;
+#[cfg(feature = "protobuf")]
+use crate::proto::*;
+use crate::reflection::*;
 use crate::serializer::SerializerType;
+use crate::service::WampService;
+use crate::tenant::with_tenant;
+use crate::transport::tcp::RawSocketHandshake;
+use crate::transport::{ConnectionInfo, Transport};
+use crate::typed::*;
+
+/// How long join_realm() waits for the event loop to be spawned
+const EVENT_LOOP_START_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Options one can set when connecting to a WAMP server
+#[derive(Clone)]
 pub struct ClientConfig {
     /// Replaces the default user agent string
     agent: String,
+    /// Realm joined by `Client::connect` when `auto_join` is set
+    realm: Option<String>,
+    /// Whether `Client::connect` joins `realm`
+    auto_join: bool,
     /// A Set of all the roles the client will support
     roles: HashSet<ClientRole>,
     /// A priority list of which serializer to use when talking to the server
@@ -26,8 +53,74 @@ pub struct ClientConfig {
     max_msg_size: u32,
     /// When using a secure transport, this option disables certificate validation
     ssl_verify: bool,
+    /// Authentication role requested when joining a realm
+    authrole: Option<String>,
+    /// Replaces the `{tenant}` placeholder of realms and URIs
+    tenant: Option<String>,
+    /// Check the URIs registered and subscribed against the strict rules
+    strict_uris: bool,
+    /// Authentication ID and source of the tokens used by `Client::join_realm`
+    token_provider: Option<(String, TokenProvider)>,
+    /// Client certificate presented during the TLS handshake
+    tls_identity: Option<native_tls::Identity>,
+    /// Delay before racing the next address of the host when connecting
+    connection_attempt_delay: Duration,
     /// Additional WebSocket headers on establish connection
     websocket_headers: HashMap<String, String>,
+    /// Origin header sent in the WebSocket upgrade request
+    websocket_origin: Option<String>,
+    /// Query parameters appended to the WebSocket uri
+    websocket_query_params: Vec<(String, String)>,
+    /// Overrides the Host header of the WebSocket upgrade request
+    websocket_host: Option<String>,
+    /// No-op procedure called by `Client::ping()` instead of a transport ping
+    ping_procedure: Option<String>,
+    /// Payload validators keyed by procedure/topic uri
+    payload_validators: HashMap<String, PayloadValidator>,
+    /// Payload codecs keyed by procedure/topic uri
+    payload_codecs: HashMap<String, Arc<dyn PayloadCodec>>,
+    /// Journals recording the events received, keyed by topic
+    event_journals: HashMap<String, EventJournal>,
+    /// Registrations and subscriptions made after each join
+    manifest: Option<Manifest>,
+    /// Octets of the RawSocket handshake
+    rawsocket_handshake: RawSocketHandshake,
+    /// Debug sink for frames that could not be deserialized
+    malformed_frame_hook: Option<MalformedFrameHook>,
+    /// Receives the time spent handling each message and request
+    dispatch_timing_hook: Option<DispatchTimingHook>,
+    /// What to do with frames that could not be deserialized
+    malformed_message_policy: MalformedMessagePolicy,
+    /// What to do with invocation results larger than the router accepts
+    oversized_response_policy: OversizedResponsePolicy,
+    /// Maximum number of invocation results waiting to be sent
+    max_pending_yields: usize,
+    /// Maximum number of queued client requests handled in a row by the event loop
+    local_request_budget: usize,
+    /// How long to wait for the server to answer during the join handshake
+    handshake_timeout: Option<Duration>,
+    /// How long the challenge handler can take to answer a CHALLENGE
+    challenge_timeout: Option<Duration>,
+    /// How long `Client::leave_realm` waits for the router's GOODBYE
+    goodbye_timeout: Option<Duration>,
+    /// Quirks of the router implementation
+    router_dialect: RouterDialect,
+    /// Results of the calls and publications sent with an idempotency key
+    idempotency_cache: Option<IdempotencyCache>,
+    /// Typed values shared with the handlers of the session
+    extensions: Extensions,
+    /// Add the context of the failure to the ERROR of failed invocations
+    error_context: bool,
+    /// How long an inline RPC handler can run before being reported as slow
+    inline_handler_budget: Duration,
+    /// What to do with the inline RPC handlers exceeding their budget
+    slow_inline_handler_policy: SlowInlineHandlerPolicy,
+    /// How long an RPC handler can run before its invocation is failed
+    handler_watchdog: Option<Duration>,
+    /// WELCOME details echoed in the HELLO of the following sessions
+    routing_tokens: Option<RoutingTokens>,
+    /// Renews the configuration of a session logged out by the router
+    reauthentication_hook: Option<ReauthenticationHook>,
 }
 
 impl Default for ClientConfig {
@@ -46,6 +139,8 @@ impl Default for ClientConfig {
         // Config with default values
         ClientConfig {
             agent: String::from(DEFAULT_AGENT_STR),
+            realm: None,
+            auto_join: false,
             roles: [
                 ClientRole::Caller,
                 ClientRole::Callee,
@@ -58,13 +153,47 @@ impl Default for ClientConfig {
             serializers: vec![SerializerType::Json, SerializerType::MsgPack],
             max_msg_size: 0,
             ssl_verify: true,
+            authrole: None,
+            tenant: None,
+            strict_uris: false,
+            token_provider: None,
+            tls_identity: None,
+            connection_attempt_delay: Duration::from_millis(250),
             websocket_headers: HashMap::new(),
+            websocket_origin: None,
+            websocket_query_params: Vec::new(),
+            websocket_host: None,
+            ping_procedure: None,
+            payload_validators: HashMap::new(),
+            payload_codecs: HashMap::new(),
+            event_journals: HashMap::new(),
+            manifest: None,
+            rawsocket_handshake: RawSocketHandshake::default(),
+            malformed_frame_hook: None,
+            dispatch_timing_hook: None,
+            malformed_message_policy: MalformedMessagePolicy::Strict,
+            oversized_response_policy: OversizedResponsePolicy::Fail,
+            max_pending_yields: 1024,
+            local_request_budget: 1,
+            handshake_timeout: Some(Duration::from_secs(30)),
+            challenge_timeout: None,
+            goodbye_timeout: None,
+            router_dialect: RouterDialect::Standard,
+            idempotency_cache: None,
+            extensions: Extensions::new(),
+            error_context: false,
+            inline_handler_budget: Duration::from_millis(10),
+            slow_inline_handler_policy: SlowInlineHandlerPolicy::Warn,
+            handler_watchdog: None,
+            routing_tokens: None,
+            reauthentication_hook: None,
         }
     }
 }
 
 impl ClientConfig {
-    /// Replaces the default user agent string. Set to a zero length string to disable
+    /// Replaces the default user agent string. Set to a zero length string to not send any agent.
+    /// See [`Client::set_agent`] to change it between sessions.
     pub fn set_agent<T: AsRef<str>>(mut self, agent: T) -> Self {
         self.agent = String::from(agent.as_ref());
         self
@@ -74,6 +203,46 @@ impl ClientConfig {
         &self.agent
     }
 
+    /// Sets the realm joined by [`Client::connect`] when auto join is enabled
+    pub fn set_realm<T: Into<String>>(mut self, realm: T) -> Self {
+        self.realm = Some(realm.into());
+        self
+    }
+    /// Returns the realm joined on connect
+    pub fn get_realm(&self) -> Option<&str> {
+        self.realm.as_deref()
+    }
+
+    /// Sets whether [`Client::connect`] joins the realm set with [`ClientConfig::set_realm`]
+    /// before returning (default false)
+    ///
+    /// ```no_run
+    /// # use wamp_async::{Client, ClientConfig, WampError};
+    /// # async fn example() -> Result<(), WampError> {
+    /// let config = ClientConfig::default()
+    ///     .set_realm("realm1")
+    ///     .set_auto_join(true);
+    /// let (client, (evt_loop, _rpc_evt_queue)) =
+    ///     Client::connect("wss://localhost:8080/ws", Some(config)).await?;
+    /// tokio::spawn(evt_loop);
+    ///
+    /// // The client is already joined to realm1
+    /// client.publish("peer.heartbeat", None, None, false).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// The realm is joined with [`Client::join_realm`] : without authentication unless a
+    /// token provider is set with [`ClientConfig::set_token_provider`].
+    pub fn set_auto_join(mut self, auto_join: bool) -> Self {
+        self.auto_join = auto_join;
+        self
+    }
+    /// Returns whether the realm is joined on connect
+    pub fn get_auto_join(&self) -> bool {
+        self.auto_join
+    }
+
     /// Sets the maximum payload size which can be sent over the transport
     /// Set to 0 to use default
     pub fn set_max_msg_size(mut self, msg_size: u32) -> Self {
@@ -118,6 +287,103 @@ impl ClientConfig {
         self.ssl_verify
     }
 
+    /// Sets whether the URIs registered and subscribed to are checked against the strict rules
+    /// before being sent to the router (default false), see [`is_valid_strict_uri_pattern`].
+    /// Patterns are checked according to their match policy.
+    pub fn set_strict_uris(mut self, strict_uris: bool) -> Self {
+        self.strict_uris = strict_uris;
+        self
+    }
+    /// Returns whether the URIs are checked against the strict rules
+    pub fn get_strict_uris(&self) -> bool {
+        self.strict_uris
+    }
+
+    /// Requests `authrole` when joining a realm (default `None`, the router picks the role)
+    ///
+    /// This lets anonymous sessions target one of the roles of a realm (e.g. read-only vs
+    /// read-write). [`Client::join_realm`] then explicitly asks for `anonymous`
+    /// authentication. The role granted by the router is found in [`SessionInfo::authrole`].
+    pub fn set_authrole<T: Into<String>>(mut self, authrole: T) -> Self {
+        self.authrole = Some(authrole.into());
+        self
+    }
+    /// Returns the authentication role requested when joining a realm
+    pub fn get_authrole(&self) -> Option<&str> {
+        self.authrole.as_deref()
+    }
+
+    /// Sets the tenant replacing the `{tenant}` placeholder (see [`crate::TENANT_PLACEHOLDER`])
+    /// in the realm joined and in the URIs subscribed, published, registered and called
+    ///
+    /// The tenant must be a valid strict URI component, see [`crate::escape_uri_component`].
+    pub fn set_tenant<T: Into<String>>(mut self, tenant: T) -> Self {
+        self.tenant = Some(tenant.into());
+        self
+    }
+    /// Returns the tenant of the client
+    pub fn get_tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    /// Makes [`Client::join_realm`] authenticate as `authid` with ticket authentication,
+    /// asking `provider` for the ticket (e.g. a JWT) each time the router challenges the client
+    ///
+    /// As the provider is consulted on every join, expiring credentials are renewed when
+    /// joining again, by [`Client::reauthenticate`] or the sessions of a
+    /// [`CallerPool`](crate::CallerPool).
+    ///
+    /// ```
+    /// # use wamp_async::{ClientConfig, WampError};
+    /// let config = ClientConfig::default().set_token_provider("service-a", || async {
+    ///     // Fetch a fresh token from the identity provider
+    ///     Ok::<_, WampError>("eyJhbGciOi...".to_string())
+    /// });
+    /// ```
+    pub fn set_token_provider<T, F, Fut>(mut self, authid: T, provider: F) -> Self
+    where
+        T: Into<String>,
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<String, WampError>> + Send + 'static,
+    {
+        self.token_provider = Some((authid.into(), Arc::new(move || Box::pin(provider()))));
+        self
+    }
+    /// Returns the authentication ID and token provider used to join a realm, if any
+    pub fn get_token_provider(&self) -> Option<(&str, &TokenProvider)> {
+        self.token_provider
+            .as_ref()
+            .map(|(authid, provider)| (authid.as_str(), provider))
+    }
+
+    /// Presents a client certificate during the TLS handshake (default `None`), e.g. to join
+    /// with [`Client::join_realm_with_tls`]
+    ///
+    /// The identity is built with [`native_tls::Identity::from_pkcs12`] or
+    /// [`native_tls::Identity::from_pkcs8`].
+    pub fn set_tls_identity(mut self, identity: native_tls::Identity) -> Self {
+        self.tls_identity = Some(identity);
+        self
+    }
+    /// Returns the client certificate presented during the TLS handshake
+    pub fn get_tls_identity(&self) -> Option<&native_tls::Identity> {
+        self.tls_identity.as_ref()
+    }
+
+    /// Sets how long to wait for a connection attempt before also trying the next address of
+    /// the host (default 250 milliseconds, at least 10 milliseconds).
+    ///
+    /// The addresses alternate between IPv6 and IPv4 so a broken route for one family does
+    /// not delay the connection by more than this delay (RFC 8305 "Happy Eyeballs").
+    pub fn set_connection_attempt_delay(mut self, delay: Duration) -> Self {
+        self.connection_attempt_delay = delay.max(Duration::from_millis(10));
+        self
+    }
+    /// Returns how long to wait for a connection attempt before trying the next address
+    pub fn get_connection_attempt_delay(&self) -> Duration {
+        self.connection_attempt_delay
+    }
+
     pub fn add_websocket_header(mut self, key: String, val: String) -> Self {
         self.websocket_headers.insert(key, val);
         self
@@ -125,6 +391,375 @@ impl ClientConfig {
     pub fn get_websocket_headers(&self) -> &HashMap<String, String> {
         &self.websocket_headers
     }
+
+    /// Sets the Origin header sent when establishing a WebSocket connection
+    pub fn set_websocket_origin<T: AsRef<str>>(mut self, origin: T) -> Self {
+        self.websocket_origin = Some(String::from(origin.as_ref()));
+        self
+    }
+    /// Returns the Origin header sent when establishing a WebSocket connection
+    pub fn get_websocket_origin(&self) -> Option<&str> {
+        self.websocket_origin.as_deref()
+    }
+
+    /// Appends a query parameter to the WebSocket uri (e.g. an authentication token)
+    pub fn add_websocket_query_param<K: AsRef<str>, V: AsRef<str>>(
+        mut self,
+        key: K,
+        val: V,
+    ) -> Self {
+        self.websocket_query_params
+            .push((String::from(key.as_ref()), String::from(val.as_ref())));
+        self
+    }
+    /// Returns the query parameters appended to the WebSocket uri
+    pub fn get_websocket_query_params(&self) -> &Vec<(String, String)> {
+        &self.websocket_query_params
+    }
+
+    /// Overrides the Host header (`host[:port]`) sent when establishing a WebSocket connection.
+    /// The connection itself is still made to the host of the uri.
+    pub fn set_websocket_host<T: AsRef<str>>(mut self, host: T) -> Self {
+        self.websocket_host = Some(String::from(host.as_ref()));
+        self
+    }
+    /// Returns the Host header override
+    pub fn get_websocket_host(&self) -> Option<&str> {
+        self.websocket_host.as_deref()
+    }
+
+    /// Sets a no-op procedure that [`Client::ping`] calls to measure latency instead of
+    /// sending a transport level ping
+    pub fn set_ping_procedure<T: AsRef<str>>(mut self, uri: T) -> Self {
+        self.ping_procedure = Some(String::from(uri.as_ref()));
+        self
+    }
+    /// Returns the procedure used by [`Client::ping`] if any
+    pub fn get_ping_procedure(&self) -> Option<&str> {
+        self.ping_procedure.as_deref()
+    }
+
+    /// Adds a validator for the payloads received by the procedure or topic `uri`.
+    ///
+    /// Invocations failing validation are rejected with `wamp.error.invalid_argument` and events
+    /// are dropped, before any user code runs.
+    pub fn add_payload_validator<T, F>(mut self, uri: T, validator: F) -> Self
+    where
+        T: AsRef<str>,
+        F: Fn(Option<&WampArgs>, Option<&WampKwArgs>) -> Result<(), String> + Send + Sync + 'static,
+    {
+        self.payload_validators
+            .insert(String::from(uri.as_ref()), Arc::new(validator));
+        self
+    }
+    /// Adds a validator for the payloads received by `uri` which must deserialize into `P`
+    /// (following the same payload rules as [`TypedTopic`]/[`TypedProcedure`])
+    pub fn add_typed_payload_validator<P: DeserializeOwned, T: AsRef<str>>(self, uri: T) -> Self {
+        self.add_payload_validator(uri, |arguments, arguments_kw| {
+            decode_payload::<P>(arguments.cloned(), arguments_kw.cloned())
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+    }
+    /// Returns the payload validators keyed by procedure/topic uri
+    pub fn get_payload_validators(&self) -> &HashMap<String, PayloadValidator> {
+        &self.payload_validators
+    }
+
+    /// Converts the payloads of the procedure or topic `uri` with `codec`, see [`PayloadCodec`]
+    ///
+    /// The codec applies to the calls, registrations, publications and subscriptions made on
+    /// `uri` exactly. For pattern based registrations and subscriptions, `uri` is the pattern.
+    pub fn add_payload_codec<T, C>(mut self, uri: T, codec: C) -> Self
+    where
+        T: AsRef<str>,
+        C: PayloadCodec + 'static,
+    {
+        self.payload_codecs
+            .insert(String::from(uri.as_ref()), Arc::new(codec));
+        self
+    }
+    /// Returns the payload codecs keyed by procedure/topic uri
+    pub fn get_payload_codecs(&self) -> &HashMap<String, Arc<dyn PayloadCodec>> {
+        &self.payload_codecs
+    }
+
+    /// Records the events received on `topic` in `journal`, see [`EventJournal`]
+    pub fn add_event_journal<T: AsRef<str>>(mut self, topic: T, journal: EventJournal) -> Self {
+        self.event_journals
+            .insert(String::from(topic.as_ref()), journal);
+        self
+    }
+    /// Returns the event journals keyed by topic
+    pub fn get_event_journals(&self) -> &HashMap<String, EventJournal> {
+        &self.event_journals
+    }
+
+    /// Sets a manifest applied with [`Client::apply_manifest`] each time the client joins a
+    /// realm. What it made is returned by [`Client::take_applied_manifest`].
+    pub fn set_manifest(mut self, manifest: Manifest) -> Self {
+        self.manifest = Some(manifest);
+        self
+    }
+    /// Returns the manifest applied when joining a realm, if any
+    pub fn get_manifest(&self) -> Option<&Manifest> {
+        self.manifest.as_ref()
+    }
+
+    /// Sets the magic and reserved octets of the RawSocket handshake, and how the reply is
+    /// validated (used by some vendor extensions)
+    pub fn set_rawsocket_handshake(mut self, handshake: RawSocketHandshake) -> Self {
+        self.rawsocket_handshake = handshake;
+        self
+    }
+    /// Returns the octets of the RawSocket handshake
+    pub fn get_rawsocket_handshake(&self) -> &RawSocketHandshake {
+        &self.rawsocket_handshake
+    }
+
+    /// Sets a hook that receives the raw frames which could not be deserialized, along with
+    /// the error
+    pub fn set_malformed_frame_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&[u8], &WampError) + Send + Sync + 'static,
+    {
+        self.malformed_frame_hook = Some(Arc::new(hook));
+        self
+    }
+    /// Returns the malformed frame hook if any
+    pub fn get_malformed_frame_hook(&self) -> Option<&MalformedFrameHook> {
+        self.malformed_frame_hook.as_ref()
+    }
+
+    /// Sets a hook that receives the time spent by the event loop handling each message from
+    /// the router and each request of the client. Nothing is timed without a hook.
+    ///
+    /// ```
+    /// # use wamp_async::{ClientConfig, DispatchTiming};
+    /// let config = ClientConfig::default().set_dispatch_timing_hook(|t: &DispatchTiming| {
+    ///     log::trace!("{:?} {} took {:?}", t.source, t.kind, t.elapsed);
+    /// });
+    /// ```
+    pub fn set_dispatch_timing_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&DispatchTiming) + Send + Sync + 'static,
+    {
+        self.dispatch_timing_hook = Some(Arc::new(hook));
+        self
+    }
+    /// Returns the dispatch timing hook if any
+    pub fn get_dispatch_timing_hook(&self) -> Option<&DispatchTimingHook> {
+        self.dispatch_timing_hook.as_ref()
+    }
+
+    /// Sets a hook renewing the configuration of a session the router killed or whose
+    /// credentials expired, before [`CallerPool`](crate::CallerPool) reconnects it. Failures
+    /// are retried after a delay.
+    ///
+    /// Sessions closed by the router end with [`WampError::SessionClosed`] whether a hook is
+    /// set or not.
+    pub fn set_reauthentication_hook<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: Fn(SessionCloseReason, ClientConfig) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<ClientConfig, WampError>> + Send + 'static,
+    {
+        self.reauthentication_hook = Some(Arc::new(move |reason, cfg| Box::pin(hook(reason, cfg))));
+        self
+    }
+    /// Returns the reauthentication hook if any
+    pub fn get_reauthentication_hook(&self) -> Option<&ReauthenticationHook> {
+        self.reauthentication_hook.as_ref()
+    }
+
+    /// Sets whether inbound frames that cannot be deserialized close the connection
+    /// ([`MalformedMessagePolicy::Strict`], default) or are dropped
+    pub fn set_malformed_message_policy(mut self, policy: MalformedMessagePolicy) -> Self {
+        self.malformed_message_policy = policy;
+        self
+    }
+    /// Returns the policy for inbound frames that cannot be deserialized
+    pub fn get_malformed_message_policy(&self) -> MalformedMessagePolicy {
+        self.malformed_message_policy
+    }
+
+    /// Sets whether invocation results larger than the maximum message size negotiated with
+    /// the router are replaced by an ERROR ([`OversizedResponsePolicy::Fail`], default) or sent
+    /// anyway
+    pub fn set_oversized_response_policy(mut self, policy: OversizedResponsePolicy) -> Self {
+        self.oversized_response_policy = policy;
+        self
+    }
+    /// Returns the policy for invocation results larger than the router accepts
+    pub fn get_oversized_response_policy(&self) -> OversizedResponsePolicy {
+        self.oversized_response_policy
+    }
+
+    /// Sets the maximum number of invocation results waiting to be written to the transport
+    /// (default 1024). Once reached, finished RPC endpoints wait for the transport before
+    /// handing over their results.
+    pub fn set_max_pending_yields(mut self, max_pending_yields: usize) -> Self {
+        self.max_pending_yields = max_pending_yields.max(1);
+        self
+    }
+    /// Returns the maximum number of invocation results waiting to be written to the transport
+    pub fn get_max_pending_yields(&self) -> usize {
+        self.max_pending_yields
+    }
+
+    /// Sets the maximum number of queued client requests the event loop handles in a row before
+    /// going back to the connection (default 1)
+    ///
+    /// The event loop picks randomly between the connection and the client requests when both
+    /// are ready, so neither can starve the other. A larger budget drains bursts of requests
+    /// (e.g. many publications) faster, at the cost of the latency of inbound messages.
+    pub fn set_local_request_budget(mut self, budget: usize) -> Self {
+        self.local_request_budget = budget.max(1);
+        self
+    }
+    /// Returns the maximum number of queued client requests handled in a row
+    pub fn get_local_request_budget(&self) -> usize {
+        self.local_request_budget
+    }
+
+    /// Sends the context of handler failures (error chain, procedure and duration) under the
+    /// [`ERROR_CONTEXT_KWARG`] keyword argument of the ERROR returned to the caller
+    /// (default false)
+    ///
+    /// The messages of the errors are visible to every caller, only enable this when they do
+    /// not leak sensitive information.
+    pub fn set_error_context(mut self, enabled: bool) -> Self {
+        self.error_context = enabled;
+        self
+    }
+    /// Returns whether the context of handler failures is sent to the caller
+    pub fn get_error_context(&self) -> bool {
+        self.error_context
+    }
+
+    /// Sets how long a handler registered with [`Client::register_inline`] can run (default
+    /// 10 milliseconds)
+    ///
+    /// A handler exceeding it blocked the event loop : a warning is logged and
+    /// [`Client::num_slow_inline_handlers`] is incremented. The handler keeps running inline
+    /// unless the policy is [`SlowInlineHandlerPolicy::Demote`], see
+    /// [`ClientConfig::set_slow_inline_handler_policy`]. As a single slow run can come from the
+    /// scheduling of the thread rather than the handler, keep the budget well above the usual
+    /// duration of the handlers.
+    pub fn set_inline_handler_budget(mut self, budget: Duration) -> Self {
+        self.inline_handler_budget = budget;
+        self
+    }
+    /// Returns how long a handler registered with [`Client::register_inline`] can run
+    pub fn get_inline_handler_budget(&self) -> Duration {
+        self.inline_handler_budget
+    }
+
+    /// Sets whether inline handlers exceeding their budget keep running inline
+    /// ([`SlowInlineHandlerPolicy::Warn`], default) or are moved to the RPC event queue
+    pub fn set_slow_inline_handler_policy(mut self, policy: SlowInlineHandlerPolicy) -> Self {
+        self.slow_inline_handler_policy = policy;
+        self
+    }
+    /// Returns what is done with the inline handlers exceeding their budget
+    pub fn get_slow_inline_handler_policy(&self) -> SlowInlineHandlerPolicy {
+        self.slow_inline_handler_policy
+    }
+
+    /// Sets how long an RPC handler can run before being aborted (default none)
+    ///
+    /// A handler exceeding it is dropped, a warning is logged and the dealer receives a
+    /// `wamp.error.timeout` ERROR, protecting the service from handlers awaiting forever. The
+    /// number of aborted handlers is returned by [`Client::num_expired_handlers`].
+    ///
+    /// Handlers registered with [`Client::register_inline`] or [`Client::register_streamed`]
+    /// are not watched.
+    pub fn set_handler_watchdog(mut self, limit: Duration) -> Self {
+        self.handler_watchdog = Some(limit);
+        self
+    }
+    /// Returns how long an RPC handler can run before being aborted
+    pub fn get_handler_watchdog(&self) -> Option<Duration> {
+        self.handler_watchdog
+    }
+
+    /// Sets how long to wait for each WELCOME or CHALLENGE message when joining a realm
+    /// (default 30 seconds). `None` waits forever.
+    ///
+    /// The event loop keeps processing other messages during the handshake.
+    pub fn set_handshake_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+    /// Returns how long to wait for each WELCOME or CHALLENGE message when joining a realm
+    pub fn get_handshake_timeout(&self) -> Option<Duration> {
+        self.handshake_timeout
+    }
+
+    /// Sets how long the challenge handler can take to answer a CHALLENGE (default `None`,
+    /// waits forever)
+    pub fn set_challenge_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.challenge_timeout = timeout;
+        self
+    }
+    /// Returns how long the challenge handler can take to answer a CHALLENGE
+    pub fn get_challenge_timeout(&self) -> Option<Duration> {
+        self.challenge_timeout
+    }
+
+    /// Makes [`Client::leave_realm`] wait up to `timeout` for the router to answer our GOODBYE
+    /// (default `None`, returns once our GOODBYE is sent)
+    pub fn set_goodbye_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.goodbye_timeout = timeout;
+        self
+    }
+    /// Returns how long [`Client::leave_realm`] waits for the router to answer our GOODBYE
+    pub fn get_goodbye_timeout(&self) -> Option<Duration> {
+        self.goodbye_timeout
+    }
+
+    /// Adapts the client to known quirks of the router implementation (default
+    /// `RouterDialect::Standard`)
+    pub fn set_router_dialect(mut self, dialect: RouterDialect) -> Self {
+        self.router_dialect = dialect;
+        self
+    }
+    /// Returns the router implementation the client adapts to
+    pub fn get_router_dialect(&self) -> RouterDialect {
+        self.router_dialect
+    }
+
+    /// Remembers the results of calls and publications sent with an idempotency key so retries
+    /// are not sent again (default `None`)
+    pub fn set_idempotency_cache(mut self, cache: IdempotencyCache) -> Self {
+        self.idempotency_cache = Some(cache);
+        self
+    }
+    /// Returns the cache of idempotent calls and publications
+    pub fn get_idempotency_cache(&self) -> Option<&IdempotencyCache> {
+        self.idempotency_cache.as_ref()
+    }
+
+    /// Shares typed values with the handlers of the session (default empty). Clones of the
+    /// config share the same values.
+    pub fn set_extensions(mut self, extensions: Extensions) -> Self {
+        self.extensions = extensions;
+        self
+    }
+    /// Returns the typed values shared with the handlers of the session
+    pub fn get_extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Echoes the routing tokens of the WELCOME details in the HELLO of the following sessions
+    /// (default `None`)
+    pub fn set_routing_tokens(mut self, tokens: RoutingTokens) -> Self {
+        self.routing_tokens = Some(tokens);
+        self
+    }
+    /// Returns the routing tokens echoed in HELLO
+    pub fn get_routing_tokens(&self) -> Option<&RoutingTokens> {
+        self.routing_tokens.as_ref()
+    }
 }
 
 /// Allows interaction as a client with a WAMP server
@@ -134,15 +769,48 @@ pub struct Client<'a> {
     /// Generic transport
     core_res: UnboundedReceiver<Result<(), WampError>>,
     core_status: ClientState,
-    /// Roles supported by the server
-    server_roles: HashSet<String>,
-    /// Current Session ID
-    session_id: Option<WampId>,
+    /// Details of the current session
+    session_info: Option<SessionInfo>,
+    /// State of the session, maintained by the event loop
+    session_state: watch::Receiver<SessionState>,
     /// Channel to send requests to the event loop
-    ctl_channel: UnboundedSender<Request<'a>>,
+    ctl_channel: CtlSender<'a>,
+    /// Details about the established connection
+    connection_info: ConnectionInfo,
+    /// Flow control of the invocation results
+    yield_flow: YieldFlowControl,
+    /// Aborts the RPC handlers running for too long
+    handler_watchdog: HandlerWatchdog,
+    /// Depth of the subscription queues
+    queue_probes: QueueProbes,
+    /// Number of duplicate replies ignored by the event loop
+    num_duplicate_replies: Arc<AtomicU64>,
+    /// Number of events dropped because a bounded subscription queue was full
+    num_dropped_events: Arc<AtomicU64>,
+    /// Number of inline RPC handlers that exceeded their budget
+    num_slow_inline_handlers: Arc<AtomicU64>,
+    /// Size of the largest invocation result sent
+    largest_response: Arc<AtomicUsize>,
+    /// Whether the event loop was polled at least once
+    event_loop_started: Arc<AtomicBool>,
+    /// Number of requests failed when the event loop stopped
+    aborted_requests: Arc<AtomicUsize>,
+    /// ERROR messages that could not be matched to a pending request
+    protocol_errors: Option<mpsc::Receiver<UnhandledError>>,
+    /// Registrations revoked by the router
+    registration_events: Option<UnboundedReceiver<RegistrationEvent>>,
+    /// Subscriptions revoked by the router
+    subscription_events: Option<UnboundedReceiver<SubscriptionEvent>>,
+    /// Invocations of the procedures registered as streamed
+    invocations: Option<InvocationStream<'a>>,
+    /// Registrations and subscriptions made by the manifests applied
+    manifest_state: Mutex<ManifestState>,
+    /// What the manifest of the configuration made when the client last joined
+    applied_manifest: Option<AppliedManifest>,
 }
 
 /// All the states a client can be in
+#[derive(Debug, Clone)]
 pub enum ClientState {
     /// The event loop hasnt been spawned yet
     NoEventLoop,
@@ -152,6 +820,95 @@ pub enum ClientState {
     Disconnected(Result<(), WampError>),
 }
 
+impl ClientState {
+    /// Returns the error that caused the disconnection, if any
+    pub fn error(&self) -> Option<&WampError> {
+        match self {
+            ClientState::Disconnected(Err(e)) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// How a connection was closed by [`Client::disconnect`]
+#[derive(Debug, Clone)]
+pub struct DisconnectSummary {
+    /// Result of leaving the realm, `None` if the client was not in a realm
+    pub leave: Option<Result<(), WampError>>,
+    /// Number of requests still waiting for the router that failed with
+    /// [`WampError::ConnectionLost`]
+    pub aborted_requests: usize,
+    /// How the connection ended : `Ok` when closed by us, or the error that closed it before
+    pub close: Result<(), WampError>,
+}
+
+/// Call in progress started by [`Client::call_with_handle`]
+pub struct CallHandle<'a> {
+    request: WampId,
+    uri: WampUri,
+    timeout: Option<Duration>,
+    result: oneshot::Receiver<Result<CallResult, WampError>>,
+    progress: Option<UnboundedReceiver<CallResult>>,
+    ctl_channel: CtlSender<'a>,
+}
+
+impl CallHandle<'_> {
+    /// Returns the request ID of the CALL message
+    pub fn request_id(&self) -> WampId {
+        self.request
+    }
+
+    /// Asks the dealer to cancel the call. The result of the call is then
+    /// [`WampError::CallCanceled`], unless the callee completed it first.
+    ///
+    /// The router must support the `call_canceling` feature.
+    pub async fn cancel(&self, mode: CancelMode) -> Result<(), WampError> {
+        let (res, result) = oneshot::channel();
+        self.ctl_channel
+            .send(Request::Cancel {
+                request: self.request,
+                mode,
+                res,
+            })
+            .map_err(|e| WampError::from(format!("Core never received our request : {}", e)))?;
+
+        match result.await {
+            Ok(r) => r,
+            Err(e) => Err(From::from(format!(
+                "Core never returned a response : {}",
+                e
+            ))),
+        }
+    }
+
+    /// Waits for the next progressive result of the call, if asked for with
+    /// [`CallOptions::with_receive_progress`]. Returns `None` once the call completed.
+    pub async fn progress(&mut self) -> Option<CallResult> {
+        match &mut self.progress {
+            Some(progress) => progress.recv().await,
+            None => None,
+        }
+    }
+
+    /// Waits for the result of the call, within the timeout of its options
+    pub async fn result(self) -> Result<CallResult, WampError> {
+        let result = match self.timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, self.result).await {
+                Ok(r) => r,
+                Err(_) => return Err(WampError::Timeout(format!("calling {}", self.uri))),
+            },
+            None => self.result.await,
+        };
+        match result {
+            Ok(r) => r,
+            Err(e) => Err(From::from(format!(
+                "Core never returned a response : {}",
+                e
+            ))),
+        }
+    }
+}
+
 impl<'a> Client<'a> {
     /// Connects to a WAMP server using the specified protocol
     ///
@@ -181,36 +938,133 @@ impl<'a> Client<'a> {
             Err(e) => return Err(WampError::InvalidUri(e)),
         };
 
-        let config = match cfg {
-            Some(c) => c,
-            // Set defaults
-            None => ClientConfig::default(),
+        // Use defaults if no config was provided
+        let config = cfg.unwrap_or_default();
+        let auto_join = match (config.get_auto_join(), config.get_realm()) {
+            (false, _) => None,
+            (true, Some(realm)) => Some(realm.to_string()),
+            (true, None) => {
+                return Err(WampError::InvalidArgument(
+                    "Auto join is enabled but no realm was set".to_string(),
+                ))
+            }
         };
 
-        let (ctl_channel, ctl_receiver) = mpsc::unbounded_channel();
+        let (ctl_channel, ctl_receiver) = ctl_channel();
         let (core_res_w, core_res) = mpsc::unbounded_channel();
 
         let ctl_sender = ctl_channel.clone();
         // Establish a connection
-        let mut conn = Core::connect(&uri, &config, (ctl_sender, ctl_receiver), core_res_w).await?;
+        let conn = Core::connect(&uri, &config, (ctl_sender, ctl_receiver), core_res_w).await?;
+
+        let (mut client, (evt_loop, rpc_evt_queue)) =
+            Client::from_core(conn, config, ctl_channel, core_res);
+        let evt_loop = match auto_join {
+            Some(realm) => client.join_with_event_loop(realm, evt_loop).await?,
+            None => evt_loop,
+        };
+
+        Ok((client, (evt_loop, rpc_evt_queue)))
+    }
+
+    /// Joins `realm` while running the event loop, which is returned once joined
+    pub(crate) async fn join_with_event_loop(
+        &mut self,
+        realm: String,
+        evt_loop: GenericFuture<'a>,
+    ) -> Result<GenericFuture<'a>, WampError> {
+        match future::select(evt_loop, Box::pin(self.join_realm(realm))).await {
+            Either::Left((res, _)) => Err(res.err().unwrap_or_else(|| {
+                From::from("Event loop stopped while joining the realm".to_string())
+            })),
+            Either::Right((res, evt_loop)) => {
+                res?;
+                Ok(evt_loop)
+            }
+        }
+    }
+
+    /// Creates a client from an already established transport
+    ///
+    /// This is useful to connect through streams which are not opened by this crate
+    /// (connection pools, custom TLS stacks, tunnels, ...). See [`crate::transport::ws::from_stream`]
+    /// and [`crate::transport::tcp::from_stream`].
+    ///
+    /// The returned values are the same as [`Client::connect`]. The realm of the config is not
+    /// joined automatically.
+    pub fn from_transport(
+        transport: Box<dyn Transport + Send>,
+        serializer: SerializerType,
+        cfg: Option<ClientConfig>,
+    ) -> (
+        Client<'a>,
+        (
+            GenericFuture<'a>,
+            Option<UnboundedReceiver<GenericFuture<'a>>>,
+        ),
+    ) {
+        let config = cfg.unwrap_or_default();
+
+        let (ctl_channel, ctl_receiver) = ctl_channel();
+        let (core_res_w, core_res) = mpsc::unbounded_channel();
+
+        let ctl_sender = ctl_channel.clone();
+        let conn = Core::new(
+            transport,
+            serializer,
+            &config,
+            (ctl_sender, ctl_receiver),
+            core_res_w,
+        );
+
+        Client::from_core(conn, config, ctl_channel, core_res)
+    }
 
+    fn from_core(
+        mut conn: Core<'a>,
+        config: ClientConfig,
+        ctl_channel: CtlSender<'a>,
+        core_res: UnboundedReceiver<Result<(), WampError>>,
+    ) -> (
+        Client<'a>,
+        (
+            GenericFuture<'a>,
+            Option<UnboundedReceiver<GenericFuture<'a>>>,
+        ),
+    ) {
         let rpc_evt_queue = if config.roles.contains(&ClientRole::Callee) {
             conn.rpc_event_queue_r.take()
         } else {
             None
         };
 
-        Ok((
+        (
             Client {
                 config,
-                server_roles: HashSet::new(),
-                session_id: None,
+                session_info: None,
+                session_state: conn.watch_session_state(),
                 ctl_channel,
                 core_res,
                 core_status: ClientState::NoEventLoop,
+                connection_info: conn.connection_info.clone(),
+                yield_flow: conn.yield_flow.clone(),
+                handler_watchdog: conn.handler_watchdog.clone(),
+                queue_probes: conn.queue_probes.clone(),
+                num_duplicate_replies: conn.num_duplicate_replies.clone(),
+                num_dropped_events: conn.num_dropped_events.clone(),
+                num_slow_inline_handlers: conn.num_slow_inline_handlers.clone(),
+                largest_response: conn.largest_response.clone(),
+                event_loop_started: conn.event_loop_started.clone(),
+                aborted_requests: conn.aborted_requests.clone(),
+                protocol_errors: conn.protocol_errors_r.take(),
+                registration_events: conn.registration_events_r.take(),
+                subscription_events: conn.subscription_events_r.take(),
+                invocations: conn.invocations_r.take().map(InvocationStream::new),
+                manifest_state: Mutex::new(ManifestState::default()),
+                applied_manifest: None,
             },
             (Box::pin(conn.event_loop()), rpc_evt_queue),
-        ))
+        )
     }
 
     /// Attempts to join a realm and start a session with the server.
@@ -219,14 +1073,27 @@ impl<'a> Client<'a> {
     async fn inner_join_realm(
         &mut self,
         realm: String,
-        authentication_methods: Vec<AuthenticationMethod>,
+        mut authentication_methods: Vec<AuthenticationMethod>,
         authentication_id: Option<String>,
         on_challenge_handler: Option<AuthenticationChallengeHandler<'a>>,
     ) -> Result<(), WampError> {
+        let realm = with_tenant(&realm, self.config.get_tenant())?;
+
         // Make sure the event loop is ready to process requests
         if let ClientState::NoEventLoop = self.get_cur_status() {
-            debug!("Called join_realm() before th event loop is ready... Waiting...");
-            self.wait_for_status_change().await;
+            debug!(
+                target: CLIENT,
+                "Called join_realm() before th event loop is ready... Waiting..."
+            );
+            if tokio::time::timeout(EVENT_LOOP_START_TIMEOUT, self.wait_for_status_change())
+                .await
+                .is_err()
+            {
+                return Err(WampError::EventLoopNotRunning);
+            }
+        }
+        if let ClientState::Disconnected(Err(WampError::EventLoopNotRunning)) = self.core_status {
+            return Err(WampError::EventLoopNotRunning);
         }
 
         // Make sure we are still connected to a server
@@ -237,36 +1104,38 @@ impl<'a> Client<'a> {
         }
 
         // Make sure we arent already part of a realm
-        if self.session_id.is_some() {
+        if let SessionState::Joining | SessionState::Joined(_) = self.session_state() {
             return Err(From::from(format!(
                 "join_realm('{}') : Client already joined to a realm",
                 realm
             )));
         }
 
+        // Routers only consider the requested role along with an authentication method
+        let authrole = self.config.authrole.clone();
+        if authrole.is_some() && authentication_methods.is_empty() {
+            authentication_methods.push(AuthenticationMethod::Anonymous);
+        }
+
         // Send a request for the core to perform the action
         let (res_sender, res) = oneshot::channel();
-        if let Err(e) = self.ctl_channel.send(Request::Join {
-            uri: realm,
+        self.send_request(Request::Join {
+            uri: realm.clone(),
             roles: self.config.roles.clone(),
             agent_str: if self.config.agent.is_empty() {
-                Some(self.config.agent.clone())
-            } else {
                 None
+            } else {
+                Some(self.config.agent.clone())
             },
             authentication_methods,
             authentication_id,
+            authrole: authrole.clone(),
             on_challenge_handler,
             res: res_sender,
-        }) {
-            return Err(From::from(format!(
-                "Core never received our request : {}",
-                e
-            )));
-        }
+        })?;
 
         // Wait for the request results
-        let (session_id, mut server_roles) = match res.await {
+        let (session_id, details) = match res.await {
             Ok(r) => r?,
             Err(e) => {
                 return Err(From::from(format!(
@@ -276,15 +1145,24 @@ impl<'a> Client<'a> {
             }
         };
 
-        // Add the server roles
-        self.server_roles.drain();
-        for (role, _) in server_roles.drain().take(1) {
-            self.server_roles.insert(role);
+        let session_info = SessionInfo::new(session_id, realm, details);
+        if let Some(authrole) = authrole {
+            if session_info.authrole.as_ref() != Some(&authrole) {
+                warn!(
+                    target: CLIENT,
+                    "Requested authrole {} but the router granted {:?}",
+                    authrole, session_info.authrole
+                );
+            }
         }
+        self.session_info = Some(session_info);
+
+        debug!(target: CLIENT, "Connected with session_id {} !", session_id);
 
-        // Set the current session
-        self.session_id = Some(session_id);
-        debug!("Connected with session_id {} !", session_id);
+        if let Some(manifest) = self.config.get_manifest() {
+            let applied = self.apply_manifest(manifest).await?;
+            self.applied_manifest = Some(applied);
+        }
 
         Ok(())
     }
@@ -292,9 +1170,31 @@ impl<'a> Client<'a> {
     /// Attempts to join a realm and start a session with the server.
     ///
     /// * `realm` - A name of the WAMP realm
+    ///
+    /// Authenticates with the tokens of [`ClientConfig::set_token_provider`] if one is set.
     pub async fn join_realm<T: Into<String>>(&mut self, realm: T) -> Result<(), WampError> {
-        self.inner_join_realm(realm.into(), vec![], None, None)
-            .await
+        let (authid, provider) = match &self.config.token_provider {
+            Some((authid, provider)) => (authid.clone(), provider.clone()),
+            None => {
+                return self
+                    .inner_join_realm(realm.into(), vec![], None, None)
+                    .await
+            }
+        };
+        self.inner_join_realm(
+            realm.into(),
+            vec![AuthenticationMethod::Ticket],
+            Some(authid),
+            Some(Box::new(move |_authentication_method, _extra| {
+                let provider = provider.clone();
+                Box::pin(async move {
+                    Ok(AuthenticationChallengeResponse::with_signature(
+                        provider().await?,
+                    ))
+                })
+            })),
+        )
+        .await
     }
 
     /// Attempts to join a realm and start a session with the server.
@@ -353,7 +1253,38 @@ impl<'a> Client<'a> {
         .await
     }
 
+    /// Joins a realm authenticating with the TLS client certificate set with
+    /// [`ClientConfig::set_tls_identity`]
+    ///
+    /// `authentication_id` is only needed when the router cannot derive it from the
+    /// certificate. Fails if the connection is not established over TLS.
+    pub async fn join_realm_with_tls<T: Into<String>>(
+        &mut self,
+        realm: T,
+        authentication_id: Option<String>,
+    ) -> Result<(), WampError> {
+        match &self.connection_info.tls {
+            Some(tls) if tls.client_certificate => {}
+            _ => {
+                return Err(WampError::InvalidArgument(
+                    "TLS authentication requires a TLS connection with a client certificate"
+                        .to_string(),
+                ))
+            }
+        }
+        self.inner_join_realm(
+            realm.into(),
+            vec![AuthenticationMethod::Tls],
+            authentication_id,
+            None,
+        )
+        .await
+    }
+
     /// Leaves the current realm and terminates the session with the server
+    ///
+    /// With [`ClientConfig::set_goodbye_timeout`], also waits for the router to answer our
+    /// GOODBYE. Fails with [`WampError::Timeout`] if it does not in time.
     pub async fn leave_realm(&mut self) -> Result<(), WampError> {
         // Make sure we are still connected to a server
         if !self.is_connected() {
@@ -363,18 +1294,14 @@ impl<'a> Client<'a> {
         }
 
         // Nothing to do if not currently in a session
-        if self.session_id.take().is_none() {
+        if !matches!(self.session_state(), SessionState::Joined(_)) {
             return Ok(());
         }
 
         // Send the request
         let (res, result) = oneshot::channel();
-        if let Err(e) = self.ctl_channel.send(Request::Leave { res }) {
-            return Err(From::from(format!(
-                "Core never received our request : {}",
-                e
-            )));
-        }
+        self.send_request(Request::Leave { res })?;
+        self.session_info = None;
 
         // Wait for the result
         match result.await {
@@ -387,28 +1314,135 @@ impl<'a> Client<'a> {
             }
         };
 
+        if let Some(timeout) = self.config.get_goodbye_timeout() {
+            self.wait_for_goodbye(timeout).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Leaves the current realm and joins it again with [`Client::join_realm`], presenting a
+    /// fresh token when a provider is set with [`ClientConfig::set_token_provider`]
+    ///
+    /// The active subscriptions are renewed in the new session : their queues keep receiving
+    /// events and their IDs remain valid for [`Client::unsubscribe`]. Events published while
+    /// rejoining are missed. Registrations are not renewed and must be registered again, except
+    /// the ones of the manifest set with [`ClientConfig::set_manifest`] which is applied again.
+    pub async fn reauthenticate(&mut self) -> Result<(), WampError> {
+        let realm = match self.session_info() {
+            Some(info) => info.realm.clone(),
+            None => {
+                return Err(From::from(
+                    "reauthenticate() : Client is not joined to a realm".to_string(),
+                ))
+            }
+        };
+        self.leave_realm().await?;
+        // The manifest is applied once its subscriptions are renewed
+        let manifest = self.config.manifest.take();
+        let joined = self.join_realm(realm).await;
+        self.config.manifest = manifest;
+        joined?;
+
+        let (res, result) = oneshot::channel();
+        self.send_request(Request::Resubscribe { res })?;
+        let results = match result.await {
+            Ok(r) => r,
+            Err(e) => {
+                return Err(From::from(format!(
+                    "Core never returned a response : {}",
+                    e
+                )))
+            }
+        };
+        for res in future::join_all(results).await {
+            match res {
+                Ok(r) => r?,
+                Err(e) => {
+                    return Err(From::from(format!(
+                        "Core never returned a response : {}",
+                        e
+                    )))
+                }
+            };
+        }
+
+        // The subscriptions of the manifest were renewed, unlike its registrations
+        let registrations = {
+            let mut state = self.manifest_state.lock().unwrap();
+            state.session_id = self.session_info().map(|info| info.session_id);
+            std::mem::take(&mut state.registrations)
+        };
+        for (rpc_id, _) in registrations.into_values() {
+            let _ = self.unregister(rpc_id).await;
+        }
+        if let Some(manifest) = self.config.get_manifest() {
+            let applied = self.apply_manifest(manifest).await?;
+            self.applied_manifest = Some(applied);
+        }
         Ok(())
     }
 
+    /// Waits for the router to answer our GOODBYE
+    async fn wait_for_goodbye(&self, timeout: Duration) -> Result<(), WampError> {
+        let mut state = self.session_state.clone();
+        let acknowledged = async {
+            loop {
+                let cur = *state.borrow_and_update();
+                match cur {
+                    SessionState::Leaving => {}
+                    SessionState::Disconnected => return Err(WampError::ConnectionLost),
+                    _ => return Ok(()),
+                }
+                if state.changed().await.is_err() {
+                    return Err(WampError::ConnectionLost);
+                }
+            }
+        };
+
+        match tokio::time::timeout(timeout, acknowledged).await {
+            Ok(r) => r,
+            Err(_) => Err(WampError::Timeout(
+                "waiting for the router's GOODBYE".to_string(),
+            )),
+        }
+    }
+
     /// Subscribes to events for the specifiec topic
     ///
     /// This function returns a subscription ID (required to unsubscribe) and
-    /// the receive end of a channel for events published on the topic.
+    /// the receive end of a channel for events published on the topic. Events are received with
+    /// their publication ID, arguments, keyword arguments and the details of the EVENT message
+    /// (e.g. the `topic` of the publication for pattern-based subscriptions, see
+    /// [`SubscribeOptions::with_match`]).
+    ///
+    /// Events are queued in the order they were received from the broker. They are handled in
+    /// that order as long as they are processed one after the other : spawning a task per event
+    /// loses the ordering, use an [`crate::OrderedEventRunner`] to keep it.
     pub async fn subscribe<T: AsRef<str>>(
         &self,
         topic: T,
     ) -> Result<(WampId, SubscriptionQueue), WampError> {
+        self.subscribe_with_options(topic, SubscribeOptions::default())
+            .await
+    }
+
+    /// Subscribes to events with specific options. See [`Client::subscribe`]
+    pub async fn subscribe_with_options<T: AsRef<str>>(
+        &self,
+        topic: T,
+        options: SubscribeOptions,
+    ) -> Result<(WampId, SubscriptionQueue), WampError> {
+        let topic = self.scoped_uri(topic.as_ref())?;
+        self.check_uri(&topic, options.get_match())?;
+
         // Send the request
         let (res, result) = oneshot::channel();
-        if let Err(e) = self.ctl_channel.send(Request::Subscribe {
-            uri: topic.as_ref().to_string(),
-            res,
-        }) {
-            return Err(From::from(format!(
-                "Core never received our request : {}",
-                e
-            )));
-        }
+        self.send_request(Request::Subscribe {
+            uri: topic,
+            options: options.to_dict(),
+            res: PendingSub::Queue(res),
+        })?;
 
         // Wait for the result
         let (sub_id, evt_queue) = match result.await {
@@ -424,16 +1458,117 @@ impl<'a> Client<'a> {
         Ok((sub_id, evt_queue))
     }
 
-    /// Unsubscribes to a previously subscribed topic
-    pub async fn unsubscribe(&self, sub_id: WampId) -> Result<(), WampError> {
+    /// Subscribes to several topics at once
+    ///
+    /// All the SUBSCRIBE messages are sent without waiting for the previous ones to be
+    /// confirmed. The results are returned in the order of `topics` once the broker answered
+    /// them all, a failed subscription does not affect the others.
+    ///
+    /// ```no_run
+    /// # use wamp_async::{Client, SubscribeOptions};
+    /// # async fn example(client: &Client<'_>) {
+    /// let subscriptions = client
+    ///     .subscribe_many(vec![
+    ///         ("app.orders", SubscribeOptions::new()),
+    ///         ("app.users", SubscribeOptions::new().with_get_retained(true)),
+    ///     ])
+    ///     .await;
+    /// for res in subscriptions {
+    ///     match res {
+    ///         Ok((sub_id, _queue)) => println!("Subscribed with ID {}", sub_id),
+    ///         Err(e) => println!("Failed to subscribe : {}", e),
+    ///     }
+    /// }
+    /// # }
+    /// ```
+    pub async fn subscribe_many<T: AsRef<str>>(
+        &self,
+        topics: Vec<(T, SubscribeOptions)>,
+    ) -> Vec<Result<(WampId, SubscriptionQueue), WampError>> {
+        future::join_all(
+            topics
+                .into_iter()
+                .map(|(topic, options)| self.subscribe_with_options(topic, options)),
+        )
+        .await
+    }
+
+    /// Subscribes to events for the specified topic, allowing multiple tasks to consume them
+    ///
+    /// Additional receivers are created with `resubscribe()` on the returned receiver. Each
+    /// receiver keeps up to `capacity` events; slower receivers lose the oldest events and get
+    /// a `Lagged` error instead.
+    pub async fn subscribe_broadcast<T: AsRef<str>>(
+        &self,
+        topic: T,
+        capacity: usize,
+    ) -> Result<(WampId, BroadcastSubscription), WampError> {
+        if capacity == 0 {
+            return Err(WampError::InvalidArgument(
+                "Broadcast capacity must be greater than 0".to_string(),
+            ));
+        }
+        let topic = self.scoped_uri(topic.as_ref())?;
+        self.check_uri(&topic, MatchPolicy::Exact)?;
+
         // Send the request
         let (res, result) = oneshot::channel();
-        if let Err(e) = self.ctl_channel.send(Request::Unsubscribe { sub_id, res }) {
-            return Err(From::from(format!(
-                "Core never received our request : {}",
+        self.send_request(Request::Subscribe {
+            uri: topic,
+            options: WampDict::new(),
+            res: PendingSub::Broadcast { capacity, res },
+        })?;
+
+        // Wait for the result
+        match result.await {
+            Ok(r) => r,
+            Err(e) => Err(From::from(format!(
+                "Core never returned a response : {}",
                 e
-            )));
+            ))),
+        }
+    }
+
+    /// Subscribes to events for the specified topic, keeping up to `capacity` unread events
+    ///
+    /// When the queue is full, new events are dropped and a [`SubscriptionItem::Lagged`] item
+    /// telling how many events were lost is queued once there is room again.
+    pub async fn subscribe_bounded<T: AsRef<str>>(
+        &self,
+        topic: T,
+        capacity: usize,
+    ) -> Result<(WampId, BoundedSubscription), WampError> {
+        if capacity == 0 {
+            return Err(WampError::InvalidArgument(
+                "Queue capacity must be greater than 0".to_string(),
+            ));
+        }
+        let topic = self.scoped_uri(topic.as_ref())?;
+        self.check_uri(&topic, MatchPolicy::Exact)?;
+
+        // Send the request
+        let (res, result) = oneshot::channel();
+        self.send_request(Request::Subscribe {
+            uri: topic,
+            options: WampDict::new(),
+            res: PendingSub::Bounded { capacity, res },
+        })?;
+
+        // Wait for the result
+        match result.await {
+            Ok(r) => r,
+            Err(e) => Err(From::from(format!(
+                "Core never returned a response : {}",
+                e
+            ))),
         }
+    }
+
+    /// Unsubscribes to a previously subscribed topic
+    pub async fn unsubscribe(&self, sub_id: WampId) -> Result<(), WampError> {
+        // Send the request
+        let (res, result) = oneshot::channel();
+        self.send_request(Request::Unsubscribe { sub_id, res })?;
 
         // Wait for the result
         match result.await {
@@ -451,39 +1586,76 @@ impl<'a> Client<'a> {
 
     /// Publishes an event on a specific topic
     ///
-    /// The caller can set `acknowledge` to true to receive unique IDs from the server
-    /// for each published event.
-    pub async fn publish<T: AsRef<str>>(
+    /// `options` is either a [`PublishOptions`] or a `bool` : the caller can set it to true to
+    /// receive unique IDs from the server for each published event.
+    ///
+    /// ```no_run
+    /// # use wamp_async::{Client, PublishOptions, WampError, WampId};
+    /// # async fn example(client: Client<'_>, auditor: WampId) -> Result<(), WampError> {
+    /// client.publish("peer.heartbeat", None, None, true).await?;
+    /// // Hide the event from a session
+    /// let options = PublishOptions::new().with_exclude([auditor]);
+    /// client.publish("peer.heartbeat", None, None, options).await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn publish<T: AsRef<str>, O: Into<PublishOptions>>(
         &self,
         topic: T,
         arguments: Option<WampArgs>,
         arguments_kw: Option<WampKwArgs>,
-        acknowledge: bool,
+        options: O,
+    ) -> Result<Option<WampId>, WampError> {
+        self.publish_with_options(topic, arguments, arguments_kw, options.into())
+            .await
+    }
+
+    /// Publishes an event with specific options. See [`Client::publish`]
+    pub async fn publish_with_options<T: AsRef<str>>(
+        &self,
+        topic: T,
+        arguments: Option<WampArgs>,
+        arguments_kw: Option<WampKwArgs>,
+        options: PublishOptions,
     ) -> Result<Option<WampId>, WampError> {
-        let mut options = WampDict::new();
+        let topic = self.scoped_uri(topic.as_ref())?;
+        let acknowledge = options.get_acknowledge();
+        let arguments_kw = with_idempotency_key(arguments_kw, options.get_idempotency_key())?;
+        let in_flight = match self
+            .idempotency(IdempotentRequest::Publish, options.get_idempotency_key())
+            .await
+        {
+            Some(Idempotency::Done(IdempotentResult::Publish(pub_id))) => {
+                debug!(
+                    target: CLIENT,
+                    "Publication with idempotency key '{}' was already sent",
+                    options.get_idempotency_key().unwrap_or_default()
+                );
+                return Ok(pub_id);
+            }
+            Some(Idempotency::Send(in_flight)) => Some(in_flight),
+            _ => None,
+        };
 
-        if acknowledge {
-            options.insert("acknowledge".to_string(), Arg::Bool(true));
-        }
         // Send the request
         let (res, result) = oneshot::channel();
-        if let Err(e) = self.ctl_channel.send(Request::Publish {
-            uri: topic.as_ref().to_string(),
-            options,
+        self.send_request(Request::Publish {
+            uri: topic,
+            options: options.to_dict(),
             arguments,
             arguments_kw,
+            local_echo: self
+                .session_info
+                .as_ref()
+                .is_some_and(|session| options.local_echo_to(session)),
             res,
-        }) {
-            return Err(From::from(format!(
-                "Core never received our request : {}",
-                e
-            )));
-        }
+        })?;
 
         let pub_id = if acknowledge {
             // Wait for the acknowledgement
             Some(match result.await {
                 Ok(Ok(r)) => r.unwrap(),
+                Ok(Err(WampError::ConnectionLost)) => return Err(WampError::ConnectionLost),
                 Ok(Err(e)) => return Err(From::from(format!("Failed to send publish : {}", e))),
                 Err(e) => {
                     return Err(From::from(format!(
@@ -495,33 +1667,190 @@ impl<'a> Client<'a> {
         } else {
             None
         };
+
+        if let Some(in_flight) = in_flight {
+            in_flight.succeeded(IdempotentResult::Publish(pub_id));
+        }
         Ok(pub_id)
     }
 
     /// Register an RPC endpoint. Upon succesful registration, a registration ID is returned (used to unregister)
     /// and calls received from the server will generate a future which will be sent on the rpc event channel
     /// returned by the call to [event_loop()](struct.Client.html#method.event_loop)
-    pub async fn register<T, F, Fut>(&self, uri: T, func_ptr: F) -> Result<WampId, WampError>
+    ///
+    /// The endpoint returns either `(Option<WampArgs>, Option<WampKwArgs>)` or an [`RpcResponse`]
+    /// to set options on the YIELD message.
+    pub async fn register<T, F, Fut, R>(&self, uri: T, func_ptr: F) -> Result<WampId, WampError>
+    where
+        T: AsRef<str>,
+        F: Fn(Option<WampArgs>, Option<WampKwArgs>) -> Fut + Send + Sync + 'a,
+        Fut: Future<Output = Result<R, WampError>> + Send + 'a,
+        R: Into<RpcResponse>,
+    {
+        self.register_with_options(uri, RegisterOptions::default(), func_ptr)
+            .await
+    }
+
+    /// Registers an RPC endpoint with specific options. See [`Client::register`]
+    ///
+    /// If the procedure is already registered by another session, [`WampError::ProcedureAlreadyExists`]
+    /// is returned unless `options` enables the takeover of existing registrations.
+    pub async fn register_with_options<T, F, Fut, R>(
+        &self,
+        uri: T,
+        options: RegisterOptions,
+        func_ptr: F,
+    ) -> Result<WampId, WampError>
     where
         T: AsRef<str>,
         F: Fn(Option<WampArgs>, Option<WampKwArgs>) -> Fut + Send + Sync + 'a,
-        Fut: Future<Output = Result<(Option<WampArgs>, Option<WampKwArgs>), WampError>> + Send + 'a,
+        Fut: Future<Output = Result<R, WampError>> + Send + 'a,
+        R: Into<RpcResponse>,
+    {
+        self.register_func(
+            uri.as_ref(),
+            &options,
+            RpcEndpoint::Func(invocation_func(func_ptr)),
+        )
+        .await
+    }
+
+    /// Registers a procedure whose invocations are handed to the stream returned by
+    /// [`Client::invocations`] instead of running a function
+    pub async fn register_streamed<T: AsRef<str>>(
+        &self,
+        uri: T,
+        options: RegisterOptions,
+    ) -> Result<WampId, WampError> {
+        self.register_func(uri.as_ref(), &options, RpcEndpoint::Stream)
+            .await
+    }
+
+    /// Registers a procedure answered within the event loop, without the round trip through
+    /// the RPC event queue
+    ///
+    /// Meant for trivial procedures like health checks : `func_ptr` must be fast and must not
+    /// block. A handler running longer than [`ClientConfig::set_inline_handler_budget`] is
+    /// reported, a panic fails the call.
+    ///
+    /// ```no_run
+    /// # use wamp_async::{Client, WampError};
+    /// # async fn example(client: Client<'_>) -> Result<(), WampError> {
+    /// client
+    ///     .register_inline("app.health", |_args, _kwargs| {
+    ///         Ok::<_, WampError>((Some(vec!["ok".into()]), None))
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn register_inline<T, F, R>(&self, uri: T, func_ptr: F) -> Result<WampId, WampError>
+    where
+        T: AsRef<str>,
+        F: Fn(Option<WampArgs>, Option<WampKwArgs>) -> Result<R, WampError> + Send + Sync + 'a,
+        R: Into<RpcResponse>,
     {
+        let func_ptr: InlineFunc<'a> = Arc::new(move |arguments, arguments_kw| {
+            func_ptr(arguments, arguments_kw).map(Into::into)
+        });
+        self.register_func(
+            uri.as_ref(),
+            &RegisterOptions::default(),
+            RpcEndpoint::Inline(func_ptr),
+        )
+        .await
+    }
+
+    /// Registers several RPC endpoints at once
+    ///
+    /// All the REGISTER messages are sent without waiting for the previous ones to be
+    /// confirmed. The results are returned in the order the endpoints were added to `batch`,
+    /// a failed registration does not affect the others.
+    pub async fn register_many(
+        &self,
+        batch: RegistrationBatch<'a>,
+    ) -> Vec<Result<WampId, WampError>> {
+        future::join_all(batch.into_procedures().into_iter().map(
+            |(uri, options, func_ptr)| async move {
+                self.register_func(&uri, &options, RpcEndpoint::Func(func_ptr))
+                    .await
+            },
+        ))
+        .await
+    }
+
+    /// Registers several RPC endpoints at once, or none of them
+    ///
+    /// Like [`Client::register_many`], but when a registration fails the endpoints that were
+    /// registered are unregistered and the first error is returned.
+    pub async fn register_all(
+        &self,
+        batch: RegistrationBatch<'a>,
+    ) -> Result<Vec<WampId>, WampError> {
+        let mut rpc_ids = Vec::new();
+        let mut error = None;
+        for res in self.register_many(batch).await {
+            match res {
+                Ok(rpc_id) => rpc_ids.push(rpc_id),
+                Err(e) => {
+                    error.get_or_insert(e);
+                }
+            }
+        }
+
+        match error {
+            None => Ok(rpc_ids),
+            Some(e) => {
+                for rpc_id in rpc_ids {
+                    let _ = self.unregister(rpc_id).await;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Registers an RPC endpoint, taking over existing registrations if `options` allows it
+    async fn register_func(
+        &self,
+        uri: &str,
+        options: &RegisterOptions,
+        endpoint: RpcEndpoint<'a>,
+    ) -> Result<WampId, WampError> {
+        let uri = &self.scoped_uri(uri)?;
+        match self.send_register(uri, options, endpoint.clone()).await {
+            Err(WampError::ProcedureAlreadyExists(_)) if options.get_takeover() => {
+                self.remove_callees(uri).await?;
+                self.send_register(uri, options, endpoint).await
+            }
+            res => res,
+        }
+    }
+
+    /// Sends a REGISTER request to the core and waits for its result
+    async fn send_register(
+        &self,
+        uri: &str,
+        options: &RegisterOptions,
+        endpoint: RpcEndpoint<'a>,
+    ) -> Result<WampId, WampError> {
+        self.check_uri(uri, options.get_match())?;
+
         // Send the request
         let (res, result) = oneshot::channel();
-        if let Err(e) = self.ctl_channel.send(Request::Register {
-            uri: uri.as_ref().to_string(),
+        self.send_request(Request::Register {
+            uri: uri.to_string(),
+            options: options.to_dict(),
             res,
-            func_ptr: Box::new(move |a, k| Box::pin(func_ptr(a, k))),
-        }) {
-            return Err(From::from(format!(
-                "Core never received our request : {}",
-                e
-            )));
-        }
+            endpoint,
+        })?;
 
         // Wait for the result
         let rpc_id = match result.await {
+            Ok(Err(WampError::ServerError(error, _)))
+                if error == "wamp.error.procedure_already_exists" =>
+            {
+                return Err(WampError::ProcedureAlreadyExists(uri.to_string()))
+            }
             Ok(r) => r?,
             Err(e) => {
                 return Err(From::from(format!(
@@ -534,16 +1863,36 @@ impl<'a> Client<'a> {
         Ok(rpc_id)
     }
 
+    /// Removes the callees of an existing registration through the registration meta API
+    async fn remove_callees(&self, uri: &str) -> Result<(), WampError> {
+        let rpc_id: Option<WampId> = self
+            .call_meta("wamp.registration.lookup", Some(vec![uri.into()]))
+            .await?;
+        let rpc_id = match rpc_id {
+            Some(id) => try_into_any_value(id)?,
+            None => return Ok(()),
+        };
+
+        let callees: Vec<WampId> = self
+            .call_meta("wamp.registration.list_callees", Some(vec![rpc_id.clone()]))
+            .await?;
+        for callee in callees {
+            warn!(target: CLIENT, "Removing session {} registered on {}", callee, uri);
+            self.call(
+                "wamp.registration.remove_callee",
+                Some(vec![rpc_id.clone(), try_into_any_value(callee)?]),
+                None,
+            )
+            .await?;
+        }
+        Ok(())
+    }
+
     /// Unregisters an RPC endpoint
     pub async fn unregister(&self, rpc_id: WampId) -> Result<(), WampError> {
         // Send the request
         let (res, result) = oneshot::channel();
-        if let Err(e) = self.ctl_channel.send(Request::Unregister { rpc_id, res }) {
-            return Err(From::from(format!(
-                "Core never received our request : {}",
-                e
-            )));
-        }
+        self.send_request(Request::Unregister { rpc_id, res })?;
 
         // Wait for the result
         match result.await {
@@ -566,31 +1915,793 @@ impl<'a> Client<'a> {
         arguments: Option<WampArgs>,
         arguments_kw: Option<WampKwArgs>,
     ) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError> {
+        self.call_with_options(uri, arguments, arguments_kw, CallOptions::default())
+            .await
+    }
+
+    /// Calls a registered RPC endpoint with specific options. See [`Client::call`]
+    pub async fn call_with_options<T: AsRef<str>>(
+        &self,
+        uri: T,
+        arguments: Option<WampArgs>,
+        arguments_kw: Option<WampKwArgs>,
+        options: CallOptions,
+    ) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError> {
+        let uri = self.scoped_uri(uri.as_ref())?;
+        // Do not outlive the invocation we are handling
+        let options = options.with_invocation_deadline();
+
+        let arguments_kw = with_idempotency_key(arguments_kw, options.get_idempotency_key())?;
+        let in_flight = match self
+            .idempotency(IdempotentRequest::Call, options.get_idempotency_key())
+            .await
+        {
+            Some(Idempotency::Done(IdempotentResult::Call(arguments, arguments_kw))) => {
+                debug!(
+                    target: CLIENT,
+                    "Call with idempotency key '{}' already succeeded",
+                    options.get_idempotency_key().unwrap_or_default()
+                );
+                return Ok((arguments, arguments_kw));
+            }
+            Some(Idempotency::Send(in_flight)) => Some(in_flight),
+            _ => None,
+        };
+
+        let timeout = options.get_timeout();
+        if timeout == Some(Duration::from_secs(0)) {
+            return Err(WampError::Timeout(format!("calling {}", uri)));
+        }
+
         // Send the request
         let (res, result) = oneshot::channel();
-        if let Err(e) = self.ctl_channel.send(Request::Call {
-            uri: uri.as_ref().to_string(),
-            options: WampDict::new(),
+        self.send_request(Request::Call {
+            uri: uri.clone(),
+            options: options.to_dict(),
             arguments,
             arguments_kw,
             res,
-        }) {
-            return Err(From::from(format!(
-                "Core never received our request : {}",
-                e
-            )));
-        }
+            sent: None,
+            progress: None,
+        })?;
 
         // Wait for the result
-        match result.await {
-            Ok(r) => r,
-            Err(e) => Err(From::from(format!(
+        let result = match timeout {
+            Some(timeout) => match tokio::time::timeout(timeout, result).await {
+                Ok(r) => r,
+                Err(_) => return Err(WampError::Timeout(format!("calling {}", uri))),
+            },
+            None => result.await,
+        };
+        let (arguments, arguments_kw) = match result {
+            Ok(r) => r?,
+            Err(e) => {
+                return Err(From::from(format!(
+                    "Core never returned a response : {}",
+                    e
+                )))
+            }
+        };
+
+        if let Some(in_flight) = in_flight {
+            in_flight.succeeded(IdempotentResult::Call(
+                arguments.clone(),
+                arguments_kw.clone(),
+            ));
+        }
+        Ok((arguments, arguments_kw))
+    }
+
+    /// Calls a registered RPC endpoint and returns once the CALL is sent. See
+    /// [`Client::call_with_options`]
+    ///
+    /// The returned handle waits for the result or cancels the call. Results are not stored in
+    /// the [`IdempotencyCache`].
+    pub async fn call_with_handle<T: AsRef<str>>(
+        &self,
+        uri: T,
+        arguments: Option<WampArgs>,
+        arguments_kw: Option<WampKwArgs>,
+        options: CallOptions,
+    ) -> Result<CallHandle<'a>, WampError> {
+        let uri = self.scoped_uri(uri.as_ref())?;
+        // Do not outlive the invocation we are handling
+        let options = options.with_invocation_deadline();
+        let timeout = options.get_timeout();
+        if timeout == Some(Duration::from_secs(0)) {
+            return Err(WampError::Timeout(format!("calling {}", uri)));
+        }
+
+        // Send the request
+        let (res, result) = oneshot::channel();
+        let (sent, request) = oneshot::channel();
+        let (progress_w, progress) = match options.get_receive_progress() {
+            true => {
+                let (w, r) = mpsc::unbounded_channel();
+                (Some(w), Some(r))
+            }
+            false => (None, None),
+        };
+        self.send_request(Request::Call {
+            uri: uri.clone(),
+            options: options.to_dict(),
+            arguments,
+            arguments_kw: with_idempotency_key(arguments_kw, options.get_idempotency_key())?,
+            res,
+            sent: Some(sent),
+            progress: progress_w,
+        })?;
+
+        // Failing to send the CALL drops `sent` and fails the result
+        let request = match request.await {
+            Ok(request) => request,
+            Err(e) => {
+                return Err(match result.await {
+                    Ok(Err(e)) => e,
+                    _ => From::from(format!("Core never returned a response : {}", e)),
+                })
+            }
+        };
+
+        Ok(CallHandle {
+            request,
+            uri,
+            timeout,
+            result,
+            progress,
+            ctl_channel: self.ctl_channel.clone(),
+        })
+    }
+
+    /// Calls several RPC endpoints at once
+    ///
+    /// All the CALL messages are sent without waiting for the previous results, within the
+    /// timeout of the batch (see [`CallBatch::with_timeout`]). The results are returned in the
+    /// order the calls were added to `batch`, a failed call does not affect the others.
+    pub async fn call_batch(&self, batch: CallBatch) -> Vec<Result<CallResult, WampError>> {
+        future::join_all(batch.into_calls().into_iter().map(
+            |(uri, arguments, arguments_kw, options)| async move {
+                self.call_with_options(uri, arguments, arguments_kw, options)
+                    .await
+            },
+        ))
+        .await
+    }
+
+    /// Sends the calls of `batch` at once and returns as soon as `quorum` of them succeed
+    ///
+    /// The calls can target several procedures, or the shards of one procedure with
+    /// [`CallOptions::with_rkey`]. Fails with [`WampError::QuorumNotReached`] once too many calls
+    /// have failed or timed out (see [`CallBatch::with_timeout`]). The results of the calls still
+    /// running when the quorum is reached are discarded.
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use wamp_async::{CallBatch, CallOptions, Client, Quorum, WampError};
+    /// # async fn example(client: Client<'_>) -> Result<(), WampError> {
+    /// let batch = ["eu", "us", "asia"].iter().fold(CallBatch::new(), |batch, region| {
+    ///     batch.add("app.stock.count", None, None, CallOptions::new().with_rkey(*region))
+    /// });
+    /// let gathered = client
+    ///     .scatter_gather(batch.with_timeout(Duration::from_secs(1)), Quorum::Majority)
+    ///     .await?;
+    /// println!("{} regions answered", gathered.results.len());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn scatter_gather(
+        &self,
+        batch: CallBatch,
+        quorum: Quorum,
+    ) -> Result<Gathered, WampError> {
+        let calls = batch.into_calls();
+        let num_calls = calls.len();
+        let required = quorum.required(num_calls);
+
+        let mut pending: FuturesUnordered<_> = calls
+            .into_iter()
+            .enumerate()
+            .map(
+                |(index, (uri, arguments, arguments_kw, options))| async move {
+                    let res = self
+                        .call_with_options(uri, arguments, arguments_kw, options)
+                        .await;
+                    (index, res)
+                },
+            )
+            .collect();
+
+        let mut gathered = Gathered::default();
+        while gathered.results.len() < required {
+            // Stop once the quorum cannot be reached anymore
+            if num_calls - gathered.errors.len() < required {
+                break;
+            }
+            match pending.next().await {
+                Some((index, Ok(result))) => gathered.results.push((index, result)),
+                Some((index, Err(e))) => gathered.errors.push((index, e)),
+                None => break,
+            }
+        }
+
+        if gathered.results.len() < required {
+            return Err(WampError::QuorumNotReached(
+                gathered.results.len(),
+                required,
+            ));
+        }
+        Ok(gathered)
+    }
+
+    /// Registers the procedures and subscribes to the topics of `manifest`, converging from the
+    /// manifest applied before in the session
+    ///
+    /// The procedures and topics of the previous manifest that are not in `manifest`, or with
+    /// other options, are unregistered and unsubscribed from. The ones left unchanged are kept.
+    /// Registrations and subscriptions made without a manifest are never touched.
+    ///
+    /// The registrations and subscriptions of a session are lost when it ends : set the manifest
+    /// with [`ClientConfig::set_manifest`] to apply it after every join. The procedures are
+    /// registered with [`Client::register_streamed`]. When a registration or subscription
+    /// fails, the ones this call made are removed.
+    pub async fn apply_manifest(&self, manifest: &Manifest) -> Result<AppliedManifest, WampError> {
+        let session_id = match self.session_info() {
+            Some(info) => info.session_id,
+            None => {
+                return Err(From::from(
+                    "apply_manifest() : Client is not joined to a realm".to_string(),
+                ))
+            }
+        };
+
+        let previous = std::mem::take(&mut *self.manifest_state.lock().unwrap());
+        let mut state = ManifestState {
+            session_id: Some(session_id),
+            ..Default::default()
+        };
+        // Entries of a previous session are gone on the router, only the event loop still
+        // knows about them
+        let same_session = previous.session_id == Some(session_id);
+        for (uri, (rpc_id, procedure)) in previous.registrations {
+            if same_session && manifest.procedures.contains(&procedure) {
+                state.registrations.insert(uri, (rpc_id, procedure));
+            } else {
+                let _ = self.unregister(rpc_id).await;
+            }
+        }
+        for (uri, (sub_id, topic)) in previous.subscriptions {
+            if same_session && manifest.topics.contains(&topic) {
+                state.subscriptions.insert(uri, (sub_id, topic));
+            } else {
+                let _ = self.unsubscribe(sub_id).await;
+            }
+        }
+
+        let mut applied = AppliedManifest::default();
+        if let Err(e) = self
+            .apply_manifest_entries(manifest, &mut state, &mut applied)
+            .await
+        {
+            for (uri, rpc_id) in applied.registrations {
+                state.registrations.remove(&uri);
+                let _ = self.unregister(rpc_id).await;
+            }
+            for (uri, (sub_id, _)) in applied.subscriptions {
+                state.subscriptions.remove(&uri);
+                let _ = self.unsubscribe(sub_id).await;
+            }
+            *self.manifest_state.lock().unwrap() = state;
+            return Err(e);
+        }
+
+        for (uri, (rpc_id, _)) in &state.registrations {
+            applied.registrations.insert(uri.clone(), *rpc_id);
+        }
+        *self.manifest_state.lock().unwrap() = state;
+        Ok(applied)
+    }
+
+    /// Makes the registrations and subscriptions of `manifest` missing from `state`, the ones
+    /// made are added to `applied`
+    async fn apply_manifest_entries(
+        &self,
+        manifest: &Manifest,
+        state: &mut ManifestState,
+        applied: &mut AppliedManifest,
+    ) -> Result<(), WampError> {
+        for procedure in &manifest.procedures {
+            if state.registrations.contains_key(&procedure.uri) {
+                continue;
+            }
+            let rpc_id = self
+                .register_streamed(&procedure.uri, procedure.options())
+                .await?;
+            applied.registrations.insert(procedure.uri.clone(), rpc_id);
+            state
+                .registrations
+                .insert(procedure.uri.clone(), (rpc_id, procedure.clone()));
+        }
+        for topic in &manifest.topics {
+            if state.subscriptions.contains_key(&topic.uri) {
+                continue;
+            }
+            let subscription = self
+                .subscribe_with_options(&topic.uri, topic.options())
+                .await?;
+            state
+                .subscriptions
+                .insert(topic.uri.clone(), (subscription.0, topic.clone()));
+            applied
+                .subscriptions
+                .insert(topic.uri.clone(), subscription);
+        }
+        Ok(())
+    }
+
+    /// Returns what the manifest set with [`ClientConfig::set_manifest`] made when the client
+    /// last joined a realm
+    pub fn take_applied_manifest(&mut self) -> Option<AppliedManifest> {
+        self.applied_manifest.take()
+    }
+
+    /// Returns the registrations and subscriptions of the client as a manifest
+    ///
+    /// Options only known by the client, like [`RegisterOptions::with_takeover`], are not
+    /// part of it.
+    pub async fn export_manifest(&self) -> Result<Manifest, WampError> {
+        let (res, result) = oneshot::channel();
+        self.send_request(Request::ListActive { res })?;
+        let active = match result.await {
+            Ok(r) => r,
+            Err(e) => {
+                return Err(From::from(format!(
+                    "Core never returned a response : {}",
+                    e
+                )))
+            }
+        };
+
+        let mut manifest = Manifest::new();
+        for (_, uri, options) in &active.registrations {
+            manifest
+                .procedures
+                .push(ProcedureManifest::from_options(uri, options));
+        }
+        for (_, uri, options) in &active.subscriptions {
+            manifest
+                .topics
+                .push(TopicManifest::from_options(uri, options));
+        }
+        manifest.procedures.sort_by(|a, b| a.uri.cmp(&b.uri));
+        manifest.topics.sort_by(|a, b| a.uri.cmp(&b.uri));
+        Ok(manifest)
+    }
+
+    /// Replaces the `{tenant}` placeholder of `uri`, see [`ClientConfig::set_tenant`]
+    fn scoped_uri(&self, uri: &str) -> Result<WampUri, WampError> {
+        with_tenant(uri, self.config.get_tenant())
+    }
+
+    /// Checks `uri` against the strict rules if enabled with [`ClientConfig::set_strict_uris`]
+    fn check_uri(&self, uri: &str, match_policy: MatchPolicy) -> Result<(), WampError> {
+        if self.config.get_strict_uris() && !is_valid_strict_uri_pattern(uri, match_policy) {
+            return Err(WampError::InvalidArgument(format!(
+                "'{}' is not a valid URI for {:?} matching",
+                uri, match_policy
+            )));
+        }
+        Ok(())
+    }
+
+    /// Looks up the idempotency key of a request in the cache, if both are set
+    async fn idempotency(
+        &self,
+        request: IdempotentRequest,
+        key: Option<&str>,
+    ) -> Option<Idempotency> {
+        let cache = self.config.get_idempotency_cache()?;
+        Some(cache.begin(request, key?).await)
+    }
+
+    /// Publishes a typed event on a topic. See [`Client::publish`]
+    pub async fn publish_typed<T: Serialize>(
+        &self,
+        topic: &TypedTopic<T>,
+        value: &T,
+        acknowledge: bool,
+    ) -> Result<Option<WampId>, WampError> {
+        let (arguments, arguments_kw) = encode_payload(value)?;
+        self.publish(topic.uri(), arguments, arguments_kw, acknowledge)
+            .await
+    }
+
+    /// Subscribes to a typed topic. Event payloads are deserialized when received from
+    /// the returned queue. See [`Client::subscribe`]
+    pub async fn subscribe_typed<T: DeserializeOwned>(
+        &self,
+        topic: &TypedTopic<T>,
+    ) -> Result<(WampId, TypedSubscription<T>), WampError> {
+        let (sub_id, queue) = self.subscribe(topic.uri()).await?;
+        Ok((sub_id, TypedSubscription::new(queue)))
+    }
+
+    /// Registers a typed RPC endpoint. See [`Client::register`]
+    pub async fn register_typed<A, R, F, Fut>(
+        &self,
+        procedure: &TypedProcedure<A, R>,
+        func_ptr: F,
+    ) -> Result<WampId, WampError>
+    where
+        A: DeserializeOwned,
+        R: Serialize,
+        F: Fn(A) -> Fut + Send + Sync + 'a,
+        Fut: Future<Output = Result<R, WampError>> + Send + 'a,
+    {
+        self.register(procedure.uri(), move |arguments, arguments_kw| {
+            let func_future = decode_payload(arguments, arguments_kw).map(&func_ptr);
+            async move { encode_payload(&func_future?.await?) }
+        })
+        .await
+    }
+
+    /// Registers each method of `service` as `<prefix>.<method>`, returning their registration IDs
+    ///
+    /// If one of the registrations fails, the methods already registered are unregistered.
+    pub async fn register_service<T, S>(
+        &self,
+        prefix: T,
+        service: S,
+    ) -> Result<Vec<WampId>, WampError>
+    where
+        T: AsRef<str>,
+        S: WampService + 'a,
+    {
+        let service = Arc::new(service);
+        let mut rpc_ids = Vec::new();
+        for method in service.methods() {
+            let service = service.clone();
+            let uri = format!("{}.{}", prefix.as_ref(), method);
+            let res = self
+                .register(uri, move |arguments, arguments_kw| {
+                    let service = service.clone();
+                    async move { service.dispatch(method, arguments, arguments_kw).await }
+                })
+                .await;
+            match res {
+                Ok(rpc_id) => rpc_ids.push(rpc_id),
+                Err(e) => {
+                    for rpc_id in rpc_ids {
+                        let _ = self.unregister(rpc_id).await;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok(rpc_ids)
+    }
+
+    /// Registers `prefix` with prefix matching and routes its invocations to the handlers of
+    /// `dispatcher`. See [`DynamicDispatcher`]
+    pub async fn register_dispatcher<T: AsRef<str>>(
+        &self,
+        prefix: T,
+        dispatcher: DynamicDispatcher<'a>,
+    ) -> Result<WampId, WampError> {
+        let prefix = self.scoped_uri(prefix.as_ref())?;
+        let uri = prefix.clone();
+        let func_ptr: InvocationFunc<'a> = Arc::new(move |details, arguments, arguments_kw| {
+            dispatcher.dispatch(&prefix, &details, arguments, arguments_kw)
+        });
+        self.send_register(
+            &uri,
+            &RegisterOptions::new().with_match(MatchPolicy::Prefix),
+            RpcEndpoint::Func(func_ptr),
+        )
+        .await
+    }
+
+    /// Calls a typed RPC endpoint. See [`Client::call`]
+    pub async fn call_typed<A: Serialize, R: DeserializeOwned>(
+        &self,
+        procedure: &TypedProcedure<A, R>,
+        value: &A,
+    ) -> Result<R, WampError> {
+        let (arguments, arguments_kw) = encode_payload(value)?;
+        let (arguments, arguments_kw) = self.call(procedure.uri(), arguments, arguments_kw).await?;
+        decode_payload(arguments, arguments_kw)
+    }
+
+    /// Registers an RPC endpoint taking and returning protobuf messages, carried in a single
+    /// binary argument. See [`Client::register`]
+    ///
+    /// Requires the `protobuf` feature.
+    #[cfg(feature = "protobuf")]
+    pub async fn register_proto<T, A, R, F, Fut>(
+        &self,
+        uri: T,
+        func_ptr: F,
+    ) -> Result<WampId, WampError>
+    where
+        T: AsRef<str>,
+        A: prost::Message + Default,
+        R: prost::Message,
+        F: Fn(A) -> Fut + Send + Sync + 'a,
+        Fut: Future<Output = Result<R, WampError>> + Send + 'a,
+    {
+        self.register(uri, move |arguments, _arguments_kw| {
+            let func_future = try_from_args_proto(arguments.unwrap_or_default()).map(&func_ptr);
+            async move {
+                let res = try_into_args_proto(&func_future?.await?)?;
+                Ok((Some(res), None))
+            }
+        })
+        .await
+    }
+
+    /// Calls an RPC endpoint taking and returning protobuf messages, see
+    /// [`Client::register_proto`]
+    ///
+    /// Requires the `protobuf` feature.
+    #[cfg(feature = "protobuf")]
+    pub async fn call_proto<T, A, R>(&self, uri: T, message: &A) -> Result<R, WampError>
+    where
+        T: AsRef<str>,
+        A: prost::Message,
+        R: prost::Message + Default,
+    {
+        let arguments = try_into_args_proto(message)?;
+        let (arguments, _) = self.call(uri, Some(arguments), None).await?;
+        try_from_args_proto(arguments.unwrap_or_default())
+    }
+
+    /// Measures the round-trip latency with the server
+    ///
+    /// By default, a WebSocket/RawSocket ping is sent. If a ping procedure was set with
+    /// [`ClientConfig::set_ping_procedure`], the latency of a call to that procedure is returned instead.
+    pub async fn ping(&self) -> Result<Duration, WampError> {
+        if let Some(uri) = self.config.get_ping_procedure() {
+            let start = Instant::now();
+            self.call(uri, None, None).await?;
+            return Ok(start.elapsed());
+        }
+
+        // Send the request
+        let (res, result) = oneshot::channel();
+        self.send_request(Request::Ping { res })?;
+
+        // Wait for the pong
+        match result.await {
+            Ok(r) => r,
+            Err(e) => Err(From::from(format!(
                 "Core never returned a response : {}",
                 e
             ))),
         }
     }
 
+    /// Lists the procedures registered on the router
+    ///
+    /// This relies on the registration meta procedures (`wamp.registration.list` and
+    /// `wamp.registration.get`). When the router also supports reflection, the schema of each
+    /// procedure is fetched with `wamp.reflection.procedure.describe`.
+    pub async fn list_procedures(&self) -> Result<Vec<ProcedureDescriptor>, WampError> {
+        let ids: MetaIdLists = self.call_meta("wamp.registration.list", None).await?;
+        let mut procedures = Vec::new();
+        for id in ids.into_ids() {
+            let mut procedure: ProcedureDescriptor = self
+                .call_meta("wamp.registration.get", Some(vec![try_into_any_value(id)?]))
+                .await?;
+            procedure.schema = self
+                .describe("wamp.reflection.procedure.describe", &procedure.uri)
+                .await?;
+            procedures.push(procedure);
+        }
+        Ok(procedures)
+    }
+
+    /// Lists the topics that have subscribers on the router
+    ///
+    /// This relies on the subscription meta procedures (`wamp.subscription.list` and
+    /// `wamp.subscription.get`). When the router also supports reflection, the schema of each
+    /// topic is fetched with `wamp.reflection.topic.describe`.
+    pub async fn list_topics(&self) -> Result<Vec<TopicDescriptor>, WampError> {
+        let ids: MetaIdLists = self.call_meta("wamp.subscription.list", None).await?;
+        let mut topics = Vec::new();
+        for id in ids.into_ids() {
+            let mut topic: TopicDescriptor = self
+                .call_meta("wamp.subscription.get", Some(vec![try_into_any_value(id)?]))
+                .await?;
+            topic.schema = self
+                .describe("wamp.reflection.topic.describe", &topic.uri)
+                .await?;
+            topics.push(topic);
+        }
+        Ok(topics)
+    }
+
+    /// Calls a meta procedure and deserializes its result
+    async fn call_meta<T: DeserializeOwned>(
+        &self,
+        procedure: &str,
+        arguments: Option<WampArgs>,
+    ) -> Result<T, WampError> {
+        let (arguments, arguments_kw) = self.call(procedure, arguments, None).await?;
+        decode_payload(arguments, arguments_kw)
+    }
+
+    /// Calls a reflection procedure, returning `None` if the router does not support it
+    async fn describe(
+        &self,
+        procedure: &str,
+        uri: &str,
+    ) -> Result<Option<WampPayloadValue>, WampError> {
+        match self.call(procedure, Some(vec![uri.into()]), None).await {
+            Ok((arguments, _)) => Ok(arguments.and_then(|a| a.into_iter().next())),
+            Err(WampError::ServerError(..)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns details about the connection with the server such as the negotiated serializer
+    /// and the WebSocket upgrade response headers
+    pub fn connection_info(&self) -> &ConnectionInfo {
+        &self.connection_info
+    }
+
+    /// Returns a channel to the event loop that is usable without borrowing the client
+    #[cfg(feature = "python")]
+    pub(crate) fn ctl_channel(&self) -> CtlSender<'a> {
+        self.ctl_channel.clone()
+    }
+
+    /// Returns the typed values shared with the handlers of the session, see
+    /// [`ClientConfig::set_extensions`]
+    pub fn extensions(&self) -> &Extensions {
+        self.config.get_extensions()
+    }
+
+    /// Returns the number of invocation results that had to wait for the transport because
+    /// too many results were already pending (see [`ClientConfig::set_max_pending_yields`])
+    pub fn num_congested_yields(&self) -> u64 {
+        self.yield_flow.num_congested()
+    }
+
+    /// Returns the number of RPC handlers aborted because they ran for too long (see
+    /// [`ClientConfig::set_handler_watchdog`])
+    pub fn num_expired_handlers(&self) -> u64 {
+        self.handler_watchdog.num_expired()
+    }
+
+    /// Returns the number of requests waiting for the event loop and of events waiting in the
+    /// queue of each subscription
+    ///
+    /// The depths are read without going through the event loop, so they are available even
+    /// when it is falling behind.
+    pub fn queue_stats(&self) -> QueueStats {
+        QueueStats {
+            control: self.ctl_channel.depth(),
+            subscriptions: self.queue_probes.stats(),
+        }
+    }
+
+    /// Takes the queue of ERROR messages that could not be matched to a pending request (e.g.
+    /// the router rejecting an invocation result). Returns `None` if it was already taken.
+    ///
+    /// Up to 64 unread errors are kept, newer errors are dropped until the queue is read.
+    pub fn protocol_errors(&mut self) -> Option<mpsc::Receiver<UnhandledError>> {
+        self.protocol_errors.take()
+    }
+
+    /// Takes the stream of the registration changes initiated by the router, such as a
+    /// registration being revoked with an unsolicited UNREGISTERED. Returns `None` if it was
+    /// already taken.
+    ///
+    /// A revoked procedure is removed from the client before the event is sent, register it
+    /// again to keep serving it.
+    pub fn registration_events(&mut self) -> Option<UnboundedReceiver<RegistrationEvent>> {
+        self.registration_events.take()
+    }
+
+    /// Takes the stream of the subscription changes initiated by the router, such as a
+    /// subscription being revoked with an unsolicited UNSUBSCRIBED. Returns `None` if it was
+    /// already taken.
+    ///
+    /// The queue of a revoked subscription is closed, subscribe again to keep receiving its
+    /// events. Queues created with [`Client::subscribe_bounded`] also end with a
+    /// [`SubscriptionItem::Revoked`] item.
+    pub fn subscription_events(&mut self) -> Option<UnboundedReceiver<SubscriptionEvent>> {
+        self.subscription_events.take()
+    }
+
+    /// Takes the stream of the invocations of the procedures registered with
+    /// [`Client::register_streamed`]. Returns `None` if it was already taken.
+    ///
+    /// Unlike the RPC event queue, the invocations are handed over as is, leaving the ordering,
+    /// batching and execution of the handlers to the caller.
+    ///
+    /// ```no_run
+    /// # use futures::StreamExt;
+    /// # use wamp_async::{Client, RegisterOptions, WampError};
+    /// # async fn example(mut client: Client<'_>) -> Result<(), WampError> {
+    /// let mut invocations = client.invocations().unwrap();
+    /// client
+    ///     .register_streamed("peer.echo", RegisterOptions::new())
+    ///     .await?;
+    ///
+    /// while let Some(invocation) = invocations.next().await {
+    ///     let res = Ok((invocation.arguments.clone(), invocation.arguments_kw.clone()));
+    ///     invocation.respond(res).await?;
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn invocations(&mut self) -> Option<InvocationStream<'a>> {
+        self.invocations.take()
+    }
+
+    /// Returns the number of RESULT/ERROR messages that were ignored because their request
+    /// had already been answered by the router
+    pub fn num_duplicate_replies(&self) -> u64 {
+        self.num_duplicate_replies.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of events dropped because the queue of a bounded subscription was
+    /// full, since the client was created
+    ///
+    /// Unlike the `Lagged` items of [`Client::subscribe_bounded`], the count is never reset.
+    pub fn num_dropped_events(&self) -> u64 {
+        self.num_dropped_events.load(Ordering::Relaxed)
+    }
+
+    /// Returns the number of times a handler registered with [`Client::register_inline`] ran
+    /// longer than [`ClientConfig::set_inline_handler_budget`]
+    pub fn num_slow_inline_handlers(&self) -> u64 {
+        self.num_slow_inline_handlers.load(Ordering::Relaxed)
+    }
+
+    /// Returns the size in bytes of the largest invocation result (YIELD or ERROR) serialized
+    /// so far, to compare with [`ConnectionInfo::max_message_size`]
+    pub fn largest_response_size(&self) -> usize {
+        self.largest_response.load(Ordering::Relaxed)
+    }
+
+    /// Returns a handle on the counters of the client, exported in the Prometheus format by
+    /// [`crate::prometheus::Metrics::render`]
+    ///
+    /// Requires the `prometheus` feature.
+    #[cfg(feature = "prometheus")]
+    pub fn metrics(&self) -> crate::prometheus::Metrics {
+        crate::prometheus::Metrics {
+            session_state: self.session_state.clone(),
+            yield_flow: self.yield_flow.clone(),
+            handler_watchdog: self.handler_watchdog.clone(),
+            num_duplicate_replies: self.num_duplicate_replies.clone(),
+            num_dropped_events: self.num_dropped_events.clone(),
+            num_slow_inline_handlers: self.num_slow_inline_handlers.clone(),
+            largest_response: self.largest_response.clone(),
+            aborted_requests: self.aborted_requests.clone(),
+        }
+    }
+
+    /// Returns the current state of the WAMP session
+    pub fn session_state(&self) -> SessionState {
+        *self.session_state.borrow()
+    }
+
+    /// Returns the details of the current session, `None` when not joined to a realm
+    pub fn session_info(&self) -> Option<&SessionInfo> {
+        match self.session_state() {
+            SessionState::Joined(_) => self.session_info.as_ref(),
+            _ => None,
+        }
+    }
+
+    /// Replaces the agent string sent when joining a realm, for the next sessions only.
+    /// Set to a zero length string to not send any agent.
+    pub fn set_agent<T: AsRef<str>>(&mut self, agent: T) {
+        self.config.agent = agent.as_ref().to_string();
+    }
+
     /// Returns the current client status
     pub fn get_cur_status(&mut self) -> &ClientState {
         // Check to see if the status changed
@@ -599,19 +2710,31 @@ impl<'a> Client<'a> {
         match new_status {
             Some(Some(state)) => self.set_next_status(state),
             None => &self.core_status,
-            Some(None) => panic!("The event loop died without sending a new status"),
+            Some(None) => self.set_event_loop_gone(),
         }
     }
 
+    /// Returns the error that caused the disconnection from the server, if any
+    pub fn get_disconnect_error(&mut self) -> Option<&WampError> {
+        self.get_cur_status().error()
+    }
+
     /// Returns whether we are connected to the server or not
     pub fn is_connected(&mut self) -> bool {
-        match self.get_cur_status() {
-            ClientState::Running => true,
-            _ => false,
-        }
+        matches!(self.get_cur_status(), ClientState::Running)
     }
 
     fn set_next_status(&mut self, new_status: Result<(), WampError>) -> &ClientState {
+        // Keep the reason of the first disconnection
+        if let ClientState::Disconnected(_) = self.core_status {
+            warn!(
+                target: CLIENT,
+                "Got new core status after already being disconnected : {:?}",
+                new_status
+            );
+            return &self.core_status;
+        }
+
         // Error means disconnection
         if new_status.is_err() {
             self.core_status = ClientState::Disconnected(new_status);
@@ -619,21 +2742,38 @@ impl<'a> Client<'a> {
         }
 
         // Progress to next state
-        match self.core_status {
-            ClientState::NoEventLoop => {
-                self.core_status = ClientState::Running;
-            }
-            ClientState::Running => {
-                self.core_status = ClientState::Disconnected(new_status);
-            }
-            ClientState::Disconnected(_) => {
-                panic!("Got new core status after already being disconnected");
-            }
-        }
+        self.core_status = match self.core_status {
+            ClientState::NoEventLoop => ClientState::Running,
+            _ => ClientState::Disconnected(new_status),
+        };
+
+        &self.core_status
+    }
 
+    /// Updates the status once the event loop is gone without telling us why
+    fn set_event_loop_gone(&mut self) -> &ClientState {
+        if let ClientState::Disconnected(_) = self.core_status {
+            return &self.core_status;
+        }
+        self.core_status = if self.event_loop_started.load(Ordering::Relaxed) {
+            ClientState::Disconnected(Ok(()))
+        } else {
+            ClientState::Disconnected(Err(WampError::EventLoopNotRunning))
+        };
         &self.core_status
     }
 
+    /// Sends a request to the event loop
+    fn send_request(&self, request: Request<'a>) -> Result<(), WampError> {
+        self.ctl_channel.send(request).map_err(|e| {
+            if self.event_loop_started.load(Ordering::Relaxed) {
+                From::from(format!("Core never received our request : {}", e))
+            } else {
+                WampError::EventLoopNotRunning
+            }
+        })
+    }
+
     // Waits until the event loop sends a status change event
     // This will update the current core_status field
     async fn wait_for_status_change(&mut self) -> &ClientState {
@@ -645,9 +2785,7 @@ impl<'a> Client<'a> {
         // Yield until we receive something
         let new_status = match self.core_res.recv().await {
             Some(v) => v,
-            None => {
-                panic!("The event loop died without sending a new status");
-            }
+            None => return self.set_event_loop_gone(),
         };
 
         // Save the new status
@@ -671,19 +2809,38 @@ impl<'a> Client<'a> {
     }
 
     /// Cleanly closes a connection with the server
-    pub async fn disconnect(mut self) {
+    ///
+    /// Leaves the realm, stops the event loop and returns how it went. Fails with
+    /// [`WampError::EventLoopNotRunning`] if the event loop is not running.
+    pub async fn disconnect(mut self) -> Result<DisconnectSummary, WampError> {
+        if let ClientState::NoEventLoop = self.get_cur_status() {
+            return Err(WampError::EventLoopNotRunning);
+        }
+
+        let mut leave = None;
         if self.is_connected() {
             // Cleanly leave realm
-            let _ = self.leave_realm().await;
+            if let SessionState::Joined(_) = self.session_state() {
+                leave = Some(self.leave_realm().await);
+            }
             // Stop the eventloop and disconnect from server
             let _ = self.ctl_channel.send(Request::Shutdown);
+        }
 
-            // Wait for return status from core
-            match self.core_res.recv().await {
-                Some(Err(e)) => error!("Error while shutting down : {:?}", e),
-                None => error!("Core never sent a status after shutting down..."),
-                _ => {}
+        // Wait for return status from core
+        let close = match self.block_until_disconnect().await {
+            ClientState::Disconnected(Err(WampError::EventLoopNotRunning)) => {
+                return Err(WampError::EventLoopNotRunning)
             }
-        }
+            ClientState::Disconnected(status) => status.clone(),
+            // Only returned once disconnected
+            _ => Ok(()),
+        };
+
+        Ok(DisconnectSummary {
+            leave,
+            aborted_requests: self.aborted_requests.load(Ordering::Relaxed),
+            close,
+        })
     }
 }
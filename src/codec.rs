@@ -0,0 +1,125 @@
+use serde_json::Value;
+
+use crate::common::*;
+use crate::error::*;
+
+/// Converts the payloads of a procedure or topic between the form handled by the application
+/// and the form sent to the router
+///
+/// Codecs are attached to a procedure or topic uri with
+/// [`crate::ClientConfig::add_payload_codec`]. They are then applied transparently : outgoing
+/// calls, publications and invocation results are encoded, received events, invocations and
+/// call results are decoded before any validator, journal or user code sees them.
+///
+/// ```
+/// # use wamp_async::{PayloadCodec, WampArgs, WampError, WampKwArgs};
+/// /// Reverses the positional arguments on the wire
+/// struct Reversed;
+///
+/// impl PayloadCodec for Reversed {
+///     fn encode(
+///         &self,
+///         arguments: Option<WampArgs>,
+///         arguments_kw: Option<WampKwArgs>,
+///     ) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError> {
+///         Ok((arguments.map(|a| a.into_iter().rev().collect()), arguments_kw))
+///     }
+///
+///     fn decode(
+///         &self,
+///         arguments: Option<WampArgs>,
+///         arguments_kw: Option<WampKwArgs>,
+///     ) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError> {
+///         self.encode(arguments, arguments_kw)
+///     }
+/// }
+/// ```
+pub trait PayloadCodec: Send + Sync {
+    /// Converts a payload of the application into the payload sent to the router
+    fn encode(
+        &self,
+        arguments: Option<WampArgs>,
+        arguments_kw: Option<WampKwArgs>,
+    ) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError>;
+
+    /// Converts a payload received from the router into the payload of the application
+    fn decode(
+        &self,
+        arguments: Option<WampArgs>,
+        arguments_kw: Option<WampKwArgs>,
+    ) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError>;
+}
+
+/// Codec sending the whole payload as a single JSON text argument
+///
+/// Useful to go through peers or routers that only forward opaque strings, or to keep the
+/// payload of a msgpack session readable. The text is `{"args": [...], "kwargs": {...}}`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct JsonTextCodec;
+
+impl PayloadCodec for JsonTextCodec {
+    fn encode(
+        &self,
+        arguments: Option<WampArgs>,
+        arguments_kw: Option<WampKwArgs>,
+    ) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError> {
+        let mut payload = WampKwArgs::new();
+        if let Some(arguments) = arguments {
+            payload.insert("args".to_string(), Value::Array(arguments));
+        }
+        if let Some(arguments_kw) = arguments_kw {
+            payload.insert("kwargs".to_string(), Value::Object(arguments_kw));
+        }
+        Ok((
+            Some(vec![Value::String(Value::Object(payload).to_string())]),
+            None,
+        ))
+    }
+
+    fn decode(
+        &self,
+        arguments: Option<WampArgs>,
+        arguments_kw: Option<WampKwArgs>,
+    ) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError> {
+        let text = match (arguments.as_deref(), arguments_kw) {
+            (Some([Value::String(text)]), None) => text,
+            _ => {
+                return Err(From::from(
+                    "Expected a single JSON text argument".to_string(),
+                ))
+            }
+        };
+        let mut payload: WampKwArgs = match serde_json::from_str(text) {
+            Ok(p) => p,
+            Err(e) => return Err(From::from(format!("Failed to decode payload : {}", e))),
+        };
+        let arguments = match payload.remove("args") {
+            Some(Value::Array(arguments)) => Some(arguments),
+            None => None,
+            Some(_) => return Err(From::from("Expected a list of arguments".to_string())),
+        };
+        let arguments_kw = match payload.remove("kwargs") {
+            Some(Value::Object(arguments_kw)) => Some(arguments_kw),
+            None => None,
+            Some(_) => {
+                return Err(From::from(
+                    "Expected a dictionary of keyword arguments".to_string(),
+                ))
+            }
+        };
+        Ok((arguments, arguments_kw))
+    }
+}
+
+/// Encodes the result of an invocation, keeping the options of the YIELD
+pub(crate) fn encode_response(
+    codec: &dyn PayloadCodec,
+    res: RpcResponse,
+) -> Result<RpcResponse, WampError> {
+    let (arguments, arguments_kw) = codec.encode(res.arguments, res.arguments_kw)?;
+    Ok(RpcResponse {
+        arguments,
+        arguments_kw,
+        options: res.options,
+    })
+}
@@ -0,0 +1,338 @@
+//! Generates typed client stubs from a WAMP interface description
+//!
+//! The interface description is a JSON document listing the procedures and topics of a
+//! service. Argument, result and payload types are Rust types that must be in scope where
+//! the generated code is included :
+//!
+//! ```json
+//! {
+//!     "name": "calculator",
+//!     "procedures": [
+//!         { "name": "add", "uri": "peer.add", "args": "(i64, i64)", "result": "i64" }
+//!     ],
+//!     "topics": [
+//!         { "name": "heartbeat", "uri": "peer.heartbeat", "payload": "Heartbeat" }
+//!     ]
+//! }
+//! ```
+//!
+//! For the description above, the generated code contains :
+//! - `ADD` and `HEARTBEAT` constants ([`crate::TypedProcedure`] and [`crate::TypedTopic`])
+//! - `CalculatorCaller` which wraps a [`crate::Client`] with `call_add()`, `publish_heartbeat()`
+//!   and `subscribe_heartbeat()` methods
+//! - a `CalculatorCallee` trait to implement and `register_calculator()` to register all of
+//!   its procedures
+//!
+//! Code is typically generated from a build script :
+//! ```no_run
+//! // In build.rs
+//! wamp_async::codegen::compile("calculator.json").unwrap();
+//! ```
+//! and included in the crate with `include!(concat!(env!("OUT_DIR"), "/calculator.rs"));`
+//!
+//! Names that are Rust keywords (e.g. `type`) are written as raw identifiers (`r#type`). Names
+//! that cannot be raw identifiers (`self`, `super`, `crate`, `Self`) and names whose constants
+//! would collide (e.g. `add` and `Add`) are rejected.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::error::*;
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Interface {
+    #[serde(default = "default_name")]
+    name: String,
+    #[serde(default)]
+    procedures: Vec<Procedure>,
+    #[serde(default)]
+    topics: Vec<Topic>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Procedure {
+    name: String,
+    uri: String,
+    #[serde(default = "unit_type")]
+    args: String,
+    #[serde(default = "unit_type")]
+    result: String,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Topic {
+    name: String,
+    uri: String,
+    #[serde(default = "unit_type")]
+    payload: String,
+}
+
+fn default_name() -> String {
+    "service".to_string()
+}
+
+fn unit_type() -> String {
+    "()".to_string()
+}
+
+/// Generates the Rust code for the JSON interface description `idl`
+pub fn generate(idl: &str) -> Result<String, WampError> {
+    let interface: Interface = match serde_json::from_str(idl) {
+        Ok(i) => i,
+        Err(e) => return Err(From::from(format!("Invalid interface description : {}", e))),
+    };
+
+    check_ident(&interface.name)?;
+    let mut constants = HashSet::new();
+    for name in interface
+        .procedures
+        .iter()
+        .map(|p| &p.name)
+        .chain(interface.topics.iter().map(|t| &t.name))
+    {
+        check_ident(name)?;
+        if !constants.insert(name.to_uppercase()) {
+            return Err(From::from(format!(
+                "Duplicate procedure or topic name : {:?}",
+                name
+            )));
+        }
+    }
+
+    let service = camel_case(&interface.name);
+    let mut out = String::new();
+    // Writing to a String never fails
+    let _ = write_interface(&mut out, &interface, &service);
+    Ok(out)
+}
+
+/// Generates the code for the interface description at `idl_path` into `$OUT_DIR`
+///
+/// The output file is named after the interface description (`calculator.json` generates
+/// `calculator.rs`). Meant to be called from a build script.
+pub fn compile<P: AsRef<Path>>(idl_path: P) -> Result<PathBuf, WampError> {
+    let idl_path = idl_path.as_ref();
+    let out_dir = match std::env::var_os("OUT_DIR") {
+        Some(d) => PathBuf::from(d),
+        None => return Err(From::from("OUT_DIR is not set".to_string())),
+    };
+    let stem = match idl_path.file_stem() {
+        Some(s) => s,
+        None => {
+            return Err(From::from(format!(
+                "Invalid interface description path : {}",
+                idl_path.display()
+            )))
+        }
+    };
+    let out_path = out_dir.join(stem).with_extension("rs");
+
+    let idl = match std::fs::read_to_string(idl_path) {
+        Ok(s) => s,
+        Err(e) => {
+            return Err(From::from(format!(
+                "Failed to read {} : {}",
+                idl_path.display(),
+                e
+            )))
+        }
+    };
+    let code = generate(&idl)?;
+    if let Err(e) = std::fs::write(&out_path, code) {
+        return Err(From::from(format!(
+            "Failed to write {} : {}",
+            out_path.display(),
+            e
+        )));
+    }
+
+    println!("cargo:rerun-if-changed={}", idl_path.display());
+    Ok(out_path)
+}
+
+fn write_interface(out: &mut String, interface: &Interface, service: &str) -> std::fmt::Result {
+    writeln!(out, "// Generated by wamp_async::codegen. Do not edit.")?;
+    writeln!(out)?;
+
+    for p in &interface.procedures {
+        writeln!(
+            out,
+            "pub const {}: ::wamp_async::TypedProcedure<{}, {}> = ::wamp_async::TypedProcedure::new({:?});",
+            p.name.to_uppercase(),
+            p.args,
+            p.result,
+            p.uri
+        )?;
+    }
+    for t in &interface.topics {
+        writeln!(
+            out,
+            "pub const {}: ::wamp_async::TypedTopic<{}> = ::wamp_async::TypedTopic::new({:?});",
+            t.name.to_uppercase(),
+            t.payload,
+            t.uri
+        )?;
+    }
+    writeln!(out)?;
+
+    // Caller stubs
+    writeln!(
+        out,
+        "/// Typed stubs for the `{}` interface",
+        interface.name
+    )?;
+    writeln!(out, "pub struct {}Caller<'c, 'a> {{", service)?;
+    writeln!(out, "    client: &'c ::wamp_async::Client<'a>,")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+    writeln!(out, "impl<'c, 'a> {}Caller<'c, 'a> {{", service)?;
+    writeln!(
+        out,
+        "    pub fn new(client: &'c ::wamp_async::Client<'a>) -> Self {{"
+    )?;
+    writeln!(out, "        Self {{ client }}")?;
+    writeln!(out, "    }}")?;
+    for p in &interface.procedures {
+        writeln!(out)?;
+        writeln!(out, "    /// Calls `{}`", p.uri)?;
+        writeln!(
+            out,
+            "    pub async fn call_{}(&self, args: &{}) -> Result<{}, ::wamp_async::WampError> {{",
+            p.name, p.args, p.result
+        )?;
+        writeln!(
+            out,
+            "        self.client.call_typed(&{}, args).await",
+            p.name.to_uppercase()
+        )?;
+        writeln!(out, "    }}")?;
+    }
+    for t in &interface.topics {
+        writeln!(out)?;
+        writeln!(out, "    /// Publishes on `{}`", t.uri)?;
+        writeln!(
+            out,
+            "    pub async fn publish_{}(&self, value: &{}, acknowledge: bool) -> Result<Option<::wamp_async::WampId>, ::wamp_async::WampError> {{",
+            t.name, t.payload
+        )?;
+        writeln!(
+            out,
+            "        self.client.publish_typed(&{}, value, acknowledge).await",
+            t.name.to_uppercase()
+        )?;
+        writeln!(out, "    }}")?;
+        writeln!(out)?;
+        writeln!(out, "    /// Subscribes to `{}`", t.uri)?;
+        writeln!(
+            out,
+            "    pub async fn subscribe_{}(&self) -> Result<(::wamp_async::WampId, ::wamp_async::TypedSubscription<{}>), ::wamp_async::WampError> {{",
+            t.name, t.payload
+        )?;
+        writeln!(
+            out,
+            "        self.client.subscribe_typed(&{}).await",
+            t.name.to_uppercase()
+        )?;
+        writeln!(out, "    }}")?;
+    }
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    // Callee skeleton
+    writeln!(
+        out,
+        "/// Implementation of the procedures of the `{}` interface",
+        interface.name
+    )?;
+    writeln!(out, "pub trait {}Callee: Send + Sync + 'static {{", service)?;
+    for p in &interface.procedures {
+        writeln!(
+            out,
+            "    fn {}(&self, args: {}) -> ::std::pin::Pin<Box<dyn ::std::future::Future<Output = Result<{}, ::wamp_async::WampError>> + Send>>;",
+            raw_ident(&p.name),
+            p.args,
+            p.result
+        )?;
+    }
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "/// Registers every procedure of the `{}` interface",
+        interface.name
+    )?;
+    writeln!(
+        out,
+        "pub async fn register_{}<T: {}Callee>(client: &::wamp_async::Client<'_>, callee: ::std::sync::Arc<T>) -> Result<Vec<::wamp_async::WampId>, ::wamp_async::WampError> {{",
+        interface.name, service
+    )?;
+    writeln!(out, "    let mut rpc_ids = Vec::new();")?;
+    for p in &interface.procedures {
+        writeln!(out, "    let c = callee.clone();")?;
+        writeln!(
+            out,
+            "    rpc_ids.push(client.register_typed(&{}, move |args| c.{}(args)).await?);",
+            p.name.to_uppercase(),
+            raw_ident(&p.name)
+        )?;
+    }
+    writeln!(out, "    let _ = callee;")?;
+    writeln!(out, "    Ok(rpc_ids)")?;
+    writeln!(out, "}}")?;
+    Ok(())
+}
+
+/// Keywords of every edition, written as raw identifiers
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+    "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let",
+    "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return",
+    "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use",
+    "virtual", "where", "while", "yield",
+];
+
+/// Keywords that cannot be raw identifiers
+const RESERVED: &[&str] = &["_", "crate", "self", "Self", "super"];
+
+fn check_ident(name: &str) -> Result<(), WampError> {
+    let mut chars = name.chars();
+    let valid = match chars.next() {
+        Some(c) => {
+            (c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    };
+    if !valid || RESERVED.contains(&name) {
+        return Err(From::from(format!("Invalid identifier : {:?}", name)));
+    }
+    Ok(())
+}
+
+/// Returns `name` as a raw identifier if it is a keyword
+fn raw_ident(name: &str) -> String {
+    if KEYWORDS.contains(&name) {
+        format!("r#{}", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn camel_case(name: &str) -> String {
+    name.split('_')
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            let mut c = s.chars();
+            match c.next() {
+                Some(f) => f.to_ascii_uppercase().to_string() + c.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
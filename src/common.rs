@@ -1,15 +1,21 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::future::Future;
 use std::hash::Hash;
 use std::num::NonZeroU64;
 use std::pin::Pin;
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use futures::FutureExt;
 use log::*;
 use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::sync::oneshot;
 
 use crate::error::*;
+use crate::log_targets::CLIENT;
+use crate::transport::TransportError;
 
 pub(crate) const DEFAULT_AGENT_STR: &str =
     concat!(env!("CARGO_PKG_NAME"), "_rs-", env!("CARGO_PKG_VERSION"));
@@ -42,6 +48,16 @@ impl WampId {
         // range [1, 2^53].
         Self(unsafe { NonZeroU64::new_unchecked(random_id + 1) })
     }
+
+    /// ID from its value on the wire, `None` for 0
+    pub(crate) fn from_raw(id: u64) -> Option<Self> {
+        NonZeroU64::new(id).map(Self)
+    }
+
+    /// Largest ID in the global scope (2^53)
+    pub(crate) fn max() -> Self {
+        Self(NonZeroU64::new(1 << 53).unwrap())
+    }
 }
 
 /// integer: a non-negative integer
@@ -62,10 +78,13 @@ pub type WampPayloadValue = serde_json::Value;
 /// Unnamed WAMP argument list
 pub type WampArgs = Vec<WampPayloadValue>;
 /// Named WAMP argument map
+///
+/// With the `preserve_order` feature, keys are kept (and serialized) in insertion order
+/// instead of being sorted.
 pub type WampKwArgs = serde_json::Map<String, WampPayloadValue>;
 
 /// Generic enum that can hold any concrete WAMP value
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(untagged)]
 pub enum Arg {
     /// uri: a string URI as defined in URIs
@@ -107,6 +126,324 @@ impl ClientRole {
             ClientRole::Subscriber => "subscriber",
         }
     }
+
+    /// Returns the advanced profile features announced for the role
+    pub fn features(&self) -> &'static [&'static str] {
+        match self {
+            ClientRole::Caller => &[
+                "sharded_registration",
+                "call_canceling",
+                "progressive_call_results",
+            ],
+            ClientRole::Callee => &[
+                "sharded_registration",
+                "caller_identification",
+                "call_timeout",
+                "progressive_call_results",
+                "registration_revocation",
+            ],
+            ClientRole::Publisher => &[
+                "publisher_exclusion",
+                "sharded_subscription",
+                "subscriber_blackwhite_listing",
+            ],
+            ClientRole::Subscriber => &["sharded_subscription", "subscription_revocation"],
+        }
+    }
+}
+
+/// Item received from a subscription with a bounded queue
+#[derive(Debug)]
+pub enum SubscriptionItem {
+    /// An event with its publication ID, args, kwargs and details
+    Event(WampId, Option<WampArgs>, Option<WampKwArgs>, WampDict),
+    /// The queue was full and `dropped` events were lost since the previous item
+    Lagged { dropped: u64 },
+    /// The broker revoked the subscription, this is the last item of the queue
+    Revoked { reason: Option<WampUri> },
+}
+
+/// ERROR message received from the router that could not be matched to a pending request
+#[derive(Debug)]
+pub struct UnhandledError {
+    /// Type of the message the error refers to (e.g. 68 for INVOCATION)
+    pub message_type: WampInteger,
+    /// ID of the request the error refers to
+    pub request: WampId,
+    /// Error uri
+    pub error: WampUri,
+    /// Error details
+    pub details: WampDict,
+    /// Error args
+    pub arguments: Option<WampArgs>,
+    /// Error kwargs
+    pub arguments_kw: Option<WampKwArgs>,
+}
+
+/// Change of a registration initiated by the router, see
+/// [`crate::Client::registration_events`]
+#[derive(Debug, Clone)]
+pub enum RegistrationEvent {
+    /// The router revoked the registration, the procedure is not invoked anymore
+    Revoked {
+        /// ID of the registration
+        registration: WampId,
+        /// Procedure of the registration
+        procedure: WampUri,
+        /// Reason given by the router (e.g. `wamp.error.registration_revoked`)
+        reason: Option<WampUri>,
+    },
+}
+
+/// Change of a subscription initiated by the router, see
+/// [`crate::Client::subscription_events`]
+#[derive(Debug, Clone)]
+pub enum SubscriptionEvent {
+    /// The router revoked the subscription, its queue is closed
+    Revoked {
+        /// ID of the subscription, as returned when subscribing
+        subscription: WampId,
+        /// Topic of the subscription
+        topic: WampUri,
+        /// Reason given by the router (e.g. `wamp.error.subscription_revoked`)
+        reason: Option<WampUri>,
+    },
+}
+
+/// Depth of the queues of a client, see [`crate::Client::queue_stats`]
+#[derive(Debug, Clone, Default)]
+pub struct QueueStats {
+    /// Requests (calls, publications, invocation results...) waiting for the event loop
+    pub control: usize,
+    /// Queues of the active subscriptions, keyed by subscription ID
+    pub subscriptions: HashMap<WampId, SubscriptionQueueStats>,
+}
+
+/// Depth of the queue of a subscription
+#[derive(Debug, Clone)]
+pub struct SubscriptionQueueStats {
+    /// Topic of the subscription
+    pub topic: WampUri,
+    /// Events waiting to be read
+    pub depth: usize,
+    /// Maximum number of events kept, `None` for unbounded queues
+    pub capacity: Option<usize>,
+}
+
+/// State of the WAMP session over the current connection
+///
+/// A session goes from `Connected` to `Joining`, `Joined`, `Leaving` and back to `Connected`.
+/// A failed join goes back to `Connected`, and any state goes to `Disconnected` once the
+/// connection is closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionState {
+    /// The connection with the router is closed
+    Disconnected,
+    /// Connected to the router but not part of a realm
+    Connected,
+    /// HELLO was sent and the router did not welcome us yet
+    Joining,
+    /// Part of a realm with the given session ID
+    Joined(WampId),
+    /// GOODBYE was sent and the router did not answer yet
+    Leaving,
+}
+
+impl SessionState {
+    /// Returns whether the session can move from this state to `next`
+    pub fn can_transition_to(&self, next: &SessionState) -> bool {
+        use SessionState::*;
+        matches!(
+            (self, next),
+            (_, Disconnected)
+                | (Connected, Joining)
+                // Joining again before the router acknowledged our GOODBYE
+                | (Leaving, Joining)
+                | (Joining, Joined(_))
+                | (Joining, Connected)
+                | (Joined(_), Leaving)
+                | (Leaving, Connected)
+        )
+    }
+}
+
+/// What the event loop does with inbound frames that cannot be deserialized (e.g. vendor
+/// extension messages)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MalformedMessagePolicy {
+    /// Closes the connection on the first malformed frame (default)
+    Strict,
+    /// Drops malformed frames with a warning
+    SkipAndLog,
+    /// Drops up to `max` malformed frames with a warning, then closes the connection
+    SkipN(usize),
+}
+
+/// What the event loop does with invocation results larger than the maximum message size
+/// negotiated with the router
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OversizedResponsePolicy {
+    /// Answers the caller with a `wamp.error.payload_size_exceeded` ERROR instead (default)
+    Fail,
+    /// Logs a warning and hands the result to the transport anyway, for transports that do not
+    /// enforce the limit. When the transport refuses it, the caller is answered with the same
+    /// ERROR.
+    Warn,
+}
+
+/// What the event loop does with the inline RPC handlers running longer than their budget, see
+/// [`crate::ClientConfig::set_inline_handler_budget`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowInlineHandlerPolicy {
+    /// Logs a warning and keeps running the handler inline (default)
+    Warn,
+    /// Logs a warning and runs the following invocations of the procedure through the RPC event
+    /// queue, like the other procedures
+    Demote,
+}
+
+/// Why the router closed a joined session, see [`WampError::SessionClosed`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SessionCloseReason {
+    /// `wamp.close.killed` : the session was killed, e.g. with `wamp.session.kill`
+    Killed,
+    /// `wamp.error.authentication_expired` : the credentials of the session expired
+    AuthenticationExpired,
+    /// `wamp.close.system_shutdown` : the router is shutting down
+    SystemShutdown,
+    /// `wamp.close.close_realm` : the realm is being closed
+    CloseRealm,
+    /// Any other reason
+    Other(WampString),
+}
+
+impl SessionCloseReason {
+    /// Returns the reason URI sent by the router
+    pub fn uri(&self) -> &str {
+        match self {
+            SessionCloseReason::Killed => "wamp.close.killed",
+            SessionCloseReason::AuthenticationExpired => "wamp.error.authentication_expired",
+            SessionCloseReason::SystemShutdown => "wamp.close.system_shutdown",
+            SessionCloseReason::CloseRealm => "wamp.close.close_realm",
+            SessionCloseReason::Other(uri) => uri,
+        }
+    }
+
+    /// Whether the session was logged out by the router, in which case rejoining with the same
+    /// credentials is likely to fail
+    pub fn is_forced_logout(&self) -> bool {
+        matches!(
+            self,
+            SessionCloseReason::Killed | SessionCloseReason::AuthenticationExpired
+        )
+    }
+}
+
+impl From<WampString> for SessionCloseReason {
+    fn from(uri: WampString) -> Self {
+        match uri.as_str() {
+            "wamp.close.killed" => SessionCloseReason::Killed,
+            "wamp.error.authentication_expired" => SessionCloseReason::AuthenticationExpired,
+            "wamp.close.system_shutdown" => SessionCloseReason::SystemShutdown,
+            "wamp.close.close_realm" => SessionCloseReason::CloseRealm,
+            _ => SessionCloseReason::Other(uri),
+        }
+    }
+}
+
+impl fmt::Display for SessionCloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.uri())
+    }
+}
+
+/// Details of the current session, as sent by the router in WELCOME
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    /// ID of the session
+    pub session_id: WampId,
+    /// Realm joined
+    pub realm: WampString,
+    /// Agent string of the router, if it sent one
+    pub agent: Option<WampString>,
+    /// Roles supported by the router (`broker`, `dealer`)
+    pub roles: Vec<WampString>,
+    /// Authentication ID of the session
+    pub authid: Option<WampString>,
+    /// Authentication role of the session
+    pub authrole: Option<WampString>,
+    /// Authentication method used to join
+    pub authmethod: Option<WampString>,
+    /// Details of the WELCOME message
+    details: WampDict,
+}
+
+impl SessionInfo {
+    pub(crate) fn new(session_id: WampId, realm: WampString, details: WampDict) -> Self {
+        let string = |key: &str| match details.get(key) {
+            Some(Arg::String(s)) | Some(Arg::Uri(s)) => Some(s.clone()),
+            _ => None,
+        };
+        let mut roles: Vec<WampString> = match details.get("roles") {
+            Some(Arg::Dict(roles)) => roles.keys().cloned().collect(),
+            _ => Vec::new(),
+        };
+        roles.sort();
+
+        Self {
+            session_id,
+            realm,
+            agent: string("agent"),
+            roles,
+            authid: string("authid"),
+            authrole: string("authrole"),
+            authmethod: string("authmethod"),
+            details,
+        }
+    }
+
+    /// Returns the details of the WELCOME message as received, including the keys that are
+    /// specific to the router (e.g. the node of a cluster)
+    pub fn raw(&self) -> &WampDict {
+        &self.details
+    }
+}
+
+/// Known quirks of router implementations the client can adapt to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RouterDialect {
+    /// Follows the WAMP specification (default)
+    Standard,
+    /// Crossbar.io, which follows the specification
+    Crossbar,
+    /// Nexus. Role details are sent without advanced profile features and the router may
+    /// answer our GOODBYE with any reason.
+    Nexus,
+    /// Bondy. `authextra` is always sent when authenticating and the router may answer our
+    /// GOODBYE with any reason.
+    Bondy,
+}
+
+impl RouterDialect {
+    /// Whether the advanced profile features of our roles are announced in HELLO
+    pub(crate) fn announces_features(&self) -> bool {
+        !matches!(self, RouterDialect::Nexus)
+    }
+
+    /// Whether HELLO carries an `authextra` dict, even empty, along with the authmethods
+    pub(crate) fn requires_authextra(&self) -> bool {
+        matches!(self, RouterDialect::Bondy)
+    }
+
+    /// Whether a GOODBYE received while leaving acknowledges ours
+    pub(crate) fn is_goodbye_ack(&self, reason: &str) -> bool {
+        match self {
+            RouterDialect::Standard | RouterDialect::Crossbar => {
+                reason == "wamp.close.goodbye_and_out"
+            }
+            RouterDialect::Nexus | RouterDialect::Bondy => true,
+        }
+    }
 }
 
 /// All the supported roles a server can have
@@ -146,6 +483,12 @@ pub enum AuthenticationMethod {
     /// [Ticket-based Authentication]: https://wamp-proto.org/_static/gen/wamp_latest.html#ticketauth
     #[strum(serialize = "ticket")]
     Ticket,
+    /// [TLS Client Certificate Authentication], the certificate set with
+    /// [`crate::ClientConfig::set_tls_identity`] proves the identity so no CHALLENGE is answered
+    ///
+    /// [TLS Client Certificate Authentication]: https://wamp-proto.org/_static/gen/wamp_latest.html#tls-certificate-authentication
+    #[strum(serialize = "tls")]
+    Tls,
 }
 
 impl Serialize for AuthenticationMethod {
@@ -198,11 +541,123 @@ impl AuthenticationChallengeResponse {
     }
 }
 
+/// Prefix of the payload strings holding binary data, as defined by WAMP for JSON. They are
+/// followed by the base64 encoded bytes.
+pub(crate) const BINARY_PREFIX: char = '\0';
+
+/// Key of the object holding a MsgPack extension value in payloads, see [`MsgPackExt`]
+pub const MSGPACK_EXT_KEY: &str = "__msgpack_ext__";
+
+/// MsgPack extension value (e.g. a timestamp) of a payload
+///
+/// Payload values are JSON values, so extension values are held in an object with a single
+/// [`MSGPACK_EXT_KEY`] key : `{"__msgpack_ext__": {"type": -1, "data": [0, 0, 0, 1]}}`. This
+/// object is sent as the extension value with the MsgPack serializer and as is with JSON.
+///
+/// ```
+/// # use wamp_async::{MsgPackExt, WampPayloadValue};
+/// let value: WampPayloadValue = MsgPackExt::from_timestamp(1_600_000_000, 0).into();
+/// let ext = MsgPackExt::from_value(&value).unwrap();
+/// assert_eq!(ext.timestamp(), Some((1_600_000_000, 0)));
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MsgPackExt {
+    /// Extension type
+    pub tag: i8,
+    /// Raw extension data
+    pub data: Vec<u8>,
+}
+
+impl MsgPackExt {
+    /// Extension type of MsgPack timestamps
+    pub const TIMESTAMP_TAG: i8 = -1;
+
+    /// Creates an extension value
+    pub fn new(tag: i8, data: Vec<u8>) -> Self {
+        Self { tag, data }
+    }
+
+    /// Creates a timestamp using the smallest of the timestamp 32/64/96 formats
+    pub fn from_timestamp(secs: i64, nanos: u32) -> Self {
+        let data = if secs >= 0 && secs >> 34 == 0 {
+            if nanos == 0 && secs >> 32 == 0 {
+                (secs as u32).to_be_bytes().to_vec()
+            } else {
+                ((nanos as u64) << 34 | secs as u64).to_be_bytes().to_vec()
+            }
+        } else {
+            let mut data = nanos.to_be_bytes().to_vec();
+            data.extend_from_slice(&secs.to_be_bytes());
+            data
+        };
+        Self::new(Self::TIMESTAMP_TAG, data)
+    }
+
+    /// Returns the seconds and nanoseconds of a timestamp, `None` if this is not a timestamp
+    pub fn timestamp(&self) -> Option<(i64, u32)> {
+        if self.tag != Self::TIMESTAMP_TAG {
+            return None;
+        }
+        let mut buf = [0u8; 12];
+        match self.data.len() {
+            4 => {
+                buf[..4].copy_from_slice(&self.data);
+                Some((
+                    u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as i64,
+                    0,
+                ))
+            }
+            8 => {
+                buf[..8].copy_from_slice(&self.data);
+                let v = u64::from_be_bytes([
+                    buf[0], buf[1], buf[2], buf[3], buf[4], buf[5], buf[6], buf[7],
+                ]);
+                Some(((v & ((1 << 34) - 1)) as i64, (v >> 34) as u32))
+            }
+            12 => {
+                buf.copy_from_slice(&self.data);
+                let nanos = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]);
+                let secs = i64::from_be_bytes([
+                    buf[4], buf[5], buf[6], buf[7], buf[8], buf[9], buf[10], buf[11],
+                ]);
+                Some((secs, nanos))
+            }
+            _ => None,
+        }
+    }
+
+    /// Returns the extension value held by a payload value, if any
+    pub fn from_value(value: &WampPayloadValue) -> Option<Self> {
+        let object = value.as_object()?;
+        if object.len() != 1 {
+            return None;
+        }
+        let ext = object.get(MSGPACK_EXT_KEY)?.as_object()?;
+        let tag = ext.get("type")?.as_i64()?;
+        let data = ext
+            .get("data")?
+            .as_array()?
+            .iter()
+            .map(|b| b.as_u64().filter(|b| *b <= u8::MAX as u64).map(|b| b as u8))
+            .collect::<Option<Vec<u8>>>()?;
+        if tag < i8::MIN as i64 || tag > i8::MAX as i64 {
+            return None;
+        }
+        Some(Self::new(tag as i8, data))
+    }
+}
+
+impl From<MsgPackExt> for WampPayloadValue {
+    fn from(ext: MsgPackExt) -> Self {
+        serde_json::json!({ MSGPACK_EXT_KEY: { "type": ext.tag, "data": ext.data } })
+    }
+}
+
 /// Convert WampPayloadValue into any serde-deserializable object
-pub fn try_from_any_value<'a, T: DeserializeOwned>(
-    value: WampPayloadValue,
-) -> Result<T, WampError> {
-    serde_json::from_value(value).map_err(|e| {
+///
+/// On failure, the error names the offending field (e.g. `inner.values[2]: invalid type`).
+pub fn try_from_any_value<T: DeserializeOwned>(value: WampPayloadValue) -> Result<T, WampError> {
+    serde_path_to_error::deserialize(value).map_err(|e| {
         WampError::SerializationError(crate::serializer::SerializerError::Deserialization(
             e.to_string(),
         ))
@@ -210,18 +665,21 @@ pub fn try_from_any_value<'a, T: DeserializeOwned>(
 }
 
 /// Convert WampArgs into any serde-deserializable object
-pub fn try_from_args<'a, T: DeserializeOwned>(value: WampArgs) -> Result<T, WampError> {
+pub fn try_from_args<T: DeserializeOwned>(value: WampArgs) -> Result<T, WampError> {
     try_from_any_value(value.into())
 }
 
 /// Convert WampArgs into any serde-deserializable object
-pub fn try_from_kwargs<'a, T: DeserializeOwned>(value: WampKwArgs) -> Result<T, WampError> {
+pub fn try_from_kwargs<T: DeserializeOwned>(value: WampKwArgs) -> Result<T, WampError> {
     try_from_any_value(value.into())
 }
 
 /// Convert any serde-serializable object into WampPayloadValue
+///
+/// 64-bit integers (and 128-bit integers within the 64-bit range) are preserved. On failure,
+/// the error names the offending field.
 pub fn try_into_any_value<T: Serialize>(value: T) -> Result<WampPayloadValue, WampError> {
-    serde_json::to_value(value).map_err(|e| {
+    serde_path_to_error::serialize(&value, serde_json::value::Serializer).map_err(|e| {
         WampError::SerializationError(crate::serializer::SerializerError::Serialization(
             e.to_string(),
         ))
@@ -230,7 +688,7 @@ pub fn try_into_any_value<T: Serialize>(value: T) -> Result<WampPayloadValue, Wa
 
 /// Convert any serde-serializable object into WampArgs
 pub fn try_into_args<T: Serialize>(value: T) -> Result<WampArgs, WampError> {
-    match serde_json::to_value(value).unwrap() {
+    match try_into_any_value(value)? {
         serde_json::value::Value::Array(array) => Ok(array),
         value => Err(WampError::SerializationError(
             crate::serializer::SerializerError::Serialization(format!(
@@ -243,7 +701,7 @@ pub fn try_into_args<T: Serialize>(value: T) -> Result<WampArgs, WampError> {
 
 /// Convert any serde-serializable object into WampKwArgs
 pub fn try_into_kwargs<T: Serialize>(value: T) -> Result<WampKwArgs, WampError> {
-    match serde_json::to_value(value).unwrap() {
+    match try_into_any_value(value)? {
         serde_json::value::Value::Object(object) => Ok(object),
         value => Err(WampError::SerializationError(
             crate::serializer::SerializerError::Serialization(format!(
@@ -254,46 +712,1080 @@ pub fn try_into_kwargs<T: Serialize>(value: T) -> Result<WampKwArgs, WampError>
     }
 }
 
-/// Returns whether a uri is valid or not (using strict rules)
-pub fn is_valid_strict_uri<T: AsRef<str>>(in_uri: T) -> bool {
-    let uri: &str = in_uri.as_ref();
-    let mut num_chars_token: usize = 0;
-    if uri.starts_with("wamp.") {
-        warn!("URI '{}' cannot start with 'wamp'", uri);
-        return false;
+/// Result of an RPC endpoint, with the options of the YIELD message sent to the dealer
+///
+/// RPC endpoints can return either an `RpcResponse` or a `(WampArgs, WampKwArgs)` tuple.
+///
+/// ```
+/// # use wamp_async::{Arg, RpcResponse};
+/// let res = RpcResponse::new(Some(vec![42.into()]), None)
+///     .with_option("x_custom", Arg::String("value".to_string()));
+/// ```
+#[derive(Debug, Default)]
+pub struct RpcResponse {
+    /// Positional results
+    pub arguments: Option<WampArgs>,
+    /// Keyword results
+    pub arguments_kw: Option<WampKwArgs>,
+    /// Options of the YIELD message
+    pub options: WampDict,
+}
+
+impl RpcResponse {
+    /// Creates a response without options
+    pub fn new(arguments: Option<WampArgs>, arguments_kw: Option<WampKwArgs>) -> Self {
+        Self {
+            arguments,
+            arguments_kw,
+            options: WampDict::new(),
+        }
     }
 
-    for (i, c) in uri.chars().enumerate() {
-        if c == '.' {
-            if num_chars_token == 0 {
-                warn!(
-                    "URI '{}' contains a zero length token ending @ index {}",
-                    uri, i
-                );
-                return false;
-            }
-            num_chars_token = 0;
-        } else {
-            num_chars_token += 1;
+    /// Adds an option to the YIELD message
+    pub fn with_option<T: Into<String>>(mut self, key: T, value: Arg) -> Self {
+        self.options.insert(key.into(), value);
+        self
+    }
+}
+
+impl From<(Option<WampArgs>, Option<WampKwArgs>)> for RpcResponse {
+    fn from((arguments, arguments_kw): (Option<WampArgs>, Option<WampKwArgs>)) -> Self {
+        Self::new(arguments, arguments_kw)
+    }
+}
+
+/// Options used when registering an RPC endpoint
+///
+/// ```
+/// # use std::time::Duration;
+/// # use wamp_async::RegisterOptions;
+/// let options = RegisterOptions::new()
+///     .with_takeover(true)
+///     .with_timeout(Duration::from_secs(30))
+///     .with_disclose_caller(true);
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct RegisterOptions {
+    force_reregister: bool,
+    takeover: bool,
+    rkey: Option<WampString>,
+    match_policy: MatchPolicy,
+    timeout: Option<Duration>,
+    disclose_caller: bool,
+}
+
+impl RegisterOptions {
+    /// Creates the default registration options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the dealer to replace any existing registration of the procedure
+    /// (`force_reregister` option)
+    pub fn with_force_reregister(mut self, force_reregister: bool) -> Self {
+        self.force_reregister = force_reregister;
+        self
+    }
+
+    /// When the procedure is already registered, removes the existing callees through the
+    /// registration meta API (`wamp.registration.remove_callee`) and registers again.
+    ///
+    /// This is meant to recover from the ghost registrations of a crashed session.
+    pub fn with_takeover(mut self, takeover: bool) -> Self {
+        self.takeover = takeover;
+        self
+    }
+
+    /// Registers this callee as the shard serving the routing key `rkey` (sharded registration)
+    pub fn with_rkey<T: Into<String>>(mut self, rkey: T) -> Self {
+        self.rkey = Some(rkey.into());
+        self
+    }
+
+    /// Sets how the dealer matches the URIs of calls against the registered URI
+    pub fn with_match(mut self, match_policy: MatchPolicy) -> Self {
+        self.match_policy = match_policy;
+        self
+    }
+
+    /// Asks the dealer to cancel the invocations that do not complete within `timeout`
+    /// (`call_timeout` feature)
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Asks the dealer to disclose the session ID of the callers in the invocation details
+    /// (`caller` detail)
+    pub fn with_disclose_caller(mut self, disclose_caller: bool) -> Self {
+        self.disclose_caller = disclose_caller;
+        self
+    }
+
+    /// Returns the invocation timeout enforced by the dealer
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Returns whether existing callees are removed when the procedure is already registered
+    pub fn get_takeover(&self) -> bool {
+        self.takeover
+    }
+
+    /// Returns how the dealer matches the URIs of calls against the registered URI
+    pub fn get_match(&self) -> MatchPolicy {
+        self.match_policy
+    }
+
+    /// Options sent in the REGISTER message
+    pub(crate) fn to_dict(&self) -> WampDict {
+        let mut options = WampDict::new();
+        if self.force_reregister {
+            options.insert("force_reregister".to_string(), Arg::Bool(true));
         }
+        if let Some(rkey) = &self.rkey {
+            options.insert("rkey".to_string(), Arg::String(rkey.clone()));
+        }
+        if let Some(match_policy) = self.match_policy.to_str() {
+            options.insert("match".to_string(), Arg::String(match_policy.to_string()));
+        }
+        if let Some(timeout) = self.timeout {
+            options.insert("timeout".to_string(), Arg::Integer(timeout_millis(timeout)));
+        }
+        if self.disclose_caller {
+            options.insert("disclose_caller".to_string(), Arg::Bool(true));
+        }
+        options
+    }
+}
+
+/// RPC endpoints registered together, see [`crate::Client::register_many`]
+///
+/// ```
+/// # use wamp_async::{RegisterOptions, RegistrationBatch, WampArgs, WampError, WampKwArgs};
+/// let batch = RegistrationBatch::new()
+///     .add("app.ping", RegisterOptions::new(), |_args, _kwargs| async {
+///         Ok::<_, WampError>((None, None))
+///     })
+///     .add("app.echo", RegisterOptions::new(), |args, kwargs| async move {
+///         Ok::<_, WampError>((args, kwargs))
+///     });
+/// assert_eq!(batch.len(), 2);
+/// ```
+#[derive(Default)]
+pub struct RegistrationBatch<'a> {
+    procedures: Vec<(WampString, RegisterOptions, InvocationFunc<'a>)>,
+}
+
+impl<'a> RegistrationBatch<'a> {
+    /// Creates an empty batch
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the RPC endpoint `uri` to the batch. See [`crate::Client::register_with_options`]
+    pub fn add<T, F, Fut, R>(mut self, uri: T, options: RegisterOptions, func_ptr: F) -> Self
+    where
+        T: Into<String>,
+        F: Fn(Option<WampArgs>, Option<WampKwArgs>) -> Fut + Send + Sync + 'a,
+        Fut: Future<Output = Result<R, WampError>> + Send + 'a,
+        R: Into<RpcResponse>,
+    {
+        self.procedures
+            .push((uri.into(), options, invocation_func(func_ptr)));
+        self
+    }
+
+    /// Returns the number of RPC endpoints in the batch
+    pub fn len(&self) -> usize {
+        self.procedures.len()
+    }
 
-        if c == '_' {
-            continue;
+    /// Returns whether the batch has no RPC endpoint
+    pub fn is_empty(&self) -> bool {
+        self.procedures.is_empty()
+    }
+
+    pub(crate) fn into_procedures(self) -> Vec<(WampString, RegisterOptions, InvocationFunc<'a>)> {
+        self.procedures
+    }
+}
+
+/// Arguments and keyword arguments returned by a call
+pub type CallResult = (Option<WampArgs>, Option<WampKwArgs>);
+
+/// Calls made together, see [`crate::Client::call_batch`]
+///
+/// ```
+/// # use std::time::Duration;
+/// # use wamp_async::{CallBatch, CallOptions};
+/// let batch = CallBatch::new()
+///     .add("app.users.count", None, None, CallOptions::new())
+///     .add("app.orders.count", Some(vec!["today".into()]), None, CallOptions::new())
+///     .with_timeout(Duration::from_secs(2));
+/// assert_eq!(batch.len(), 2);
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct CallBatch {
+    calls: Vec<(WampUri, Option<WampArgs>, Option<WampKwArgs>, CallOptions)>,
+    timeout: Option<Duration>,
+}
+
+impl CallBatch {
+    /// Creates an empty batch
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a call of the RPC endpoint `uri` to the batch. See [`crate::Client::call_with_options`]
+    pub fn add<T: Into<String>>(
+        mut self,
+        uri: T,
+        arguments: Option<WampArgs>,
+        arguments_kw: Option<WampKwArgs>,
+        options: CallOptions,
+    ) -> Self {
+        self.calls
+            .push((uri.into(), arguments, arguments_kw, options));
+        self
+    }
+
+    /// Gives all the calls of the batch at most `timeout` to complete. The calls with a shorter
+    /// timeout of their own keep it.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the timeout shared by the calls of the batch
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Returns the number of calls in the batch
+    pub fn len(&self) -> usize {
+        self.calls.len()
+    }
+
+    /// Returns whether the batch has no call
+    pub fn is_empty(&self) -> bool {
+        self.calls.is_empty()
+    }
+
+    /// Returns the calls with the timeout of the batch applied to their options
+    pub(crate) fn into_calls(
+        self,
+    ) -> Vec<(WampUri, Option<WampArgs>, Option<WampKwArgs>, CallOptions)> {
+        let timeout = self.timeout;
+        self.calls
+            .into_iter()
+            .map(|(uri, arguments, arguments_kw, options)| {
+                let options = match (timeout, options.get_timeout()) {
+                    (Some(batch), Some(own)) if own <= batch => options,
+                    (Some(batch), _) => options.with_timeout(batch),
+                    (None, _) => options,
+                };
+                (uri, arguments, arguments_kw, options)
+            })
+            .collect()
+    }
+}
+
+/// Number of successful calls [`crate::Client::scatter_gather`] waits for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Quorum {
+    /// Every call of the batch
+    #[default]
+    All,
+    /// More than half of the calls of the batch
+    Majority,
+    /// At least this number of calls
+    AtLeast(usize),
+}
+
+impl Quorum {
+    /// Number of successful calls required out of `num_calls`
+    pub fn required(self, num_calls: usize) -> usize {
+        match self {
+            Quorum::All => num_calls,
+            Quorum::Majority => num_calls / 2 + 1,
+            Quorum::AtLeast(n) => n,
         }
+    }
+}
 
-        if !c.is_lowercase() {
-            warn!(
-                "URI '{}' contains a non lower case character @ index {}",
-                uri, i
+/// Results collected by [`crate::Client::scatter_gather`], along with the index of their call in
+/// the batch
+#[derive(Debug, Default)]
+pub struct Gathered {
+    /// Results of the calls that succeeded, in the order they completed
+    pub results: Vec<(usize, CallResult)>,
+    /// Errors of the calls that failed before the quorum was reached
+    pub errors: Vec<(usize, WampError)>,
+}
+
+/// Value of a `timeout` option, in milliseconds rounded up as 0 disables the timeout
+fn timeout_millis(timeout: Duration) -> WampInteger {
+    ((timeout.as_micros() + 999) / 1000).max(1) as WampInteger
+}
+
+/// How a registered URI (or subscribed topic) is matched by the router
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchPolicy {
+    /// The URI must be identical
+    #[default]
+    Exact,
+    /// The URI must start with the registered URI (e.g. `com.myapp.` matches `com.myapp.echo`)
+    Prefix,
+    /// Empty components of the registered URI match any component (e.g. `com..echo`)
+    Wildcard,
+}
+
+impl MatchPolicy {
+    /// Value of the `match` option, `None` for the default exact matching
+    pub(crate) fn to_str(self) -> Option<&'static str> {
+        match self {
+            MatchPolicy::Exact => None,
+            MatchPolicy::Prefix => Some("prefix"),
+            MatchPolicy::Wildcard => Some("wildcard"),
+        }
+    }
+}
+
+/// Options used when calling an RPC endpoint
+///
+/// ```
+/// # use std::time::Duration;
+/// # use wamp_async::CallOptions;
+/// // Only the callee serving the shard of "customer-42" will be invoked
+/// let options = CallOptions::new()
+///     .with_rkey("customer-42")
+///     .with_timeout(Duration::from_secs(5));
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct CallOptions {
+    rkey: Option<WampString>,
+    idempotency_key: Option<WampString>,
+    timeout: Option<Duration>,
+    receive_progress: bool,
+}
+
+impl CallOptions {
+    /// Creates the default call options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Routes the call to the shard serving the routing key `rkey` (partitioned call)
+    pub fn with_rkey<T: Into<String>>(mut self, rkey: T) -> Self {
+        self.rkey = Some(rkey.into());
+        self
+    }
+
+    /// Sends `key` in the [`IDEMPOTENCY_KEY_KWARG`] keyword argument so the callee can detect
+    /// retries. When an [`IdempotencyCache`] is configured, a call with a key that already
+    /// succeeded returns the cached result instead of being sent again.
+    pub fn with_idempotency_key<T: Into<String>>(mut self, key: T) -> Self {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+
+    /// Returns the idempotency key of the call
+    pub fn get_idempotency_key(&self) -> Option<&str> {
+        self.idempotency_key.as_deref()
+    }
+
+    /// Asks the dealer to cancel the call if it does not complete within `timeout`. The result
+    /// is not waited for longer than that.
+    ///
+    /// Calls made while handling an invocation with a timeout are given at most the time left
+    /// before the deadline of the invocation (see [`invocation_deadline`]).
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Returns the timeout of the call
+    pub fn get_timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Asks the callee to send progressive results before the final one. They are received
+    /// with [`crate::CallHandle::progress`] and ignored by the other ways of calling.
+    pub fn with_receive_progress(mut self, receive_progress: bool) -> Self {
+        self.receive_progress = receive_progress;
+        self
+    }
+
+    /// Returns whether progressive results are asked for
+    pub fn get_receive_progress(&self) -> bool {
+        self.receive_progress
+    }
+
+    /// Reduces the timeout to the time left before the deadline of the invocation being
+    /// handled, if any
+    pub(crate) fn with_invocation_deadline(mut self) -> Self {
+        if let Some(deadline) = invocation_deadline() {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            self.timeout = Some(match self.timeout {
+                Some(timeout) => timeout.min(remaining),
+                None => remaining,
+            });
+        }
+        self
+    }
+
+    /// Options sent in the CALL message
+    pub(crate) fn to_dict(&self) -> WampDict {
+        let mut options = WampDict::new();
+        if let Some(rkey) = &self.rkey {
+            options.insert("runmode".to_string(), Arg::String("partition".to_string()));
+            options.insert("rkey".to_string(), Arg::String(rkey.clone()));
+        }
+        if let Some(timeout) = self.timeout {
+            options.insert("timeout".to_string(), Arg::Integer(timeout_millis(timeout)));
+        }
+        if self.receive_progress {
+            options.insert("receive_progress".to_string(), Arg::Bool(true));
+        }
+        options
+    }
+}
+
+/// How the dealer cancels a call, see [`crate::CallHandle::cancel`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CancelMode {
+    /// The call fails right away, the callee is not told and its result is discarded
+    Skip,
+    /// The callee is interrupted and the call fails once the callee acknowledges it
+    #[default]
+    Kill,
+    /// The callee is interrupted and the call fails right away
+    KillNoWait,
+}
+
+impl CancelMode {
+    /// Value of the `mode` option of the CANCEL message
+    pub(crate) fn to_str(self) -> &'static str {
+        match self {
+            CancelMode::Skip => "skip",
+            CancelMode::Kill => "kill",
+            CancelMode::KillNoWait => "killnowait",
+        }
+    }
+}
+
+tokio::task_local! {
+    static INVOCATION_DEADLINE: Instant;
+}
+
+/// Returns when the invocation being handled times out, if the dealer forwarded the timeout
+/// of the call
+///
+/// The deadline is known by the future returned by an RPC function, not by the tasks it
+/// spawns.
+pub fn invocation_deadline() -> Option<Instant> {
+    INVOCATION_DEADLINE.try_with(|deadline| *deadline).ok()
+}
+
+/// Runs `func` within the deadline given by the `timeout` of the invocation details
+pub(crate) fn with_invocation_deadline<'a>(
+    details: &WampDict,
+    func: RpcFuture<'a>,
+) -> RpcFuture<'a> {
+    let millis = match details.get("timeout") {
+        Some(Arg::Id(millis)) => NonZeroU64::from(*millis).get(),
+        Some(Arg::Integer(millis)) => *millis as u64,
+        _ => 0,
+    };
+    // 0 means no timeout
+    if millis == 0 {
+        return func;
+    }
+
+    let deadline = Instant::now() + Duration::from_millis(millis);
+    Box::pin(INVOCATION_DEADLINE.scope(deadline, func))
+}
+
+/// Options used when publishing an event
+///
+/// ```
+/// # use wamp_async::PublishOptions;
+/// let options = PublishOptions::new()
+///     .with_acknowledge(true)
+///     .with_rkey("customer-42")
+///     .with_eligible_authrole(["backend"]);
+/// ```
+///
+/// A `bool` converts into options only asking for an acknowledge, as accepted by
+/// [`crate::Client::publish`].
+#[derive(Debug, Default, Clone)]
+pub struct PublishOptions {
+    acknowledge: bool,
+    rkey: Option<WampString>,
+    retain: bool,
+    local_echo: bool,
+    idempotency_key: Option<WampString>,
+    exclude_me: Option<bool>,
+    exclude: Vec<WampId>,
+    exclude_authid: Vec<WampString>,
+    exclude_authrole: Vec<WampString>,
+    /// White-lists are sent as soon as they are set, even empty
+    eligible: Option<Vec<WampId>>,
+    eligible_authid: Option<Vec<WampString>>,
+    eligible_authrole: Option<Vec<WampString>>,
+}
+
+impl From<bool> for PublishOptions {
+    fn from(acknowledge: bool) -> Self {
+        Self::new().with_acknowledge(acknowledge)
+    }
+}
+
+impl PublishOptions {
+    /// Creates the default publish options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the broker to acknowledge the publication with its ID
+    pub fn with_acknowledge(mut self, acknowledge: bool) -> Self {
+        self.acknowledge = acknowledge;
+        self
+    }
+
+    /// Delivers the event to the subscribers of the shard serving the routing key `rkey`
+    /// (sharded subscription)
+    pub fn with_rkey<T: Into<String>>(mut self, rkey: T) -> Self {
+        self.rkey = Some(rkey.into());
+        self
+    }
+
+    /// Asks the broker to keep the event as the latest one of the topic, so it can be sent to
+    /// future subscribers (see [`SubscribeOptions::with_get_retained`])
+    pub fn with_retain(mut self, retain: bool) -> Self {
+        self.retain = retain;
+        self
+    }
+
+    /// Also delivers the event right away to the subscriptions of this client on the exact
+    /// same topic, without waiting for the broker. The local copy carries a publication ID
+    /// generated by the client.
+    ///
+    /// The event is not echoed when the black or white-listing options exclude this session,
+    /// nor with a routing key (see [`PublishOptions::with_rkey`]) as the broker picks the
+    /// subscribers of the shard.
+    pub fn with_local_echo(mut self, local_echo: bool) -> Self {
+        self.local_echo = local_echo;
+        self
+    }
+
+    /// Sends `key` in the [`IDEMPOTENCY_KEY_KWARG`] keyword argument so subscribers can detect
+    /// retries. When an [`IdempotencyCache`] is configured, a publication with a key that was
+    /// already published is not sent again.
+    pub fn with_idempotency_key<T: Into<String>>(mut self, key: T) -> Self {
+        self.idempotency_key = Some(key.into());
+        self
+    }
+
+    /// Whether the broker skips the subscriptions of the publishing session, which it does
+    /// by default (publisher exclusion). With `false`, local echo is disabled as the event
+    /// is already received from the broker.
+    pub fn with_exclude_me(mut self, exclude_me: bool) -> Self {
+        self.exclude_me = Some(exclude_me);
+        self
+    }
+
+    /// Does not deliver the event to the given sessions (subscriber black-listing)
+    pub fn with_exclude<I: IntoIterator<Item = WampId>>(mut self, sessions: I) -> Self {
+        self.exclude.extend(sessions);
+        self
+    }
+
+    /// Does not deliver the event to the sessions authenticated with the given authids
+    pub fn with_exclude_authid<I, T>(mut self, authids: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.exclude_authid
+            .extend(authids.into_iter().map(Into::into));
+        self
+    }
+
+    /// Does not deliver the event to the sessions authenticated with the given authroles
+    pub fn with_exclude_authrole<I, T>(mut self, authroles: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.exclude_authrole
+            .extend(authroles.into_iter().map(Into::into));
+        self
+    }
+
+    /// Only delivers the event to the given sessions (subscriber white-listing). An empty
+    /// list delivers the event to no one.
+    pub fn with_eligible<I: IntoIterator<Item = WampId>>(mut self, sessions: I) -> Self {
+        self.eligible.get_or_insert_with(Vec::new).extend(sessions);
+        self
+    }
+
+    /// Only delivers the event to the sessions authenticated with the given authids
+    pub fn with_eligible_authid<I, T>(mut self, authids: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.eligible_authid
+            .get_or_insert_with(Vec::new)
+            .extend(authids.into_iter().map(Into::into));
+        self
+    }
+
+    /// Only delivers the event to the sessions authenticated with the given authroles
+    pub fn with_eligible_authrole<I, T>(mut self, authroles: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<String>,
+    {
+        self.eligible_authrole
+            .get_or_insert_with(Vec::new)
+            .extend(authroles.into_iter().map(Into::into));
+        self
+    }
+
+    /// Returns whether the publication is acknowledged by the broker
+    pub fn get_acknowledge(&self) -> bool {
+        self.acknowledge
+    }
+
+    /// Returns whether the event is delivered to the local subscriptions
+    pub fn get_local_echo(&self) -> bool {
+        self.local_echo
+    }
+
+    /// Returns whether the publishing session is excluded from the receivers of the event
+    pub fn get_exclude_me(&self) -> bool {
+        self.exclude_me.unwrap_or(true)
+    }
+
+    /// Returns the idempotency key of the publication
+    pub fn get_idempotency_key(&self) -> Option<&str> {
+        self.idempotency_key.as_deref()
+    }
+
+    /// Whether the event is delivered to the local subscriptions of `session`, which the
+    /// broker would otherwise have to select as a receiver
+    pub(crate) fn local_echo_to(&self, session: &SessionInfo) -> bool {
+        if !self.local_echo || !self.get_exclude_me() || self.rkey.is_some() {
+            return false;
+        }
+        let listed = |list: &[WampString], value: &Option<WampString>| {
+            value.as_ref().is_some_and(|v| list.contains(v))
+        };
+        let excluded = self.exclude.contains(&session.session_id)
+            || listed(&self.exclude_authid, &session.authid)
+            || listed(&self.exclude_authrole, &session.authrole);
+        let eligible = self
+            .eligible
+            .as_ref()
+            .map_or(true, |e| e.contains(&session.session_id))
+            && self
+                .eligible_authid
+                .as_ref()
+                .map_or(true, |e| listed(e, &session.authid))
+            && self
+                .eligible_authrole
+                .as_ref()
+                .map_or(true, |e| listed(e, &session.authrole));
+        !excluded && eligible
+    }
+
+    /// Options sent in the PUBLISH message
+    pub(crate) fn to_dict(&self) -> WampDict {
+        let mut options = WampDict::new();
+        if self.acknowledge {
+            options.insert("acknowledge".to_string(), Arg::Bool(true));
+        }
+        if let Some(rkey) = &self.rkey {
+            options.insert("rkey".to_string(), Arg::String(rkey.clone()));
+        }
+        if self.retain {
+            options.insert("retain".to_string(), Arg::Bool(true));
+        }
+        if let Some(exclude_me) = self.exclude_me {
+            options.insert("exclude_me".to_string(), Arg::Bool(exclude_me));
+        }
+
+        let sessions = |ids: &[WampId]| Arg::List(ids.iter().map(|id| Arg::Id(*id)).collect());
+        let strings = |s: &[WampString]| Arg::List(s.iter().cloned().map(Arg::String).collect());
+        if !self.exclude.is_empty() {
+            options.insert("exclude".to_string(), sessions(&self.exclude));
+        }
+        if !self.exclude_authid.is_empty() {
+            options.insert("exclude_authid".to_string(), strings(&self.exclude_authid));
+        }
+        if !self.exclude_authrole.is_empty() {
+            options.insert(
+                "exclude_authrole".to_string(),
+                strings(&self.exclude_authrole),
             );
+        }
+        if let Some(eligible) = &self.eligible {
+            options.insert("eligible".to_string(), sessions(eligible));
+        }
+        if let Some(eligible_authid) = &self.eligible_authid {
+            options.insert("eligible_authid".to_string(), strings(eligible_authid));
+        }
+        if let Some(eligible_authrole) = &self.eligible_authrole {
+            options.insert("eligible_authrole".to_string(), strings(eligible_authrole));
+        }
+        options
+    }
+}
+
+/// Options used when subscribing to a topic
+///
+/// ```
+/// # use wamp_async::{MatchPolicy, SubscribeOptions};
+/// let options = SubscribeOptions::new().with_get_retained(true);
+/// // Events published on any topic starting with "com.myapp." are received, the `topic` detail
+/// // of each event tells which one
+/// let options = SubscribeOptions::new().with_match(MatchPolicy::Prefix);
+/// ```
+#[derive(Debug, Default, Clone)]
+pub struct SubscribeOptions {
+    get_retained: bool,
+    match_policy: MatchPolicy,
+}
+
+impl SubscribeOptions {
+    /// Creates the default subscribe options
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks the broker to send the retained event of the topic, if any, right after subscribing
+    pub fn with_get_retained(mut self, get_retained: bool) -> Self {
+        self.get_retained = get_retained;
+        self
+    }
+
+    /// Sets how the broker matches the topics of publications against the subscribed topic
+    pub fn with_match(mut self, match_policy: MatchPolicy) -> Self {
+        self.match_policy = match_policy;
+        self
+    }
+
+    /// Returns how the broker matches the topics of publications against the subscribed topic
+    pub fn get_match(&self) -> MatchPolicy {
+        self.match_policy
+    }
+
+    /// Options sent in the SUBSCRIBE message
+    pub(crate) fn to_dict(&self) -> WampDict {
+        let mut options = WampDict::new();
+        if self.get_retained {
+            options.insert("get_retained".to_string(), Arg::Bool(true));
+        }
+        if let Some(match_policy) = self.match_policy.to_str() {
+            options.insert("match".to_string(), Arg::String(match_policy.to_string()));
+        }
+        options
+    }
+}
+
+/// Keyword argument of the ERROR sent for a failed invocation when
+/// [`crate::ClientConfig::set_error_context`] is enabled
+///
+/// Its value is a dictionary with :
+/// - `chain` : the message of the handler's error followed by the ones of its sources
+/// - `procedure` : the procedure that was called
+/// - `duration_ms` : how long the handler ran, in milliseconds
+pub const ERROR_CONTEXT_KWARG: &str = "wamp_async.error_context";
+
+/// Keyword argument carrying the idempotency key of calls and publications
+pub const IDEMPOTENCY_KEY_KWARG: &str = "idempotency_key";
+
+/// Adds the idempotency key to the keyword arguments, failing if they already hold one
+pub(crate) fn with_idempotency_key(
+    arguments_kw: Option<WampKwArgs>,
+    key: Option<&str>,
+) -> Result<Option<WampKwArgs>, WampError> {
+    let key = match key {
+        Some(k) => k,
+        None => return Ok(arguments_kw),
+    };
+    let mut arguments_kw = arguments_kw.unwrap_or_default();
+    if arguments_kw.contains_key(IDEMPOTENCY_KEY_KWARG) {
+        return Err(WampError::InvalidArgument(format!(
+            "The keyword arguments already hold an '{}' while an idempotency key is set",
+            IDEMPOTENCY_KEY_KWARG
+        )));
+    }
+    arguments_kw.insert(IDEMPOTENCY_KEY_KWARG.to_string(), key.into());
+    Ok(Some(arguments_kw))
+}
+
+/// Kind of request sent with an idempotency key, each kind has its own keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(crate) enum IdempotentRequest {
+    Call,
+    Publish,
+}
+
+/// Result of a call or publication that succeeded with an idempotency key
+#[derive(Clone)]
+pub(crate) enum IdempotentResult {
+    Call(Option<WampArgs>, Option<WampKwArgs>),
+    Publish(Option<WampId>),
+}
+
+/// What to do with a request sent with an idempotency key
+pub(crate) enum Idempotency {
+    /// The request already succeeded with this result
+    Done(IdempotentResult),
+    /// The request must be sent, its result is stored with [`InFlight::succeeded`]
+    Send(InFlight),
+}
+
+/// Request sent with an idempotency key, the duplicates sent meanwhile wait for its result
+///
+/// When dropped before succeeding, the duplicates are sent in its place.
+pub(crate) struct InFlight {
+    cache: IdempotencyCache,
+    key: Option<(IdempotentRequest, String)>,
+}
+
+impl InFlight {
+    /// Stores the result of the request and hands it to the duplicates waiting for it
+    pub(crate) fn succeeded(mut self, result: IdempotentResult) {
+        if let Some(key) = self.key.take() {
+            self.cache.complete(key, Some(result));
+        }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.cache.complete(key, None);
+        }
+    }
+}
+
+/// Remembers the results of the latest calls and publications sent with an idempotency key
+///
+/// Calls and publications have their own keys. A request sent while another one with the same
+/// key is in flight waits for its result instead of being sent, and is only sent if it fails.
+///
+/// The cache can be shared between clients (e.g. the client created after a reconnect) by
+/// passing clones of it to [`ClientConfig::set_idempotency_cache`](struct.ClientConfig.html#method.set_idempotency_cache).
+///
+/// ```
+/// # use wamp_async::{ClientConfig, IdempotencyCache};
+/// let cache = IdempotencyCache::new(1024);
+/// let config = ClientConfig::default().set_idempotency_cache(cache.clone());
+/// ```
+#[derive(Clone)]
+pub struct IdempotencyCache {
+    inner: Arc<Mutex<IdempotencyCacheInner>>,
+}
+
+struct IdempotencyCacheInner {
+    capacity: usize,
+    order: VecDeque<(IdempotentRequest, String)>,
+    results: HashMap<(IdempotentRequest, String), IdempotentResult>,
+    /// Duplicates waiting for the result of the requests in flight
+    in_flight: HashMap<(IdempotentRequest, String), Vec<oneshot::Sender<()>>>,
+}
+
+impl IdempotencyCache {
+    /// Creates a cache holding the results of up to `capacity` keys (at least 1)
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(IdempotencyCacheInner {
+                capacity: capacity.max(1),
+                order: VecDeque::new(),
+                results: HashMap::new(),
+                in_flight: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Returns the number of keys in the cache
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().results.len()
+    }
+
+    /// Returns whether the cache is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the result of the request if it already succeeded, waiting for the one in
+    /// flight with the same key if any. Otherwise the request must be sent.
+    pub(crate) async fn begin(&self, request: IdempotentRequest, key: &str) -> Idempotency {
+        let key = (request, key.to_string());
+        loop {
+            let done = {
+                let mut inner = self.inner.lock().unwrap();
+                if let Some(result) = inner.results.get(&key) {
+                    return Idempotency::Done(result.clone());
+                }
+                match inner.in_flight.get_mut(&key) {
+                    Some(waiting) => {
+                        let (tx, rx) = oneshot::channel();
+                        waiting.push(tx);
+                        rx
+                    }
+                    None => {
+                        inner.in_flight.insert(key.clone(), Vec::new());
+                        return Idempotency::Send(InFlight {
+                            cache: self.clone(),
+                            key: Some(key),
+                        });
+                    }
+                }
+            };
+            // Whether it succeeded or not, the cache tells what to do next
+            let _ = done.await;
+        }
+    }
+
+    /// Ends the request in flight with `key`, storing its result if it succeeded
+    fn complete(&self, key: (IdempotentRequest, String), result: Option<IdempotentResult>) {
+        let mut inner = self.inner.lock().unwrap();
+        let waiting = inner.in_flight.remove(&key).unwrap_or_default();
+        if let Some(result) = result {
+            if inner.results.insert(key.clone(), result).is_none() {
+                inner.order.push_back(key);
+                if inner.order.len() > inner.capacity {
+                    if let Some(oldest) = inner.order.pop_front() {
+                        inner.results.remove(&oldest);
+                    }
+                }
+            }
+        }
+        for tx in waiting {
+            let _ = tx.send(());
+        }
+    }
+}
+
+/// Tokens a router returns in the WELCOME details that must be sent back in the HELLO of the
+/// following sessions, e.g. for sticky routing behind a load balancer
+///
+/// Only the keys given to [`RoutingTokens::new`] are remembered. The tokens can be kept across
+/// reconnects by passing clones of it to
+/// [`ClientConfig::set_routing_tokens`](struct.ClientConfig.html#method.set_routing_tokens).
+///
+/// ```
+/// # use wamp_async::{ClientConfig, RoutingTokens};
+/// let tokens = RoutingTokens::new(vec!["cookie", "node"]);
+/// let config = ClientConfig::default().set_routing_tokens(tokens.clone());
+/// ```
+#[derive(Clone)]
+pub struct RoutingTokens {
+    inner: Arc<Mutex<RoutingTokensInner>>,
+}
+
+struct RoutingTokensInner {
+    keys: Vec<String>,
+    tokens: WampDict,
+}
+
+impl RoutingTokens {
+    /// Remembers the WELCOME details named `keys`
+    pub fn new<T: Into<String>>(keys: Vec<T>) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(RoutingTokensInner {
+                keys: keys.into_iter().map(Into::into).collect(),
+                tokens: WampDict::new(),
+            })),
+        }
+    }
+
+    /// Returns the token received for `key`
+    pub fn get(&self, key: &str) -> Option<Arg> {
+        self.inner.lock().unwrap().tokens.get(key).cloned()
+    }
+
+    /// Forgets the tokens received, the next HELLO is sent without them
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().tokens.clear();
+    }
+
+    /// Remembers the tokens of a WELCOME. Tokens missing from it are kept.
+    pub(crate) fn update(&self, welcome: &WampDict) {
+        let mut inner = self.inner.lock().unwrap();
+        let RoutingTokensInner { keys, tokens } = &mut *inner;
+        for key in keys.iter() {
+            if let Some(token) = welcome.get(key) {
+                tokens.insert(key.clone(), token.clone());
+            }
+        }
+    }
+
+    /// Adds the tokens to the details of a HELLO, without replacing the ones already set
+    pub(crate) fn add_to(&self, hello: &mut WampDict) {
+        for (key, token) in &self.inner.lock().unwrap().tokens {
+            hello.entry(key.clone()).or_insert_with(|| token.clone());
+        }
+    }
+}
+
+/// Returns whether a uri is valid or not (using strict rules)
+pub fn is_valid_strict_uri<T: AsRef<str>>(in_uri: T) -> bool {
+    is_valid_strict_uri_pattern(in_uri, MatchPolicy::Exact)
+}
+
+/// Returns whether a uri is valid using strict rules, for the way the router matches it
+///
+/// Components are made of lower case letters, digits and `_`. Empty components are only
+/// allowed in wildcard patterns, and a trailing dot only in prefixes :
+/// ```
+/// # use wamp_async::{is_valid_strict_uri_pattern, MatchPolicy};
+/// assert!(is_valid_strict_uri_pattern("peer..9", MatchPolicy::Wildcard));
+/// assert!(!is_valid_strict_uri_pattern("peer..9", MatchPolicy::Exact));
+/// assert!(is_valid_strict_uri_pattern("com.myapp.", MatchPolicy::Prefix));
+/// assert!(!is_valid_strict_uri_pattern("com..myapp", MatchPolicy::Prefix));
+/// ```
+pub fn is_valid_strict_uri_pattern<T: AsRef<str>>(in_uri: T, match_policy: MatchPolicy) -> bool {
+    let uri: &str = in_uri.as_ref();
+    if uri.starts_with("wamp.") {
+        warn!(target: CLIENT, "URI '{}' cannot start with 'wamp'", uri);
+        return false;
+    }
+
+    let components: Vec<&str> = uri.split('.').collect();
+    let last = components.len() - 1;
+    for (i, component) in components.iter().enumerate() {
+        let may_be_empty = match match_policy {
+            MatchPolicy::Exact => false,
+            MatchPolicy::Prefix => i == last && i > 0,
+            MatchPolicy::Wildcard => true,
+        };
+        if component.is_empty() && !may_be_empty {
+            warn!(target: CLIENT, "URI '{}' contains a zero length component @ {}", uri, i);
             return false;
         }
-        if !c.is_alphanumeric() {
-            warn!("URI '{}' contains an invalid character @ index {}", uri, i);
+        if let Some(c) = component
+            .chars()
+            .find(|c| !matches!(c, 'a'..='z' | '0'..='9' | '_'))
+        {
+            warn!(target: CLIENT, "URI '{}' contains an invalid character '{}'", uri, c);
             return false;
         }
     }
 
+    if components.iter().all(|c| c.is_empty()) {
+        warn!(target: CLIENT, "URI '{}' only contains zero length components", uri);
+        return false;
+    }
+
     true
 }
 
@@ -301,15 +1793,50 @@ pub fn is_valid_strict_uri<T: AsRef<str>>(in_uri: T) -> bool {
 pub type GenericFuture<'a> = Pin<Box<dyn Future<Output = Result<(), WampError>> + Send + 'a>>;
 /// Type returned by RPC functions
 pub type RpcFuture<'a> = std::pin::Pin<
-    Box<
-        dyn std::future::Future<Output = Result<(Option<WampArgs>, Option<WampKwArgs>), WampError>>
-            + Send
-            + 'a,
-    >,
+    Box<dyn std::future::Future<Output = Result<RpcResponse, WampError>> + Send + 'a>,
 >;
 /// Generic function that can receive RPC calls
 pub type RpcFunc<'a> =
     Box<dyn Fn(Option<WampArgs>, Option<WampKwArgs>) -> RpcFuture<'a> + Send + Sync + 'a>;
+/// RPC function as stored by the event loop, also receiving the details of the invocation
+pub(crate) type InvocationFunc<'a> =
+    Arc<dyn Fn(WampDict, Option<WampArgs>, Option<WampKwArgs>) -> RpcFuture<'a> + Send + Sync + 'a>;
+
+/// How the event loop handles the invocations of a registered procedure
+#[derive(Clone)]
+pub(crate) enum RpcEndpoint<'a> {
+    /// Runs the function, through the RPC event queue
+    Func(InvocationFunc<'a>),
+    /// Hands the invocations to the stream of `Client::invocations()`
+    Stream,
+    /// Runs the function within the event loop
+    Inline(InlineFunc<'a>),
+}
+
+/// RPC function run within the event loop, see `Client::register_inline()`
+pub(crate) type InlineFunc<'a> = Arc<
+    dyn Fn(Option<WampArgs>, Option<WampKwArgs>) -> Result<RpcResponse, WampError>
+        + Send
+        + Sync
+        + 'a,
+>;
+
+/// Wraps an RPC endpoint of the user into the function stored by the event loop
+pub(crate) fn invocation_func<'a, F, Fut, R>(func_ptr: F) -> InvocationFunc<'a>
+where
+    F: Fn(Option<WampArgs>, Option<WampKwArgs>) -> Fut + Send + Sync + 'a,
+    Fut: Future<Output = Result<R, WampError>> + Send + 'a,
+    R: Into<RpcResponse>,
+{
+    Arc::new(move |_details, arguments, arguments_kw| {
+        Box::pin(func_ptr(arguments, arguments_kw).map(|r| r.map(Into::into)))
+    })
+}
+
+/// Validates the payload of an invocation or event before it is handed to user code.
+/// On failure, returns a description of why the payload was rejected.
+pub type PayloadValidator =
+    Arc<dyn Fn(Option<&WampArgs>, Option<&WampKwArgs>) -> Result<(), String> + Send + Sync>;
 
 /// Authentication Challenge function that should handle a CHALLENGE request during authentication flow.
 /// See more details in [`crate::Client::join_realm_with_authentication`]
@@ -327,3 +1854,62 @@ pub type AuthenticationChallengeHandler<'a> = Box<
         + Sync
         + 'a,
 >;
+
+/// Returns the token (e.g. a JWT) presented for ticket authentication, see
+/// [`crate::ClientConfig::set_token_provider`]
+pub type TokenProvider = Arc<
+    dyn Fn() -> std::pin::Pin<
+            Box<dyn std::future::Future<Output = Result<String, WampError>> + Send + 'static>,
+        > + Send
+        + Sync,
+>;
+
+/// Vendor extension hook for the RawSocket handshake.
+///
+/// Receives the 4 bytes sent by the client and the 4 bytes replied by the server. When set,
+/// the hook is responsible for validating the reserved octets (which must otherwise be 0).
+pub type RawSocketHandshakeHook =
+    Arc<dyn Fn(&[u8; 4], &[u8; 4]) -> Result<(), TransportError> + Send + Sync>;
+
+/// Receives the raw frames that could not be deserialized along with the error, to debug
+/// interoperability issues with non-conformant routers.
+pub type MalformedFrameHook = Arc<dyn Fn(&[u8], &WampError) + Send + Sync>;
+
+/// What the event loop was handling when it was timed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DispatchSource {
+    /// A message received from the router
+    Peer,
+    /// A request made through the [`crate::Client`]
+    Local,
+}
+
+/// Time spent by the event loop handling a single message or request
+#[derive(Debug, Clone)]
+pub struct DispatchTiming {
+    pub source: DispatchSource,
+    /// Type of the message (e.g. `EVENT`) or of the request (e.g. `call`)
+    pub kind: &'static str,
+    pub elapsed: Duration,
+}
+
+/// Receives the time spent by the event loop on each message and request, to find out which
+/// ones dominate the processing time. The hook runs on the event loop and must return quickly.
+pub type DispatchTimingHook = Arc<dyn Fn(&DispatchTiming) + Send + Sync>;
+
+/// Renews the configuration of a session the router logged out (see
+/// [`SessionCloseReason::is_forced_logout`]) before [`crate::CallerPool`] reconnects it, e.g.
+/// with a new TLS identity. Receives the configuration used so far.
+pub type ReauthenticationHook = Arc<
+    dyn Fn(
+            SessionCloseReason,
+            crate::ClientConfig,
+        ) -> std::pin::Pin<
+            Box<
+                dyn std::future::Future<Output = Result<crate::ClientConfig, WampError>>
+                    + Send
+                    + 'static,
+            >,
+        > + Send
+        + Sync,
+>;
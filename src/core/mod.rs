@@ -1,12 +1,26 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
+use futures::Stream;
 use log::*;
 use tokio::select;
-use tokio::sync::oneshot::Sender;
-use tokio::sync::{mpsc, mpsc::UnboundedReceiver, mpsc::UnboundedSender};
+use tokio::sync::mpsc::error::{SendError, TrySendError};
+use tokio::sync::oneshot::{self, Sender};
+use tokio::sync::{
+    broadcast, mpsc, mpsc::UnboundedReceiver, mpsc::UnboundedSender, watch, OwnedSemaphorePermit,
+    Semaphore,
+};
 
+use crate::codec::{encode_response, PayloadCodec};
 use crate::common::*;
 use crate::error::*;
+use crate::extensions::Extensions;
+use crate::journal::{EventJournal, JournaledEvent};
+use crate::log_targets::{AUTH, CORE, PAYLOAD};
 use crate::serializer::*;
 use crate::transport::*;
 
@@ -14,7 +28,9 @@ mod recv;
 mod send;
 
 use crate::client;
+use crate::invocation::Invocation;
 use crate::message::*;
+pub(crate) use recv::error_context;
 pub use send::Request;
 
 pub enum Status {
@@ -23,20 +39,155 @@ pub enum Status {
     Ok,
 }
 
+impl Status {
+    /// Status after failing to send a request. A message refused for its size was not
+    /// written so the connection is still usable.
+    pub fn after_send_error(e: &WampError) -> Self {
+        match e {
+            WampError::ConnectionError(TransportError::MessageTooLarge(..)) => Status::Ok,
+            _ => Status::Shutdown,
+        }
+    }
+}
+
 pub type JoinResult = Sender<
     Result<
         (
             WampId,                   // Session ID
-            HashMap<WampString, Arg>, // Server roles
+            HashMap<WampString, Arg>, // Details of the WELCOME
         ),
         WampError,
     >,
 >;
-pub type SubscriptionQueue = UnboundedReceiver<(
-    WampId,           // Publish event ID
-    Option<WampArgs>, // Publish args
-    Option<WampKwArgs>,
-)>; // publish kwargs
+/// Answer of the challenge handler to a CHALLENGE
+type ChallengeFuture<'a> = std::pin::Pin<
+    Box<
+        dyn std::future::Future<Output = Result<AuthenticationChallengeResponse, WampError>>
+            + Send
+            + 'a,
+    >,
+>;
+
+/// Join handshake waiting for the server or the challenge handler
+struct PendingJoin<'a> {
+    on_challenge_handler: Option<AuthenticationChallengeHandler<'a>>,
+    res: JoinResult,
+    /// Answer to the CHALLENGE being computed
+    challenge: Option<ChallengeFuture<'a>>,
+    /// Whether the router already sent a CHALLENGE
+    challenged: bool,
+    /// When the current step of the handshake times out
+    deadline: Option<tokio::time::Instant>,
+}
+
+pub type QueuedEvent = (
+    WampId,             // Publish event ID
+    Option<WampArgs>,   // Publish args
+    Option<WampKwArgs>, // Publish kwargs
+    WampDict,           // Event details
+);
+
+/// Unbounded queue of the events of a subscription, see [`crate::Client::subscribe`]
+///
+/// The queue counts the events waiting to be read, so its depth is reported by
+/// [`crate::Client::queue_stats`].
+pub struct SubscriptionQueue {
+    queue: UnboundedReceiver<QueuedEvent>,
+    depth: Arc<AtomicUsize>,
+}
+
+impl SubscriptionQueue {
+    /// Receives the next event, `None` once the subscription is closed
+    pub async fn recv(&mut self) -> Option<QueuedEvent> {
+        let evt = self.queue.recv().await;
+        self.received(evt)
+    }
+
+    /// Receives the next event if one is waiting
+    pub fn try_recv(&mut self) -> Result<QueuedEvent, mpsc::error::TryRecvError> {
+        let evt = self.queue.try_recv()?;
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+        Ok(evt)
+    }
+
+    /// Receives the next event, blocking the current thread. Panics within an async context.
+    pub fn blocking_recv(&mut self) -> Option<QueuedEvent> {
+        let evt = self.queue.blocking_recv();
+        self.received(evt)
+    }
+
+    /// Polls for the next event, see [`UnboundedReceiver::poll_recv`]
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<QueuedEvent>> {
+        self.queue.poll_recv(cx).map(|evt| self.received(evt))
+    }
+
+    /// Closes the queue, the events already queued can still be received
+    pub fn close(&mut self) {
+        self.queue.close()
+    }
+
+    /// Returns the number of events waiting to be read
+    pub fn len(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether no event is waiting to be read
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn received(&self, evt: Option<QueuedEvent>) -> Option<QueuedEvent> {
+        if evt.is_some() {
+            self.depth.fetch_sub(1, Ordering::Relaxed);
+        }
+        evt
+    }
+}
+
+impl Stream for SubscriptionQueue {
+    type Item = QueuedEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.poll_recv(cx)
+    }
+}
+
+impl std::fmt::Debug for SubscriptionQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SubscriptionQueue")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// Sending half of a [`SubscriptionQueue`], held by the event loop
+pub(crate) struct SubscriptionQueueSender {
+    queue: UnboundedSender<QueuedEvent>,
+    depth: Arc<AtomicUsize>,
+}
+
+impl SubscriptionQueueSender {
+    /// Queues an event, returns false if the queue is closed
+    fn send(&self, evt: QueuedEvent) -> bool {
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        if self.queue.send(evt).is_err() {
+            self.depth.fetch_sub(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+}
+
+/// Creates the queue of the events of a subscription
+pub(crate) fn subscription_queue() -> (SubscriptionQueueSender, SubscriptionQueue) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let depth = Arc::new(AtomicUsize::new(0));
+    let sender = SubscriptionQueueSender {
+        queue: tx,
+        depth: depth.clone(),
+    };
+    (sender, SubscriptionQueue { queue: rx, depth })
+}
 pub type PendingSubResult = Sender<
     Result<
         (
@@ -46,6 +197,454 @@ pub type PendingSubResult = Sender<
         WampError,
     >,
 >;
+pub type BroadcastSubscription = broadcast::Receiver<(
+    WampId,             // Publish event ID
+    Option<WampArgs>,   // Publish args
+    Option<WampKwArgs>, // Publish kwargs
+    WampDict,           // Event details
+)>;
+pub type BroadcastSubscriptionSender = broadcast::Sender<(
+    WampId,             // Publish event ID
+    Option<WampArgs>,   // Publish args
+    Option<WampKwArgs>, // Publish kwargs
+    WampDict,           // Event details
+)>;
+pub type PendingBroadcastSubResult = Sender<
+    Result<
+        (
+            WampId,                // Subcription ID
+            BroadcastSubscription, // Receiver for incoming events
+        ),
+        WampError,
+    >,
+>;
+pub type BoundedSubscription = mpsc::Receiver<SubscriptionItem>;
+pub type PendingBoundedSubResult = Sender<
+    Result<
+        (
+            WampId,              // Subcription ID
+            BoundedSubscription, // Queue for incoming events
+        ),
+        WampError,
+    >,
+>;
+
+/// Subscription request waiting for the server's reply
+pub enum PendingSub {
+    /// Events are forwarded to a single queue
+    Queue(PendingSubResult),
+    /// Events are broadcasted to every receiver, keeping up to `capacity` events per receiver
+    Broadcast {
+        capacity: usize,
+        res: PendingBroadcastSubResult,
+    },
+    /// Events are forwarded to a queue holding up to `capacity` events
+    Bounded {
+        capacity: usize,
+        res: PendingBoundedSubResult,
+    },
+}
+
+impl PendingSub {
+    fn fail(self, e: WampError) {
+        match self {
+            PendingSub::Queue(res) => {
+                let _ = res.send(Err(e));
+            }
+            PendingSub::Broadcast { res, .. } => {
+                let _ = res.send(Err(e));
+            }
+            PendingSub::Bounded { res, .. } => {
+                let _ = res.send(Err(e));
+            }
+        }
+    }
+}
+
+/// Where the events of a subscription are forwarded
+pub(crate) enum EventSink {
+    Queue(SubscriptionQueueSender),
+    Broadcast(BroadcastSubscriptionSender),
+    Bounded {
+        queue: mpsc::Sender<SubscriptionItem>,
+        /// Events dropped since the last `Lagged` item was queued
+        dropped: AtomicU64,
+        /// Events dropped by every bounded subscription of the client, never reset
+        total_dropped: Arc<AtomicU64>,
+    },
+}
+
+impl EventSink {
+    /// Number of events waiting to be read
+    fn depth(&self) -> usize {
+        match self {
+            EventSink::Queue(q) => q.depth.load(Ordering::Relaxed),
+            EventSink::Broadcast(q) => q.len(),
+            EventSink::Bounded { queue, .. } => queue.max_capacity() - queue.capacity(),
+        }
+    }
+
+    /// Forwards an event, returns false if the client is not listening anymore
+    fn send(&self, evt: (WampId, Option<WampArgs>, Option<WampKwArgs>, WampDict)) -> bool {
+        let (queue, dropped, total_dropped) = match self {
+            EventSink::Queue(q) => return q.send(evt),
+            EventSink::Broadcast(q) => return q.send(evt).is_ok(),
+            EventSink::Bounded {
+                queue,
+                dropped,
+                total_dropped,
+            } => (queue, dropped, total_dropped),
+        };
+
+        // Let the client know about the dropped events before queuing new ones
+        let lagged = dropped.load(Ordering::Relaxed);
+        if lagged > 0 {
+            match queue.try_send(SubscriptionItem::Lagged { dropped: lagged }) {
+                Ok(()) => dropped.store(0, Ordering::Relaxed),
+                Err(TrySendError::Full(_)) => {
+                    dropped.fetch_add(1, Ordering::Relaxed);
+                    total_dropped.fetch_add(1, Ordering::Relaxed);
+                    return true;
+                }
+                Err(TrySendError::Closed(_)) => return false,
+            }
+        }
+
+        let (publication, arguments, arguments_kw, details) = evt;
+        match queue.try_send(SubscriptionItem::Event(
+            publication,
+            arguments,
+            arguments_kw,
+            details,
+        )) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                dropped.fetch_add(1, Ordering::Relaxed);
+                total_dropped.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            Err(TrySendError::Closed(_)) => false,
+        }
+    }
+}
+
+/// Limits the number of invocation results waiting to be written to the transport
+#[derive(Clone)]
+pub struct YieldFlowControl {
+    slots: Arc<Semaphore>,
+    num_congested: Arc<AtomicU64>,
+}
+
+impl YieldFlowControl {
+    fn new(max_pending: usize) -> Self {
+        Self {
+            slots: Arc::new(Semaphore::new(max_pending)),
+            num_congested: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Waits until an invocation result can be handed over to the event loop
+    pub(crate) async fn acquire(&self) -> Result<OwnedSemaphorePermit, WampError> {
+        if let Ok(slot) = self.slots.clone().try_acquire_owned() {
+            return Ok(slot);
+        }
+        self.num_congested.fetch_add(1, Ordering::Relaxed);
+        match self.slots.clone().acquire_owned().await {
+            Ok(slot) => Ok(slot),
+            Err(e) => Err(From::from(format!("Yield flow control closed : {}", e))),
+        }
+    }
+
+    /// Returns a slot if one is available right away
+    pub(crate) fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.slots.clone().try_acquire_owned().ok()
+    }
+
+    /// Number of invocation results that had to wait
+    pub fn num_congested(&self) -> u64 {
+        self.num_congested.load(Ordering::Relaxed)
+    }
+}
+
+/// Sends the requests to the event loop, counting the ones it did not handle yet
+#[derive(Clone)]
+pub struct CtlSender<'a> {
+    tx: UnboundedSender<Request<'a>>,
+    depth: Arc<AtomicUsize>,
+}
+
+impl<'a> CtlSender<'a> {
+    /// Queues a request for the event loop
+    #[allow(clippy::result_large_err)]
+    pub fn send(&self, req: Request<'a>) -> Result<(), SendError<Request<'a>>> {
+        self.depth.fetch_add(1, Ordering::Relaxed);
+        let res = self.tx.send(req);
+        if res.is_err() {
+            self.depth.fetch_sub(1, Ordering::Relaxed);
+        }
+        res
+    }
+
+    /// Number of requests waiting for the event loop
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    /// Called by the event loop when it takes a request out of the channel
+    fn received(&self) {
+        self.depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Creates the channel of the requests sent to the event loop
+pub fn ctl_channel<'a>() -> (CtlSender<'a>, UnboundedReceiver<Request<'a>>) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    (
+        CtlSender {
+            tx,
+            depth: Arc::new(AtomicUsize::new(0)),
+        },
+        rx,
+    )
+}
+
+/// Queues of the subscriptions, shared with the client to report their depth
+///
+/// The sinks are not kept alive : a subscription removed by the event loop disappears from
+/// the stats and its queue gets closed as usual.
+#[derive(Clone, Default)]
+pub struct QueueProbes {
+    probes: Arc<Mutex<HashMap<WampId, QueueProbe>>>,
+}
+
+struct QueueProbe {
+    topic: WampUri,
+    capacity: Option<usize>,
+    sink: Weak<EventSink>,
+}
+
+impl QueueProbes {
+    /// Watches the queue of a new subscription, keyed by the ID known by the client
+    fn insert(&self, sub_id: WampId, topic: &str, capacity: Option<usize>, sink: &Arc<EventSink>) {
+        self.probes.lock().unwrap().insert(
+            sub_id,
+            QueueProbe {
+                topic: topic.to_string(),
+                capacity,
+                sink: Arc::downgrade(sink),
+            },
+        );
+    }
+
+    /// Returns the depth of the queues of the active subscriptions
+    pub fn stats(&self) -> HashMap<WampId, SubscriptionQueueStats> {
+        let mut probes = self.probes.lock().unwrap();
+        probes.retain(|_, p| p.sink.strong_count() > 0);
+        probes
+            .iter()
+            .filter_map(|(sub_id, p)| {
+                let sink = p.sink.upgrade()?;
+                Some((
+                    *sub_id,
+                    SubscriptionQueueStats {
+                        topic: p.topic.clone(),
+                        depth: sink.depth(),
+                        capacity: p.capacity,
+                    },
+                ))
+            })
+            .collect()
+    }
+}
+
+/// Aborts the RPC handlers running for longer than the configured limit
+#[derive(Clone)]
+pub struct HandlerWatchdog {
+    limit: Option<Duration>,
+    num_expired: Arc<AtomicU64>,
+}
+
+impl HandlerWatchdog {
+    fn new(limit: Option<Duration>) -> Self {
+        Self {
+            limit,
+            num_expired: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Fails the handler of `procedure` with [`WampError::HandlerTimeout`] if it runs for too long
+    pub(crate) fn guard<'a>(&self, procedure: WampUri, func: RpcFuture<'a>) -> RpcFuture<'a> {
+        let limit = match self.limit {
+            Some(limit) => limit,
+            None => return func,
+        };
+        let num_expired = self.num_expired.clone();
+        Box::pin(async move {
+            match tokio::time::timeout(limit, func).await {
+                Ok(res) => res,
+                Err(_) => {
+                    num_expired.fetch_add(1, Ordering::Relaxed);
+                    warn!(
+                        target: CORE,
+                        "Handler of {} did not complete within {:?}, aborting it",
+                        procedure, limit
+                    );
+                    Err(WampError::HandlerTimeout(procedure, limit))
+                }
+            }
+        })
+    }
+
+    /// Number of handlers aborted by the watchdog
+    pub fn num_expired(&self) -> u64 {
+        self.num_expired.load(Ordering::Relaxed)
+    }
+}
+
+/// Number of unread reports kept in the protocol error queue
+const MAX_PROTOCOL_ERRORS: usize = 64;
+
+/// Number of completed request IDs remembered to detect duplicate replies
+const NUM_RECENT_REQUESTS: usize = 256;
+
+/// Remembers the most recently completed request IDs
+struct RecentRequests {
+    order: VecDeque<WampId>,
+    ids: HashSet<WampId>,
+}
+
+impl RecentRequests {
+    fn new() -> Self {
+        Self {
+            order: VecDeque::with_capacity(NUM_RECENT_REQUESTS),
+            ids: HashSet::with_capacity(NUM_RECENT_REQUESTS),
+        }
+    }
+
+    fn insert(&mut self, request: WampId) {
+        if self.order.len() == NUM_RECENT_REQUESTS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        if self.ids.insert(request) {
+            self.order.push_back(request);
+        }
+    }
+
+    fn contains(&self, request: &WampId) -> bool {
+        self.ids.contains(request)
+    }
+}
+
+/// A subscription acknowledged by the server
+struct ActiveSubscription {
+    topic: WampUri,
+    /// Options subscribed with, to subscribe again in a new session
+    options: WampDict,
+    sink: Arc<EventSink>,
+    validator: Option<PayloadValidator>,
+    codec: Option<Arc<dyn PayloadCodec>>,
+    journal: Option<EventJournal>,
+}
+
+impl ActiveSubscription {
+    /// Decodes and validates an event and forwards it to the client
+    fn deliver(
+        &self,
+        subscription: WampId,
+        publication: WampId,
+        details: WampDict,
+        arguments: Option<WampArgs>,
+        arguments_kw: Option<WampKwArgs>,
+    ) {
+        let (arguments, arguments_kw) = match &self.codec {
+            Some(codec) => match codec.decode(arguments, arguments_kw) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    warn!(
+                        target: CORE,
+                        "Dropping event {} for subscription {} that could not be decoded : {}",
+                        publication, subscription, e
+                    );
+                    return;
+                }
+            },
+            None => (arguments, arguments_kw),
+        };
+
+        if let Some(validator) = &self.validator {
+            if let Err(e) = validator(arguments.as_ref(), arguments_kw.as_ref()) {
+                warn!(
+                    target: CORE,
+                    "Dropping event {} for subscription {} with invalid payload : {}",
+                    publication, subscription, e
+                );
+                return;
+            }
+        }
+
+        let journal = match &self.journal {
+            Some(j) => j,
+            None => {
+                forward(
+                    &self.sink,
+                    subscription,
+                    publication,
+                    arguments,
+                    arguments_kw,
+                    details,
+                );
+                return;
+            }
+        };
+
+        // Journaled events are forwarded by the journal writer once written
+        let sink = self.sink.clone();
+        let event = JournaledEvent {
+            publication,
+            arguments,
+            arguments_kw,
+        };
+        journal.append(event, move |written, event| {
+            if let Err(e) = written {
+                warn!(
+                    target: CORE,
+                    "Failed to journal event {} for subscription {} : {}",
+                    publication, subscription, e
+                );
+            }
+            forward(
+                &sink,
+                subscription,
+                publication,
+                event.arguments,
+                event.arguments_kw,
+                details,
+            );
+        });
+    }
+}
+
+/// Forwards an event to the client
+fn forward(
+    sink: &EventSink,
+    subscription: WampId,
+    publication: WampId,
+    arguments: Option<WampArgs>,
+    arguments_kw: Option<WampKwArgs>,
+    details: WampDict,
+) {
+    if !sink.send((publication, arguments, arguments_kw, details)) {
+        warn!(
+            target: CORE,
+            "Client not listenning to subscription {} but did not unsubscribe...",
+            subscription
+        );
+        // TODO : Should we be nice and send an UNSUBSCRIBE to the server ?
+    }
+}
+
 pub type PendingRegisterResult = Sender<
     Result<
         WampId, // Registration ID
@@ -62,37 +661,163 @@ pub type PendingCallResult = Sender<
     >,
 >;
 
+/// Results of subscribing again to the active subscriptions, one per subscription
+pub type PendingResubscribeResult =
+    Sender<Vec<oneshot::Receiver<Result<Option<WampId>, WampError>>>>;
+
+/// Request sent to the peer that waits for its reply
+pub enum PendingRequest<'a> {
+    Subscribe(WampUri, WampDict, Option<PayloadValidator>, PendingSub),
+    /// Active subscription subscribed again in a new session, keyed by its current ID
+    Resubscribe(WampId, Sender<Result<Option<WampId>, WampError>>),
+    Register(WampUri, WampDict, RpcEndpoint<'a>, PendingRegisterResult),
+    /// Call with the queue of its progressive results, if asked for, and the codec of its
+    /// procedure
+    Call(
+        PendingCallResult,
+        Option<UnboundedSender<CallResult>>,
+        Option<Arc<dyn PayloadCodec>>,
+    ),
+    /// Requests that simply succeed or fail (publish, unsubscribe, unregister)
+    Transaction(Sender<Result<Option<WampId>, WampError>>),
+}
+
+impl PendingRequest<'_> {
+    /// Fails the request with `e`
+    fn fail(self, e: WampError) {
+        match self {
+            PendingRequest::Subscribe(_, _, _, res) => res.fail(e),
+            PendingRequest::Resubscribe(_, res) => {
+                let _ = res.send(Err(e));
+            }
+            PendingRequest::Register(_, _, _, res) => {
+                let _ = res.send(Err(e));
+            }
+            PendingRequest::Call(res, ..) => {
+                let _ = res.send(Err(e));
+            }
+            PendingRequest::Transaction(res) => {
+                let _ = res.send(Err(e));
+            }
+        }
+    }
+}
+
+/// Registrations and subscriptions of the client with the URI and options they were made with
+pub struct ActiveEntries {
+    pub registrations: Vec<(WampId, WampUri, WampDict)>,
+    pub subscriptions: Vec<(WampId, WampUri, WampDict)>,
+}
+
+pub type PendingPingResult = Sender<
+    Result<
+        Duration, // Round-trip time
+        WampError,
+    >,
+>;
+
 pub struct Core<'a> {
     /// Generic transport
     sock: Box<dyn Transport + Send>,
-    valid_session: bool,
+    /// State of the session, shared with the client
+    session_state: watch::Sender<SessionState>,
     core_res: UnboundedSender<Result<(), WampError>>,
+    /// Whether the event loop was polled at least once
+    pub event_loop_started: Arc<AtomicBool>,
+    /// Number of requests failed when the event loop stopped
+    pub aborted_requests: Arc<AtomicUsize>,
     /// Generic serializer
     serializer: Box<dyn SerializerImpl + Send>,
+    /// Details gathered while establishing the transport
+    pub connection_info: ConnectionInfo,
     /// Holds the request_id queues waiting for messages
-    ctl_sender: UnboundedSender<Request<'a>>,
+    ctl_sender: CtlSender<'a>,
     /// Channel for receiving client requests
     ctl_channel: Option<UnboundedReceiver<Request<'a>>>, //Wrapped in option so we can give ownership to eventloop
 
-    /// Holds set of pending requests
-    pending_requests: HashSet<WampId>,
-    /// Holds generic transactions that can succeed/fail
-    pending_transactions: HashMap<WampId, Sender<Result<Option<WampId>, WampError>>>,
+    /// Why the router closed the session, reported once the event loop stops
+    close_reason: Option<WampError>,
+    /// Requests waiting for a reply of the peer, keyed by request ID
+    pending_requests: HashMap<WampId, PendingRequest<'a>>,
+    /// Requests that were recently answered by the peer
+    completed_requests: RecentRequests,
+    /// Calls that already failed when they were canceled, their late replies are ignored
+    canceled_calls: RecentRequests,
+    /// Number of replies ignored because their request was already answered
+    pub num_duplicate_replies: Arc<AtomicU64>,
+    /// Number of events dropped because a bounded subscription queue was full
+    pub num_dropped_events: Arc<AtomicU64>,
+    /// Number of inline RPC handlers that exceeded their budget
+    pub num_slow_inline_handlers: Arc<AtomicU64>,
+    /// Size of the largest invocation result sent
+    pub largest_response: Arc<AtomicUsize>,
+    /// What to do with invocation results larger than the router accepts
+    oversized_response_policy: OversizedResponsePolicy,
+    /// ERROR messages that could not be matched to a pending request
+    protocol_errors_w: mpsc::Sender<UnhandledError>,
+    pub protocol_errors_r: Option<mpsc::Receiver<UnhandledError>>,
+    /// Registrations revoked by the router
+    registration_events_w: UnboundedSender<RegistrationEvent>,
+    pub registration_events_r: Option<UnboundedReceiver<RegistrationEvent>>,
+    /// Subscriptions revoked by the router
+    subscription_events_w: UnboundedSender<SubscriptionEvent>,
+    pub subscription_events_r: Option<UnboundedReceiver<SubscriptionEvent>>,
 
-    /// Pending subscription requests sent to the server
-    pending_sub: HashMap<WampId, PendingSubResult>,
     /// Current subscriptions
-    subscriptions: HashMap<WampId, UnboundedSender<(WampId, Option<WampArgs>, Option<WampKwArgs>)>>,
+    subscriptions: HashMap<WampId, ActiveSubscription>,
+    /// Current ID of the subscriptions renewed in a new session, keyed by the ID known by the
+    /// client
+    subscription_aliases: HashMap<WampId, WampId>,
+    /// Payload validators keyed by procedure/topic uri
+    payload_validators: HashMap<WampUri, PayloadValidator>,
+    /// Payload codecs keyed by procedure/topic uri
+    payload_codecs: HashMap<WampUri, Arc<dyn PayloadCodec>>,
+    /// Journals recording the events received, keyed by topic
+    event_journals: HashMap<WampUri, EventJournal>,
+    /// Debug sink for frames that could not be deserialized
+    malformed_frame_hook: Option<MalformedFrameHook>,
+    dispatch_timing_hook: Option<DispatchTimingHook>,
+    local_request_budget: usize,
+    /// What to do with frames that could not be deserialized
+    malformed_message_policy: MalformedMessagePolicy,
+    /// Number of frames that could not be deserialized
+    num_malformed_messages: usize,
+    /// Join handshake in progress
+    pending_join: Option<PendingJoin<'a>>,
+    /// How long to wait for the server to answer during the join handshake
+    handshake_timeout: Option<Duration>,
+    /// How long the challenge handler can take to answer a CHALLENGE
+    challenge_timeout: Option<Duration>,
+    /// Quirks of the router implementation
+    router_dialect: RouterDialect,
+    /// WELCOME details echoed in HELLO
+    routing_tokens: Option<RoutingTokens>,
 
-    /// Pending RPC registration requests sent to the server
-    pending_register: HashMap<WampId, (RpcFunc<'a>, PendingRegisterResult)>,
-    /// Currently registered RPC endpoints
-    rpc_endpoints: HashMap<WampId, RpcFunc<'a>>,
+    /// Currently registered RPC endpoints and their procedure uri
+    rpc_endpoints: HashMap<WampId, (WampUri, WampDict, RpcEndpoint<'a>)>,
+    /// Add the context of the failure to the ERROR of failed invocations
+    error_context: bool,
+    /// How long an inline RPC handler can run before being reported as slow
+    inline_handler_budget: Duration,
+    /// What to do with the inline RPC handlers exceeding their budget
+    slow_inline_handler_policy: SlowInlineHandlerPolicy,
+    /// Typed values shared with the RPC handlers
+    extensions: Extensions,
     /// Queue passed back to the client caller to handle rpc events
     pub rpc_event_queue_r: Option<UnboundedReceiver<GenericFuture<'a>>>,
     rpc_event_queue_w: UnboundedSender<GenericFuture<'a>>,
+    /// Stream passed back to the client for the procedures registered as streamed
+    pub invocations_r: Option<UnboundedReceiver<Invocation<'a>>>,
+    invocations_w: UnboundedSender<Invocation<'a>>,
+    /// Flow control of the invocation results
+    pub yield_flow: YieldFlowControl,
+    /// Aborts the RPC handlers running for too long
+    pub handler_watchdog: HandlerWatchdog,
+    /// Depth of the subscription queues
+    pub queue_probes: QueueProbes,
 
-    pending_call: HashMap<WampId, PendingCallResult>,
+    /// Transport pings waiting for a pong, keyed by payload
+    pending_pings: HashMap<Vec<u8>, (Instant, PendingPingResult)>,
 }
 
 impl<'a> Core<'a> {
@@ -100,12 +825,12 @@ impl<'a> Core<'a> {
     pub async fn connect(
         uri: &url::Url,
         cfg: &client::ClientConfig,
-        ctl_channel: (UnboundedSender<Request<'a>>, UnboundedReceiver<Request<'a>>),
+        ctl_channel: (CtlSender<'a>, UnboundedReceiver<Request<'a>>),
         core_res: UnboundedSender<Result<(), WampError>>,
     ) -> Result<Core<'a>, WampError> {
         // Connect to the router using the requested transport
         let (sock, serializer_type) = match uri.scheme() {
-            "ws" | "wss" => ws::connect(uri, &cfg).await?,
+            "ws" | "wss" => ws::connect(uri, cfg).await?,
             "tcp" | "tcps" => {
                 let host_port = match uri.port() {
                     Some(p) => p,
@@ -119,42 +844,98 @@ impl<'a> Core<'a> {
                     uri.host_str().unwrap(),
                     host_port,
                     uri.scheme() != "tcp",
-                    &cfg,
+                    cfg,
                 )
                 .await?
             }
             s => return Err(From::from(format!("Unknown uri scheme : {}", s))),
         };
 
-        debug!("Connected with serializer : {:?}", serializer_type);
+        Ok(Core::new(sock, serializer_type, cfg, ctl_channel, core_res))
+    }
 
-        let serializer: Box<dyn SerializerImpl + Send> = match serializer_type {
-            SerializerType::Json => Box::new(json::JsonSerializer {}),
-            SerializerType::MsgPack => Box::new(msgpack::MsgPackSerializer {}),
-        };
+    /// Creates the core from an already established transport
+    pub fn new(
+        sock: Box<dyn Transport + Send>,
+        serializer_type: SerializerType,
+        cfg: &client::ClientConfig,
+        ctl_channel: (CtlSender<'a>, UnboundedReceiver<Request<'a>>),
+        core_res: UnboundedSender<Result<(), WampError>>,
+    ) -> Core<'a> {
+        debug!(target: CORE, "Connected with serializer : {:?}", serializer_type);
+
+        let serializer = serializer_type.serializer();
 
         //let (rpc_result_w, rpc_result_r) = mpsc::unbounded_channel();
         let (rpc_event_queue_w, rpc_event_queue_r) = mpsc::unbounded_channel();
+        let (invocations_w, invocations_r) = mpsc::unbounded_channel();
+        let (protocol_errors_w, protocol_errors_r) = mpsc::channel(MAX_PROTOCOL_ERRORS);
+        let (registration_events_w, registration_events_r) = mpsc::unbounded_channel();
+        let (subscription_events_w, subscription_events_r) = mpsc::unbounded_channel();
 
-        Ok(Core {
+        let connection_info = ConnectionInfo {
+            serializer: serializer_type,
+            response_headers: sock.response_headers().to_vec(),
+            tls: sock.tls_info().cloned(),
+            max_message_size: sock.max_send_size(),
+        };
+
+        Core {
             sock,
+            connection_info,
             core_res,
-            valid_session: false,
+            session_state: watch::channel(SessionState::Connected).0,
             serializer,
             ctl_sender: ctl_channel.0,
             ctl_channel: Some(ctl_channel.1),
-            pending_requests: HashSet::new(),
-            pending_transactions: HashMap::new(),
+            close_reason: None,
+            pending_requests: HashMap::new(),
+            completed_requests: RecentRequests::new(),
+            canceled_calls: RecentRequests::new(),
+            num_duplicate_replies: Arc::new(AtomicU64::new(0)),
+            num_dropped_events: Arc::new(AtomicU64::new(0)),
+            num_slow_inline_handlers: Arc::new(AtomicU64::new(0)),
+            largest_response: Arc::new(AtomicUsize::new(0)),
+            oversized_response_policy: cfg.get_oversized_response_policy(),
+            event_loop_started: Arc::new(AtomicBool::new(false)),
+            aborted_requests: Arc::new(AtomicUsize::new(0)),
+            protocol_errors_w,
+            protocol_errors_r: Some(protocol_errors_r),
+            registration_events_w,
+            registration_events_r: Some(registration_events_r),
+            subscription_events_w,
+            subscription_events_r: Some(subscription_events_r),
 
-            pending_sub: HashMap::new(),
             subscriptions: HashMap::new(),
+            subscription_aliases: HashMap::new(),
+            payload_validators: cfg.get_payload_validators().clone(),
+            payload_codecs: cfg.get_payload_codecs().clone(),
+            event_journals: cfg.get_event_journals().clone(),
+            malformed_frame_hook: cfg.get_malformed_frame_hook().cloned(),
+            dispatch_timing_hook: cfg.get_dispatch_timing_hook().cloned(),
+            local_request_budget: cfg.get_local_request_budget(),
+            malformed_message_policy: cfg.get_malformed_message_policy(),
+            num_malformed_messages: 0,
+            pending_join: None,
+            handshake_timeout: cfg.get_handshake_timeout(),
+            challenge_timeout: cfg.get_challenge_timeout(),
+            router_dialect: cfg.get_router_dialect(),
+            routing_tokens: cfg.get_routing_tokens().cloned(),
 
-            pending_register: HashMap::new(),
             rpc_endpoints: HashMap::new(),
+            error_context: cfg.get_error_context(),
+            inline_handler_budget: cfg.get_inline_handler_budget(),
+            slow_inline_handler_policy: cfg.get_slow_inline_handler_policy(),
+            extensions: cfg.get_extensions().clone(),
             rpc_event_queue_r: Some(rpc_event_queue_r),
             rpc_event_queue_w,
-            pending_call: HashMap::new(),
-        })
+            invocations_r: Some(invocations_r),
+            invocations_w,
+            yield_flow: YieldFlowControl::new(cfg.get_max_pending_yields()),
+            handler_watchdog: HandlerWatchdog::new(cfg.get_handler_watchdog()),
+            queue_probes: QueueProbes::default(),
+            pending_pings: HashMap::new(),
+        }
     }
 
     /// Event loop that handles outbound/inboud events
@@ -162,75 +943,193 @@ impl<'a> Core<'a> {
         let mut ctl_channel = self.ctl_channel.take().unwrap();
 
         // Notify the client that we are now running the event loop
+        self.event_loop_started.store(true, Ordering::Relaxed);
         let _ = self.core_res.send(Ok(()));
-        loop {
+        // Reason of the shutdown, sent to the client once the transport is closed
+        let status = loop {
+            // The join handshake progresses alongside the other messages
+            let mut challenge = self.pending_join.as_mut().and_then(|j| j.challenge.take());
+            let join_deadline = self.pending_join.as_ref().and_then(|j| j.deadline);
+
             match select! {
                 // Peer sent us a message
                 msg = self.recv() => {
                     match msg {
+                        Err(e) if self.skip_malformed_message(&e) => {
+                            warn!(target: CORE, "Dropping malformed message : {}", e);
+                            Status::Ok
+                        },
+                        Err(e) if self.pending_join.is_some() => {
+                            self.fail_join(e);
+                            break None;
+                        },
                         Err(e) => {
                             /* The WAMP spec leaves it up to the server implementation
                             to decide whether to close a connection or not after a
                             GOODBYE message (leaving the realm). If we have left the realm,
                             treat a recv() error as expected */
-                            if self.valid_session {
-                                error!("Failed to recv : {:?}", e);
-                                let _ = self.core_res.send(Err(e));
+                            if let SessionState::Joined(_) = self.session_state() {
+                                error!(target: CORE, "Failed to recv : {:?}", e);
+                                break Some(Err(e));
                             }
 
-                            break;
+                            break None;
+                        },
+                        Ok(m) => {
+                            let kind = m.name();
+                            let started = self.dispatch_started();
+                            let status = self.handle_peer_msg(m).await;
+                            self.dispatch_done(DispatchSource::Peer, kind, started);
+                            status
                         },
-                        Ok(m) => self.handle_peer_msg(m).await,
                     }
                 },
                 // client wants to send a message
                 req = ctl_channel.recv() => {
                     let req = match req {
                         Some(r) => r,
-                        None => {
-                            let _ = self.core_res.send(Err(WampError::ClientDied));
-                            break;
-                        }
+                        None => break Some(Err(WampError::ClientDied)),
                     };
-                    self.handle_local_request(req).await
+                    self.ctl_sender.received();
+                    self.handle_local_requests(req, &mut ctl_channel).await
+                },
+                // The challenge handler answered
+                resp = async { challenge.as_mut().unwrap().await }, if challenge.is_some() => {
+                    challenge = None;
+                    send::authenticate(&mut self, resp).await
+                },
+                // The server or the challenge handler took too long
+                _ = tokio::time::sleep_until(
+                    join_deadline.unwrap_or_else(tokio::time::Instant::now)
+                ), if join_deadline.is_some() => {
+                    let step = if challenge.is_some() {
+                        "waiting for the challenge handler"
+                    } else {
+                        "waiting for the server to answer HELLO"
+                    };
+                    self.fail_join(WampError::Timeout(step.to_string()));
+                    Status::Shutdown
                 }
             } {
-                Status::Shutdown => {
-                    let _ = self.core_res.send(Ok(()));
-                    break;
-                }
+                Status::Shutdown => break Some(self.close_reason.take().map_or(Ok(()), Err)),
                 Status::Ok => {}
             }
-        }
-        debug!("Event loop shutting down !");
 
-        self.shutdown().await;
+            // Keep polling the challenge handler on the next iteration
+            if let (Some(challenge), Some(join)) = (challenge, self.pending_join.as_mut()) {
+                join.challenge = Some(challenge);
+            }
+        };
+        debug!(target: CORE, "Event loop shutting down !");
+
+        self.shutdown(status).await;
 
         Ok(())
     }
 
+    /// Handles `req` and the requests queued behind it, up to the local request budget
+    async fn handle_local_requests(
+        &mut self,
+        mut req: Request<'a>,
+        ctl_channel: &mut UnboundedReceiver<Request<'a>>,
+    ) -> Status {
+        let mut handled = 0;
+        loop {
+            let kind = req.name();
+            let started = self.dispatch_started();
+            let status = self.handle_local_request(req).await;
+            self.dispatch_done(DispatchSource::Local, kind, started);
+            handled += 1;
+            if let Status::Shutdown = status {
+                return status;
+            }
+            if handled == self.local_request_budget {
+                return Status::Ok;
+            }
+
+            req = match ctl_channel.try_recv() {
+                Ok(r) => r,
+                Err(_) => return Status::Ok,
+            };
+            self.ctl_sender.received();
+        }
+    }
+
+    /// Start time of a dispatch, only taken when a timing hook is set
+    fn dispatch_started(&self) -> Option<Instant> {
+        self.dispatch_timing_hook.as_ref().map(|_| Instant::now())
+    }
+
+    /// Reports the time spent on a dispatch to the timing hook
+    fn dispatch_done(&self, source: DispatchSource, kind: &'static str, started: Option<Instant>) {
+        if let (Some(hook), Some(started)) = (&self.dispatch_timing_hook, started) {
+            hook(&DispatchTiming {
+                source,
+                kind,
+                elapsed: started.elapsed(),
+            });
+        }
+    }
+
+    /// Returns whether the event loop should keep going after failing to receive a message
+    fn skip_malformed_message(&mut self, e: &WampError) -> bool {
+        if !matches!(e, WampError::SerializationError(_)) {
+            return false;
+        }
+
+        self.num_malformed_messages += 1;
+        match self.malformed_message_policy {
+            MalformedMessagePolicy::Strict => false,
+            MalformedMessagePolicy::SkipAndLog => true,
+            MalformedMessagePolicy::SkipN(max) => self.num_malformed_messages <= max,
+        }
+    }
+
     /// Handles unsolicited messages from the peer (events, rpc calls, etc...)
     async fn handle_peer_msg<'b>(&'b mut self, msg: Msg) -> Status
     where
         'a: 'b,
     {
-        // Make sure we were expecting this message if it has a request ID
-        if let Some(ref request) = msg.request_id() {
-            if !self.pending_requests.remove(request) {
-                warn!("Peer sent a response to an unknown request : {}", request);
+        // Make sure we were expecting this message if it has a request ID. Errors about
+        // invocations refer to requests of the router.
+        let is_invocation_error = matches!(
+            msg,
+            Msg::Error {
+                typ: INVOCATION_ID | YIELD_ID,
+                ..
+            }
+        );
+        let mut pending = None;
+        if let Some(ref request) = msg.request_id().filter(|_| !is_invocation_error) {
+            pending = self.pending_requests.remove(request);
+            if pending.is_some() {
+                self.completed_requests.insert(*request);
+            } else if self.canceled_calls.contains(request) {
+                debug!(target: CORE, "Ignoring reply to canceled call : {}", request);
+                return Status::Ok;
+            } else if self.completed_requests.contains(request) {
+                // Some proxies send the same reply more than once
+                debug!(target: CORE, "Ignoring duplicate reply to request : {}", request);
+                self.num_duplicate_replies.fetch_add(1, Ordering::Relaxed);
+                return Status::Ok;
+            } else if !matches!(msg, Msg::Error { .. }) {
+                warn!(target: CORE, "Peer sent a response to an unknown request : {}", request);
                 return Status::Ok;
             }
+            // ERRORs of unknown requests are reported by recv::error
         }
         match msg {
             Msg::Subscribed {
                 request,
                 subscription,
-            } => recv::subscribed(self, request, subscription).await,
-            Msg::Unsubscribed { request } => recv::unsubscribed(self, request).await,
+            } => recv::subscribed(self, request, pending, subscription).await,
+            Msg::Unsubscribed { request, details } => {
+                recv::unsubscribed(self, request, details, pending).await
+            }
             Msg::Published {
                 request,
                 publication,
-            } => recv::published(self, request, publication).await,
+            } => recv::published(self, request, pending, publication).await,
             Msg::Event {
                 subscription,
                 publication,
@@ -251,8 +1150,10 @@ impl<'a> Core<'a> {
             Msg::Registered {
                 request,
                 registration,
-            } => recv::registered(self, request, registration).await,
-            Msg::Unregistered { request } => recv::unregisterd(self, request).await,
+            } => recv::registered(self, request, pending, registration).await,
+            Msg::Unregistered { request, details } => {
+                recv::unregisterd(self, request, details, pending).await
+            }
             Msg::Invocation {
                 request,
                 registration,
@@ -275,7 +1176,12 @@ impl<'a> Core<'a> {
                 details,
                 arguments,
                 arguments_kw,
-            } => recv::call_result(self, request, details, arguments, arguments_kw).await,
+            } => recv::call_result(self, request, pending, details, arguments, arguments_kw).await,
+            Msg::Welcome { session, details } => recv::welcome(self, session, details).await,
+            Msg::Challenge {
+                authentication_method,
+                extra,
+            } => recv::challenge(self, authentication_method, extra).await,
             Msg::Goodbye { details, reason } => recv::goodbye(self, details, reason).await,
             Msg::Abort { details, reason } => recv::abort(self, details, reason).await,
             Msg::Error {
@@ -285,9 +1191,22 @@ impl<'a> Core<'a> {
                 error,
                 arguments,
                 arguments_kw,
-            } => recv::error(self, typ, request, details, error, arguments, arguments_kw).await,
+            } => {
+                recv::error(
+                    self,
+                    typ,
+                    request,
+                    pending,
+                    details,
+                    error,
+                    arguments,
+                    arguments_kw,
+                )
+                .await
+            }
             _ => {
-                warn!("Recevied unhandled message {:?}", msg);
+                warn!(target: CORE, "Received unhandled message {}", msg.name());
+                trace!(target: PAYLOAD, "Unhandled message : {:?}", msg);
                 Status::Ok
             }
         }
@@ -304,6 +1223,7 @@ impl<'a> Core<'a> {
                 agent_str,
                 authentication_methods,
                 authentication_id,
+                authrole,
                 on_challenge_handler,
                 res,
             } => {
@@ -314,27 +1234,43 @@ impl<'a> Core<'a> {
                     agent_str,
                     authentication_methods,
                     authentication_id,
+                    authrole,
                     on_challenge_handler,
                     res,
                 )
                 .await
             }
             Request::Leave { res } => send::leave_realm(self, res).await,
-            Request::Subscribe { uri, res } => send::subscribe(self, uri, res).await,
+            Request::Subscribe { uri, options, res } => {
+                send::subscribe(self, uri, options, res).await
+            }
             Request::Unsubscribe { sub_id, res } => send::unsubscribe(self, sub_id, res).await,
+            Request::Resubscribe { res } => send::resubscribe(self, res).await,
             Request::Publish {
                 uri,
                 options,
                 arguments,
                 arguments_kw,
+                local_echo,
                 res,
-            } => send::publish(self, uri, options, arguments, arguments_kw, res).await,
-            Request::Register { uri, res, func_ptr } => {
-                send::register(self, uri, res, func_ptr).await
-            }
+            } => send::publish(self, uri, options, arguments, arguments_kw, local_echo, res).await,
+            Request::Register {
+                uri,
+                options,
+                res,
+                endpoint,
+            } => send::register(self, uri, options, res, endpoint).await,
             Request::Unregister { rpc_id, res } => send::unregister(self, rpc_id, res).await,
-            Request::InvocationResult { request, res } => {
-                send::invoke_yield(self, request, res).await
+            Request::InvocationResult {
+                request,
+                res,
+                error_context,
+                slot,
+            } => {
+                let status = send::invoke_yield(self, request, res, error_context).await;
+                // The result has been written to the transport
+                drop(slot);
+                status
             }
             Request::Call {
                 uri,
@@ -342,7 +1278,24 @@ impl<'a> Core<'a> {
                 arguments,
                 arguments_kw,
                 res,
-            } => send::call(self, uri, options, arguments, arguments_kw, res).await,
+                sent,
+                progress,
+            } => {
+                send::call(
+                    self,
+                    uri,
+                    options,
+                    arguments,
+                    arguments_kw,
+                    res,
+                    sent,
+                    progress,
+                )
+                .await
+            }
+            Request::Cancel { request, mode, res } => send::cancel(self, request, mode, res).await,
+            Request::Ping { res } => send::ping(self, res).await,
+            Request::ListActive { res } => send::list_active(self, res).await,
         }
     }
 
@@ -350,48 +1303,219 @@ impl<'a> Core<'a> {
     pub async fn send(&mut self, msg: &Msg) -> Result<(), WampError> {
         // Serialize the data
         let payload = self.serializer.pack(msg)?;
+        self.send_packed(msg, &payload).await
+    }
 
-        match std::str::from_utf8(&payload) {
-            Ok(v) => debug!("Send : {}", v),
-            Err(_) => debug!("Send : {:?}", msg),
+    /// Sends `msg` already serialized as `payload`
+    pub async fn send_packed(&mut self, msg: &Msg, payload: &[u8]) -> Result<(), WampError> {
+        debug!(target: CORE, "Send : {}", msg.name());
+        match std::str::from_utf8(payload) {
+            Ok(v) => trace!(target: PAYLOAD, "Send : {}", v),
+            Err(_) => trace!(target: PAYLOAD, "Send : {:?}", msg),
         };
 
         // Send to host
-        self.sock.send(&payload).await?;
+        self.sock.send(payload).await?;
 
         Ok(())
     }
 
+    /// Serializes a message
+    pub fn pack(&self, msg: &Msg) -> Result<Vec<u8>, WampError> {
+        Ok(self.serializer.pack(msg)?)
+    }
+
     /// Receives a message and deserializes it
     pub async fn recv<'b>(&'b mut self) -> Result<Msg, WampError>
     where
         'a: 'b,
     {
         // Receive a full message from the host
-        let payload = self.sock.recv().await?;
+        let payload = loop {
+            match self.sock.recv().await? {
+                TransportMsg::Wamp(p) => break p,
+                TransportMsg::Pong(p) => self.pong(p),
+            }
+        };
 
         // Deserialize into a Msg
         let msg = self.serializer.unpack(&payload);
 
+        if let Ok(msg) = &msg {
+            debug!(target: CORE, "Recv : {}", msg.name());
+        }
         match std::str::from_utf8(&payload) {
-            Ok(v) => debug!("Recv : {}", v),
-            Err(_) => debug!("Recv : {:?}", msg),
+            Ok(v) => trace!(target: PAYLOAD, "Recv : {}", v),
+            Err(_) => trace!(target: PAYLOAD, "Recv : {:?}", msg),
         };
 
-        Ok(msg?)
+        match msg {
+            Ok(msg) => Ok(msg),
+            Err(e) => {
+                let e = WampError::from(e);
+                if let Some(hook) = &self.malformed_frame_hook {
+                    hook(&payload, &e);
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Completes the pending ping matching the pong payload
+    fn pong(&mut self, payload: Vec<u8>) {
+        match self.pending_pings.remove(&payload) {
+            Some((sent_at, res)) => {
+                let _ = res.send(Ok(sent_at.elapsed()));
+            }
+            None => {
+                debug!(target: CORE, "Peer sent a pong for a ping we never sent");
+                trace!(target: PAYLOAD, "Unexpected pong : {:?}", payload);
+            }
+        }
     }
 
-    /// Closes the transport
-    pub async fn shutdown(mut self) {
+    /// Closes the transport and reports `status` to the client
+    pub async fn shutdown(mut self, status: Option<Result<(), WampError>>) {
+        let aborted = self.fail_pending_requests();
+        self.aborted_requests.store(aborted, Ordering::Relaxed);
+        let _ = self.set_session_state(SessionState::Disconnected);
         // Close the transport
         self.sock.close().await;
+
+        if let Some(status) = status {
+            let _ = self.core_res.send(status);
+        }
+    }
+
+    /// Returns when the server must have answered the current step of the join handshake
+    fn handshake_deadline(&self) -> Option<tokio::time::Instant> {
+        self.handshake_timeout
+            .map(|t| tokio::time::Instant::now() + t)
+    }
+
+    /// Moves a subscription renewed in a new session to its new ID
+    fn renew_subscription(&mut self, sub_id: WampId, new_id: WampId) {
+        let sub = match self.subscriptions.remove(&sub_id) {
+            Some(s) => s,
+            // Unsubscribed in the meantime
+            None => return,
+        };
+        self.subscriptions.insert(new_id, sub);
+
+        // The client keeps using the first ID of the subscription
+        match self
+            .subscription_aliases
+            .values_mut()
+            .find(|current| **current == sub_id)
+        {
+            Some(current) => *current = new_id,
+            None => {
+                self.subscription_aliases.insert(sub_id, new_id);
+            }
+        }
+    }
+
+    /// Aborts the join handshake in progress, if any
+    fn fail_join(&mut self, e: WampError) {
+        if let Some(join) = self.pending_join.take() {
+            let _ = self.set_session_state(SessionState::Connected);
+            let _ = join.res.send(Err(e));
+        }
+    }
+
+    /// Fails every request still waiting for the peer with [`WampError::ConnectionLost`].
+    /// Returns the number of requests failed.
+    fn fail_pending_requests(&mut self) -> usize {
+        let count = self.pending_join.iter().count()
+            + self.pending_requests.len()
+            + self.pending_pings.len();
+
+        self.fail_join(WampError::ConnectionLost);
+        for (_, pending) in self.pending_requests.drain() {
+            pending.fail(WampError::ConnectionLost);
+        }
+        for (_, (_, res)) in self.pending_pings.drain() {
+            let _ = res.send(Err(WampError::ConnectionLost));
+        }
+
+        count
+    }
+
+    /// Returns a receiver that follows the state of the session
+    pub fn watch_session_state(&self) -> watch::Receiver<SessionState> {
+        self.session_state.subscribe()
+    }
+
+    /// Returns the current state of the session
+    fn session_state(&self) -> SessionState {
+        *self.session_state.borrow()
+    }
+
+    /// Moves the session to `next`, failing if the transition is not allowed
+    fn set_session_state(&self, next: SessionState) -> Result<(), WampError> {
+        let cur = self.session_state();
+        if !cur.can_transition_to(&next) {
+            return Err(WampError::InvalidSessionState(cur, next));
+        }
+        debug!(target: CORE, "Session state : {:?} -> {:?}", cur, next);
+        self.session_state.send_replace(next);
+        Ok(())
+    }
+
+    /// Forwards an ERROR message that could not be matched to a pending request. Reports are
+    /// dropped when nobody reads them.
+    fn report_error(&self, report: UnhandledError) {
+        let _ = self.protocol_errors_w.try_send(report);
+    }
+
+    /// Notifies the client of a registration change initiated by the router
+    fn report_registration(&self, event: RegistrationEvent) {
+        let _ = self.registration_events_w.send(event);
+    }
+
+    /// Notifies the client of a subscription change initiated by the router
+    fn report_subscription(&self, event: SubscriptionEvent) {
+        let _ = self.subscription_events_w.send(event);
+    }
+
+    /// Removes a subscription revoked by the router, closing its queue
+    ///
+    /// Returns the ID known by the client and the topic of the subscription.
+    fn revoke_subscription(
+        &mut self,
+        sub_id: WampId,
+        reason: Option<&WampUri>,
+    ) -> Option<(WampId, WampUri)> {
+        let sub = self.subscriptions.remove(&sub_id)?;
+        // The client keeps using the first ID of the subscription
+        let alias = self
+            .subscription_aliases
+            .iter()
+            .find(|(_, current)| **current == sub_id)
+            .map(|(alias, _)| *alias);
+        let client_id = match alias {
+            Some(alias) => {
+                self.subscription_aliases.remove(&alias);
+                alias
+            }
+            None => sub_id,
+        };
+
+        // Bounded queues tell the reason along with the events
+        if let EventSink::Bounded { queue, .. } = sub.sink.as_ref() {
+            let _ = queue.try_send(SubscriptionItem::Revoked {
+                reason: reason.cloned(),
+            });
+        }
+        Some((client_id, sub.topic))
     }
 
-    /// Generates a new request_id and inserts it into the pending_requests
-    fn create_request(&mut self) -> WampId {
+    /// Generates a request_id that is not used by a pending request. The request is added to
+    /// the pending requests once sent.
+    fn create_request(&self) -> WampId {
         let mut request = WampId::generate();
         // Pick a unique request_id
-        while !self.pending_requests.insert(request) {
+        while self.pending_requests.contains_key(&request) {
             request = WampId::generate();
         }
         request
@@ -1,10 +1,25 @@
+use std::panic::{self, AssertUnwindSafe};
+
 use crate::core::*;
 
-pub async fn subscribed(core: &mut Core<'_>, request: WampId, sub_id: WampId) -> Status {
-    let res = match core.pending_sub.remove(&request) {
-        Some(v) => v,
-        None => {
+pub async fn subscribed(
+    core: &mut Core<'_>,
+    request: WampId,
+    pending: Option<PendingRequest<'_>>,
+    sub_id: WampId,
+) -> Status {
+    let (topic, options, validator, res) = match pending {
+        Some(PendingRequest::Subscribe(topic, options, validator, res)) => {
+            (topic, options, validator, res)
+        }
+        Some(PendingRequest::Resubscribe(current_id, res)) => {
+            core.renew_subscription(current_id, sub_id);
+            let _ = res.send(Ok(Some(sub_id)));
+            return Status::Ok;
+        }
+        _ => {
             warn!(
+                target: CORE,
                 "Server sent subscribed event for ID we never asked for : {}",
                 request
             );
@@ -13,24 +28,69 @@ pub async fn subscribed(core: &mut Core<'_>, request: WampId, sub_id: WampId) ->
     };
 
     if core.subscriptions.contains_key(&sub_id) {
-        warn!("Server sent subcribed event for ID we already we subscribed to...");
+        warn!(target: CORE, "Server sent subcribed event for ID we already we subscribed to...");
         return Status::Ok;
     }
 
-    // Add the subscription ID to our subscription map
-    let (evt_queue_w, evt_queue_r) = mpsc::unbounded_channel();
-    let _ = core.subscriptions.insert(sub_id, evt_queue_w);
+    let journal = core.event_journals.get(&topic).cloned();
+    let codec = core.payload_codecs.get(&topic).cloned();
 
     // Send the event queue back to the requestor
-    let _ = res.send(Ok((sub_id, evt_queue_r)));
+    let (sink, capacity) = match res {
+        PendingSub::Queue(res) => {
+            let (evt_queue_w, evt_queue_r) = subscription_queue();
+            let _ = res.send(Ok((sub_id, evt_queue_r)));
+            (EventSink::Queue(evt_queue_w), None)
+        }
+        PendingSub::Bounded { capacity, res } => {
+            let (evt_queue_w, evt_queue_r) = mpsc::channel(capacity);
+            let _ = res.send(Ok((sub_id, evt_queue_r)));
+            let sink = EventSink::Bounded {
+                queue: evt_queue_w,
+                dropped: AtomicU64::new(0),
+                total_dropped: core.num_dropped_events.clone(),
+            };
+            (sink, Some(capacity))
+        }
+        PendingSub::Broadcast { capacity, res } => {
+            let (evt_queue_w, evt_queue_r) = broadcast::channel(capacity);
+            let _ = res.send(Ok((sub_id, evt_queue_r)));
+            (EventSink::Broadcast(evt_queue_w), Some(capacity))
+        }
+    };
+
+    // Add the subscription ID to our subscription map
+    let sink = Arc::new(sink);
+    core.queue_probes.insert(sub_id, &topic, capacity, &sink);
+    let _ = core.subscriptions.insert(
+        sub_id,
+        ActiveSubscription {
+            topic,
+            options,
+            sink,
+            validator,
+            codec,
+            journal,
+        },
+    );
 
     Status::Ok
 }
-pub async fn unsubscribed(core: &mut Core<'_>, request: WampId) -> Status {
-    let res = match core.pending_transactions.remove(&request) {
-        Some(v) => v,
-        None => {
+pub async fn unsubscribed(
+    core: &mut Core<'_>,
+    request: Option<WampId>,
+    details: WampDict,
+    pending: Option<PendingRequest<'_>>,
+) -> Status {
+    let request = match request {
+        Some(r) => r,
+        None => return subscription_revoked(core, details),
+    };
+    let res = match pending {
+        Some(PendingRequest::Transaction(res)) => res,
+        _ => {
             warn!(
+                target: CORE,
                 "Server sent unsubscribed event for ID we never asked for : {}",
                 request
             );
@@ -43,11 +103,56 @@ pub async fn unsubscribed(core: &mut Core<'_>, request: WampId) -> Status {
 
     Status::Ok
 }
-pub async fn published(core: &mut Core<'_>, request: WampId, pub_id: WampId) -> Status {
-    let res = match core.pending_transactions.remove(&request) {
-        Some(v) => v,
+/// Removes a subscription revoked by the router
+fn subscription_revoked(core: &mut Core<'_>, details: WampDict) -> Status {
+    let sub_id = match details.get("subscription") {
+        Some(Arg::Id(id)) => *id,
+        _ => {
+            warn!(
+                target: CORE,
+                "Server revoked a subscription without its ID : {:?}",
+                details
+            );
+            return Status::Ok;
+        }
+    };
+    let reason = match details.get("reason") {
+        Some(Arg::Uri(reason)) | Some(Arg::String(reason)) => Some(reason.clone()),
+        _ => None,
+    };
+    let (subscription, topic) = match core.revoke_subscription(sub_id, reason.as_ref()) {
+        Some(s) => s,
         None => {
+            warn!(target: CORE, "Server revoked an unknown subscription : {}", sub_id);
+            return Status::Ok;
+        }
+    };
+    warn!(
+        target: CORE,
+        "Server revoked the subscription to {} ({:?})",
+        topic,
+        reason
+    );
+
+    core.report_subscription(SubscriptionEvent::Revoked {
+        subscription,
+        topic,
+        reason,
+    });
+    Status::Ok
+}
+
+pub async fn published(
+    _core: &mut Core<'_>,
+    request: WampId,
+    pending: Option<PendingRequest<'_>>,
+    pub_id: WampId,
+) -> Status {
+    let res = match pending {
+        Some(PendingRequest::Transaction(res)) => res,
+        _ => {
             warn!(
+                target: CORE,
                 "Server sent published event for ID we never asked for : {}",
                 request
             );
@@ -62,14 +167,15 @@ pub async fn event(
     core: &mut Core<'_>,
     subscription: WampId,
     publication: WampId,
-    _details: WampDict,
+    details: WampDict,
     arguments: Option<WampArgs>,
     arguments_kw: Option<WampKwArgs>,
 ) -> Status {
-    let evt_queue = match core.subscriptions.get(&subscription) {
+    let active_sub = match core.subscriptions.get(&subscription) {
         Some(e) => e,
         None => {
             warn!(
+                target: CORE,
                 "Server sent event for sub ID we are not subscribed to : {}",
                 subscription
             );
@@ -78,24 +184,23 @@ pub async fn event(
     };
 
     // Forward the event to the client
-    if evt_queue
-        .send((publication, arguments, arguments_kw))
-        .is_err()
-    {
-        warn!(
-            "Client not listenning to subscription {} but did not unsubscribe...",
-            subscription
-        );
-        // TODO : Should we be nice and send an UNSUBSCRIBE to the server ?
-    }
+    active_sub.deliver(subscription, publication, details, arguments, arguments_kw);
 
     Status::Ok
 }
-pub async fn registered(core: &mut Core<'_>, request: WampId, rpc_id: WampId) -> Status {
-    let (rpc_func, res) = match core.pending_register.remove(&request) {
-        Some(v) => v,
-        None => {
+pub async fn registered<'a>(
+    core: &mut Core<'a>,
+    request: WampId,
+    pending: Option<PendingRequest<'a>>,
+    rpc_id: WampId,
+) -> Status {
+    let (uri, options, rpc_func, res) = match pending {
+        Some(PendingRequest::Register(uri, options, rpc_func, res)) => {
+            (uri, options, rpc_func, res)
+        }
+        _ => {
             warn!(
+                target: CORE,
                 "Server sent subscribed event for ID we never asked for : {}",
                 request
             );
@@ -105,23 +210,32 @@ pub async fn registered(core: &mut Core<'_>, request: WampId, rpc_id: WampId) ->
 
     // Check for ID collision
     if core.rpc_endpoints.contains_key(&rpc_id) {
-        warn!("Server sent registered ID we already had registered");
+        warn!(target: CORE, "Server sent registered ID we already had registered");
         return Status::Ok;
     }
 
     // Add the registered ID to our registered rpc map
-    let _ = core.rpc_endpoints.insert(rpc_id, rpc_func);
+    let _ = core.rpc_endpoints.insert(rpc_id, (uri, options, rpc_func));
 
     // Send the rpc info back to the requestor
     let _ = res.send(Ok(rpc_id));
 
     Status::Ok
 }
-pub async fn unregisterd(core: &mut Core<'_>, request: WampId) -> Status {
-    let res = match core.pending_transactions.remove(&request) {
-        Some(v) => v,
-        None => {
-            warn!("Server sent unsolicited unregistered ID : {}", request);
+pub async fn unregisterd(
+    core: &mut Core<'_>,
+    request: Option<WampId>,
+    details: WampDict,
+    pending: Option<PendingRequest<'_>>,
+) -> Status {
+    let request = match request {
+        Some(r) => r,
+        None => return registration_revoked(core, details),
+    };
+    let res = match pending {
+        Some(PendingRequest::Transaction(res)) => res,
+        _ => {
+            warn!(target: CORE, "Server sent unsolicited unregistered ID : {}", request);
             return Status::Ok;
         }
     };
@@ -132,34 +246,114 @@ pub async fn unregisterd(core: &mut Core<'_>, request: WampId) -> Status {
     Status::Ok
 }
 
+/// Removes a registration revoked by the router
+fn registration_revoked(core: &mut Core<'_>, details: WampDict) -> Status {
+    let registration = match details.get("registration") {
+        Some(Arg::Id(id)) => *id,
+        _ => {
+            warn!(
+                target: CORE,
+                "Server revoked a registration without its ID : {:?}",
+                details
+            );
+            return Status::Ok;
+        }
+    };
+    let procedure = match core.rpc_endpoints.remove(&registration) {
+        Some((uri, _, _)) => uri,
+        None => {
+            warn!(target: CORE, "Server revoked an unknown registration : {}", registration);
+            return Status::Ok;
+        }
+    };
+    let reason = match details.get("reason") {
+        Some(Arg::Uri(reason)) | Some(Arg::String(reason)) => Some(reason.clone()),
+        _ => None,
+    };
+    warn!(
+        target: CORE,
+        "Server revoked the registration of {} ({:?})",
+        procedure, reason
+    );
+
+    core.report_registration(RegistrationEvent::Revoked {
+        registration,
+        procedure,
+        reason,
+    });
+    Status::Ok
+}
+
 /// Runs the RPC function and forwards the result
+///
+/// When `procedure` is set, the context of a failure is sent along with the ERROR.
 async fn rpc_func_runner(
-    ctl_channel: UnboundedSender<Request<'_>>,
+    ctl_channel: CtlSender<'_>,
+    yield_flow: YieldFlowControl,
     request: WampId,
+    procedure: Option<WampUri>,
     rpc_func: RpcFuture<'_>,
 ) -> Result<(), WampError> {
     // Run the RPC func
+    let started = Instant::now();
     let res = rpc_func.await;
 
+    let error_context = match (&res, procedure) {
+        (Err(e), Some(procedure)) => Some(error_context(e, procedure, started.elapsed())),
+        _ => None,
+    };
+
+    // Wait for the transport to catch up if too many results are pending
+    let slot = yield_flow.acquire().await?;
+
     // Send the result
-    match ctl_channel.send(Request::InvocationResult { request, res }) {
+    match ctl_channel.send(Request::InvocationResult {
+        request,
+        res,
+        error_context,
+        slot: Some(slot),
+    }) {
         Ok(_) => Ok(()),
         Err(_) => Err(From::from("Event loop has died !".to_string())),
     }
 }
 
-pub async fn invocation(
-    core: &mut Core<'_>,
+/// Describes a failed invocation, as documented by [`ERROR_CONTEXT_KWARG`]
+pub(crate) fn error_context(
+    error: &WampError,
+    procedure: WampUri,
+    elapsed: Duration,
+) -> WampKwArgs {
+    let mut chain: WampArgs = vec![error.to_string().into()];
+    let mut source = std::error::Error::source(error);
+    while let Some(e) = source {
+        chain.push(e.to_string().into());
+        source = e.source();
+    }
+
+    let mut context = WampKwArgs::new();
+    context.insert("chain".to_string(), chain.into());
+    context.insert("procedure".to_string(), procedure.into());
+    context.insert(
+        "duration_ms".to_string(),
+        (elapsed.as_millis() as u64).into(),
+    );
+    context
+}
+
+pub async fn invocation<'a>(
+    core: &mut Core<'a>,
     request: WampId,
     registration: WampId,
-    _details: WampDict,
+    details: WampDict,
     arguments: Option<WampArgs>,
     arguments_kw: Option<WampKwArgs>,
 ) -> Status {
-    let rpc_func = match core.rpc_endpoints.get(&registration) {
-        Some(e) => e,
+    let (uri, endpoint) = match core.rpc_endpoints.get(&registration) {
+        Some((uri, _, endpoint)) => (uri, endpoint),
         None => {
             warn!(
+                target: CORE,
                 "Server sent invocation for rpc ID but we do not have this endpoint : {}",
                 registration
             );
@@ -167,16 +361,65 @@ pub async fn invocation(
         }
     };
 
+    // Pattern based registrations tell which procedure was called
+    let procedure = match details.get("procedure") {
+        Some(Arg::Uri(uri)) | Some(Arg::String(uri)) => uri.clone(),
+        _ => uri.clone(),
+    };
+
+    let rpc_func = match endpoint {
+        RpcEndpoint::Func(f) => f,
+        RpcEndpoint::Inline(f) => {
+            let func_ptr = f.clone();
+            return inline_invocation(
+                core,
+                request,
+                registration,
+                procedure,
+                func_ptr,
+                arguments,
+                arguments_kw,
+            )
+            .await;
+        }
+        RpcEndpoint::Stream => {
+            return stream_invocation(
+                core,
+                request,
+                registration,
+                procedure,
+                details,
+                arguments,
+                arguments_kw,
+            )
+            .await
+        }
+    };
+
     let ctl_channel = core.ctl_sender.clone();
-    let func_future = rpc_func(arguments, arguments_kw);
+    let yield_flow = core.yield_flow.clone();
+    let func_future = core.handler_watchdog.guard(
+        procedure.clone(),
+        core.extensions.scope(with_invocation_deadline(
+            &details,
+            rpc_func(details.clone(), arguments, arguments_kw),
+        )),
+    );
 
     // Forward the event to the client
     if core
         .rpc_event_queue_w
-        .send(Box::pin(rpc_func_runner(ctl_channel, request, func_future)))
+        .send(Box::pin(rpc_func_runner(
+            ctl_channel,
+            yield_flow,
+            request,
+            core.error_context.then_some(procedure),
+            func_future,
+        )))
         .is_err()
     {
         warn!(
+            target: CORE,
             "Client not listenning to rpc events but got invocation for rpc ID {}",
             registration
         );
@@ -185,17 +428,155 @@ pub async fn invocation(
 
     Status::Ok
 }
-pub async fn call_result(
-    core: &mut Core<'_>,
+
+/// Runs an inline RPC function and sends its result right away
+async fn inline_invocation<'a>(
+    core: &mut Core<'a>,
     request: WampId,
-    _details: WampDict,
+    registration: WampId,
+    procedure: WampUri,
+    func_ptr: InlineFunc<'a>,
     arguments: Option<WampArgs>,
     arguments_kw: Option<WampKwArgs>,
 ) -> Status {
-    let res = match core.pending_call.remove(&request) {
-        Some(r) => r,
-        None => {
+    let started = Instant::now();
+    let res = match panic::catch_unwind(AssertUnwindSafe(|| {
+        core.extensions
+            .sync_scope(|| func_ptr(arguments, arguments_kw))
+    })) {
+        Ok(res) => res,
+        Err(_) => Err(From::from(format!(
+            "The inline handler of {} panicked",
+            procedure
+        ))),
+    };
+    let elapsed = started.elapsed();
+
+    // The handler blocked the event loop for too long
+    if elapsed > core.inline_handler_budget {
+        core.num_slow_inline_handlers
+            .fetch_add(1, Ordering::Relaxed);
+        match core.slow_inline_handler_policy {
+            SlowInlineHandlerPolicy::Warn => warn!(
+                target: CORE,
+                "Inline handler of {} ran for {:?}, over its budget of {:?}",
+                procedure, elapsed, core.inline_handler_budget
+            ),
+            SlowInlineHandlerPolicy::Demote => {
+                warn!(
+                    target: CORE,
+                    "Inline handler of {} ran for {:?}, its invocations now go through the RPC event queue",
+                    procedure, elapsed
+                );
+                if let Some((_, _, endpoint)) = core.rpc_endpoints.get_mut(&registration) {
+                    *endpoint =
+                        RpcEndpoint::Func(Arc::new(move |_details, arguments, arguments_kw| {
+                            let func_ptr = func_ptr.clone();
+                            Box::pin(async move { func_ptr(arguments, arguments_kw) })
+                        }));
+                }
+            }
+        }
+    }
+
+    let error_context = match &res {
+        Err(e) if core.error_context => Some(error_context(e, procedure, elapsed)),
+        _ => None,
+    };
+    send::invoke_yield(core, request, res, error_context).await
+}
+
+/// Hands the invocation of a streamed procedure to the client
+async fn stream_invocation<'a>(
+    core: &mut Core<'a>,
+    request: WampId,
+    registration: WampId,
+    procedure: WampUri,
+    details: WampDict,
+    arguments: Option<WampArgs>,
+    arguments_kw: Option<WampKwArgs>,
+) -> Status {
+    let uri = core.rpc_endpoints.get(&registration).map(|(uri, _, _)| uri);
+    let codec = uri.and_then(|uri| core.payload_codecs.get(uri)).cloned();
+    let (arguments, arguments_kw) = match &codec {
+        Some(codec) => match codec.decode(arguments, arguments_kw) {
+            Ok(payload) => payload,
+            Err(e) => {
+                let e = WampError::InvalidArgument(e.to_string());
+                return send::invoke_yield(core, request, Err(e), None).await;
+            }
+        },
+        None => (arguments, arguments_kw),
+    };
+
+    // Reject invalid invocations before they reach the user
+    let validator = uri.and_then(|uri| core.payload_validators.get(uri));
+    if let Some(validator) = validator {
+        if let Err(e) = validator(arguments.as_ref(), arguments_kw.as_ref()) {
+            return send::invoke_yield(core, request, Err(WampError::InvalidArgument(e)), None)
+                .await;
+        }
+    }
+
+    let invocation = Invocation::new(
+        request,
+        registration,
+        procedure,
+        details,
+        arguments,
+        arguments_kw,
+        core.ctl_sender.clone(),
+        core.yield_flow.clone(),
+        core.error_context,
+        codec,
+        core.extensions.clone(),
+    );
+    if let Err(e) = core.invocations_w.send(invocation) {
+        // The invocation fails the call when dropped
+        warn!(
+            target: CORE,
+            "Client not listenning to invocations but got invocation for rpc ID {}",
+            registration
+        );
+        drop(e);
+    }
+
+    Status::Ok
+}
+
+pub async fn call_result<'a>(
+    core: &mut Core<'a>,
+    request: WampId,
+    pending: Option<PendingRequest<'a>>,
+    details: WampDict,
+    arguments: Option<WampArgs>,
+    arguments_kw: Option<WampKwArgs>,
+) -> Status {
+    let (res, codec) = match pending {
+        // A progressive result, the call is still pending
+        Some(PendingRequest::Call(res, progress, codec))
+            if matches!(details.get("progress"), Some(Arg::Bool(true))) =>
+        {
+            if let Some(progress) = &progress {
+                match decode_result(codec.as_deref(), arguments, arguments_kw) {
+                    Ok(payload) => {
+                        let _ = progress.send(payload);
+                    }
+                    Err(e) => warn!(
+                        target: CORE,
+                        "Dropping progressive result of call {} that could not be decoded : {}",
+                        request, e
+                    ),
+                }
+            }
+            core.pending_requests
+                .insert(request, PendingRequest::Call(res, progress, codec));
+            return Status::Ok;
+        }
+        Some(PendingRequest::Call(res, _, codec)) => (res, codec),
+        _ => {
             warn!(
+                target: CORE,
                 "Server sent result for CALL we never sent : request id {}",
                 request
             );
@@ -204,21 +585,42 @@ pub async fn call_result(
     };
 
     // Forward the event to the client
-    if res.send(Ok((arguments, arguments_kw))).is_err() {
-        warn!("Client not waiting for call result id {}", request);
+    let payload = decode_result(codec.as_deref(), arguments, arguments_kw);
+    if res.send(payload).is_err() {
+        warn!(target: CORE, "Client not waiting for call result id {}", request);
         // TODO : Should we be nice and send an UNSUBSCRIBE to the server ?
     }
 
     Status::Ok
 }
 
+/// Decodes the result of a call with the codec of its procedure, if any
+fn decode_result(
+    codec: Option<&dyn PayloadCodec>,
+    arguments: Option<WampArgs>,
+    arguments_kw: Option<WampKwArgs>,
+) -> Result<CallResult, WampError> {
+    match codec {
+        Some(codec) => codec.decode(arguments, arguments_kw),
+        None => Ok((arguments, arguments_kw)),
+    }
+}
+
 pub async fn goodbye(core: &mut Core<'_>, details: WampDict, reason: WampString) -> Status {
-    debug!("Server sent goodbye : {:?} {:?}", details, reason);
+    debug!(target: CORE, "Server sent goodbye : {:?} {:?}", details, reason);
 
-    if !core.valid_session && reason == "wamp.close.goodbye_and_out" {
+    let state = core.session_state();
+    if core.router_dialect.is_goodbye_ack(&reason)
+        && matches!(state, SessionState::Leaving | SessionState::Joining)
+    {
+        // The router acknowledged our GOODBYE. We may already be joining a realm again.
+        if state == SessionState::Leaving {
+            let _ = core.set_session_state(SessionState::Connected);
+        }
         Status::Ok
     } else {
-        debug!("Peer is closing on us !");
+        debug!(target: CORE, "Peer is closing on us !");
+        core.close_reason = Some(WampError::SessionClosed(reason.into(), details));
         let _ = core
             .send(&Msg::Goodbye {
                 details: WampDict::new(),
@@ -229,63 +631,136 @@ pub async fn goodbye(core: &mut Core<'_>, details: WampDict, reason: WampString)
     }
 }
 
-pub async fn abort(_core: &mut Core<'_>, details: WampDict, reason: WampString) -> Status {
-    error!("Server sent abort : {:?} {:?}", details, reason);
+pub async fn abort(core: &mut Core<'_>, details: WampDict, reason: WampString) -> Status {
+    error!(target: CORE, "Server sent abort : {:?} {:?}", details, reason);
+    if core.pending_join.is_some() {
+        core.fail_join(WampError::ServerError(reason, details));
+    } else {
+        core.close_reason = Some(WampError::SessionClosed(reason.into(), details));
+    }
     Status::Shutdown
 }
+
+pub async fn welcome(core: &mut Core<'_>, session: WampId, details: WampDict) -> Status {
+    let join = match core.pending_join.take() {
+        Some(j) => j,
+        None => {
+            warn!(target: CORE, "Server sent WELCOME but we are not joining a realm");
+            return Status::Ok;
+        }
+    };
+
+    if let Some(tokens) = &core.routing_tokens {
+        tokens.update(&details);
+    }
+
+    // Return the pertinent info to the caller
+    if let Err(e) = core.set_session_state(SessionState::Joined(session)) {
+        let _ = join.res.send(Err(e));
+        return Status::Shutdown;
+    }
+    let _ = join.res.send(Ok((session, details)));
+
+    Status::Ok
+}
+
+pub async fn challenge(
+    core: &mut Core<'_>,
+    authentication_method: AuthenticationMethod,
+    extra: WampDict,
+) -> Status {
+    let challenge_deadline = core
+        .challenge_timeout
+        .map(|t| tokio::time::Instant::now() + t);
+    let join = match core.pending_join.as_mut() {
+        Some(j) => j,
+        None => {
+            warn!(target: AUTH, "Server sent CHALLENGE but we are not joining a realm");
+            return Status::Ok;
+        }
+    };
+    debug!(
+        target: AUTH,
+        "Server sent a {:?} CHALLENGE", authentication_method
+    );
+
+    // A single CHALLENGE is sent per handshake
+    if join.challenged {
+        warn!(target: AUTH, "Server sent a second CHALLENGE during the handshake");
+        let _ = core
+            .send(&Msg::Abort {
+                details: WampDict::new(),
+                reason: "wamp.error.protocol_violation".to_string(),
+            })
+            .await;
+        core.fail_join(WampError::ProtocolError(
+            "CHALLENGE received twice during the handshake".to_string(),
+        ));
+        return Status::Shutdown;
+    }
+    join.challenged = true;
+
+    match join.on_challenge_handler {
+        Some(ref on_challenge_handler) => {
+            // The answer is sent once the handler completes, see Core::event_loop
+            join.challenge = Some(on_challenge_handler(authentication_method, extra));
+            join.deadline = challenge_deadline;
+            Status::Ok
+        }
+        None => {
+            core.fail_join(From::from(
+                "Server requested a CHALLENGE to authenticate, but there was no challenge handler provided".to_string()
+            ));
+            Status::Shutdown
+        }
+    }
+}
 // Handles an error sent by the peer
+#[allow(clippy::too_many_arguments)]
 pub async fn error(
     core: &mut Core<'_>,
     typ: WampInteger,
     request: WampId,
+    pending: Option<PendingRequest<'_>>,
     details: WampDict,
     error: WampUri,
-    _arguments: Option<WampArgs>,
-    _arguments_kw: Option<WampKwArgs>,
+    arguments: Option<WampArgs>,
+    arguments_kw: Option<WampKwArgs>,
 ) -> Status {
-    let error = WampError::ServerError(error, details);
-    match typ {
-        SUBSCRIBE_ID => {
-            let res = match core.pending_sub.remove(&request) {
-                Some(r) => r,
-                None => {
-                    warn!("Received error for subscribe message we never sent");
-                    return Status::Ok;
-                }
-            };
-            let _ = res.send(Err(error));
-        }
-        REGISTER_ID => {
-            let (_, res) = match core.pending_register.remove(&request) {
-                Some(r) => r,
-                None => {
-                    warn!("Received error for RPC register message we never sent");
-                    return Status::Ok;
-                }
-            };
-            let _ = res.send(Err(error));
-        }
-        CALL_ID => {
-            let res = match core.pending_call.remove(&request) {
-                Some(r) => r,
-                None => {
-                    warn!("Received error for CALL message we never sent");
-                    return Status::Ok;
-                }
-            };
-            let _ = res.send(Err(error));
-        }
-        PUBLISH_ID | UNSUBSCRIBE_ID | UNREGISTER_ID => {
-            let res = match core.pending_transactions.remove(&request) {
-                Some(r) => r,
-                None => {
-                    warn!("Received error for message we never sent");
-                    return Status::Ok;
-                }
-            };
-            let _ = res.send(Err(error));
+    match (typ, pending) {
+        (INVOCATION_ID | YIELD_ID, _) => {
+            warn!(
+                target: CORE,
+                "Router rejected our result for invocation {} : {}",
+                request, error
+            );
+        }
+        // The callee acknowledged the cancellation of the call
+        (CALL_ID, Some(pending @ PendingRequest::Call(..))) if error == "wamp.error.canceled" => {
+            pending.fail(WampError::CallCanceled);
+            return Status::Ok;
+        }
+        // The request failed
+        (_, Some(pending)) => {
+            pending.fail(WampError::ServerError(error, details));
+            return Status::Ok;
+        }
+        (_, None) => {
+            warn!(
+                target: CORE,
+                "Peer sent an error for an unknown request {} : {}",
+                request, error
+            );
         }
-        _ => {}
     };
+
+    core.report_error(UnhandledError {
+        message_type: typ,
+        request,
+        error,
+        details,
+        arguments,
+        arguments_kw,
+    });
     Status::Ok
 }
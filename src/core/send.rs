@@ -1,11 +1,17 @@
 use std::collections::{HashMap, HashSet};
+use std::num::NonZeroU64;
+use std::sync::atomic::Ordering;
+use std::time::Instant;
 
+use futures::FutureExt;
 use log::*;
-use tokio::sync::oneshot::Sender;
+use tokio::sync::oneshot::{self, Sender};
+use tokio::sync::OwnedSemaphorePermit;
 
 use crate::common::*;
 use crate::core::*;
 use crate::message::*;
+use crate::transport::TransportError;
 
 pub type JoinRealmResult = Result<(WampId, HashMap<WampString, Arg>), WampError>;
 pub enum Request<'a> {
@@ -16,6 +22,7 @@ pub enum Request<'a> {
         agent_str: Option<WampString>,
         authentication_methods: Vec<AuthenticationMethod>,
         authentication_id: Option<WampString>,
+        authrole: Option<WampString>,
         on_challenge_handler: Option<AuthenticationChallengeHandler<'a>>,
         res: Sender<JoinRealmResult>,
     },
@@ -24,23 +31,30 @@ pub enum Request<'a> {
     },
     Subscribe {
         uri: WampString,
-        res: PendingSubResult,
+        options: WampDict,
+        res: PendingSub,
     },
     Unsubscribe {
         sub_id: WampId,
         res: Sender<Result<Option<WampId>, WampError>>,
     },
+    /// Subscribes again to the topics of the active subscriptions, after joining a new session
+    Resubscribe {
+        res: PendingResubscribeResult,
+    },
     Publish {
         uri: WampString,
         options: WampDict,
         arguments: Option<WampArgs>,
         arguments_kw: Option<WampKwArgs>,
+        local_echo: bool,
         res: Sender<Result<Option<WampId>, WampError>>,
     },
     Register {
         uri: WampString,
+        options: WampDict,
         res: PendingRegisterResult,
-        func_ptr: RpcFunc<'a>,
+        endpoint: RpcEndpoint<'a>,
     },
     Unregister {
         rpc_id: WampId,
@@ -48,7 +62,11 @@ pub enum Request<'a> {
     },
     InvocationResult {
         request: WampId,
-        res: Result<(Option<WampArgs>, Option<WampKwArgs>), WampError>,
+        res: Result<RpcResponse, WampError>,
+        /// Context of the failure, sent in the ERROR kwargs
+        error_context: Option<WampKwArgs>,
+        /// Held until the result is sent, `None` when it skipped the flow control
+        slot: Option<OwnedSemaphorePermit>,
     },
     Call {
         uri: WampString,
@@ -56,25 +74,74 @@ pub enum Request<'a> {
         arguments: Option<WampArgs>,
         arguments_kw: Option<WampKwArgs>,
         res: PendingCallResult,
+        /// Receives the request ID once the CALL is sent
+        sent: Option<Sender<WampId>>,
+        /// Receives the progressive results of the call
+        progress: Option<UnboundedSender<CallResult>>,
+    },
+    Cancel {
+        request: WampId,
+        mode: CancelMode,
+        res: Sender<Result<(), WampError>>,
+    },
+    Ping {
+        res: PendingPingResult,
+    },
+    /// Lists the registrations and subscriptions of the client
+    ListActive {
+        res: Sender<ActiveEntries>,
     },
 }
 
-/// Handler for any join realm request. This will send a HELLO and wait for the WELCOME response
-pub async fn join_realm(
-    core: &mut Core<'_>,
+impl Request<'_> {
+    /// Returns the name of the request
+    pub fn name(&self) -> &'static str {
+        match self {
+            Request::Shutdown => "shutdown",
+            Request::Join { .. } => "join",
+            Request::Leave { .. } => "leave",
+            Request::Subscribe { .. } => "subscribe",
+            Request::Unsubscribe { .. } => "unsubscribe",
+            Request::Resubscribe { .. } => "resubscribe",
+            Request::Publish { .. } => "publish",
+            Request::Register { .. } => "register",
+            Request::Unregister { .. } => "unregister",
+            Request::InvocationResult { .. } => "invocation_result",
+            Request::Call { .. } => "call",
+            Request::Cancel { .. } => "cancel",
+            Request::Ping { .. } => "ping",
+            Request::ListActive { .. } => "list_active",
+        }
+    }
+}
+
+/// Handler for any join realm request. This will send a HELLO, the WELCOME response is handled
+/// by the event loop
+#[allow(clippy::too_many_arguments)]
+pub async fn join_realm<'a>(
+    core: &mut Core<'a>,
     uri: WampString,
     roles: HashSet<ClientRole>,
     agent_str: Option<WampString>,
     authentication_methods: Vec<AuthenticationMethod>,
     authid: Option<WampString>,
-    on_challenge_handler: Option<AuthenticationChallengeHandler<'_>>,
+    authrole: Option<WampString>,
+    on_challenge_handler: Option<AuthenticationChallengeHandler<'a>>,
     res: JoinResult,
 ) -> Status {
     let mut details: WampDict = WampDict::new();
     let mut client_roles: WampDict = WampDict::new();
     // Add all of our roles
     for role in &roles {
-        client_roles.insert(String::from(role.to_str()), Arg::Dict(WampDict::new()));
+        let mut role_details = WampDict::new();
+        if core.router_dialect.announces_features() {
+            let mut features = WampDict::new();
+            for feature in role.features() {
+                features.insert(feature.to_string(), Arg::Bool(true));
+            }
+            role_details.insert("features".to_owned(), Arg::Dict(features));
+        }
+        client_roles.insert(String::from(role.to_str()), Arg::Dict(role_details));
     }
     details.insert("roles".to_owned(), Arg::Dict(client_roles));
 
@@ -94,12 +161,28 @@ pub async fn join_realm(
                     .collect::<Vec<_>>(),
             ),
         );
+        if core.router_dialect.requires_authextra() {
+            details.insert("authextra".to_owned(), Arg::Dict(WampDict::new()));
+        }
     }
 
     if let Some(authid) = authid {
         details.insert("authid".to_owned(), Arg::String(authid));
     }
 
+    if let Some(authrole) = authrole {
+        details.insert("authrole".to_owned(), Arg::String(authrole));
+    }
+
+    if let Some(tokens) = &core.routing_tokens {
+        tokens.add_to(&mut details);
+    }
+
+    if let Err(e) = core.set_session_state(SessionState::Joining) {
+        let _ = res.send(Err(e));
+        return Status::Ok;
+    }
+
     // Send hello with our info
     if let Err(e) = core
         .send(&Msg::Hello {
@@ -112,64 +195,54 @@ pub async fn join_realm(
         return Status::Shutdown;
     }
 
-    // Make sure the server responded with the proper message
-    let (session_id, server_roles) = loop {
-        // Receive the response to the HELLO message (either WELCOME or CHALLENGE are expected)
-        let resp = match core.recv().await {
-            Ok(r) => r,
-            Err(e) => {
-                let _ = res.send(Err(e));
-                return Status::Shutdown;
-            }
-        };
-
-        match resp {
-            Msg::Welcome { session, details } => break (session, details),
-            Msg::Challenge {
-                authentication_method,
-                extra,
-            } => {
-                if let Some(ref on_challenge_handler) = on_challenge_handler {
-                    match on_challenge_handler(authentication_method, extra).await {
-                        Ok(AuthenticationChallengeResponse { signature, extra }) => {
-                            if let Err(e) = core.send(&Msg::Authenticate { signature, extra }).await
-                            {
-                                let _ = res.send(Err(e));
-                                return Status::Shutdown;
-                            }
-                        }
-                        Err(e) => {
-                            let _ = res.send(Err(e));
-                            return Status::Shutdown;
-                        }
-                    }
-                } else {
-                    let _ = res.send(Err(From::from(
-                        "Server requested a CHALLENGE to authenticate, but there was no challenge handler provided".to_string()
-                    )));
-                    return Status::Shutdown;
-                }
-            }
-            m => {
-                let _ = res.send(Err(From::from(format!(
-                    "Server did not respond with WELCOME : {:?}",
-                    m
-                ))));
-                return Status::Shutdown;
-            }
+    // The WELCOME or CHALLENGE response is handled by the event loop along with other messages
+    core.pending_join = Some(PendingJoin {
+        on_challenge_handler,
+        res,
+        challenge: None,
+        challenged: false,
+        deadline: core.handshake_deadline(),
+    });
+
+    Status::Ok
+}
+
+/// Sends the answer of the challenge handler to the server
+pub async fn authenticate(
+    core: &mut Core<'_>,
+    resp: Result<AuthenticationChallengeResponse, WampError>,
+) -> Status {
+    let AuthenticationChallengeResponse { signature, extra } = match resp {
+        Ok(r) => r,
+        Err(e) => {
+            warn!(target: AUTH, "Challenge handler failed : {}", e);
+            core.fail_join(e);
+            return Status::Shutdown;
         }
     };
 
-    // Return the pertinent info to the caller
-    core.valid_session = true;
-    let _ = res.send(Ok((session_id, server_roles)));
+    if let Err(e) = core.send(&Msg::Authenticate { signature, extra }).await {
+        core.fail_join(e);
+        return Status::Shutdown;
+    }
+
+    debug!(target: AUTH, "Answered the CHALLENGE");
+
+    // Wait for the server again
+    let deadline = core.handshake_deadline();
+    if let Some(join) = core.pending_join.as_mut() {
+        join.deadline = deadline;
+    }
 
     Status::Ok
 }
 
 /// Handler for any leave realm request. This function will send a GOODBYE and wait for a GOODBYE response
 pub async fn leave_realm(core: &mut Core<'_>, res: Sender<Result<(), WampError>>) -> Status {
-    core.valid_session = false;
+    if let Err(e) = core.set_session_state(SessionState::Leaving) {
+        let _ = res.send(Err(e));
+        return Status::Ok;
+    }
 
     if let Err(e) = core
         .send(&Msg::Goodbye {
@@ -187,36 +260,111 @@ pub async fn leave_realm(core: &mut Core<'_>, res: Sender<Result<(), WampError>>
     Status::Ok
 }
 
-pub async fn subscribe(core: &mut Core<'_>, topic: WampString, res: PendingSubResult) -> Status {
+pub async fn subscribe(
+    core: &mut Core<'_>,
+    topic: WampString,
+    options: WampDict,
+    res: PendingSub,
+) -> Status {
     let request = core.create_request();
+    let validator = core.payload_validators.get(&topic).cloned();
 
     if let Err(e) = core
         .send(&Msg::Subscribe {
             request,
-            topic,
-            options: WampDict::new(),
+            topic: topic.clone(),
+            options: options.clone(),
         })
         .await
     {
-        core.pending_requests.remove(&request);
-        let _ = res.send(Err(e));
+        res.fail(e);
         return Status::Shutdown;
     }
 
-    core.pending_sub.insert(request, res);
+    core.pending_requests.insert(
+        request,
+        PendingRequest::Subscribe(topic, options, validator, res),
+    );
 
     Status::Ok
 }
 
+/// Subscribes again to the topics of the active subscriptions. Their events keep being
+/// forwarded to the same queues once the router acknowledges them.
+pub async fn resubscribe(core: &mut Core<'_>, res: PendingResubscribeResult) -> Status {
+    let subscriptions: Vec<(WampId, WampUri, WampDict)> = core
+        .subscriptions
+        .iter()
+        .map(|(sub_id, sub)| (*sub_id, sub.topic.clone(), sub.options.clone()))
+        .collect();
+
+    let mut results = Vec::with_capacity(subscriptions.len());
+    let mut status = Status::Ok;
+    for (sub_id, topic, options) in subscriptions {
+        let request = core.create_request();
+        let (sub_res, result) = oneshot::channel();
+        results.push(result);
+
+        if let Err(e) = core
+            .send(&Msg::Subscribe {
+                request,
+                topic,
+                options,
+            })
+            .await
+        {
+            let _ = sub_res.send(Err(e));
+            status = Status::Shutdown;
+            break;
+        }
+        core.pending_requests
+            .insert(request, PendingRequest::Resubscribe(sub_id, sub_res));
+    }
+
+    let _ = res.send(results);
+    status
+}
+
+/// Lists the registrations and subscriptions along with the options they were made with
+pub async fn list_active(core: &mut Core<'_>, res: Sender<ActiveEntries>) -> Status {
+    let registrations = core
+        .rpc_endpoints
+        .iter()
+        .map(|(rpc_id, (uri, options, _))| (*rpc_id, uri.clone(), options.clone()))
+        .collect();
+
+    // Report the subscriptions renewed in a new session under the ID known by the client
+    let aliases: HashMap<WampId, WampId> = core
+        .subscription_aliases
+        .iter()
+        .map(|(known, current)| (*current, *known))
+        .collect();
+    let subscriptions = core
+        .subscriptions
+        .iter()
+        .map(|(sub_id, sub)| {
+            let sub_id = aliases.get(sub_id).unwrap_or(sub_id);
+            (*sub_id, sub.topic.clone(), sub.options.clone())
+        })
+        .collect();
+
+    let _ = res.send(ActiveEntries {
+        registrations,
+        subscriptions,
+    });
+    Status::Ok
+}
+
 pub async fn unsubscribe(
     core: &mut Core<'_>,
     sub_id: WampId,
     res: Sender<Result<Option<WampId>, WampError>>,
 ) -> Status {
+    let sub_id = core.subscription_aliases.remove(&sub_id).unwrap_or(sub_id);
     match core.subscriptions.remove(&sub_id) {
         Some(_v) => { /*drop*/ }
         None => {
-            warn!("Tried to unsubscribe using invalid sub_id : {}", sub_id);
+            warn!(target: CORE, "Tried to unsubscribe using invalid sub_id : {}", sub_id);
             let _ = res.send(Err(From::from(
                 "Tried to unsubscribe from unknown sub_id".to_string(),
             )));
@@ -233,12 +381,12 @@ pub async fn unsubscribe(
         })
         .await
     {
-        core.pending_requests.remove(&request);
         let _ = res.send(Err(e));
         return Status::Shutdown;
     }
 
-    core.pending_transactions.insert(request, res);
+    core.pending_requests
+        .insert(request, PendingRequest::Transaction(res));
 
     Status::Ok
 }
@@ -249,10 +397,29 @@ pub async fn publish(
     options: WampDict,
     arguments: Option<WampArgs>,
     arguments_kw: Option<WampKwArgs>,
+    local_echo: bool,
     res: Sender<Result<Option<WampId>, WampError>>,
 ) -> Status {
     let request = core.create_request();
+    // The broker only answers acknowledged publications
+    let acknowledge = matches!(options.get("acknowledge"), Some(Arg::Bool(true)));
+    // Our own subscriptions to the topic decode the event like the ones of the other peers
+    let (arguments, arguments_kw) = match core.payload_codecs.get(&uri) {
+        Some(codec) => match codec.encode(arguments, arguments_kw) {
+            Ok(payload) => payload,
+            Err(e) => {
+                let _ = res.send(Err(e));
+                return Status::Ok;
+            }
+        },
+        None => (arguments, arguments_kw),
+    };
 
+    let echo = if local_echo {
+        Some((uri.clone(), arguments.clone(), arguments_kw.clone()))
+    } else {
+        None
+    };
     if let Err(e) = core
         .send(&Msg::Publish {
             request,
@@ -263,12 +430,34 @@ pub async fn publish(
         })
         .await
     {
-        core.pending_requests.remove(&request);
+        let status = Status::after_send_error(&e);
         let _ = res.send(Err(e));
-        return Status::Shutdown;
+        return status;
+    }
+
+    // Deliver the event to our own subscriptions without waiting for the broker. As the broker
+    // excludes the publisher by default, the event is not received twice.
+    if let Some((uri, arguments, arguments_kw)) = echo {
+        let publication = WampId::generate();
+        for (sub_id, active_sub) in &core.subscriptions {
+            if active_sub.topic == uri {
+                active_sub.deliver(
+                    *sub_id,
+                    publication,
+                    WampDict::new(),
+                    arguments.clone(),
+                    arguments_kw.clone(),
+                );
+            }
+        }
     }
 
-    core.pending_transactions.insert(request, res);
+    if acknowledge {
+        core.pending_requests
+            .insert(request, PendingRequest::Transaction(res));
+    } else {
+        let _ = res.send(Ok(None));
+    }
 
     Status::Ok
 }
@@ -276,25 +465,79 @@ pub async fn publish(
 pub async fn register<'a>(
     core: &mut Core<'a>,
     uri: WampString,
+    options: WampDict,
     res: PendingRegisterResult,
-    func_ptr: RpcFunc<'a>,
+    endpoint: RpcEndpoint<'a>,
 ) -> Status {
     let request = core.create_request();
 
+    // Reject invalid invocations before they reach the user's function
+    let endpoint = match (endpoint, core.payload_validators.get(&uri).cloned()) {
+        (RpcEndpoint::Func(func_ptr), Some(validator)) => RpcEndpoint::Func(Arc::new(
+            move |details, arguments, arguments_kw| match validator(
+                arguments.as_ref(),
+                arguments_kw.as_ref(),
+            ) {
+                Ok(()) => func_ptr(details, arguments, arguments_kw),
+                Err(e) => Box::pin(async move { Err(WampError::InvalidArgument(e)) }),
+            },
+        )),
+        (RpcEndpoint::Inline(func_ptr), Some(validator)) => {
+            RpcEndpoint::Inline(Arc::new(move |arguments, arguments_kw| {
+                validator(arguments.as_ref(), arguments_kw.as_ref())
+                    .map_err(WampError::InvalidArgument)?;
+                func_ptr(arguments, arguments_kw)
+            }))
+        }
+        (endpoint, _) => endpoint,
+    };
+
+    // Decode the invocations before they are validated and encode the results
+    let endpoint = match (endpoint, core.payload_codecs.get(&uri).cloned()) {
+        (RpcEndpoint::Func(func_ptr), Some(codec)) => {
+            RpcEndpoint::Func(Arc::new(move |details, arguments, arguments_kw| {
+                let (arguments, arguments_kw) = match codec.decode(arguments, arguments_kw) {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        return Box::pin(
+                            async move { Err(WampError::InvalidArgument(e.to_string())) },
+                        )
+                    }
+                };
+                let codec = codec.clone();
+                Box::pin(
+                    func_ptr(details, arguments, arguments_kw)
+                        .map(move |res| encode_response(codec.as_ref(), res?)),
+                )
+            }))
+        }
+        (RpcEndpoint::Inline(func_ptr), Some(codec)) => {
+            RpcEndpoint::Inline(Arc::new(move |arguments, arguments_kw| {
+                let (arguments, arguments_kw) = codec
+                    .decode(arguments, arguments_kw)
+                    .map_err(|e| WampError::InvalidArgument(e.to_string()))?;
+                encode_response(codec.as_ref(), func_ptr(arguments, arguments_kw)?)
+            }))
+        }
+        (endpoint, _) => endpoint,
+    };
+
     if let Err(e) = core
         .send(&Msg::Register {
             request,
-            procedure: uri,
-            options: WampDict::new(),
+            procedure: uri.clone(),
+            options: options.clone(),
         })
         .await
     {
-        core.pending_requests.remove(&request);
         let _ = res.send(Err(e));
         return Status::Shutdown;
     }
 
-    core.pending_register.insert(request, (func_ptr, res));
+    core.pending_requests.insert(
+        request,
+        PendingRequest::Register(uri, options, endpoint, res),
+    );
     Status::Ok
 }
 
@@ -306,7 +549,7 @@ pub async fn unregister(
     match core.rpc_endpoints.remove(&rpc_id) {
         Some(_v) => { /*drop*/ }
         None => {
-            warn!("Tried to unregister RPC using invalid ID : {}", rpc_id);
+            warn!(target: CORE, "Tried to unregister RPC using invalid ID : {}", rpc_id);
             let _ = res.send(Err(From::from(
                 "Tried to unregister RPC using invalid ID".to_string(),
             )));
@@ -323,12 +566,12 @@ pub async fn unregister(
         })
         .await
     {
-        core.pending_requests.remove(&request);
         let _ = res.send(Err(e));
         return Status::Shutdown;
     }
 
-    core.pending_transactions.insert(request, res);
+    core.pending_requests
+        .insert(request, PendingRequest::Transaction(res));
 
     Status::Ok
 }
@@ -336,31 +579,133 @@ pub async fn unregister(
 pub async fn invoke_yield(
     core: &mut Core<'_>,
     request: WampId,
-    res: Result<(Option<WampArgs>, Option<WampKwArgs>), WampError>,
+    res: Result<RpcResponse, WampError>,
+    error_context: Option<WampKwArgs>,
 ) -> Status {
-    let msg: Msg = match res {
-        Ok((arguments, arguments_kw)) => Msg::Yield {
+    let msg = invocation_reply(request, res, error_context);
+    let payload = match core.pack(&msg) {
+        Ok(p) => p,
+        Err(e) => {
+            error!(
+                target: CORE,
+                "Failed to serialize the result of invocation {} : {}",
+                request, e
+            );
+            return Status::Shutdown;
+        }
+    };
+    core.largest_response
+        .fetch_max(payload.len(), Ordering::Relaxed);
+
+    // Refuse results the router would reject instead of failing mid-flight
+    let refused = match core.connection_info.max_message_size {
+        Some(max) if payload.len() > max => {
+            warn!(
+                target: CORE,
+                "The result of invocation {} is {} bytes, larger than the {} bytes accepted by the router",
+                request,
+                payload.len(),
+                max
+            );
+            match core.oversized_response_policy {
+                OversizedResponsePolicy::Fail => Some((payload.len(), max)),
+                OversizedResponsePolicy::Warn => None,
+            }
+        }
+        _ => None,
+    };
+    let (size, max) = match refused {
+        Some(refused) => refused,
+        None => match core.send_packed(&msg, &payload).await {
+            Ok(()) => return Status::Ok,
+            // The transport refused the result, the caller must still get an answer
+            Err(WampError::ConnectionError(TransportError::MessageTooLarge(size, max))) => {
+                (size, max)
+            }
+            Err(e) => return Status::after_send_error(&e),
+        },
+    };
+    let msg = invocation_reply(request, Err(WampError::PayloadTooLarge(size, max)), None);
+    match core.send(&msg).await {
+        Ok(()) => Status::Ok,
+        Err(e) => Status::after_send_error(&e),
+    }
+}
+
+/// Builds the YIELD or ERROR answering an invocation
+fn invocation_reply(
+    request: WampId,
+    res: Result<RpcResponse, WampError>,
+    error_context: Option<WampKwArgs>,
+) -> Msg {
+    let context_kw = error_context.map(|context| {
+        let mut kwargs = WampKwArgs::new();
+        kwargs.insert(ERROR_CONTEXT_KWARG.to_string(), context.into());
+        kwargs
+    });
+    match res {
+        Ok(RpcResponse {
+            arguments,
+            arguments_kw,
+            options,
+        }) => Msg::Yield {
             request,
-            options: WampDict::new(),
+            options,
             arguments,
             arguments_kw,
         },
+        Err(WampError::InvalidArgument(e)) => Msg::Error {
+            typ: INVOCATION_ID as WampInteger,
+            request,
+            details: WampDict::new(),
+            error: "wamp.error.invalid_argument".to_string(),
+            arguments: Some(vec![e.into()]),
+            arguments_kw: context_kw,
+        },
+        Err(WampError::Unavailable(e)) => Msg::Error {
+            typ: INVOCATION_ID as WampInteger,
+            request,
+            details: WampDict::new(),
+            error: "wamp.error.unavailable".to_string(),
+            arguments: Some(vec![e.into()]),
+            arguments_kw: context_kw,
+        },
+        Err(e @ WampError::HandlerTimeout(..)) => Msg::Error {
+            typ: INVOCATION_ID as WampInteger,
+            request,
+            details: WampDict::new(),
+            error: "wamp.error.timeout".to_string(),
+            arguments: Some(vec![e.to_string().into()]),
+            arguments_kw: context_kw,
+        },
+        Err(e @ WampError::PayloadTooLarge(..)) => Msg::Error {
+            typ: INVOCATION_ID as WampInteger,
+            request,
+            details: WampDict::new(),
+            error: "wamp.error.payload_size_exceeded".to_string(),
+            arguments: Some(vec![e.to_string().into()]),
+            arguments_kw: context_kw,
+        },
+        Err(WampError::ServerError(error, details)) => Msg::Error {
+            typ: INVOCATION_ID as WampInteger,
+            request,
+            details,
+            error,
+            arguments: None,
+            arguments_kw: context_kw,
+        },
         Err(e) => Msg::Error {
             typ: INVOCATION_ID as WampInteger,
             request,
             details: WampDict::new(),
             error: "wamp.async.rs.rpc.failed".to_string(),
             arguments: Some(vec![format!("{:?}", e).into()]),
-            arguments_kw: None,
+            arguments_kw: context_kw,
         },
-    };
-    if core.send(&msg).await.is_err() {
-        return Status::Shutdown;
     }
-
-    Status::Ok
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn call(
     core: &mut Core<'_>,
     uri: WampString,
@@ -368,8 +713,21 @@ pub async fn call(
     arguments: Option<WampArgs>,
     arguments_kw: Option<WampKwArgs>,
     res: PendingCallResult,
+    sent: Option<Sender<WampId>>,
+    progress: Option<UnboundedSender<CallResult>>,
 ) -> Status {
     let request = core.create_request();
+    let codec = core.payload_codecs.get(&uri).cloned();
+    let (arguments, arguments_kw) = match &codec {
+        Some(codec) => match codec.encode(arguments, arguments_kw) {
+            Ok(payload) => payload,
+            Err(e) => {
+                let _ = res.send(Err(e));
+                return Status::Ok;
+            }
+        },
+        None => (arguments, arguments_kw),
+    };
 
     if let Err(e) = core
         .send(&Msg::Call {
@@ -381,12 +739,74 @@ pub async fn call(
         })
         .await
     {
-        core.pending_requests.remove(&request);
+        let status = Status::after_send_error(&e);
+        let _ = res.send(Err(e));
+        return status;
+    }
+
+    core.pending_requests
+        .insert(request, PendingRequest::Call(res, progress, codec));
+    if let Some(sent) = sent {
+        let _ = sent.send(request);
+    }
+
+    Status::Ok
+}
+
+/// Asks the dealer to cancel a pending call
+///
+/// With the `skip` and `killnowait` modes, the call fails right away and the ERROR sent by the
+/// dealer is ignored. With `kill`, it fails once the dealer forwards the ERROR of the callee.
+pub async fn cancel(
+    core: &mut Core<'_>,
+    request: WampId,
+    mode: CancelMode,
+    res: Sender<Result<(), WampError>>,
+) -> Status {
+    if !matches!(
+        core.pending_requests.get(&request),
+        Some(PendingRequest::Call(..))
+    ) {
+        let _ = res.send(Err(From::from(format!(
+            "Tried to cancel call {} which is not pending",
+            request
+        ))));
+        return Status::Ok;
+    }
+
+    let mut options = WampDict::new();
+    options.insert("mode".to_string(), Arg::String(mode.to_str().to_string()));
+    if let Err(e) = core.send(&Msg::Cancel { request, options }).await {
+        let status = Status::after_send_error(&e);
         let _ = res.send(Err(e));
+        return status;
+    }
+
+    if mode != CancelMode::Kill {
+        if let Some(pending) = core.pending_requests.remove(&request) {
+            pending.fail(WampError::CallCanceled);
+        }
+        core.canceled_calls.insert(request);
+    }
+    let _ = res.send(Ok(()));
+
+    Status::Ok
+}
+
+/// Sends a transport level ping. The result is sent once the matching pong is received
+pub async fn ping(core: &mut Core<'_>, res: PendingPingResult) -> Status {
+    let payload = NonZeroU64::from(WampId::generate())
+        .get()
+        .to_be_bytes()
+        .to_vec();
+    let sent_at = Instant::now();
+
+    if let Err(e) = core.sock.ping(&payload).await {
+        let _ = res.send(Err(e.into()));
         return Status::Shutdown;
     }
 
-    core.pending_call.insert(request, res);
+    core.pending_pings.insert(payload, (sent_at, res));
 
     Status::Ok
 }
@@ -0,0 +1,112 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+
+use futures::FutureExt;
+
+use crate::common::*;
+use crate::error::*;
+
+type Handler<'a> =
+    Arc<dyn Fn(Option<WampArgs>, Option<WampKwArgs>) -> RpcFuture<'a> + Send + Sync + 'a>;
+
+/// Routes the invocations of a prefix registration to handlers added and removed at runtime
+///
+/// The dispatcher is registered once with [`crate::Client::register_dispatcher`]. Calls to
+/// `<prefix>.<name>` are handed to the handler added under `name`, without registering or
+/// unregistering anything on the dealer. Calls without a handler fail with
+/// `wamp.error.no_such_procedure`.
+///
+/// ```
+/// # use wamp_async::{DynamicDispatcher, WampArgs, WampError, WampKwArgs};
+/// let plugins = DynamicDispatcher::new();
+/// plugins.add("echo", |args: Option<WampArgs>, kwargs: Option<WampKwArgs>| async move {
+///     Ok::<_, WampError>((args, kwargs))
+/// });
+/// assert_eq!(plugins.names(), vec!["echo".to_string()]);
+/// plugins.remove("echo");
+/// ```
+#[derive(Clone)]
+pub struct DynamicDispatcher<'a> {
+    handlers: Arc<RwLock<HashMap<String, Handler<'a>>>>,
+}
+
+impl<'a> Default for DynamicDispatcher<'a> {
+    fn default() -> Self {
+        Self {
+            handlers: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+}
+
+impl<'a> DynamicDispatcher<'a> {
+    /// Creates a dispatcher without handlers
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Handles the calls to `<prefix>.<name>`, replacing the previous handler of `name`
+    pub fn add<T, F, Fut, R>(&self, name: T, handler: F)
+    where
+        T: Into<String>,
+        F: Fn(Option<WampArgs>, Option<WampKwArgs>) -> Fut + Send + Sync + 'a,
+        Fut: Future<Output = Result<R, WampError>> + Send + 'a,
+        R: Into<RpcResponse>,
+    {
+        let handler: Handler<'a> = Arc::new(move |arguments, arguments_kw| {
+            Box::pin(handler(arguments, arguments_kw).map(|r| r.map(Into::into)))
+        });
+        self.handlers.write().unwrap().insert(name.into(), handler);
+    }
+
+    /// Stops handling the calls to `<prefix>.<name>`. Returns whether there was a handler.
+    pub fn remove(&self, name: &str) -> bool {
+        self.handlers.write().unwrap().remove(name).is_some()
+    }
+
+    /// Returns the names that currently have a handler
+    pub fn names(&self) -> Vec<String> {
+        self.handlers.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Runs the handler of the procedure called, as told by the invocation details
+    pub(crate) fn dispatch(
+        &self,
+        prefix: &str,
+        details: &WampDict,
+        arguments: Option<WampArgs>,
+        arguments_kw: Option<WampKwArgs>,
+    ) -> RpcFuture<'a> {
+        let procedure = match details.get("procedure") {
+            Some(Arg::Uri(uri)) | Some(Arg::String(uri)) => uri.as_str(),
+            // Without the procedure, the call was made on the prefix itself
+            _ => prefix,
+        };
+        let handler = match handler_name(prefix, procedure) {
+            Some(name) => self.handlers.read().unwrap().get(name).cloned(),
+            None => None,
+        };
+
+        match handler {
+            Some(handler) => handler(arguments, arguments_kw),
+            None => {
+                let error = WampError::ServerError(
+                    "wamp.error.no_such_procedure".to_string(),
+                    WampDict::new(),
+                );
+                Box::pin(async move { Err(error) })
+            }
+        }
+    }
+}
+
+/// Returns the name of the handler of `procedure`, `None` if it is not `<prefix>.<name>`
+///
+/// The prefix must end at a component boundary : `com.apple.x` is not under `com.app`.
+fn handler_name<'p>(prefix: &str, procedure: &'p str) -> Option<&'p str> {
+    let name = procedure.strip_prefix(prefix)?;
+    if prefix.ends_with('.') || name.is_empty() {
+        return Some(name);
+    }
+    name.strip_prefix('.')
+}
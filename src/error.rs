@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use quick_error::*;
 use url::ParseError;
 
@@ -7,7 +9,7 @@ use crate::transport::TransportError;
 
 quick_error! {
     /// Types of errors a WAMP client can encounter
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     pub enum WampError {
         UnknownError(e: String) {
             from()
@@ -38,6 +40,14 @@ quick_error! {
         ProtocolError(e: String) {
             display("An unexpected WAMP message was received: {}", e)
         }
+        /// The connection was lost while the request was waiting for the peer
+        ConnectionLost {
+            display("The connection was lost before the request completed")
+        }
+        /// The event loop future returned when connecting was dropped or never spawned
+        EventLoopNotRunning {
+            display("The event loop is not running, the future returned by Client::connect() must be spawned")
+        }
         /// The client has been dropped while the event loop was running
         ClientDied {
             display("The client has exited without sending Shutdown")
@@ -46,6 +56,47 @@ quick_error! {
         RequestIdCollision {
             display("There was a collision with a unique request id")
         }
+        /// The arguments of an invocation or event are invalid
+        InvalidArgument(e: String) {
+            display("Invalid argument: {}", e)
+        }
+        /// An operation did not complete in time
+        Timeout(e: String) {
+            display("Timed out while {}", e)
+        }
+        /// An RPC handler was aborted by the watchdog, see
+        /// [`crate::ClientConfig::set_handler_watchdog`]
+        HandlerTimeout(procedure: String, limit: Duration) {
+            display("The handler of {} did not complete within {:?}", procedure, limit)
+        }
+        /// The callee cannot handle the invocation right now
+        Unavailable(e: String) {
+            display("The callee is unavailable: {}", e)
+        }
+        /// A message is larger than the maximum size negotiated with the router
+        PayloadTooLarge(size: usize, max: usize) {
+            display("The payload of {} bytes is larger than the maximum message size of {} bytes", size, max)
+        }
+        /// The procedure is already registered by another session
+        ProcedureAlreadyExists(uri: String) {
+            display("The procedure {} is already registered by another session. If it belongs to a stale session, register with RegisterOptions::with_force_reregister() or RegisterOptions::with_takeover()", uri)
+        }
+        /// The request is not allowed in the current state of the session
+        InvalidSessionState(from: SessionState, to: SessionState) {
+            display("The session cannot go from {:?} to {:?}", from, to)
+        }
+        /// The call was canceled with [`crate::CallHandle::cancel`]
+        CallCanceled {
+            display("The call was canceled")
+        }
+        /// Too many calls of a scatter-gather failed to reach the quorum
+        QuorumNotReached(successes: usize, required: usize) {
+            display("Only {} calls succeeded out of the {} required", successes, required)
+        }
+        /// The router closed the session with GOODBYE or ABORT
+        SessionClosed(reason: SessionCloseReason, details: WampDict) {
+            display("The router closed the session: {} {:?}", reason, details)
+        }
         /// The server sent us an Error message
         ServerError(uri: String, details: WampDict) {
             context(uri: String, details: WampDict) -> (uri, details)
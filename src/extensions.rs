@@ -0,0 +1,113 @@
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use crate::common::*;
+
+/// Typed values shared by a client with the handlers of its session
+///
+/// Holds at most one value per type, like the extensions of the `http` crate. Clones share the
+/// same values, so they can be filled before or after connecting :
+/// ```
+/// # use std::sync::atomic::{AtomicUsize, Ordering};
+/// # use wamp_async::{ClientConfig, Extensions};
+/// struct CallsCount(AtomicUsize);
+///
+/// let extensions = Extensions::new();
+/// extensions.insert(CallsCount(AtomicUsize::new(0)));
+/// let config = ClientConfig::default().set_extensions(extensions.clone());
+///
+/// let count = config.get_extensions().get::<CallsCount>().unwrap();
+/// count.0.fetch_add(1, Ordering::Relaxed);
+/// assert_eq!(extensions.get::<CallsCount>().unwrap().0.load(Ordering::Relaxed), 1);
+/// ```
+///
+/// RPC functions reach the extensions of their client with [`Extensions::current`], streamed
+/// procedures with [`crate::Invocation::extensions`] and subscription consumers with
+/// [`crate::Client::extensions`]. Values needing mutation must bring their own interior
+/// mutability (atomics, `Mutex`, ...).
+#[derive(Clone, Default)]
+pub struct Extensions {
+    values: Arc<RwLock<HashMap<TypeId, Arc<dyn Any + Send + Sync>>>>,
+}
+
+impl Extensions {
+    /// Creates an empty set of extensions
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the extensions of the client handling the current invocation
+    ///
+    /// Only known by the future returned by an RPC function (or the inline handler itself), not
+    /// by the tasks it spawns.
+    pub fn current() -> Option<Extensions> {
+        CURRENT_EXTENSIONS.try_with(|ext| ext.clone()).ok()
+    }
+
+    /// Stores `value`, returning the previous value of the same type
+    pub fn insert<T: Any + Send + Sync>(&self, value: T) -> Option<Arc<T>> {
+        self.values
+            .write()
+            .unwrap()
+            .insert(TypeId::of::<T>(), Arc::new(value))
+            .and_then(|prev| prev.downcast().ok())
+    }
+
+    /// Returns the value of type `T`
+    pub fn get<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.values
+            .read()
+            .unwrap()
+            .get(&TypeId::of::<T>())
+            .cloned()
+            .and_then(|value| value.downcast().ok())
+    }
+
+    /// Removes and returns the value of type `T`
+    pub fn remove<T: Any + Send + Sync>(&self) -> Option<Arc<T>> {
+        self.values
+            .write()
+            .unwrap()
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+    }
+
+    /// Returns whether a value of type `T` is stored
+    pub fn contains<T: Any + Send + Sync>(&self) -> bool {
+        self.values.read().unwrap().contains_key(&TypeId::of::<T>())
+    }
+
+    /// Returns the number of values stored
+    pub fn len(&self) -> usize {
+        self.values.read().unwrap().len()
+    }
+
+    /// Returns whether no value is stored
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Runs `func` with these extensions as the current ones
+    pub(crate) fn scope<'a>(&self, func: RpcFuture<'a>) -> RpcFuture<'a> {
+        Box::pin(CURRENT_EXTENSIONS.scope(self.clone(), func))
+    }
+
+    /// Runs the inline handler `func` with these extensions as the current ones
+    pub(crate) fn sync_scope<R, F: FnOnce() -> R>(&self, func: F) -> R {
+        CURRENT_EXTENSIONS.sync_scope(self.clone(), func)
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extensions")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+tokio::task_local! {
+    static CURRENT_EXTENSIONS: Extensions;
+}
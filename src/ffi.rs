@@ -0,0 +1,346 @@
+//! C API over the blocking client
+//!
+//! Payloads are exchanged as JSON strings. Functions returning a pointer return NULL on
+//! failure, functions returning an `int` return a negative value on failure. The reason of
+//! the last failure on the calling thread is available through [`wamp_last_error`]. Panics
+//! are caught and reported as failures.
+//!
+//! To build a shared library :
+//! ```text
+//! cargo rustc --release --features ffi --crate-type cdylib
+//! ```
+//!
+//! Requires the `ffi` feature.
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::num::NonZeroU64;
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use serde::de::DeserializeOwned;
+
+use crate::blocking::Client;
+use crate::client::ClientConfig;
+use crate::common::*;
+
+/// Opaque handle to a connected client
+pub struct WampClient {
+    client: Client,
+}
+
+/// Called for every event received on a subscription with the user data passed to
+/// [`wamp_subscribe`] and the event as `{"publication": id, "args": [..], "kwargs": {..}}`.
+/// NULL is rejected by [`wamp_subscribe`].
+pub type WampEventCallback =
+    Option<extern "C" fn(user_data: *mut c_void, event_json: *const c_char)>;
+
+struct UserData(*mut c_void);
+// The caller of wamp_subscribe guarantees user_data can be used from another thread
+unsafe impl Send for UserData {}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error<T: ToString>(e: T) {
+    let msg = CString::new(e.to_string().replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|l| *l.borrow_mut() = Some(msg));
+}
+
+/// Runs the body of an exported function, returning `failed` instead of unwinding into C
+/// when it panics. The panic is reported through [`wamp_last_error`].
+fn guard<T>(failed: T, body: impl FnOnce() -> T) -> T {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(v) => v,
+        Err(payload) => {
+            let reason = match payload.downcast_ref::<&str>() {
+                Some(s) => s.to_string(),
+                None => match payload.downcast_ref::<String>() {
+                    Some(s) => s.clone(),
+                    None => "unknown reason".to_string(),
+                },
+            };
+            set_last_error(format!("Panicked : {}", reason));
+            failed
+        }
+    }
+}
+
+unsafe fn to_str<'a>(s: *const c_char, name: &str) -> Result<&'a str, String> {
+    if s.is_null() {
+        return Err(format!("{} is NULL", name));
+    }
+    match CStr::from_ptr(s).to_str() {
+        Ok(s) => Ok(s),
+        Err(e) => Err(format!("{} is not valid UTF-8 : {}", name, e)),
+    }
+}
+
+unsafe fn from_json<T: DeserializeOwned>(
+    s: *const c_char,
+    name: &str,
+) -> Result<Option<T>, String> {
+    if s.is_null() {
+        return Ok(None);
+    }
+    match serde_json::from_str(to_str(s, name)?) {
+        Ok(v) => Ok(Some(v)),
+        Err(e) => Err(format!("{} is not valid : {}", name, e)),
+    }
+}
+
+fn to_c_string(value: serde_json::Value) -> *mut c_char {
+    match CString::new(value.to_string()) {
+        Ok(s) => s.into_raw(),
+        Err(e) => {
+            set_last_error(e);
+            ptr::null_mut()
+        }
+    }
+}
+
+/// Returns the reason of the last failure on the calling thread, or NULL.
+///
+/// The returned string is valid until the next call to this library on the same thread.
+#[no_mangle]
+pub extern "C" fn wamp_last_error() -> *const c_char {
+    guard(ptr::null(), || {
+        LAST_ERROR.with(|l| match l.borrow().as_ref() {
+            Some(e) => e.as_ptr(),
+            None => ptr::null(),
+        })
+    })
+}
+
+/// Connects to a WAMP server and joins `realm`. Returns NULL on failure.
+///
+/// # Safety
+/// `uri` and `realm` must be valid NUL terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn wamp_connect(uri: *const c_char, realm: *const c_char) -> *mut WampClient {
+    guard(ptr::null_mut(), || {
+        let res = (|| {
+            let uri = to_str(uri, "uri")?;
+            let realm = to_str(realm, "realm")?;
+            let mut client =
+                Client::connect(uri, Some(ClientConfig::default())).map_err(|e| e.to_string())?;
+            client.join_realm(realm).map_err(|e| e.to_string())?;
+            Ok::<_, String>(client)
+        })();
+
+        match res {
+            Ok(client) => Box::into_raw(Box::new(WampClient { client })),
+            Err(e) => {
+                set_last_error(e);
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Calls `procedure` with optional JSON `args` (array) and `kwargs` (object).
+///
+/// Returns the result as `{"args": [..], "kwargs": {..}}` or NULL on failure. The returned
+/// string must be freed with [`wamp_string_free`].
+///
+/// # Safety
+/// `client` must come from [`wamp_connect`]. `procedure` must be a valid NUL terminated string,
+/// `args` and `kwargs` must be NULL or valid NUL terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn wamp_call(
+    client: *mut WampClient,
+    procedure: *const c_char,
+    args: *const c_char,
+    kwargs: *const c_char,
+) -> *mut c_char {
+    guard(ptr::null_mut(), || {
+        let client = match client.as_ref() {
+            Some(c) => c,
+            None => {
+                set_last_error("client is NULL");
+                return ptr::null_mut();
+            }
+        };
+        let res = (|| {
+            let procedure = to_str(procedure, "procedure")?;
+            let args: Option<WampArgs> = from_json(args, "args")?;
+            let kwargs: Option<WampKwArgs> = from_json(kwargs, "kwargs")?;
+            client
+                .client
+                .call(procedure, args, kwargs)
+                .map_err(|e| e.to_string())
+        })();
+
+        match res {
+            Ok((args, kwargs)) => {
+                to_c_string(serde_json::json!({ "args": args, "kwargs": kwargs }))
+            }
+            Err(e) => {
+                set_last_error(e);
+                ptr::null_mut()
+            }
+        }
+    })
+}
+
+/// Publishes on `topic` with optional JSON `args` (array) and `kwargs` (object).
+///
+/// Returns 0 on success.
+///
+/// # Safety
+/// `client` must come from [`wamp_connect`]. `topic` must be a valid NUL terminated string,
+/// `args` and `kwargs` must be NULL or valid NUL terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn wamp_publish(
+    client: *mut WampClient,
+    topic: *const c_char,
+    args: *const c_char,
+    kwargs: *const c_char,
+) -> c_int {
+    guard(-1, || {
+        let client = match client.as_ref() {
+            Some(c) => c,
+            None => {
+                set_last_error("client is NULL");
+                return -1;
+            }
+        };
+        let res = (|| {
+            let topic = to_str(topic, "topic")?;
+            let args: Option<WampArgs> = from_json(args, "args")?;
+            let kwargs: Option<WampKwArgs> = from_json(kwargs, "kwargs")?;
+            client
+                .client
+                .publish(topic, args, kwargs, false)
+                .map_err(|e| e.to_string())
+        })();
+
+        match res {
+            Ok(_) => 0,
+            Err(e) => {
+                set_last_error(e);
+                -1
+            }
+        }
+    })
+}
+
+/// Subscribes to `topic`, calling `callback` from a dedicated thread for every event.
+///
+/// Returns the subscription ID or 0 on failure.
+///
+/// # Safety
+/// `client` must come from [`wamp_connect`] and `topic` must be a valid NUL terminated string.
+/// `user_data` must be safe to use from the callback thread until the subscription ends.
+#[no_mangle]
+pub unsafe extern "C" fn wamp_subscribe(
+    client: *mut WampClient,
+    topic: *const c_char,
+    callback: WampEventCallback,
+    user_data: *mut c_void,
+) -> u64 {
+    guard(0, || {
+        let callback = match callback {
+            Some(c) => c,
+            None => {
+                set_last_error("callback is NULL");
+                return 0;
+            }
+        };
+        let client = match client.as_ref() {
+            Some(c) => c,
+            None => {
+                set_last_error("client is NULL");
+                return 0;
+            }
+        };
+        let res = (|| {
+            let topic = to_str(topic, "topic")?;
+            client.client.subscribe(topic).map_err(|e| e.to_string())
+        })();
+
+        let (sub_id, events) = match res {
+            Ok(r) => r,
+            Err(e) => {
+                set_last_error(e);
+                return 0;
+            }
+        };
+
+        let user_data = UserData(user_data);
+        std::thread::spawn(move || {
+            for (publication, args, kwargs, _) in events {
+                let event = serde_json::json!({
+                    "publication": publication,
+                    "args": args,
+                    "kwargs": kwargs,
+                });
+                if let Ok(event) = CString::new(event.to_string()) {
+                    callback(user_data.0, event.as_ptr());
+                }
+            }
+        });
+
+        NonZeroU64::from(sub_id).get()
+    })
+}
+
+/// Unsubscribes from a subscription created by [`wamp_subscribe`]. Returns 0 on success.
+///
+/// # Safety
+/// `client` must come from [`wamp_connect`].
+#[no_mangle]
+pub unsafe extern "C" fn wamp_unsubscribe(client: *mut WampClient, sub_id: u64) -> c_int {
+    guard(-1, || {
+        let client = match client.as_ref() {
+            Some(c) => c,
+            None => {
+                set_last_error("client is NULL");
+                return -1;
+            }
+        };
+        let sub_id: WampId = match serde_json::from_value(sub_id.into()) {
+            Ok(id) => id,
+            Err(e) => {
+                set_last_error(format!("Invalid subscription ID : {}", e));
+                return -1;
+            }
+        };
+
+        match client.client.unsubscribe(sub_id) {
+            Ok(_) => 0,
+            Err(e) => {
+                set_last_error(e);
+                -1
+            }
+        }
+    })
+}
+
+/// Disconnects and frees a client
+///
+/// # Safety
+/// `client` must be NULL or come from [`wamp_connect`] and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn wamp_disconnect(client: *mut WampClient) {
+    guard((), || {
+        if !client.is_null() {
+            let _ = Box::from_raw(client).client.disconnect();
+        }
+    })
+}
+
+/// Frees a string returned by this library
+///
+/// # Safety
+/// `s` must be NULL or come from this library and must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn wamp_string_free(s: *mut c_char) {
+    guard((), || {
+        if !s.is_null() {
+            drop(CString::from_raw(s));
+        }
+    })
+}
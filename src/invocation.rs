@@ -0,0 +1,203 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use futures::Stream;
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::OwnedSemaphorePermit;
+
+use crate::codec::{encode_response, PayloadCodec};
+use crate::common::*;
+use crate::core::{error_context, CtlSender, Request, YieldFlowControl};
+use crate::error::*;
+use crate::extensions::Extensions;
+
+/// Invocation of a procedure registered with [`crate::Client::register_streamed`]
+///
+/// The invocation is answered with [`Invocation::respond`], optionally preceded by progressive
+/// results sent with [`Invocation::progress`]. An invocation dropped without being answered
+/// fails the call.
+pub struct Invocation<'a> {
+    /// Registration the invocation is for
+    pub registration: WampId,
+    /// Procedure called. For pattern based registrations, this is the actual procedure and not
+    /// the registered pattern.
+    pub procedure: WampUri,
+    /// Details of the INVOCATION message (e.g. `timeout`, `caller`)
+    pub details: WampDict,
+    pub arguments: Option<WampArgs>,
+    pub arguments_kw: Option<WampKwArgs>,
+    extensions: Extensions,
+    reply: Option<Reply<'a>>,
+}
+
+/// What is needed to send the result of an invocation
+struct Reply<'a> {
+    request: WampId,
+    ctl_channel: CtlSender<'a>,
+    yield_flow: YieldFlowControl,
+    /// Send the context of a failure along with the ERROR
+    error_context: bool,
+    /// Codec of the registered procedure, encoding the results
+    codec: Option<Arc<dyn PayloadCodec>>,
+    received: Instant,
+}
+
+impl<'a> Reply<'a> {
+    fn send(
+        &self,
+        res: Result<RpcResponse, WampError>,
+        procedure: WampUri,
+        slot: Option<OwnedSemaphorePermit>,
+    ) -> Result<(), WampError> {
+        let res = match (&self.codec, res) {
+            (Some(codec), Ok(res)) => encode_response(codec.as_ref(), res),
+            (_, res) => res,
+        };
+        let error_context = match &res {
+            Err(e) if self.error_context => {
+                Some(error_context(e, procedure, self.received.elapsed()))
+            }
+            _ => None,
+        };
+        match self.ctl_channel.send(Request::InvocationResult {
+            request: self.request,
+            res,
+            error_context,
+            slot,
+        }) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(From::from("Event loop has died !".to_string())),
+        }
+    }
+}
+
+impl<'a> Invocation<'a> {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        request: WampId,
+        registration: WampId,
+        procedure: WampUri,
+        details: WampDict,
+        arguments: Option<WampArgs>,
+        arguments_kw: Option<WampKwArgs>,
+        ctl_channel: CtlSender<'a>,
+        yield_flow: YieldFlowControl,
+        error_context: bool,
+        codec: Option<Arc<dyn PayloadCodec>>,
+        extensions: Extensions,
+    ) -> Self {
+        Self {
+            registration,
+            procedure,
+            details,
+            arguments,
+            arguments_kw,
+            extensions,
+            reply: Some(Reply {
+                request,
+                ctl_channel,
+                yield_flow,
+                error_context,
+                codec,
+                received: Instant::now(),
+            }),
+        }
+    }
+
+    /// Returns the typed values shared by the client with its handlers, see
+    /// [`crate::ClientConfig::set_extensions`]
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Returns whether the caller asked for progressive results
+    pub fn receives_progress(&self) -> bool {
+        matches!(self.details.get("receive_progress"), Some(Arg::Bool(true)))
+    }
+
+    /// Sends a progressive result to the caller, before the final one sent with
+    /// [`Invocation::respond`]
+    ///
+    /// Fails if the caller did not ask for progressive results (see
+    /// [`Invocation::receives_progress`]).
+    pub async fn progress<R: Into<RpcResponse>>(&self, res: R) -> Result<(), WampError> {
+        if !self.receives_progress() {
+            return Err(From::from(
+                "The caller did not ask for progressive results".to_string(),
+            ));
+        }
+        let reply = match &self.reply {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        let slot = reply.yield_flow.acquire().await?;
+        reply.send(
+            Ok(res.into().with_option("progress", Arg::Bool(true))),
+            self.procedure.clone(),
+            Some(slot),
+        )
+    }
+
+    /// Sends the result of the invocation to the caller
+    ///
+    /// Waits for the transport to catch up if too many results are pending (see
+    /// [`crate::ClientConfig::set_max_pending_yields`]).
+    pub async fn respond<R: Into<RpcResponse>>(
+        mut self,
+        res: Result<R, WampError>,
+    ) -> Result<(), WampError> {
+        let reply = match self.reply.take() {
+            Some(r) => r,
+            None => return Ok(()),
+        };
+        let slot = reply.yield_flow.acquire().await?;
+        reply.send(
+            res.map(Into::into),
+            std::mem::take(&mut self.procedure),
+            Some(slot),
+        )
+    }
+}
+
+impl Drop for Invocation<'_> {
+    fn drop(&mut self) {
+        if let Some(reply) = self.reply.take() {
+            // Cannot wait for the transport here, the result skips the flow control if needed
+            let slot = reply.yield_flow.try_acquire();
+            let _ = reply.send(
+                Err(From::from(
+                    "The invocation was dropped without a response".to_string(),
+                )),
+                std::mem::take(&mut self.procedure),
+                slot,
+            );
+        }
+    }
+}
+
+/// Stream of the invocations of the procedures registered with
+/// [`crate::Client::register_streamed`], see [`crate::Client::invocations`]
+pub struct InvocationStream<'a> {
+    invocations: UnboundedReceiver<Invocation<'a>>,
+}
+
+impl<'a> InvocationStream<'a> {
+    pub(crate) fn new(invocations: UnboundedReceiver<Invocation<'a>>) -> Self {
+        Self { invocations }
+    }
+
+    /// Waits for the next invocation. Returns `None` once the event loop stopped.
+    pub async fn recv(&mut self) -> Option<Invocation<'a>> {
+        self.invocations.recv().await
+    }
+}
+
+impl<'a> Stream for InvocationStream<'a> {
+    type Item = Invocation<'a>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.invocations.poll_recv(cx)
+    }
+}
@@ -0,0 +1,217 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use log::*;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::common::*;
+use crate::error::*;
+use crate::log_targets::CORE;
+
+/// Line to write, with what to do once it is written
+type Job = (Vec<u8>, Box<dyn FnOnce(Result<(), WampError>) + Send>);
+
+/// Event recorded in an [`EventJournal`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournaledEvent {
+    /// ID of the publication
+    pub publication: WampId,
+    /// Positional arguments of the event
+    pub arguments: Option<WampArgs>,
+    /// Keyword arguments of the event
+    pub arguments_kw: Option<WampKwArgs>,
+}
+
+/// Append-only file recording the events received on a topic
+///
+/// Events are appended, one JSON object per line, before being forwarded to the subscription.
+/// The writes are done by a background task so a slow disk delays the journaled events, but
+/// never the event loop of the client.
+/// After a restart, [`EventJournal::replay`] returns the events received by the previous process
+/// so the ones it did not handle can be processed, even if the router keeps no event history.
+///
+/// ```no_run
+/// # use wamp_async::{ClientConfig, EventJournal, WampError};
+/// # fn example() -> Result<(), WampError> {
+/// let journal = EventJournal::open("orders.journal")?;
+/// for event in journal.replay()? {
+///     println!("Missed order {:?}", event.arguments);
+/// }
+/// journal.clear()?;
+///
+/// let config = ClientConfig::default().add_event_journal("app.orders.created", journal);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct EventJournal {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+    /// Queue of the writer task, started by the first append
+    writer: Arc<Mutex<Option<mpsc::UnboundedSender<Job>>>>,
+}
+
+impl EventJournal {
+    /// Opens the journal at `path`, creating it if needed
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, WampError> {
+        let path = path.as_ref().to_path_buf();
+        let file = match OpenOptions::new().create(true).append(true).open(&path) {
+            Ok(f) => f,
+            Err(e) => {
+                return Err(From::from(format!(
+                    "Failed to open {} : {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+        Ok(Self {
+            path,
+            file: Arc::new(Mutex::new(file)),
+            writer: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Returns the path of the journal
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the events recorded in the journal, oldest first
+    ///
+    /// A last line left incomplete by a crash is skipped.
+    pub fn replay(&self) -> Result<Vec<JournaledEvent>, WampError> {
+        let _file = self.file.lock().unwrap();
+        let reader = match File::open(&self.path) {
+            Ok(f) => BufReader::new(f),
+            Err(e) => {
+                return Err(From::from(format!(
+                    "Failed to read {} : {}",
+                    self.path.display(),
+                    e
+                )))
+            }
+        };
+
+        let mut lines = reader.lines().peekable();
+        let mut events = Vec::new();
+        while let Some(line) = lines.next() {
+            let line = match line {
+                Ok(l) => l,
+                Err(e) => {
+                    return Err(From::from(format!(
+                        "Failed to read {} : {}",
+                        self.path.display(),
+                        e
+                    )))
+                }
+            };
+            match serde_json::from_str(&line) {
+                Ok(event) => events.push(event),
+                Err(_) if lines.peek().is_none() => break,
+                Err(e) => {
+                    return Err(From::from(format!(
+                        "Corrupted event in {} : {}",
+                        self.path.display(),
+                        e
+                    )))
+                }
+            }
+        }
+        Ok(events)
+    }
+
+    /// Removes the events recorded in the journal, once they were replayed
+    pub fn clear(&self) -> Result<(), WampError> {
+        let file = self.file.lock().unwrap();
+        if let Err(e) = file.set_len(0) {
+            return Err(From::from(format!(
+                "Failed to clear {} : {}",
+                self.path.display(),
+                e
+            )));
+        }
+        Ok(())
+    }
+
+    /// Appends an event to the journal from the writer task, then calls `then` with the result
+    /// and the event
+    ///
+    /// Events are written in the order they are appended. Must be called from a Tokio runtime.
+    pub(crate) fn append<F>(&self, event: JournaledEvent, then: F)
+    where
+        F: FnOnce(Result<(), WampError>, JournaledEvent) + Send + 'static,
+    {
+        let mut line = match serde_json::to_vec(&event) {
+            Ok(l) => l,
+            Err(e) => {
+                return then(
+                    Err(From::from(format!("Failed to serialize event : {}", e))),
+                    event,
+                )
+            }
+        };
+        line.push(b'\n');
+
+        let mut writer = self.writer.lock().unwrap();
+        let mut job: Job = (line, Box::new(move |res| then(res, event)));
+        if let Some(queue) = writer.as_ref() {
+            // The writer is gone if the runtime it was started on was shut down
+            match queue.send(job) {
+                Ok(()) => return,
+                Err(mpsc::error::SendError(j)) => job = j,
+            }
+        }
+        let (queue, jobs) = mpsc::unbounded_channel();
+        tokio::spawn(write_jobs(self.path.clone(), self.file.clone(), jobs));
+        let _ = queue.send(job);
+        *writer = Some(queue);
+    }
+}
+
+impl std::fmt::Debug for EventJournal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventJournal")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+/// Writes the queued lines of a journal, batching the ones queued while writing
+async fn write_jobs(path: PathBuf, file: Arc<Mutex<File>>, mut jobs: mpsc::UnboundedReceiver<Job>) {
+    while let Some(job) = jobs.recv().await {
+        let mut batch = vec![job];
+        while let Ok(job) = jobs.try_recv() {
+            batch.push(job);
+        }
+
+        let file = file.clone();
+        let (batch, res) = match tokio::task::spawn_blocking(move || {
+            let mut file = file.lock().unwrap();
+            let mut res = Ok(());
+            for (line, _) in &batch {
+                res = file.write_all(line);
+                if res.is_err() {
+                    break;
+                }
+            }
+            (batch, res)
+        })
+        .await
+        {
+            Ok(r) => r,
+            Err(e) => {
+                error!(target: CORE, "Journal writer for {} failed : {}", path.display(), e);
+                return;
+            }
+        };
+
+        let res = res.map_err(|e| format!("Failed to write {} : {}", path.display(), e));
+        for (_, then) in batch {
+            then(res.clone().map_err(From::from));
+        }
+    }
+}
@@ -1,12 +1,68 @@
+#[cfg(feature = "auth")]
+pub mod auth;
+#[cfg(feature = "blocking")]
+pub mod blocking;
 mod client;
+mod codec;
+pub mod codegen;
 mod common;
 mod core;
+mod dispatcher;
 mod error;
+mod extensions;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod invocation;
+mod journal;
+pub mod log_targets;
+mod manifest;
 mod message;
+mod pool;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+#[cfg(feature = "protobuf")]
+mod proto;
+#[cfg(feature = "python")]
+mod python;
+mod readiness;
+mod reflection;
+mod runner;
 mod serializer;
-mod transport;
+mod service;
+mod state;
+mod tenant;
+pub mod transport;
+mod typed;
+mod values;
 
-pub use client::{Client, ClientConfig, ClientState};
+pub use crate::core::SubscriptionQueue;
+pub use client::{CallHandle, Client, ClientConfig, ClientState, DisconnectSummary};
+pub use codec::{JsonTextCodec, PayloadCodec};
 pub use common::*;
+pub use dispatcher::DynamicDispatcher;
 pub use error::*;
-pub use serializer::SerializerType;
+pub use extensions::Extensions;
+pub use invocation::{Invocation, InvocationStream};
+pub use journal::{EventJournal, JournaledEvent};
+pub use manifest::{AppliedManifest, Manifest, ProcedureManifest, TopicManifest};
+pub use pool::CallerPool;
+#[cfg(feature = "protobuf")]
+pub use proto::{try_from_args_proto, try_into_args_proto};
+pub use readiness::ReadinessGate;
+pub use reflection::{ProcedureDescriptor, TopicDescriptor};
+pub use runner::{
+    InvocationPool, KeyedInvocationRunner, OrderedEventRunner, RpcEventPump, ShutdownToken,
+};
+pub use serializer::{SerializerError, SerializerType};
+pub use service::{dispatch_typed, WampService};
+pub use state::SharedState;
+pub use tenant::{escape_uri_component, is_valid_uri_component, UriTemplate, TENANT_PLACEHOLDER};
+pub use transport::tcp::RawSocketHandshake;
+pub use transport::{ConnectionInfo, HandshakeBytes, TransportError};
+pub use typed::{TypedProcedure, TypedSubscription, TypedTopic};
+#[cfg(feature = "chrono")]
+pub use values::WampDateTime;
+#[cfg(feature = "rust_decimal")]
+pub use values::WampDecimal;
+#[cfg(feature = "uuid")]
+pub use values::WampUuid;
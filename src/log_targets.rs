@@ -0,0 +1,20 @@
+//! Targets of the log records emitted by the crate
+//!
+//! Each subsystem logs under its own target, so its verbosity can be chosen independently
+//! (e.g. with `env_logger`, `RUST_LOG=wamp_async=info,wamp_async::transport=debug`).
+//!
+//! The content of the messages is only logged under [`PAYLOAD`], at the trace level, as it
+//! may hold sensitive data : enable it explicitly with `RUST_LOG=wamp_async::payload=trace`.
+
+/// Client API : joining realms, registrations and calls made by the application
+pub const CLIENT: &str = "wamp_async::client";
+/// Event loop dispatching the messages and the requests of the client
+pub const CORE: &str = "wamp_async::core";
+/// WebSocket and RawSocket connections
+pub const TRANSPORT: &str = "wamp_async::transport";
+/// Serialization of the messages
+pub const SERIALIZER: &str = "wamp_async::serializer";
+/// Authentication challenges
+pub const AUTH: &str = "wamp_async::auth";
+/// Serialized messages and frames sent and received, logged at the trace level
+pub const PAYLOAD: &str = "wamp_async::payload";
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::common::*;
+use crate::core::SubscriptionQueue;
+use crate::error::*;
+
+/// Registrations and subscriptions of a service described as data
+///
+/// A manifest set with [`crate::ClientConfig::set_manifest`] is applied after every join, so a
+/// service reconnecting to the router ends up with the same WAMP surface. It can be kept in a
+/// JSON file along with the rest of the configuration of the service, and the current surface of
+/// a client is exported with [`crate::Client::export_manifest`].
+///
+/// ```
+/// # use wamp_async::{Manifest, MatchPolicy, ProcedureManifest, TopicManifest};
+/// let manifest = Manifest::from_json(
+///     r#"{
+///         "procedures": [{ "uri": "app.orders.", "match": "prefix", "timeout_ms": 5000 }],
+///         "topics": [{ "uri": "app.stock.changed", "get_retained": true }]
+///     }"#,
+/// )
+/// .unwrap();
+/// assert_eq!(manifest.procedures[0].match_policy, MatchPolicy::Prefix);
+///
+/// let manifest = Manifest::new()
+///     .with_procedure(ProcedureManifest::new("app.orders.list"))
+///     .with_topic(TopicManifest::new("app.stock.changed"));
+/// let json = manifest.to_json().unwrap();
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Procedures registered with [`crate::Client::register_streamed`]
+    #[serde(default)]
+    pub procedures: Vec<ProcedureManifest>,
+    /// Topics subscribed to
+    #[serde(default)]
+    pub topics: Vec<TopicManifest>,
+}
+
+impl Manifest {
+    /// Creates an empty manifest
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a procedure to the manifest
+    pub fn with_procedure(mut self, procedure: ProcedureManifest) -> Self {
+        self.procedures.push(procedure);
+        self
+    }
+
+    /// Adds a topic to the manifest
+    pub fn with_topic(mut self, topic: TopicManifest) -> Self {
+        self.topics.push(topic);
+        self
+    }
+
+    /// Parses a manifest from JSON
+    pub fn from_json(json: &str) -> Result<Self, WampError> {
+        match serde_json::from_str(json) {
+            Ok(m) => Ok(m),
+            Err(e) => Err(From::from(format!("Invalid manifest : {}", e))),
+        }
+    }
+
+    /// Serializes the manifest to JSON
+    pub fn to_json(&self) -> Result<String, WampError> {
+        match serde_json::to_string_pretty(self) {
+            Ok(j) => Ok(j),
+            Err(e) => Err(From::from(format!("Failed to serialize manifest : {}", e))),
+        }
+    }
+}
+
+/// Procedure of a [`Manifest`], see [`RegisterOptions`] for the meaning of the options
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProcedureManifest {
+    /// URI (or URI pattern) of the procedure
+    pub uri: WampUri,
+    /// How the dealer matches the URIs of calls
+    #[serde(rename = "match", default)]
+    pub match_policy: MatchPolicy,
+    /// Invocation timeout enforced by the dealer, in milliseconds
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Routing key of the shard served
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rkey: Option<WampString>,
+    /// Whether the session ID of the callers is disclosed
+    #[serde(default)]
+    pub disclose_caller: bool,
+    /// Whether an existing registration of the procedure is replaced
+    #[serde(default)]
+    pub force_reregister: bool,
+    /// Whether the existing callees of the procedure are removed
+    #[serde(default)]
+    pub takeover: bool,
+}
+
+impl ProcedureManifest {
+    /// Creates a procedure registered with the default options
+    pub fn new<T: Into<String>>(uri: T) -> Self {
+        Self {
+            uri: uri.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Reads a procedure from the options of its REGISTER
+    pub(crate) fn from_options(uri: &str, options: &WampDict) -> Self {
+        Self {
+            uri: uri.to_string(),
+            match_policy: match_policy(options),
+            timeout_ms: match options.get("timeout") {
+                Some(Arg::Integer(millis)) => Some(*millis as u64),
+                _ => None,
+            },
+            rkey: match options.get("rkey") {
+                Some(Arg::String(rkey)) => Some(rkey.clone()),
+                _ => None,
+            },
+            disclose_caller: matches!(options.get("disclose_caller"), Some(Arg::Bool(true))),
+            force_reregister: matches!(options.get("force_reregister"), Some(Arg::Bool(true))),
+            takeover: false,
+        }
+    }
+
+    /// Returns the options the procedure is registered with
+    pub fn options(&self) -> RegisterOptions {
+        let mut options = RegisterOptions::new()
+            .with_match(self.match_policy)
+            .with_disclose_caller(self.disclose_caller)
+            .with_force_reregister(self.force_reregister)
+            .with_takeover(self.takeover);
+        if let Some(millis) = self.timeout_ms {
+            options = options.with_timeout(Duration::from_millis(millis));
+        }
+        if let Some(rkey) = &self.rkey {
+            options = options.with_rkey(rkey.clone());
+        }
+        options
+    }
+}
+
+/// Topic of a [`Manifest`], see [`SubscribeOptions`] for the meaning of the options
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TopicManifest {
+    /// URI (or URI pattern) of the topic
+    pub uri: WampUri,
+    /// How the broker matches the topics of publications
+    #[serde(rename = "match", default)]
+    pub match_policy: MatchPolicy,
+    /// Whether the retained event of the topic is sent after subscribing
+    #[serde(default)]
+    pub get_retained: bool,
+}
+
+impl TopicManifest {
+    /// Creates a topic subscribed with the default options
+    pub fn new<T: Into<String>>(uri: T) -> Self {
+        Self {
+            uri: uri.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Reads a topic from the options of its SUBSCRIBE
+    pub(crate) fn from_options(uri: &str, options: &WampDict) -> Self {
+        Self {
+            uri: uri.to_string(),
+            match_policy: match_policy(options),
+            get_retained: matches!(options.get("get_retained"), Some(Arg::Bool(true))),
+        }
+    }
+
+    /// Returns the options the topic is subscribed with
+    pub fn options(&self) -> SubscribeOptions {
+        SubscribeOptions::new()
+            .with_get_retained(self.get_retained)
+            .with_match(self.match_policy)
+    }
+}
+
+/// Reads the `match` option of a REGISTER or SUBSCRIBE
+fn match_policy(options: &WampDict) -> MatchPolicy {
+    match options.get("match") {
+        Some(Arg::String(m)) if m == "prefix" => MatchPolicy::Prefix,
+        Some(Arg::String(m)) if m == "wildcard" => MatchPolicy::Wildcard,
+        _ => MatchPolicy::Exact,
+    }
+}
+
+/// Registrations and subscriptions made by [`crate::Client::apply_manifest`], keyed by URI
+///
+/// The invocations of the procedures are received from [`crate::Client::invocations`].
+#[derive(Debug, Default)]
+pub struct AppliedManifest {
+    /// Registration ID of each procedure
+    pub registrations: HashMap<WampUri, WampId>,
+    /// Subscription ID and event queue of each topic subscribed to. The topics that were
+    /// already subscribed to with the same options keep their previous queue and are not listed.
+    pub subscriptions: HashMap<WampUri, (WampId, SubscriptionQueue)>,
+}
+
+/// Registrations and subscriptions made by the manifests applied to a client, keyed by URI
+#[derive(Debug, Default)]
+pub(crate) struct ManifestState {
+    /// Session they were made in
+    pub session_id: Option<WampId>,
+    pub registrations: HashMap<WampUri, (WampId, ProcedureManifest)>,
+    pub subscriptions: HashMap<WampUri, (WampId, TopicManifest)>,
+}
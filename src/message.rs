@@ -1,10 +1,12 @@
 use std::fmt;
+use std::num::NonZeroU64;
 
-use serde::de::{Deserializer, Error, SeqAccess, Visitor};
+use serde::de::{Deserializer, Error, MapAccess, SeqAccess, Visitor};
 use serde::ser::Serializer;
 use serde::{Deserialize, Serialize};
 
 use crate::common::*;
+use crate::values::timestamp_to_rfc3339;
 
 // Message IDs
 pub const HELLO_ID: WampInteger = 1;
@@ -22,6 +24,7 @@ pub const UNSUBSCRIBE_ID: WampInteger = 34;
 pub const UNSUBSCRIBED_ID: WampInteger = 35;
 pub const EVENT_ID: WampInteger = 36;
 pub const CALL_ID: WampInteger = 48;
+pub const CANCEL_ID: WampInteger = 49;
 pub const RESULT_ID: WampInteger = 50;
 pub const REGISTER_ID: WampInteger = 64;
 pub const REGISTERED_ID: WampInteger = 65;
@@ -90,7 +93,13 @@ pub enum Msg {
         subscription: WampId,
     },
     /// Acknowledge sent by a Broker to a Subscriber to acknowledge unsubscription.
-    Unsubscribed { request: WampId },
+    ///
+    /// `request` is `None` (0 on the wire) when the Broker revokes the subscription, the
+    /// `details` then hold the `subscription` and the `reason`.
+    Unsubscribed {
+        request: Option<WampId>,
+        details: WampDict,
+    },
     /// Event dispatched by Broker to Subscribers for subscriptions the event was matching.
     Event {
         subscription: WampId,
@@ -107,6 +116,8 @@ pub enum Msg {
         arguments: Option<WampArgs>,
         arguments_kw: Option<WampKwArgs>,
     },
+    /// Sent by a Caller to cancel a call still in progress.
+    Cancel { request: WampId, options: WampDict },
     /// Result of a call as returned by Dealer to Caller.
     Result {
         request: WampId,
@@ -131,7 +142,13 @@ pub enum Msg {
         registration: WampId,
     },
     /// Acknowledge sent by a Dealer to a Callee for successful unregistration.
-    Unregistered { request: WampId },
+    ///
+    /// `request` is `None` (0 on the wire) when the Dealer revokes the registration, the
+    /// `details` then hold the `registration` and the `reason`.
+    Unregistered {
+        request: Option<WampId>,
+        details: WampDict,
+    },
     /// Actual invocation of an endpoint sent by Dealer to a Callee.
     Invocation {
         request: WampId,
@@ -150,6 +167,35 @@ pub enum Msg {
 }
 
 impl Msg {
+    /// Returns the name of the message type
+    pub fn name(&self) -> &'static str {
+        match self {
+            Msg::Hello { .. } => "HELLO",
+            Msg::Welcome { .. } => "WELCOME",
+            Msg::Abort { .. } => "ABORT",
+            Msg::Challenge { .. } => "CHALLENGE",
+            Msg::Authenticate { .. } => "AUTHENTICATE",
+            Msg::Goodbye { .. } => "GOODBYE",
+            Msg::Error { .. } => "ERROR",
+            Msg::Publish { .. } => "PUBLISH",
+            Msg::Published { .. } => "PUBLISHED",
+            Msg::Subscribe { .. } => "SUBSCRIBE",
+            Msg::Subscribed { .. } => "SUBSCRIBED",
+            Msg::Unsubscribe { .. } => "UNSUBSCRIBE",
+            Msg::Unsubscribed { .. } => "UNSUBSCRIBED",
+            Msg::Event { .. } => "EVENT",
+            Msg::Call { .. } => "CALL",
+            Msg::Cancel { .. } => "CANCEL",
+            Msg::Result { .. } => "RESULT",
+            Msg::Register { .. } => "REGISTER",
+            Msg::Registered { .. } => "REGISTERED",
+            Msg::Unregister { .. } => "UNREGISTER",
+            Msg::Unregistered { .. } => "UNREGISTERED",
+            Msg::Invocation { .. } => "INVOCATION",
+            Msg::Yield { .. } => "YIELD",
+        }
+    }
+
     pub fn request_id(&self) -> Option<WampId> {
         Some(*match self {
             Msg::Error { ref request, .. } => request,
@@ -158,13 +204,22 @@ impl Msg {
             Msg::Subscribe { ref request, .. } => request,
             Msg::Subscribed { ref request, .. } => request,
             Msg::Unsubscribe { ref request, .. } => request,
-            Msg::Unsubscribed { ref request } => request,
+            Msg::Unsubscribed {
+                request: Some(ref request),
+                ..
+            } => request,
+            Msg::Unsubscribed { request: None, .. } => return None,
             Msg::Call { ref request, .. } => request,
+            Msg::Cancel { ref request, .. } => request,
             Msg::Result { ref request, .. } => request,
             Msg::Register { ref request, .. } => request,
             Msg::Registered { ref request, .. } => request,
             Msg::Unregister { ref request, .. } => request,
-            Msg::Unregistered { ref request } => request,
+            Msg::Unregistered {
+                request: Some(ref request),
+                ..
+            } => request,
+            Msg::Unregistered { request: None, .. } => return None,
             Msg::Yield { ref request, .. } => request,
             Msg::Hello { .. }
             | Msg::Welcome { .. }
@@ -228,12 +283,20 @@ impl Serialize for Msg {
                         request,
                         details,
                         error,
-                        arguments.as_ref().unwrap_or(&WampArgs::new()),
-                        arguments_kw,
+                        PayloadArgs(arguments.as_ref().unwrap_or(&WampArgs::new())),
+                        PayloadKwArgs(arguments_kw),
                     )
                         .serialize(serializer)
                 } else if let Some(arguments) = arguments {
-                    (ERROR_ID, typ, request, details, error, arguments).serialize(serializer)
+                    (
+                        ERROR_ID,
+                        typ,
+                        request,
+                        details,
+                        error,
+                        PayloadArgs(arguments),
+                    )
+                        .serialize(serializer)
                 } else {
                     (ERROR_ID, typ, request, details, error).serialize(serializer)
                 }
@@ -251,12 +314,13 @@ impl Serialize for Msg {
                         request,
                         options,
                         topic,
-                        arguments.as_ref().unwrap_or(&WampArgs::new()),
-                        arguments_kw
+                        PayloadArgs(arguments.as_ref().unwrap_or(&WampArgs::new())),
+                        PayloadKwArgs(arguments_kw),
                     )
                         .serialize(serializer)
                 } else if let Some(arguments) = arguments {
-                    (PUBLISH_ID, request, options, topic, arguments).serialize(serializer)
+                    (PUBLISH_ID, request, options, topic, PayloadArgs(arguments))
+                        .serialize(serializer)
                 } else {
                     (PUBLISH_ID, request, options, topic).serialize(serializer)
                 }
@@ -278,7 +342,17 @@ impl Serialize for Msg {
                 ref request,
                 ref subscription,
             } => (UNSUBSCRIBE_ID, request, subscription).serialize(serializer),
-            Msg::Unsubscribed { ref request } => (UNSUBSCRIBED_ID, request).serialize(serializer),
+            Msg::Unsubscribed {
+                ref request,
+                ref details,
+            } => {
+                let request = request.map_or(0, |r| NonZeroU64::from(r).get());
+                if details.is_empty() {
+                    (UNSUBSCRIBED_ID, request).serialize(serializer)
+                } else {
+                    (UNSUBSCRIBED_ID, request, details).serialize(serializer)
+                }
+            }
             Msg::Event {
                 ref subscription,
                 ref publication,
@@ -292,12 +366,19 @@ impl Serialize for Msg {
                         subscription,
                         publication,
                         details,
-                        arguments.as_ref().unwrap_or(&WampArgs::new()),
-                        arguments_kw,
+                        PayloadArgs(arguments.as_ref().unwrap_or(&WampArgs::new())),
+                        PayloadKwArgs(arguments_kw),
                     )
                         .serialize(serializer)
                 } else if let Some(arguments) = arguments {
-                    (EVENT_ID, subscription, publication, details, arguments).serialize(serializer)
+                    (
+                        EVENT_ID,
+                        subscription,
+                        publication,
+                        details,
+                        PayloadArgs(arguments),
+                    )
+                        .serialize(serializer)
                 } else {
                     (EVENT_ID, subscription, publication, details).serialize(serializer)
                 }
@@ -315,16 +396,21 @@ impl Serialize for Msg {
                         request,
                         options,
                         procedure,
-                        arguments.as_ref().unwrap_or(&WampArgs::new()),
-                        arguments_kw,
+                        PayloadArgs(arguments.as_ref().unwrap_or(&WampArgs::new())),
+                        PayloadKwArgs(arguments_kw),
                     )
                         .serialize(serializer)
                 } else if let Some(arguments) = arguments {
-                    (CALL_ID, request, options, procedure, arguments).serialize(serializer)
+                    (CALL_ID, request, options, procedure, PayloadArgs(arguments))
+                        .serialize(serializer)
                 } else {
                     (CALL_ID, request, options, procedure).serialize(serializer)
                 }
             }
+            Msg::Cancel {
+                ref request,
+                ref options,
+            } => (CANCEL_ID, request, options).serialize(serializer),
             Msg::Result {
                 ref request,
                 ref details,
@@ -336,12 +422,12 @@ impl Serialize for Msg {
                         RESULT_ID,
                         request,
                         details,
-                        arguments.as_ref().unwrap_or(&WampArgs::new()),
-                        arguments_kw
+                        PayloadArgs(arguments.as_ref().unwrap_or(&WampArgs::new())),
+                        PayloadKwArgs(arguments_kw),
                     )
                         .serialize(serializer)
                 } else if let Some(arguments) = arguments {
-                    (RESULT_ID, request, details, arguments).serialize(serializer)
+                    (RESULT_ID, request, details, PayloadArgs(arguments)).serialize(serializer)
                 } else {
                     (RESULT_ID, request, details).serialize(serializer)
                 }
@@ -359,7 +445,17 @@ impl Serialize for Msg {
                 ref request,
                 ref registration,
             } => (UNREGISTER_ID, request, registration).serialize(serializer),
-            Msg::Unregistered { ref request } => (UNREGISTERED_ID, request).serialize(serializer),
+            Msg::Unregistered {
+                ref request,
+                ref details,
+            } => {
+                let request = request.map_or(0, |r| NonZeroU64::from(r).get());
+                if details.is_empty() {
+                    (UNREGISTERED_ID, request).serialize(serializer)
+                } else {
+                    (UNREGISTERED_ID, request, details).serialize(serializer)
+                }
+            }
             Msg::Invocation {
                 ref request,
                 ref registration,
@@ -373,12 +469,19 @@ impl Serialize for Msg {
                         request,
                         registration,
                         details,
-                        arguments.as_ref().unwrap_or(&WampArgs::new()),
-                        arguments_kw,
+                        PayloadArgs(arguments.as_ref().unwrap_or(&WampArgs::new())),
+                        PayloadKwArgs(arguments_kw),
                     )
                         .serialize(serializer)
                 } else if let Some(arguments) = arguments {
-                    (INVOCATION_ID, request, registration, details, arguments).serialize(serializer)
+                    (
+                        INVOCATION_ID,
+                        request,
+                        registration,
+                        details,
+                        PayloadArgs(arguments),
+                    )
+                        .serialize(serializer)
                 } else {
                     (INVOCATION_ID, request, registration, details).serialize(serializer)
                 }
@@ -394,12 +497,12 @@ impl Serialize for Msg {
                         YIELD_ID,
                         request,
                         options,
-                        arguments.as_ref().unwrap_or(&WampArgs::new()),
-                        arguments_kw
+                        PayloadArgs(arguments.as_ref().unwrap_or(&WampArgs::new())),
+                        PayloadKwArgs(arguments_kw),
                     )
                         .serialize(serializer)
                 } else if let Some(arguments) = arguments {
-                    (YIELD_ID, request, options, arguments).serialize(serializer)
+                    (YIELD_ID, request, options, PayloadArgs(arguments)).serialize(serializer)
                 } else {
                     (YIELD_ID, request, options).serialize(serializer)
                 }
@@ -408,6 +511,240 @@ impl Serialize for Msg {
     }
 }
 
+/// Serializes the positional arguments of a message, see [`PayloadValue`]
+struct PayloadArgs<'a>(&'a WampArgs);
+
+impl Serialize for PayloadArgs<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(self.0.iter().map(PayloadValue))
+    }
+}
+
+/// Serializes the keyword arguments of a message, see [`PayloadValue`]
+struct PayloadKwArgs<'a>(&'a WampKwArgs);
+
+impl Serialize for PayloadKwArgs<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_map(self.0.iter().map(|(k, v)| (k, PayloadValue(v))))
+    }
+}
+
+/// Serializes a payload value, writing numbers as native numbers in binary formats
+///
+/// With the `arbitrary_precision` feature, numbers are kept as strings that only the JSON
+/// serializer understands. Other serializers get the closest native number instead.
+///
+/// [`MsgPackExt`] objects are written as extension values in binary formats. In text formats,
+/// timestamps are written as RFC3339 strings.
+///
+/// Strings holding binary data as defined by WAMP for JSON (a NUL character followed by the
+/// base64 encoded bytes) are written as binary values in binary formats.
+struct PayloadValue<'a>(&'a WampPayloadValue);
+
+impl Serialize for PayloadValue<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let human_readable = serializer.is_human_readable();
+        match self.0 {
+            WampPayloadValue::Number(n) if !human_readable => {
+                if let Some(v) = n.as_u64() {
+                    serializer.serialize_u64(v)
+                } else if let Some(v) = n.as_i64() {
+                    serializer.serialize_i64(v)
+                } else {
+                    serializer.serialize_f64(n.as_f64().unwrap_or(f64::NAN))
+                }
+            }
+            WampPayloadValue::String(s) if !human_readable && s.starts_with(BINARY_PREFIX) => {
+                match base64::decode(&s[BINARY_PREFIX.len_utf8()..]) {
+                    Ok(bytes) => serializer.serialize_bytes(&bytes),
+                    Err(_) => serializer.serialize_str(s),
+                }
+            }
+            WampPayloadValue::Array(a) => serializer.collect_seq(a.iter().map(PayloadValue)),
+            WampPayloadValue::Object(o) => {
+                if let Some(ext) = MsgPackExt::from_value(self.0) {
+                    if !human_readable {
+                        return serializer.serialize_newtype_struct(
+                            MSGPACK_EXT_STRUCT,
+                            &(ext.tag, ExtData(&ext.data)),
+                        );
+                    }
+                    if let Some(date) = timestamp_to_rfc3339(&ext) {
+                        return serializer.serialize_str(&date);
+                    }
+                }
+                serializer.collect_map(o.iter().map(|(k, v)| (k, PayloadValue(v))))
+            }
+            v => v.serialize(serializer),
+        }
+    }
+}
+
+/// Name of the newtype struct used by `rmp_serde` for extension values
+const MSGPACK_EXT_STRUCT: &str = "_ExtStruct";
+
+/// Data of an extension value, serialized as binary
+struct ExtData<'a>(&'a [u8]);
+
+impl Serialize for ExtData<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+/// Reads the optional positional arguments of a message
+fn next_args<'de, V: SeqAccess<'de>>(v: &mut V) -> Result<Option<WampArgs>, V::Error> {
+    Ok(v.next_element::<Option<Vec<OwnedPayloadValue>>>()?
+        .flatten()
+        .map(|args| args.into_iter().map(|a| a.0).collect()))
+}
+
+/// Reads the optional keyword arguments of a message
+fn next_kwargs<'de, V: SeqAccess<'de>>(v: &mut V) -> Result<Option<WampKwArgs>, V::Error> {
+    v.next_element::<Option<OwnedPayloadValue>>()?
+        .flatten()
+        .map(|kwargs| match kwargs.0 {
+            WampPayloadValue::Object(o) => Ok(o),
+            _ => Err(V::Error::custom("keyword arguments must be a map")),
+        })
+        .transpose()
+}
+
+/// Deserializes a payload value, turning the extension values of binary formats into
+/// [`MsgPackExt`] objects and their binary values into the strings WAMP uses for JSON
+struct OwnedPayloadValue(WampPayloadValue);
+
+impl<'de> Deserialize<'de> for OwnedPayloadValue {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // Keeps the handling of arbitrary precision numbers by serde_json
+        if deserializer.is_human_readable() {
+            return WampPayloadValue::deserialize(deserializer).map(OwnedPayloadValue);
+        }
+        deserializer.deserialize_any(PayloadValueVisitor)
+    }
+}
+
+struct PayloadValueVisitor;
+
+impl<'de> Visitor<'de> for PayloadValueVisitor {
+    type Value = OwnedPayloadValue;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a payload value")
+    }
+
+    fn visit_bool<E: Error>(self, v: bool) -> Result<Self::Value, E> {
+        Ok(OwnedPayloadValue(v.into()))
+    }
+
+    fn visit_i64<E: Error>(self, v: i64) -> Result<Self::Value, E> {
+        Ok(OwnedPayloadValue(v.into()))
+    }
+
+    fn visit_u64<E: Error>(self, v: u64) -> Result<Self::Value, E> {
+        Ok(OwnedPayloadValue(v.into()))
+    }
+
+    fn visit_f64<E: Error>(self, v: f64) -> Result<Self::Value, E> {
+        Ok(OwnedPayloadValue(v.into()))
+    }
+
+    fn visit_str<E: Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(OwnedPayloadValue(v.into()))
+    }
+
+    fn visit_string<E: Error>(self, v: String) -> Result<Self::Value, E> {
+        Ok(OwnedPayloadValue(v.into()))
+    }
+
+    fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        let mut value = String::from(BINARY_PREFIX);
+        value.push_str(&base64::encode(v));
+        Ok(OwnedPayloadValue(value.into()))
+    }
+
+    fn visit_unit<E: Error>(self) -> Result<Self::Value, E> {
+        Ok(OwnedPayloadValue(WampPayloadValue::Null))
+    }
+
+    fn visit_none<E: Error>(self) -> Result<Self::Value, E> {
+        Ok(OwnedPayloadValue(WampPayloadValue::Null))
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        OwnedPayloadValue::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let mut values = Vec::new();
+        while let Some(OwnedPayloadValue(v)) = seq.next_element()? {
+            values.push(v);
+        }
+        Ok(OwnedPayloadValue(WampPayloadValue::Array(values)))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut values = WampKwArgs::new();
+        while let Some((k, OwnedPayloadValue(v))) = map.next_entry::<String, _>()? {
+            values.insert(k, v);
+        }
+        Ok(OwnedPayloadValue(WampPayloadValue::Object(values)))
+    }
+
+    /// Extension values are presented by `rmp_serde` as a newtype struct holding the
+    /// extension type and its data
+    fn visit_newtype_struct<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Self::Value, D::Error> {
+        let (tag, OwnedExtData(data)) = <(i8, OwnedExtData)>::deserialize(deserializer)?;
+        Ok(OwnedPayloadValue(MsgPackExt::new(tag, data).into()))
+    }
+}
+
+/// Data of an extension value, deserialized from binary
+struct OwnedExtData(Vec<u8>);
+
+impl<'de> Deserialize<'de> for OwnedExtData {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ExtDataVisitor;
+        impl<'de> Visitor<'de> for ExtDataVisitor {
+            type Value = OwnedExtData;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("extension data")
+            }
+
+            fn visit_bytes<E: Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                Ok(OwnedExtData(v.to_vec()))
+            }
+
+            fn visit_byte_buf<E: Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+                Ok(OwnedExtData(v))
+            }
+        }
+        deserializer.deserialize_bytes(ExtDataVisitor)
+    }
+}
+
 /// Deserialization from the WAMP tuple to the struct
 impl<'de> Deserialize<'de> for Msg {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -490,8 +827,8 @@ impl<'de> Deserialize<'de> for Msg {
                     error: v
                         .next_element()?
                         .ok_or_else(|| Error::missing_field("error"))?,
-                    arguments: v.next_element()?.unwrap_or(None),
-                    arguments_kw: v.next_element()?.unwrap_or(None),
+                    arguments: next_args(&mut v)?,
+                    arguments_kw: next_kwargs(&mut v)?,
                 })
             }
             fn de_publish<'de, V: SeqAccess<'de>>(&self, mut v: V) -> Result<Msg, V::Error> {
@@ -505,8 +842,8 @@ impl<'de> Deserialize<'de> for Msg {
                     topic: v
                         .next_element()?
                         .ok_or_else(|| Error::missing_field("topic"))?,
-                    arguments: v.next_element()?.unwrap_or(None),
-                    arguments_kw: v.next_element()?.unwrap_or(None),
+                    arguments: next_args(&mut v)?,
+                    arguments_kw: next_kwargs(&mut v)?,
                 })
             }
             fn de_published<'de, V: SeqAccess<'de>>(&self, mut v: V) -> Result<Msg, V::Error> {
@@ -553,10 +890,12 @@ impl<'de> Deserialize<'de> for Msg {
                 })
             }
             fn de_unsubscribed<'de, V: SeqAccess<'de>>(&self, mut v: V) -> Result<Msg, V::Error> {
+                let request: u64 = v
+                    .next_element()?
+                    .ok_or_else(|| Error::missing_field("request"))?;
                 Ok(Msg::Unsubscribed {
-                    request: v
-                        .next_element()?
-                        .ok_or_else(|| Error::missing_field("request"))?,
+                    request: WampId::from_raw(request),
+                    details: v.next_element()?.unwrap_or_default(),
                 })
             }
             fn de_event<'de, V: SeqAccess<'de>>(&self, mut v: V) -> Result<Msg, V::Error> {
@@ -570,8 +909,8 @@ impl<'de> Deserialize<'de> for Msg {
                     details: v
                         .next_element()?
                         .ok_or_else(|| Error::missing_field("details"))?,
-                    arguments: v.next_element()?.unwrap_or(None),
-                    arguments_kw: v.next_element()?.unwrap_or(None),
+                    arguments: next_args(&mut v)?,
+                    arguments_kw: next_kwargs(&mut v)?,
                 })
             }
             fn de_call<'de, V: SeqAccess<'de>>(&self, mut v: V) -> Result<Msg, V::Error> {
@@ -585,8 +924,18 @@ impl<'de> Deserialize<'de> for Msg {
                     procedure: v
                         .next_element()?
                         .ok_or_else(|| Error::missing_field("procedure"))?,
-                    arguments: v.next_element()?.unwrap_or(None),
-                    arguments_kw: v.next_element()?.unwrap_or(None),
+                    arguments: next_args(&mut v)?,
+                    arguments_kw: next_kwargs(&mut v)?,
+                })
+            }
+            fn de_cancel<'de, V: SeqAccess<'de>>(&self, mut v: V) -> Result<Msg, V::Error> {
+                Ok(Msg::Cancel {
+                    request: v
+                        .next_element()?
+                        .ok_or_else(|| Error::missing_field("request"))?,
+                    options: v
+                        .next_element()?
+                        .ok_or_else(|| Error::missing_field("options"))?,
                 })
             }
             fn de_result<'de, V: SeqAccess<'de>>(&self, mut v: V) -> Result<Msg, V::Error> {
@@ -597,8 +946,8 @@ impl<'de> Deserialize<'de> for Msg {
                     details: v
                         .next_element()?
                         .ok_or_else(|| Error::missing_field("details"))?,
-                    arguments: v.next_element()?.unwrap_or(None),
-                    arguments_kw: v.next_element()?.unwrap_or(None),
+                    arguments: next_args(&mut v)?,
+                    arguments_kw: next_kwargs(&mut v)?,
                 })
             }
             fn de_register<'de, V: SeqAccess<'de>>(&self, mut v: V) -> Result<Msg, V::Error> {
@@ -635,10 +984,12 @@ impl<'de> Deserialize<'de> for Msg {
                 })
             }
             fn de_unregistered<'de, V: SeqAccess<'de>>(&self, mut v: V) -> Result<Msg, V::Error> {
+                let request: u64 = v
+                    .next_element()?
+                    .ok_or_else(|| Error::missing_field("request"))?;
                 Ok(Msg::Unregistered {
-                    request: v
-                        .next_element()?
-                        .ok_or_else(|| Error::missing_field("request"))?,
+                    request: WampId::from_raw(request),
+                    details: v.next_element()?.unwrap_or_default(),
                 })
             }
             fn de_invocation<'de, V: SeqAccess<'de>>(&self, mut v: V) -> Result<Msg, V::Error> {
@@ -652,8 +1003,8 @@ impl<'de> Deserialize<'de> for Msg {
                     details: v
                         .next_element()?
                         .ok_or_else(|| Error::missing_field("details"))?,
-                    arguments: v.next_element()?.unwrap_or(None),
-                    arguments_kw: v.next_element()?.unwrap_or(None),
+                    arguments: next_args(&mut v)?,
+                    arguments_kw: next_kwargs(&mut v)?,
                 })
             }
             fn de_yield<'de, V: SeqAccess<'de>>(&self, mut v: V) -> Result<Msg, V::Error> {
@@ -664,8 +1015,8 @@ impl<'de> Deserialize<'de> for Msg {
                     options: v
                         .next_element()?
                         .ok_or_else(|| Error::missing_field("options"))?,
-                    arguments: v.next_element()?.unwrap_or(None),
-                    arguments_kw: v.next_element()?.unwrap_or(None),
+                    arguments: next_args(&mut v)?,
+                    arguments_kw: next_kwargs(&mut v)?,
                 })
             }
         }
@@ -700,6 +1051,7 @@ impl<'de> Deserialize<'de> for Msg {
                     UNSUBSCRIBED_ID => self.de_unsubscribed(v),
                     EVENT_ID => self.de_event(v),
                     CALL_ID => self.de_call(v),
+                    CANCEL_ID => self.de_cancel(v),
                     RESULT_ID => self.de_result(v),
                     REGISTER_ID => self.de_register(v),
                     REGISTERED_ID => self.de_registered(v),
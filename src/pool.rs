@@ -0,0 +1,361 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use futures::future;
+use log::*;
+use tokio::select;
+use tokio::sync::watch;
+
+use crate::client::{Client, ClientConfig};
+use crate::common::*;
+use crate::error::*;
+use crate::log_targets::CLIENT;
+use crate::serializer::SerializerType;
+use crate::transport::Transport;
+
+/// Delay between two attempts at replacing a dead session
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+/// Delay between two pings of a session, also used as the ping timeout
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// A joined session of the pool
+struct Session {
+    client: Client<'static>,
+    /// Number of calls waiting for a result
+    in_flight: AtomicUsize,
+}
+
+/// Counts a call as in flight until it completes or is dropped
+struct InFlight(Arc<Session>);
+
+impl InFlight {
+    fn new(session: Arc<Session>) -> Self {
+        session.in_flight.fetch_add(1, Ordering::Relaxed);
+        Self(session)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+type Slots = Vec<RwLock<Option<Arc<Session>>>>;
+
+/// Connects a new session with the given config and joins it to the realm of the config
+type Connector = Arc<
+    dyn Fn(
+            ClientConfig,
+        ) -> Pin<
+            Box<
+                dyn Future<Output = Result<(Client<'static>, GenericFuture<'static>), WampError>>
+                    + Send,
+            >,
+        > + Send
+        + Sync,
+>;
+
+/// Distributes calls across several sessions joined to the same realm
+///
+/// Each call is sent on the session with the fewest calls in flight. Sessions are pinged
+/// regularly and the ones that lost their connection or stopped answering are replaced.
+///
+/// ```no_run
+/// # use wamp_async::{CallerPool, ClientConfig, ClientRole, WampError};
+/// # async fn example() -> Result<(), WampError> {
+/// let cfg = ClientConfig::default().set_roles(vec![ClientRole::Caller]);
+/// let (pool, sessions) = CallerPool::new("ws://localhost:8080/ws", "realm1", cfg, 8).await?;
+/// tokio::spawn(sessions);
+/// let (args, kwargs) = pool.call("peer.echo", Some(vec![12.into()]), None).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct CallerPool {
+    slots: Arc<Slots>,
+    /// Stops the sessions once dropped
+    _closed: watch::Sender<()>,
+}
+
+impl CallerPool {
+    /// Connects `size` sessions (at least 1) to `url` and joins them to `realm`.
+    ///
+    /// The returned future runs the sessions and replaces the dead ones, it must be spawned.
+    /// It completes once the pool is dropped and the sessions are closed.
+    pub async fn new<U: AsRef<str>, R: AsRef<str>>(
+        url: U,
+        realm: R,
+        cfg: ClientConfig,
+        size: usize,
+    ) -> Result<(Self, GenericFuture<'static>), WampError> {
+        let url = url.as_ref().to_string();
+        let connector: Connector = Arc::new(move |cfg| {
+            let url = url.clone();
+            Box::pin(async move {
+                let (client, (evt_loop, _)) = Client::connect(url, Some(cfg)).await?;
+                Ok((client, evt_loop))
+            })
+        });
+        Self::with_connector(connector, realm.as_ref(), cfg, size).await
+    }
+
+    /// Opens `size` sessions (at least 1) over the transports returned by `connect` and joins
+    /// them to `realm`.
+    ///
+    /// `connect` is called again for each session that has to be replaced. This is useful to
+    /// connect through streams which are not opened by this crate, see
+    /// [`Client::from_transport`]. The returned future is the same as [`CallerPool::new`].
+    pub async fn from_transports<R, C, F>(
+        connect: C,
+        serializer: SerializerType,
+        realm: R,
+        cfg: ClientConfig,
+        size: usize,
+    ) -> Result<(Self, GenericFuture<'static>), WampError>
+    where
+        R: AsRef<str>,
+        C: Fn() -> F + Send + Sync + 'static,
+        F: Future<Output = Result<Box<dyn Transport + Send>, WampError>> + Send + 'static,
+    {
+        let connector: Connector = Arc::new(move |cfg| {
+            let transport = connect();
+            Box::pin(async move {
+                let transport = transport.await?;
+                let realm = cfg.get_realm().unwrap_or_default().to_string();
+                let (mut client, (evt_loop, _)) =
+                    Client::from_transport(transport, serializer, Some(cfg));
+                let evt_loop = client.join_with_event_loop(realm, evt_loop).await?;
+                Ok((client, evt_loop))
+            })
+        });
+        Self::with_connector(connector, realm.as_ref(), cfg, size).await
+    }
+
+    async fn with_connector(
+        connector: Connector,
+        realm: &str,
+        cfg: ClientConfig,
+        size: usize,
+    ) -> Result<(Self, GenericFuture<'static>), WampError> {
+        let cfg = cfg.set_realm(realm).set_auto_join(true);
+
+        let mut slots = Vec::new();
+        let mut evt_loops = Vec::new();
+        for _ in 0..size.max(1) {
+            let (session, evt_loop) = connect(&connector, &cfg).await?;
+            slots.push(RwLock::new(Some(session)));
+            evt_loops.push(evt_loop);
+        }
+        let slots = Arc::new(slots);
+        let (closed_w, closed_r) = watch::channel(());
+
+        let runners = evt_loops
+            .into_iter()
+            .enumerate()
+            .map(|(index, evt_loop)| {
+                run_slot(
+                    slots.clone(),
+                    index,
+                    connector.clone(),
+                    cfg.clone(),
+                    evt_loop,
+                    closed_r.clone(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        Ok((
+            Self {
+                slots,
+                _closed: closed_w,
+            },
+            Box::pin(async move {
+                future::join_all(runners).await;
+                Ok(())
+            }),
+        ))
+    }
+
+    /// Returns the number of sessions currently joined
+    pub fn num_sessions(&self) -> usize {
+        self.slots
+            .iter()
+            .filter(|slot| slot.read().unwrap().is_some())
+            .count()
+    }
+
+    /// Calls a registered RPC endpoint on the least busy session
+    pub async fn call<T: AsRef<str>>(
+        &self,
+        uri: T,
+        arguments: Option<WampArgs>,
+        arguments_kw: Option<WampKwArgs>,
+    ) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError> {
+        self.call_with_options(uri, arguments, arguments_kw, CallOptions::default())
+            .await
+    }
+
+    /// Calls a registered RPC endpoint on the least busy session with additional options
+    pub async fn call_with_options<T: AsRef<str>>(
+        &self,
+        uri: T,
+        arguments: Option<WampArgs>,
+        arguments_kw: Option<WampKwArgs>,
+        options: CallOptions,
+    ) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError> {
+        let session = match self.least_busy() {
+            Some(s) => InFlight::new(s),
+            None => {
+                return Err(From::from(
+                    "None of the sessions of the pool are joined".to_string(),
+                ))
+            }
+        };
+
+        session
+            .0
+            .client
+            .call_with_options(uri, arguments, arguments_kw, options)
+            .await
+    }
+
+    fn least_busy(&self) -> Option<Arc<Session>> {
+        self.slots
+            .iter()
+            .filter_map(|slot| slot.read().unwrap().clone())
+            .filter(|s| matches!(s.client.session_state(), SessionState::Joined(_)))
+            .min_by_key(|s| s.in_flight.load(Ordering::Relaxed))
+    }
+}
+
+/// Connects a new session joined to the realm
+async fn connect(
+    connector: &Connector,
+    cfg: &ClientConfig,
+) -> Result<(Arc<Session>, GenericFuture<'static>), WampError> {
+    let (client, evt_loop) = connector(cfg.clone()).await?;
+
+    Ok((
+        Arc::new(Session {
+            client,
+            in_flight: AtomicUsize::new(0),
+        }),
+        evt_loop,
+    ))
+}
+
+/// Runs the session of a slot and replaces it when it dies, until the pool is dropped
+async fn run_slot(
+    slots: Arc<Slots>,
+    index: usize,
+    connector: Connector,
+    mut cfg: ClientConfig,
+    evt_loop: GenericFuture<'static>,
+    mut closed: watch::Receiver<()>,
+) {
+    let mut evt_loop = Some(evt_loop);
+    loop {
+        let evt_loop = match evt_loop.take() {
+            Some(e) => e,
+            None => {
+                let res = select! {
+                    res = connect(&connector, &cfg) => res,
+                    _ = closed.changed() => return,
+                };
+                match res {
+                    Ok((session, evt_loop)) => {
+                        *slots[index].write().unwrap() = Some(session);
+                        evt_loop
+                    }
+                    Err(e) => {
+                        warn!(
+                            target: CLIENT,
+                            "Failed to replace session {} of the pool : {}",
+                            index, e
+                        );
+                        select! {
+                            _ = tokio::time::sleep(RECONNECT_DELAY) => continue,
+                            _ = closed.changed() => return,
+                        }
+                    }
+                }
+            }
+        };
+
+        // Dropping the event loop closes the connection
+        let res = select! {
+            res = evt_loop => res,
+            _ = health_check(&slots[index], index) => Ok(()),
+            _ = closed.changed() => return,
+        };
+        if let Err(e) = &res {
+            warn!(target: CLIENT, "Session {} of the pool disconnected : {}", index, e);
+        }
+        *slots[index].write().unwrap() = None;
+
+        if let Err(WampError::SessionClosed(reason, _)) = res {
+            if reason.is_forced_logout() {
+                cfg = select! {
+                    cfg = reauthenticate(cfg, reason, index) => cfg,
+                    _ = closed.changed() => return,
+                };
+            }
+        }
+    }
+}
+
+/// Renews the configuration of a session logged out by the router, retrying until the
+/// reauthentication hook succeeds
+async fn reauthenticate(
+    cfg: ClientConfig,
+    reason: SessionCloseReason,
+    index: usize,
+) -> ClientConfig {
+    let hook = match cfg.get_reauthentication_hook() {
+        Some(hook) => hook.clone(),
+        None => return cfg,
+    };
+    loop {
+        match hook(reason.clone(), cfg.clone()).await {
+            Ok(cfg) => return cfg,
+            Err(e) => {
+                warn!(
+                    target: CLIENT,
+                    "Failed to reauthenticate session {} of the pool : {}",
+                    index, e
+                );
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    }
+}
+
+/// Returns once the session of the slot is no longer healthy
+async fn health_check(slot: &RwLock<Option<Arc<Session>>>, index: usize) {
+    loop {
+        tokio::time::sleep(HEALTH_CHECK_INTERVAL).await;
+        let session = match slot.read().unwrap().clone() {
+            Some(session) => session,
+            None => return,
+        };
+
+        if !matches!(session.client.session_state(), SessionState::Joined(_)) {
+            warn!(target: CLIENT, "Session {} of the pool left the realm", index);
+            return;
+        }
+        match tokio::time::timeout(HEALTH_CHECK_INTERVAL, session.client.ping()).await {
+            Ok(Ok(_)) => {}
+            Ok(Err(e)) => {
+                warn!(target: CLIENT, "Session {} of the pool failed to ping : {}", index, e);
+                return;
+            }
+            Err(_) => {
+                warn!(target: CLIENT, "Session {} of the pool did not answer a ping", index);
+                return;
+            }
+        }
+    }
+}
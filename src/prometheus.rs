@@ -0,0 +1,211 @@
+//! Prometheus exporter of the client metrics
+//!
+//! [`crate::Client::metrics`] returns a [`Metrics`] handle that keeps reading the counters of
+//! the client, even once it was moved into another task. Its [`Metrics::render`] method
+//! formats them in the Prometheus text exposition format, and [`Metrics::serve`] answers the
+//! scrapes on `/metrics` without any other HTTP server.
+//!
+//! ```no_run
+//! # use wamp_async::{Client, WampError};
+//! # async fn example(client: Client<'static>) -> Result<(), WampError> {
+//! let metrics = client.metrics();
+//! tokio::spawn(metrics.serve("0.0.0.0:9464"));
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Requires the `prometheus` feature.
+
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::*;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::sync::watch;
+
+use crate::common::*;
+use crate::core::{HandlerWatchdog, YieldFlowControl};
+use crate::error::*;
+use crate::log_targets::CLIENT;
+
+/// Content type of the Prometheus text exposition format
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Largest scrape request read before answering
+const MAX_REQUEST_SIZE: usize = 8192;
+
+/// How long a scrape can take to send its request before the connection is closed
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pause after a failed accept, so errors such as running out of file descriptors do not spin
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+/// Handle on the counters of a [`crate::Client`]
+#[derive(Clone)]
+pub struct Metrics {
+    pub(crate) session_state: watch::Receiver<SessionState>,
+    pub(crate) yield_flow: YieldFlowControl,
+    pub(crate) handler_watchdog: HandlerWatchdog,
+    pub(crate) num_duplicate_replies: Arc<AtomicU64>,
+    pub(crate) num_dropped_events: Arc<AtomicU64>,
+    pub(crate) num_slow_inline_handlers: Arc<AtomicU64>,
+    pub(crate) largest_response: Arc<AtomicUsize>,
+    pub(crate) aborted_requests: Arc<AtomicUsize>,
+}
+
+impl Metrics {
+    /// Formats the metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let joined = matches!(*self.session_state.borrow(), SessionState::Joined(_));
+        let mut out = String::new();
+        metric(
+            &mut out,
+            "wamp_client_session_joined",
+            "gauge",
+            "Whether the client is joined to a realm",
+            joined as u64,
+        );
+        metric(
+            &mut out,
+            "wamp_client_congested_yields_total",
+            "counter",
+            "Invocation results that had to wait for the transport",
+            self.yield_flow.num_congested(),
+        );
+        metric(
+            &mut out,
+            "wamp_client_expired_handlers_total",
+            "counter",
+            "RPC handlers aborted by the watchdog",
+            self.handler_watchdog.num_expired(),
+        );
+        metric(
+            &mut out,
+            "wamp_client_duplicate_replies_total",
+            "counter",
+            "Replies ignored because their request was already answered",
+            self.num_duplicate_replies.load(Ordering::Relaxed),
+        );
+        metric(
+            &mut out,
+            "wamp_client_dropped_events_total",
+            "counter",
+            "Events dropped because a bounded subscription queue was full",
+            self.num_dropped_events.load(Ordering::Relaxed),
+        );
+        metric(
+            &mut out,
+            "wamp_client_slow_inline_handlers_total",
+            "counter",
+            "Inline RPC handlers that ran longer than their budget",
+            self.num_slow_inline_handlers.load(Ordering::Relaxed),
+        );
+        metric(
+            &mut out,
+            "wamp_client_largest_response_bytes",
+            "gauge",
+            "Size of the largest invocation result sent",
+            self.largest_response.load(Ordering::Relaxed) as u64,
+        );
+        metric(
+            &mut out,
+            "wamp_client_aborted_requests_total",
+            "counter",
+            "Requests failed because the event loop stopped",
+            self.aborted_requests.load(Ordering::Relaxed) as u64,
+        );
+        out
+    }
+
+    /// Answers the Prometheus scrapes of `/metrics` on `addr`
+    ///
+    /// Only fails when `addr` cannot be bound. Failures to accept or answer a scrape are logged
+    /// and the endpoint keeps serving.
+    pub async fn serve<A: ToSocketAddrs>(self, addr: A) -> Result<(), WampError> {
+        let listener = match TcpListener::bind(addr).await {
+            Ok(l) => l,
+            Err(e) => {
+                return Err(From::from(format!(
+                    "Failed to bind metrics endpoint : {}",
+                    e
+                )))
+            }
+        };
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(s) => s,
+                Err(e) => {
+                    warn!(target: CLIENT, "Failed to accept metrics scrape : {}", e);
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                    continue;
+                }
+            };
+            let metrics = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = metrics.answer(stream).await {
+                    debug!(target: CLIENT, "Failed to answer metrics scrape from {} : {}", peer, e);
+                }
+            });
+        }
+    }
+
+    /// Answers a single HTTP request
+    async fn answer(&self, mut stream: TcpStream) -> std::io::Result<()> {
+        // Only the request line matters, the headers are read and ignored
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        let read = async {
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < MAX_REQUEST_SIZE
+            {
+                let len = stream.read(&mut buf).await?;
+                if len == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..len]);
+            }
+            Ok::<_, std::io::Error>(())
+        };
+        match tokio::time::timeout(REQUEST_TIMEOUT, read).await {
+            Ok(res) => res?,
+            Err(_) => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::TimedOut,
+                    "no request received",
+                ))
+            }
+        }
+
+        let request = String::from_utf8_lossy(&request);
+        let mut request_line = request.lines().next().unwrap_or("").split(' ');
+        let (status, body) = match (request_line.next(), request_line.next()) {
+            (Some("GET"), Some("/metrics")) => ("200 OK", self.render()),
+            _ => ("404 Not Found", String::new()),
+        };
+
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            CONTENT_TYPE,
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await?;
+        stream.shutdown().await
+    }
+}
+
+impl std::fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.render())
+    }
+}
+
+/// Appends a metric with its HELP and TYPE lines
+fn metric(out: &mut String, name: &str, typ: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, typ);
+    let _ = writeln!(out, "{} {}", name, value);
+}
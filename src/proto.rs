@@ -0,0 +1,43 @@
+//! Protobuf payloads
+//!
+//! Messages are encoded with `prost` and carried in a single binary positional argument. As
+//! payloads are JSON values, the binary argument follows the WAMP convention for binary data
+//! in JSON : a string starting with a NUL character followed by the base64 encoded bytes. The
+//! MsgPack serializer sends it as a binary value.
+//!
+//! Requires the `protobuf` feature.
+
+use prost::Message;
+
+use crate::common::*;
+use crate::error::*;
+use crate::serializer::SerializerError;
+
+/// Convert a protobuf message into WampArgs holding its encoded bytes
+pub fn try_into_args_proto<M: Message>(message: &M) -> Result<WampArgs, WampError> {
+    let mut value = String::from(BINARY_PREFIX);
+    value.push_str(&base64::encode(message.encode_to_vec()));
+    Ok(vec![WampPayloadValue::String(value)])
+}
+
+/// Convert WampArgs holding the encoded bytes of a protobuf message into the message
+pub fn try_from_args_proto<M: Message + Default>(value: WampArgs) -> Result<M, WampError> {
+    let encoded = match value.as_slice() {
+        [WampPayloadValue::String(s)] if s.starts_with(BINARY_PREFIX) => {
+            &s[BINARY_PREFIX.len_utf8()..]
+        }
+        _ => return Err(deserialization_error("expected a single binary argument")),
+    };
+    let bytes = match base64::decode(encoded) {
+        Ok(b) => b,
+        Err(e) => return Err(deserialization_error(e)),
+    };
+    M::decode(bytes.as_slice()).map_err(deserialization_error)
+}
+
+fn deserialization_error<E: std::fmt::Display>(e: E) -> WampError {
+    WampError::SerializationError(SerializerError::Deserialization(format!(
+        "failed to decode protobuf message : {}",
+        e
+    )))
+}
@@ -0,0 +1,292 @@
+//! Python bindings
+//!
+//! Exposes an asyncio compatible `Client` to Python :
+//! ```python
+//! import wamp_async
+//!
+//! client = await wamp_async.Client.connect("ws://localhost:8080/ws")
+//! await client.join_realm("realm1")
+//! args, kwargs = await client.call("peer.echo", [1, 2], {"key": "value"})
+//! sub = await client.subscribe("peer.heartbeat")
+//! async for pub_id, args, kwargs in sub:
+//!     print(pub_id, args, kwargs)
+//! await client.disconnect()
+//! ```
+//!
+//! Payloads are converted to and from Python objects through JSON. To build the Python
+//! module :
+//! ```text
+//! cargo rustc --release --features python,pyo3/extension-module --crate-type cdylib
+//! ```
+//!
+//! Disconnect the clients before the interpreter exits. Their event loops run on a Tokio
+//! runtime that outlives the interpreter, and one still running while Python finalizes can
+//! abort the process.
+//!
+//! Requires the `python` feature.
+
+use std::sync::Arc;
+
+use pyo3::exceptions::{PyRuntimeError, PyStopAsyncIteration, PyValueError};
+use pyo3::prelude::*;
+use pyo3_async_runtimes::tokio::future_into_py;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::{Mutex, RwLock};
+
+use crate::common::*;
+use crate::core::{CtlSender, Request, SubscriptionQueue};
+use crate::error::*;
+
+fn to_py_err(e: WampError) -> PyErr {
+    PyRuntimeError::new_err(e.to_string())
+}
+
+fn not_connected() -> PyErr {
+    PyRuntimeError::new_err("The client is disconnected")
+}
+
+/// Converts a Python object into a payload through its JSON representation
+fn from_py<T: DeserializeOwned>(obj: Option<&Bound<'_, PyAny>>) -> PyResult<Option<T>> {
+    let obj = match obj {
+        Some(o) if !o.is_none() => o,
+        _ => return Ok(None),
+    };
+    let json: String = obj
+        .py()
+        .import("json")?
+        .call_method1("dumps", (obj,))?
+        .extract()?;
+    match serde_json::from_str(&json) {
+        Ok(v) => Ok(Some(v)),
+        Err(e) => Err(PyValueError::new_err(e.to_string())),
+    }
+}
+
+/// Converts a payload into a Python object through its JSON representation
+fn to_py<T: Serialize>(py: Python<'_>, value: &T) -> PyResult<PyObject> {
+    let json = match serde_json::to_string(value) {
+        Ok(s) => s,
+        Err(e) => return Err(PyValueError::new_err(e.to_string())),
+    };
+    Ok(py.import("json")?.call_method1("loads", (json,))?.unbind())
+}
+
+/// Python wrapper over [`crate::Client`]
+#[pyclass(name = "Client", module = "wamp_async")]
+struct PyClient {
+    client: Arc<RwLock<Option<crate::Client<'static>>>>,
+    /// Stops the event loop without waiting for the requests holding the client
+    ctl_channel: CtlSender<'static>,
+}
+
+#[pymethods]
+impl PyClient {
+    /// Connects to a WAMP server and starts the event loop
+    #[staticmethod]
+    fn connect(py: Python<'_>, uri: String) -> PyResult<Bound<'_, PyAny>> {
+        future_into_py(py, async move {
+            let (client, (evt_loop, rpc_evt_queue)) =
+                crate::Client::connect(uri, None).await.map_err(to_py_err)?;
+            tokio::spawn(evt_loop);
+            if let Some(mut rpc_evt_queue) = rpc_evt_queue {
+                tokio::spawn(async move {
+                    while let Some(rpc_event) = rpc_evt_queue.recv().await {
+                        tokio::spawn(rpc_event);
+                    }
+                });
+            }
+            Ok(PyClient {
+                ctl_channel: client.ctl_channel(),
+                client: Arc::new(RwLock::new(Some(client))),
+            })
+        })
+    }
+
+    /// Attempts to join a realm
+    fn join_realm<'py>(&self, py: Python<'py>, realm: String) -> PyResult<Bound<'py, PyAny>> {
+        let client = self.client.clone();
+        future_into_py(py, async move {
+            match client.write().await.as_mut() {
+                Some(c) => c.join_realm(realm).await.map_err(to_py_err),
+                None => Err(not_connected()),
+            }
+        })
+    }
+
+    /// Leaves the current realm
+    fn leave_realm<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let client = self.client.clone();
+        future_into_py(py, async move {
+            match client.write().await.as_mut() {
+                Some(c) => c.leave_realm().await.map_err(to_py_err),
+                None => Err(not_connected()),
+            }
+        })
+    }
+
+    /// Calls a procedure, returning its `(args, kwargs)`
+    #[pyo3(signature = (uri, args=None, kwargs=None))]
+    fn call<'py>(
+        &self,
+        py: Python<'py>,
+        uri: String,
+        args: Option<&Bound<'py, PyAny>>,
+        kwargs: Option<&Bound<'py, PyAny>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let arguments: Option<WampArgs> = from_py(args)?;
+        let arguments_kw: Option<WampKwArgs> = from_py(kwargs)?;
+        let client = self.client.clone();
+        future_into_py(py, async move {
+            let (arguments, arguments_kw) = match client.read().await.as_ref() {
+                Some(c) => c
+                    .call(uri, arguments, arguments_kw)
+                    .await
+                    .map_err(to_py_err)?,
+                None => return Err(not_connected()),
+            };
+            Python::with_gil(|py| Ok((to_py(py, &arguments)?, to_py(py, &arguments_kw)?)))
+        })
+    }
+
+    /// Publishes an event, returning the publication ID when `acknowledge` is set
+    #[pyo3(signature = (topic, args=None, kwargs=None, acknowledge=false))]
+    fn publish<'py>(
+        &self,
+        py: Python<'py>,
+        topic: String,
+        args: Option<&Bound<'py, PyAny>>,
+        kwargs: Option<&Bound<'py, PyAny>>,
+        acknowledge: bool,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let arguments: Option<WampArgs> = from_py(args)?;
+        let arguments_kw: Option<WampKwArgs> = from_py(kwargs)?;
+        let client = self.client.clone();
+        future_into_py(py, async move {
+            let pub_id = match client.read().await.as_ref() {
+                Some(c) => c
+                    .publish(topic, arguments, arguments_kw, acknowledge)
+                    .await
+                    .map_err(to_py_err)?,
+                None => return Err(not_connected()),
+            };
+            Ok(pub_id.map(|id| std::num::NonZeroU64::from(id).get()))
+        })
+    }
+
+    /// Subscribes to a topic, returning an asynchronous iterator over its events
+    fn subscribe<'py>(&self, py: Python<'py>, topic: String) -> PyResult<Bound<'py, PyAny>> {
+        let client = self.client.clone();
+        future_into_py(py, async move {
+            let (sub_id, queue) = match client.read().await.as_ref() {
+                Some(c) => c.subscribe(topic).await.map_err(to_py_err)?,
+                None => return Err(not_connected()),
+            };
+            Ok(PySubscription {
+                sub_id,
+                queue: Arc::new(Mutex::new(queue)),
+            })
+        })
+    }
+
+    /// Unsubscribes from a subscription
+    fn unsubscribe<'py>(
+        &self,
+        py: Python<'py>,
+        subscription: &PySubscription,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let client = self.client.clone();
+        let sub_id = subscription.sub_id;
+        future_into_py(py, async move {
+            match client.read().await.as_ref() {
+                Some(c) => c.unsubscribe(sub_id).await.map_err(to_py_err),
+                None => Err(not_connected()),
+            }
+        })
+    }
+
+    /// Cleanly closes the connection with the server
+    ///
+    /// Calls and other requests still waiting for the router are failed
+    fn disconnect<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let client = self.client.clone();
+        let ctl_channel = self.ctl_channel.clone();
+        future_into_py(py, async move {
+            // The pending requests hold the client until the router answers them. Stopping the
+            // event loop fails them instead of waiting for answers that may never come
+            let mut guard = match client.try_write() {
+                Ok(g) => g,
+                Err(_) => {
+                    let _ = ctl_channel.send(Request::Shutdown);
+                    client.write().await
+                }
+            };
+            let c = guard.take();
+            drop(guard);
+            if let Some(c) = c {
+                c.disconnect().await.map_err(to_py_err)?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Events of a subscription
+#[pyclass(name = "Subscription", module = "wamp_async")]
+struct PySubscription {
+    sub_id: WampId,
+    queue: Arc<Mutex<SubscriptionQueue>>,
+}
+
+impl PySubscription {
+    fn next_event<'py>(
+        &self,
+        py: Python<'py>,
+        on_close: fn() -> PyResult<PyObject>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let queue = self.queue.clone();
+        future_into_py(py, async move {
+            let (pub_id, arguments, arguments_kw, _) = match queue.lock().await.recv().await {
+                Some(evt) => evt,
+                None => return on_close(),
+            };
+            Python::with_gil(|py| {
+                let evt = (
+                    std::num::NonZeroU64::from(pub_id).get(),
+                    to_py(py, &arguments)?,
+                    to_py(py, &arguments_kw)?,
+                );
+                Ok(evt.into_pyobject(py)?.into_any().unbind())
+            })
+        })
+    }
+}
+
+#[pymethods]
+impl PySubscription {
+    /// Subscription ID
+    #[getter]
+    fn id(&self) -> u64 {
+        std::num::NonZeroU64::from(self.sub_id).get()
+    }
+
+    /// Waits for the next `(publication_id, args, kwargs)`, returning None once the
+    /// subscription is closed
+    fn recv<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.next_event(py, || Ok(Python::with_gil(|py| py.None())))
+    }
+
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __anext__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        self.next_event(py, || Err(PyStopAsyncIteration::new_err(())))
+    }
+}
+
+#[pymodule]
+fn wamp_async(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyClient>()?;
+    m.add_class::<PySubscription>()?;
+    Ok(())
+}
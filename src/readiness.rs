@@ -0,0 +1,114 @@
+use crate::client::Client;
+use crate::common::*;
+use crate::error::*;
+
+/// Announces that a service is ready once all of its RPC endpoints are registered
+///
+/// Orchestration systems probing readiness at the WAMP level can either watch the retained
+/// event published on the readiness topic or call the health procedure. Neither is available
+/// before every registration of the service succeeded.
+///
+/// ```no_run
+/// # use wamp_async::{Client, ReadinessGate, RegisterOptions, RegistrationBatch, WampError};
+/// # async fn example(client: &Client<'_>) -> Result<(), WampError> {
+/// let batch = RegistrationBatch::new().add(
+///     "app.orders.list",
+///     RegisterOptions::new(),
+///     |_args, _kwargs| async { Ok::<_, WampError>((None, None)) },
+/// );
+///
+/// let gate = ReadinessGate::new(client)
+///     .with_topic("app.orders.ready")
+///     .with_health_procedure("app.orders.health");
+/// let rpc_ids = gate.open(batch).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ReadinessGate<'c, 'a> {
+    client: &'c Client<'a>,
+    topic: Option<String>,
+    health_procedure: Option<String>,
+}
+
+impl<'c, 'a> ReadinessGate<'c, 'a> {
+    /// Creates a gate that announces nothing until a topic or health procedure is set
+    pub fn new(client: &'c Client<'a>) -> Self {
+        Self {
+            client,
+            topic: None,
+            health_procedure: None,
+        }
+    }
+
+    /// Publishes a retained event with the `ready` keyword argument on `topic`
+    pub fn with_topic<T: Into<String>>(mut self, topic: T) -> Self {
+        self.topic = Some(topic.into());
+        self
+    }
+
+    /// Registers `uri`, returning the `ready` keyword argument when called
+    pub fn with_health_procedure<T: Into<String>>(mut self, uri: T) -> Self {
+        self.health_procedure = Some(uri.into());
+        self
+    }
+
+    /// Registers the RPC endpoints of `batch` then announces the service as ready
+    ///
+    /// Returns the registration IDs of `batch`, followed by the one of the health procedure if
+    /// any. When a step fails, the endpoints already registered are unregistered and nothing
+    /// is announced.
+    pub async fn open(&self, batch: RegistrationBatch<'a>) -> Result<Vec<WampId>, WampError> {
+        let mut rpc_ids = self.client.register_all(batch).await?;
+
+        if let Err(e) = self.announce(&mut rpc_ids).await {
+            for rpc_id in rpc_ids {
+                let _ = self.client.unregister(rpc_id).await;
+            }
+            return Err(e);
+        }
+        Ok(rpc_ids)
+    }
+
+    /// Publishes a retained event telling the service is no longer ready. The health procedure
+    /// is removed along with the other endpoints with [`Client::unregister`].
+    pub async fn close(&self) -> Result<(), WampError> {
+        self.publish(false).await
+    }
+
+    async fn announce(&self, rpc_ids: &mut Vec<WampId>) -> Result<(), WampError> {
+        if let Some(uri) = &self.health_procedure {
+            let rpc_id = self
+                .client
+                .register(uri, |_args, _kwargs| async {
+                    Ok::<_, WampError>((None, Some(ready_kwargs(true))))
+                })
+                .await?;
+            rpc_ids.push(rpc_id);
+        }
+        self.publish(true).await
+    }
+
+    async fn publish(&self, ready: bool) -> Result<(), WampError> {
+        let topic = match &self.topic {
+            Some(topic) => topic,
+            None => return Ok(()),
+        };
+        self.client
+            .publish_with_options(
+                topic,
+                None,
+                Some(ready_kwargs(ready)),
+                PublishOptions::new()
+                    .with_retain(true)
+                    .with_acknowledge(true),
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+fn ready_kwargs(ready: bool) -> WampKwArgs {
+    let mut kwargs = WampKwArgs::new();
+    kwargs.insert("ready".to_string(), ready.into());
+    kwargs
+}
@@ -0,0 +1,74 @@
+use serde::Deserialize;
+
+use crate::common::*;
+
+/// A procedure registered on the router
+///
+/// Returned by [`crate::Client::list_procedures`]
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProcedureDescriptor {
+    /// Registration ID of the procedure
+    #[serde(rename = "id")]
+    pub registration_id: WampId,
+    /// URI (or URI pattern) of the procedure
+    pub uri: WampUri,
+    /// Matching policy of the registration (`exact`, `prefix` or `wildcard`)
+    #[serde(rename = "match", default)]
+    pub match_policy: String,
+    /// Invocation policy of the registration (`single`, `roundrobin`, ...)
+    #[serde(default)]
+    pub invoke: String,
+    /// Date at which the registration was created
+    #[serde(default)]
+    pub created: String,
+    /// Schema of the procedure, when the router supports reflection
+    #[serde(skip)]
+    pub schema: Option<WampPayloadValue>,
+    /// Keys of the router's description not covered by the other fields
+    #[serde(flatten)]
+    pub extra: WampKwArgs,
+}
+
+/// A topic with at least one subscriber on the router
+///
+/// Returned by [`crate::Client::list_topics`]
+#[derive(Debug, Clone, Deserialize)]
+pub struct TopicDescriptor {
+    /// Subscription ID of the topic
+    #[serde(rename = "id")]
+    pub subscription_id: WampId,
+    /// URI (or URI pattern) of the topic
+    pub uri: WampUri,
+    /// Matching policy of the subscription (`exact`, `prefix` or `wildcard`)
+    #[serde(rename = "match", default)]
+    pub match_policy: String,
+    /// Date at which the subscription was created
+    #[serde(default)]
+    pub created: String,
+    /// Schema of the event payload, when the router supports reflection
+    #[serde(skip)]
+    pub schema: Option<WampPayloadValue>,
+    /// Keys of the router's description not covered by the other fields
+    #[serde(flatten)]
+    pub extra: WampKwArgs,
+}
+
+/// Result of `wamp.registration.list` and `wamp.subscription.list`
+#[derive(Deserialize)]
+pub(crate) struct MetaIdLists {
+    #[serde(default)]
+    pub exact: Vec<WampId>,
+    #[serde(default)]
+    pub prefix: Vec<WampId>,
+    #[serde(default)]
+    pub wildcard: Vec<WampId>,
+}
+
+impl MetaIdLists {
+    pub fn into_ids(self) -> impl Iterator<Item = WampId> {
+        self.exact
+            .into_iter()
+            .chain(self.prefix)
+            .chain(self.wildcard)
+    }
+}
@@ -0,0 +1,448 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::*;
+use tokio::sync::{mpsc, oneshot, Mutex, Notify, Semaphore};
+use tokio::task::JoinHandle;
+
+use crate::common::*;
+use crate::error::*;
+use crate::log_targets::CLIENT;
+
+type Job = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Runs RPC invocations in order for a given key, and concurrently across keys
+///
+/// Each invocation is assigned to a lane by hashing one of its keyword arguments. Invocations
+/// sharing a lane are executed one after the other, in the order they were received from the
+/// router. The lanes are interleaved on the task running the runner future.
+///
+/// ```no_run
+/// # use wamp_async::{Client, KeyedInvocationRunner, WampArgs, WampKwArgs, WampError};
+/// async fn update(
+///     args: Option<WampArgs>,
+///     kwargs: Option<WampKwArgs>,
+/// ) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError> {
+///     Ok((args, kwargs))
+/// }
+///
+/// # async fn example(client: Client<'_>) -> Result<(), WampError> {
+/// let (runner, lanes) = KeyedInvocationRunner::new(4);
+/// tokio::spawn(lanes);
+/// client
+///     .register("peer.entity.update", runner.wrap("entity_id", update))
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct KeyedInvocationRunner {
+    lanes: Arc<Vec<mpsc::UnboundedSender<Job>>>,
+}
+
+impl KeyedInvocationRunner {
+    /// Creates a runner with `num_lanes` lanes (at least 1).
+    ///
+    /// The returned future runs the lanes and must be spawned.
+    pub fn new(num_lanes: usize) -> (Self, GenericFuture<'static>) {
+        let mut lanes = Vec::new();
+        let mut workers = Vec::new();
+        for _ in 0..num_lanes.max(1) {
+            let (lane_w, mut lane_r) = mpsc::unbounded_channel::<Job>();
+            lanes.push(lane_w);
+            workers.push(async move {
+                while let Some(job) = lane_r.recv().await {
+                    job.await;
+                }
+            });
+        }
+
+        (
+            Self {
+                lanes: Arc::new(lanes),
+            },
+            Box::pin(async move {
+                futures::future::join_all(workers).await;
+                Ok(())
+            }),
+        )
+    }
+
+    /// Wraps an RPC endpoint so its invocations are run on the lane of their `key` keyword
+    /// argument. Invocations without this argument all share the same lane.
+    pub fn wrap<F, Fut, R>(
+        &self,
+        key: &str,
+        func_ptr: F,
+    ) -> impl Fn(Option<WampArgs>, Option<WampKwArgs>) -> RpcFuture<'static> + Send + Sync + 'static
+    where
+        F: Fn(Option<WampArgs>, Option<WampKwArgs>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, WampError>> + Send + 'static,
+        R: Into<RpcResponse>,
+    {
+        let lanes = self.lanes.clone();
+        let key = key.to_string();
+        move |arguments, arguments_kw| {
+            let mut hasher = DefaultHasher::new();
+            arguments_kw
+                .as_ref()
+                .and_then(|kw| kw.get(&key))
+                .map(|v| v.to_string())
+                .hash(&mut hasher);
+            let lane = &lanes[(hasher.finish() % lanes.len() as u64) as usize];
+
+            // Queue the invocation right away to preserve the order of arrival
+            let func_future = func_ptr(arguments, arguments_kw);
+            let (res, result) = oneshot::channel();
+            let queued = lane
+                .send(Box::pin(async move {
+                    let _ = res.send(func_future.await.map(Into::into));
+                }))
+                .is_ok();
+
+            Box::pin(async move {
+                if !queued {
+                    return Err(From::from("Invocation lanes are not running".to_string()));
+                }
+                job_result(result).await
+            })
+        }
+    }
+}
+
+/// Runs RPC invocations on a fixed number of workers
+///
+/// Invocations wait in a queue of up to `queue_limit` entries for a free worker. Once the queue
+/// is full, new invocations are rejected with `wamp.error.unavailable` so the dealer can
+/// fail the call (or try another callee) instead of the callee being overwhelmed.
+///
+/// ```no_run
+/// # use wamp_async::{Client, InvocationPool, WampArgs, WampKwArgs, WampError};
+/// async fn resize(
+///     args: Option<WampArgs>,
+///     kwargs: Option<WampKwArgs>,
+/// ) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError> {
+///     Ok((args, kwargs))
+/// }
+///
+/// # async fn example(client: Client<'_>) -> Result<(), WampError> {
+/// let (pool, workers) = InvocationPool::new(8, 100);
+/// tokio::spawn(workers);
+/// client.register("peer.image.resize", pool.wrap(resize)).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct InvocationPool {
+    queue: mpsc::UnboundedSender<Job>,
+    /// Limits the number of running and waiting invocations
+    slots: Arc<Semaphore>,
+}
+
+impl InvocationPool {
+    /// Creates a pool running up to `num_workers` invocations concurrently (at least 1), with
+    /// up to `queue_limit` invocations waiting for a worker (at least 1).
+    ///
+    /// The returned future runs the workers and must be spawned. The workers are interleaved on
+    /// the task running it, so CPU bound handlers should hand their work to
+    /// `tokio::task::spawn_blocking`.
+    pub fn new(num_workers: usize, queue_limit: usize) -> (Self, GenericFuture<'static>) {
+        let num_workers = num_workers.max(1);
+        let (queue_w, queue_r) = mpsc::unbounded_channel::<Job>();
+        let queue_r = Arc::new(Mutex::new(queue_r));
+        let mut workers = Vec::new();
+        for _ in 0..num_workers {
+            let queue_r = queue_r.clone();
+            workers.push(async move {
+                loop {
+                    let job = queue_r.lock().await.recv().await;
+                    match job {
+                        Some(job) => job.await,
+                        None => break,
+                    }
+                }
+            });
+        }
+
+        (
+            Self {
+                queue: queue_w,
+                slots: Arc::new(Semaphore::new(num_workers + queue_limit.max(1))),
+            },
+            Box::pin(async move {
+                futures::future::join_all(workers).await;
+                Ok(())
+            }),
+        )
+    }
+
+    /// Wraps an RPC endpoint so its invocations are run by the pool
+    pub fn wrap<F, Fut, R>(
+        &self,
+        func_ptr: F,
+    ) -> impl Fn(Option<WampArgs>, Option<WampKwArgs>) -> RpcFuture<'static> + Send + Sync + 'static
+    where
+        F: Fn(Option<WampArgs>, Option<WampKwArgs>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, WampError>> + Send + 'static,
+        R: Into<RpcResponse>,
+    {
+        let queue = self.queue.clone();
+        let slots = self.slots.clone();
+        move |arguments, arguments_kw| {
+            // Reject the invocation right away when the pool is saturated
+            let slot = match slots.clone().try_acquire_owned() {
+                Ok(slot) => slot,
+                Err(_) => {
+                    return Box::pin(async {
+                        Err(WampError::Unavailable(
+                            "Too many pending invocations".to_string(),
+                        ))
+                    })
+                }
+            };
+
+            let func_future = func_ptr(arguments, arguments_kw);
+            let (res, result) = oneshot::channel();
+            let queued = queue
+                .send(Box::pin(async move {
+                    let _ = res.send(func_future.await.map(Into::into));
+                    drop(slot);
+                }))
+                .is_ok();
+
+            Box::pin(async move {
+                if !queued {
+                    return Err(From::from("Invocation workers are not running".to_string()));
+                }
+                job_result(result).await
+            })
+        }
+    }
+}
+
+/// Runs event handlers one at a time and in order for a given subscription
+///
+/// Spawning a task per event lets events of the same subscription be handled out of order.
+/// Handlers spawned through this runner are instead run one after the other, in the order they
+/// were spawned, for each subscription. The handlers of different subscriptions are
+/// interleaved on the task running the runner future, they do not run in parallel.
+///
+/// ```no_run
+/// # use wamp_async::{Client, OrderedEventRunner, WampError};
+/// # async fn example(client: Client<'_>) -> Result<(), WampError> {
+/// let (runner, lanes) = OrderedEventRunner::new();
+/// tokio::spawn(lanes);
+///
+/// let (sub_id, mut events) = client.subscribe("peer.entity.updated").await?;
+/// while let Some((pub_id, args, _kwargs, _details)) = events.recv().await {
+///     runner.spawn(sub_id, async move {
+///         println!("{} : {:?}", pub_id, args);
+///     });
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct OrderedEventRunner {
+    jobs: mpsc::UnboundedSender<(WampId, Job)>,
+}
+
+impl OrderedEventRunner {
+    /// Creates a runner.
+    ///
+    /// The returned future runs the handlers and must be spawned. It completes once every
+    /// runner is dropped and the handlers are done.
+    pub fn new() -> (Self, GenericFuture<'static>) {
+        let (jobs_w, mut jobs_r) = mpsc::unbounded_channel::<(WampId, Job)>();
+
+        let lanes = async move {
+            // Handlers waiting for the running one of their subscription
+            let mut waiting: HashMap<WampId, VecDeque<Job>> = HashMap::new();
+            let mut running = FuturesUnordered::new();
+            let mut accepting = true;
+
+            while accepting || !running.is_empty() {
+                tokio::select! {
+                    job = jobs_r.recv(), if accepting => match job {
+                        Some((sub_id, job)) => match waiting.get_mut(&sub_id) {
+                            Some(lane) => lane.push_back(job),
+                            None => {
+                                waiting.insert(sub_id, VecDeque::new());
+                                running.push(run_job(sub_id, job));
+                            }
+                        },
+                        None => accepting = false,
+                    },
+                    Some(sub_id) = running.next() => {
+                        match waiting.get_mut(&sub_id).and_then(|lane| lane.pop_front()) {
+                            Some(job) => running.push(run_job(sub_id, job)),
+                            None => {
+                                waiting.remove(&sub_id);
+                            }
+                        }
+                    }
+                }
+            }
+            Ok(())
+        };
+
+        (Self { jobs: jobs_w }, Box::pin(lanes))
+    }
+
+    /// Runs `handler` once the handlers previously spawned for `sub_id` are done. Returns
+    /// false if the runner future is not running anymore.
+    pub fn spawn<F>(&self, sub_id: WampId, handler: F) -> bool
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.jobs.send((sub_id, Box::pin(handler))).is_ok()
+    }
+}
+
+/// Tells an [`RpcEventPump`] to stop. Clones share the same state.
+#[derive(Clone, Default)]
+pub struct ShutdownToken {
+    inner: Arc<ShutdownTokenInner>,
+}
+
+#[derive(Default)]
+struct ShutdownTokenInner {
+    shutdown: AtomicBool,
+    notify: Notify,
+}
+
+impl ShutdownToken {
+    /// Creates a token that was not shut down
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks everything holding a clone of the token to stop
+    pub fn shutdown(&self) {
+        self.inner.shutdown.store(true, Ordering::SeqCst);
+        self.inner.notify.notify_waiters();
+    }
+
+    /// Returns whether [`ShutdownToken::shutdown`] was called
+    pub fn is_shutdown(&self) -> bool {
+        self.inner.shutdown.load(Ordering::SeqCst)
+    }
+
+    /// Completes once [`ShutdownToken::shutdown`] is called
+    pub async fn wait(&self) {
+        loop {
+            // Created before the check so a concurrent shutdown is not missed
+            let notified = self.inner.notify.notified();
+            if self.is_shutdown() {
+                return;
+            }
+            notified.await;
+        }
+    }
+}
+
+/// Drains the RPC event queue returned by [`crate::Client::connect`], running up to
+/// `concurrency` invocations at once
+///
+/// Each invocation runs in its own task. Once the limit is reached, the next invocations wait
+/// in the queue until one completes instead of being spawned without bound.
+///
+/// ```no_run
+/// # use wamp_async::{Client, RpcEventPump, ShutdownToken, WampError};
+/// # async fn example() -> Result<(), WampError> {
+/// let (mut client, (evt_loop, rpc_evt_queue)) =
+///     Client::connect("tcp://localhost:8081", None).await?;
+/// tokio::spawn(evt_loop);
+///
+/// let shutdown = ShutdownToken::new();
+/// let pump = RpcEventPump::spawn(rpc_evt_queue.unwrap(), 32, shutdown.clone());
+///
+/// // ... register and serve ...
+///
+/// // Stop taking invocations and wait for the ones running
+/// shutdown.shutdown();
+/// pump.join().await;
+/// # Ok(())
+/// # }
+/// ```
+pub struct RpcEventPump {
+    handle: JoinHandle<()>,
+}
+
+impl RpcEventPump {
+    /// Spawns the pump on the current tokio runtime. `concurrency` is at least 1.
+    ///
+    /// The pump stops taking invocations once `shutdown` is triggered or the queue is closed,
+    /// then waits for the invocations still running.
+    pub fn spawn(
+        mut queue: mpsc::UnboundedReceiver<GenericFuture<'static>>,
+        concurrency: usize,
+        shutdown: ShutdownToken,
+    ) -> Self {
+        let concurrency = concurrency.clamp(1, Semaphore::MAX_PERMITS.min(u32::MAX as usize));
+        let slots = Arc::new(Semaphore::new(concurrency));
+
+        let handle = tokio::spawn(async move {
+            loop {
+                let slot = tokio::select! {
+                    _ = shutdown.wait() => break,
+                    // The semaphore is never closed
+                    slot = slots.clone().acquire_owned() => slot.unwrap(),
+                };
+                let rpc_event = tokio::select! {
+                    _ = shutdown.wait() => break,
+                    rpc_event = queue.recv() => match rpc_event {
+                        Some(e) => e,
+                        None => break,
+                    },
+                };
+
+                tokio::spawn(async move {
+                    if let Err(e) = rpc_event.await {
+                        warn!(
+                            target: CLIENT,
+                            "Failed to return the result of an invocation : {}",
+                            e
+                        );
+                    }
+                    drop(slot);
+                });
+            }
+
+            // Wait for the invocations in flight
+            let _ = slots.acquire_many(concurrency as u32).await;
+        });
+
+        Self { handle }
+    }
+
+    /// Completes once the pump stopped and its invocations are done
+    pub async fn join(self) {
+        let _ = self.handle.await;
+    }
+}
+
+/// Runs an event handler, returning its subscription once done
+async fn run_job(sub_id: WampId, job: Job) -> WampId {
+    job.await;
+    sub_id
+}
+
+/// Waits for the result of a queued invocation
+async fn job_result(
+    result: oneshot::Receiver<Result<RpcResponse, WampError>>,
+) -> Result<RpcResponse, WampError> {
+    match result.await {
+        Ok(r) => r,
+        Err(e) => Err(From::from(format!(
+            "Invocation was dropped before returning a response : {}",
+            e
+        ))),
+    }
+}
@@ -1,19 +1,29 @@
 use crate::message::*;
 use crate::serializer::*;
+#[allow(unused_imports)]
 pub use serde_json::error::Error;
-use serde_json::{from_slice, to_vec};
+use serde_json::{from_slice, to_vec, Deserializer};
 
 pub struct JsonSerializer {}
 impl SerializerImpl for JsonSerializer {
     fn pack(&self, value: &Msg) -> Result<Vec<u8>, SerializerError> {
         match to_vec(value) {
             Ok(v) => Ok(v),
-            Err(e) => Err(SerializerError::Serialization(e.to_string())),
+            Err(e) => {
+                debug!(target: SERIALIZER, "Failed to serialize {} : {}", value.name(), e);
+                Err(SerializerError::Serialization(e.to_string()))
+            }
         }
     }
-    fn unpack<'a>(&self, v: &'a [u8]) -> Result<Msg, SerializerError> {
-        match from_slice(v) {
-            Ok(v) => Ok(v),
+    fn unpack(&self, v: &[u8]) -> Result<Msg, SerializerError> {
+        let mut de = Deserializer::from_slice(v);
+        let msg = match serde_path_to_error::deserialize(&mut de) {
+            Ok(msg) => msg,
+            Err(e) => return Err(deserialization_error(e, v, from_slice(v).ok())),
+        };
+        // Reject trailing characters like from_slice() does
+        match de.end() {
+            Ok(_) => Ok(msg),
             Err(e) => Err(SerializerError::Deserialization(e.to_string())),
         }
     }
@@ -1,12 +1,17 @@
+use log::*;
 use quick_error::*;
 
+use std::fmt::Display;
+
+use crate::common::*;
+use crate::log_targets::SERIALIZER;
 use crate::message::Msg;
 
 pub mod json;
 pub mod msgpack;
 
 #[repr(u8)]
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 /// Message serialization algorithms
 pub enum SerializerType {
     Json = 1,
@@ -38,10 +43,18 @@ impl SerializerType {
             SerializerType::MsgPack => "wamp.2.msgpack",
         }
     }
+
+    /// Returns an implementation of the serializer
+    pub(crate) fn serializer(self) -> Box<dyn SerializerImpl + Send> {
+        match self {
+            SerializerType::Json => Box::new(json::JsonSerializer {}),
+            SerializerType::MsgPack => Box::new(msgpack::MsgPackSerializer {}),
+        }
+    }
 }
 
 quick_error! {
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     pub enum SerializerError {
         Serialization(e: String) {
             display("Failed to serialize message: {}", e)
@@ -57,5 +70,36 @@ quick_error! {
 
 pub trait SerializerImpl {
     fn pack(&self, value: &Msg) -> Result<Vec<u8>, SerializerError>;
-    fn unpack<'a>(&self, v: &'a [u8]) -> Result<Msg, SerializerError>;
+    fn unpack(&self, v: &[u8]) -> Result<Msg, SerializerError>;
+}
+
+/// Number of bytes of the frame included in deserialization errors
+const FRAME_EXCERPT_LEN: usize = 32;
+
+/// Builds a deserialization error naming the message type, the offending element and an
+/// excerpt of the frame.
+///
+/// `elements` is the frame decoded as a generic list, used to recover the message type.
+fn deserialization_error<E: Display>(
+    e: serde_path_to_error::Error<E>,
+    frame: &[u8],
+    elements: Option<Vec<WampPayloadValue>>,
+) -> SerializerError {
+    let msg_type = match elements.as_ref().and_then(|e| e.first()) {
+        Some(id) => id.to_string(),
+        None => "unknown".to_string(),
+    };
+    let mut excerpt = frame
+        .iter()
+        .take(FRAME_EXCERPT_LEN)
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<String>>()
+        .join(" ");
+    if frame.len() > FRAME_EXCERPT_LEN {
+        excerpt.push_str(&format!(" ... ({} bytes)", frame.len()));
+    }
+
+    let e = format!("{} (message type {}, frame : {})", e, msg_type, excerpt);
+    debug!(target: SERIALIZER, "Failed to deserialize message : {}", e);
+    SerializerError::Deserialization(e)
 }
@@ -1,19 +1,23 @@
 use crate::message::*;
 use crate::serializer::*;
-use rmp_serde::{from_slice, to_vec};
+use rmp_serde::{from_slice, to_vec, Deserializer};
 
 pub struct MsgPackSerializer {}
 impl SerializerImpl for MsgPackSerializer {
     fn pack(&self, value: &Msg) -> Result<Vec<u8>, SerializerError> {
         match to_vec(value) {
             Ok(v) => Ok(v),
-            Err(e) => Err(SerializerError::Serialization(e.to_string())),
+            Err(e) => {
+                debug!(target: SERIALIZER, "Failed to serialize {} : {}", value.name(), e);
+                Err(SerializerError::Serialization(e.to_string()))
+            }
         }
     }
-    fn unpack<'a>(&self, v: &'a [u8]) -> Result<Msg, SerializerError> {
-        match from_slice(v) {
-            Ok(v) => Ok(v),
-            Err(e) => Err(SerializerError::Deserialization(e.to_string())),
+    fn unpack(&self, v: &[u8]) -> Result<Msg, SerializerError> {
+        let mut de = Deserializer::from_read_ref(v);
+        match serde_path_to_error::deserialize(&mut de) {
+            Ok(msg) => Ok(msg),
+            Err(e) => Err(deserialization_error(e, v, from_slice(v).ok())),
         }
     }
 }
@@ -0,0 +1,78 @@
+use std::future::Future;
+
+use async_trait::async_trait;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::common::*;
+use crate::error::*;
+use crate::typed::{decode_payload, encode_payload};
+
+/// A set of RPC endpoints registered together under a prefix
+///
+/// Each method is registered as `<prefix>.<method>` by [`crate::Client::register_service`] and
+/// its invocations are handed to [`WampService::dispatch`]. The trait is implemented with the
+/// [`async-trait`](https://docs.rs/async-trait) crate.
+///
+/// ```
+/// use async_trait::async_trait;
+/// use wamp_async::{dispatch_typed, WampArgs, WampError, WampKwArgs, WampService};
+///
+/// struct Calculator;
+///
+/// #[async_trait]
+/// impl WampService for Calculator {
+///     fn methods(&self) -> Vec<&'static str> {
+///         vec!["add", "neg"]
+///     }
+///
+///     async fn dispatch(
+///         &self,
+///         method: &str,
+///         arguments: Option<WampArgs>,
+///         arguments_kw: Option<WampKwArgs>,
+///     ) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError> {
+///         match method {
+///             "add" => {
+///                 dispatch_typed(arguments, arguments_kw, |(a, b): (i64, i64)| async move {
+///                     Ok(a + b)
+///                 })
+///                 .await
+///             }
+///             "neg" => dispatch_typed(arguments, arguments_kw, |a: i64| async move { Ok(-a) }).await,
+///             _ => Err(WampError::InvalidArgument(format!("Unknown method {}", method))),
+///         }
+///     }
+/// }
+/// ```
+#[async_trait]
+pub trait WampService: Send + Sync {
+    /// Names of the methods of the service
+    fn methods(&self) -> Vec<&'static str>;
+
+    /// Handles an invocation of one of the methods
+    async fn dispatch(
+        &self,
+        method: &str,
+        arguments: Option<WampArgs>,
+        arguments_kw: Option<WampKwArgs>,
+    ) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError>;
+}
+
+/// Decodes the payload of an invocation into `A`, runs `method` and encodes its result
+///
+/// The payload is converted like the ones of [`crate::Client::register_typed`].
+pub async fn dispatch_typed<A, R, F, Fut>(
+    arguments: Option<WampArgs>,
+    arguments_kw: Option<WampKwArgs>,
+    method: F,
+) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError>
+where
+    A: DeserializeOwned,
+    R: Serialize,
+    F: FnOnce(A) -> Fut,
+    Fut: Future<Output = Result<R, WampError>>,
+{
+    let args = decode_payload(arguments, arguments_kw)
+        .map_err(|e| WampError::InvalidArgument(e.to_string()))?;
+    encode_payload(&method(args).await?)
+}
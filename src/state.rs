@@ -0,0 +1,81 @@
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::client::Client;
+use crate::common::*;
+use crate::error::*;
+use crate::typed::{encode_payload, TypedSubscription};
+
+/// Key/value store shared through the retained events of a topic prefix
+///
+/// Each key is the topic `<prefix>.<key>`. Putting a value publishes it as a retained event
+/// and watching a key subscribes to its topic, asking the broker for the retained event so the
+/// current value is received first. The broker must support event retention.
+///
+/// ```no_run
+/// # use wamp_async::{Client, SharedState, WampError};
+/// # async fn example(client: &Client<'_>) -> Result<(), WampError> {
+/// let state = SharedState::new(client, "app.state");
+/// state.put("mode", &"maintenance").await?;
+///
+/// let (_sub_id, mut mode) = state.watch::<String>("mode").await?;
+/// while let Some((_pub_id, value)) = mode.recv().await {
+///     println!("Mode is now {}", value?);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+pub struct SharedState<'c, 'a> {
+    client: &'c Client<'a>,
+    prefix: String,
+}
+
+impl<'c, 'a> SharedState<'c, 'a> {
+    /// Stores the keys under the topic prefix `prefix`
+    pub fn new<T: Into<String>>(client: &'c Client<'a>, prefix: T) -> Self {
+        Self {
+            client,
+            prefix: prefix.into(),
+        }
+    }
+
+    /// Returns the topic of `key`
+    pub fn topic(&self, key: &str) -> String {
+        format!("{}.{}", self.prefix, key)
+    }
+
+    /// Sets the value of `key`, waiting for the broker to acknowledge it
+    ///
+    /// Retained events cannot be removed, store an `Option` to be able to clear a key.
+    pub async fn put<V: Serialize>(&self, key: &str, value: &V) -> Result<(), WampError> {
+        let (arguments, arguments_kw) = encode_payload(value)?;
+        self.client
+            .publish_with_options(
+                self.topic(key),
+                arguments,
+                arguments_kw,
+                PublishOptions::new()
+                    .with_retain(true)
+                    .with_acknowledge(true),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Watches the value of `key`
+    ///
+    /// The current value, if any, is the first one received. The returned subscription ID is
+    /// used to stop watching with [`Client::unsubscribe`].
+    pub async fn watch<V: DeserializeOwned>(
+        &self,
+        key: &str,
+    ) -> Result<(WampId, TypedSubscription<V>), WampError> {
+        let (sub_id, queue) = self
+            .client
+            .subscribe_with_options(
+                self.topic(key),
+                SubscribeOptions::new().with_get_retained(true),
+            )
+            .await?;
+        Ok((sub_id, TypedSubscription::new(queue)))
+    }
+}
@@ -0,0 +1,125 @@
+use crate::common::*;
+use crate::error::*;
+
+/// Placeholder replaced by the tenant of the client, see [`crate::ClientConfig::set_tenant`]
+pub const TENANT_PLACEHOLDER: &str = "{tenant}";
+
+/// URI or realm built from a template with `{name}` placeholders
+///
+/// Every value must be a valid strict URI component (lower case letters, digits and `_`), so a
+/// value cannot reach the URIs of another tenant. Arbitrary values are made valid with
+/// [`escape_uri_component`].
+///
+/// ```
+/// # use wamp_async::{escape_uri_component, UriTemplate};
+/// let template = UriTemplate::new("com.app.{tenant}.orders.{region}");
+/// assert_eq!(
+///     template.render(&[("tenant", "acme"), ("region", "eu")]).unwrap(),
+///     "com.app.acme.orders.eu"
+/// );
+/// assert!(template.render(&[("tenant", "acme.admin"), ("region", "eu")]).is_err());
+///
+/// let tenant = escape_uri_component("Acme Corp");
+/// assert_eq!(tenant, "_41cme_20_43orp");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UriTemplate {
+    template: String,
+}
+
+impl UriTemplate {
+    /// Creates a template, e.g. `com.app.{tenant}.orders`
+    pub fn new<T: Into<String>>(template: T) -> Self {
+        Self {
+            template: template.into(),
+        }
+    }
+
+    /// Returns the names of the placeholders of the template, in order
+    pub fn placeholders(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        let mut rest = self.template.as_str();
+        while let Some(start) = rest.find('{') {
+            match rest[start..].find('}') {
+                Some(end) => {
+                    names.push(&rest[start + 1..start + end]);
+                    rest = &rest[start + end + 1..];
+                }
+                None => break,
+            }
+        }
+        names
+    }
+
+    /// Replaces the placeholders with `values`, given as `(name, value)` pairs
+    ///
+    /// Fails if a placeholder has no value or a value is not a valid strict URI component.
+    pub fn render(&self, values: &[(&str, &str)]) -> Result<String, WampError> {
+        let mut uri = self.template.clone();
+        for name in self.placeholders() {
+            let value = match values.iter().find(|(n, _)| *n == name) {
+                Some((_, v)) => v,
+                None => {
+                    return Err(WampError::InvalidArgument(format!(
+                        "No value for '{{{}}}' in '{}'",
+                        name, self.template
+                    )))
+                }
+            };
+            if !is_valid_uri_component(value) {
+                return Err(WampError::InvalidArgument(format!(
+                    "'{}' is not a valid URI component for '{{{}}}'",
+                    value, name
+                )));
+            }
+            uri = uri.replacen(&format!("{{{}}}", name), value, 1);
+        }
+        Ok(uri)
+    }
+}
+
+/// Returns whether `value` is a non empty component of a strict URI
+pub fn is_valid_uri_component(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| matches!(c, 'a'..='z' | '0'..='9' | '_'))
+}
+
+/// Turns any string into a valid strict URI component
+///
+/// Lower case letters and digits are kept, every other byte is written as `_` followed by its
+/// hexadecimal value, so distinct values give distinct components.
+pub fn escape_uri_component(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for b in value.bytes() {
+        match b {
+            b'a'..=b'z' | b'0'..=b'9' => escaped.push(b as char),
+            _ => escaped.push_str(&format!("_{:02x}", b)),
+        }
+    }
+    escaped
+}
+
+/// Replaces [`TENANT_PLACEHOLDER`] in `uri` by `tenant`
+pub(crate) fn with_tenant(uri: &str, tenant: Option<&str>) -> Result<WampUri, WampError> {
+    if !uri.contains(TENANT_PLACEHOLDER) {
+        return Ok(uri.to_string());
+    }
+    let tenant = match tenant {
+        Some(t) => t,
+        None => {
+            return Err(WampError::InvalidArgument(format!(
+                "'{}' refers to the tenant but the client has none",
+                uri
+            )))
+        }
+    };
+    if !is_valid_uri_component(tenant) {
+        return Err(WampError::InvalidArgument(format!(
+            "'{}' is not a valid URI component for the tenant",
+            tenant
+        )));
+    }
+    Ok(uri.replace(TENANT_PLACEHOLDER, tenant))
+}
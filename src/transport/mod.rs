@@ -1,44 +1,205 @@
 use async_trait::async_trait;
 use quick_error::*;
+use tokio::io::{AsyncRead, AsyncWrite};
+
+use crate::common::*;
+use crate::error::WampError;
+use crate::message::Msg;
+use crate::serializer::SerializerType;
 
 pub mod tcp;
-pub use tcp::*;
 
 pub mod websocket;
 pub use crate::transport::websocket as ws;
-pub use ws::*;
+
+/// Any bidirectional byte stream a transport can be established over
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
 
 #[async_trait]
 pub trait Transport {
     /// Sends a whole wamp message over the transport
     async fn send(&mut self, data: &[u8]) -> Result<(), TransportError>;
-    /// Receives a whole wamp message from the transport
-    async fn recv(&mut self) -> Result<Vec<u8>, TransportError>;
+    /// Receives a whole wamp message (or a ping reply) from the transport
+    async fn recv(&mut self) -> Result<TransportMsg, TransportError>;
+    /// Sends a transport level ping carrying `payload`. The peer's reply is returned
+    /// by `recv()` as a [`TransportMsg::Pong`]
+    async fn ping(&mut self, payload: &[u8]) -> Result<(), TransportError>;
     /// Closes the transport connection with the host
     async fn close(&mut self);
+    /// Returns the headers the host replied with when establishing the connection (if any)
+    fn response_headers(&self) -> &[(String, String)] {
+        &[]
+    }
+    /// Returns the details of the TLS session the transport runs over (if any)
+    fn tls_info(&self) -> Option<&TlsInfo> {
+        None
+    }
+    /// Returns the maximum size of the messages the host accepts, if negotiated
+    fn max_send_size(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Messages received from a transport
+#[derive(Debug)]
+pub enum TransportMsg {
+    /// A whole wamp message
+    Wamp(Vec<u8>),
+    /// Reply to a ping sent with [`Transport::ping`]
+    Pong(Vec<u8>),
+}
+
+/// Details about an established connection
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+    /// Serializer negotiated with the server
+    pub serializer: SerializerType,
+    /// HTTP headers of the WebSocket upgrade response (empty for RawSocket)
+    pub response_headers: Vec<(String, String)>,
+    /// TLS session of the connection (`None` without TLS or over a stream given by the user)
+    pub tls: Option<TlsInfo>,
+    /// Maximum size of the messages the router accepts, when negotiated by the transport
+    /// (RawSocket)
+    pub max_message_size: Option<usize>,
+}
+
+impl ConnectionInfo {
+    /// Returns the value of the first response header matching `name` (case insensitive)
+    pub fn get_response_header<T: AsRef<str>>(&self, name: T) -> Option<&str> {
+        self.response_headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name.as_ref()))
+            .map(|(_, val)| val.as_str())
+    }
+
+    /// Returns the size in bytes of the YIELD carrying `arguments` and `arguments_kw`, as
+    /// serialized on this connection
+    ///
+    /// Callees can compare it with [`ConnectionInfo::max_message_size`] to fail fast instead of
+    /// producing a result the router would refuse :
+    /// ```
+    /// # use wamp_async::{ConnectionInfo, WampArgs, WampError};
+    /// fn check(info: &ConnectionInfo, arguments: &WampArgs) -> Result<(), WampError> {
+    ///     let size = info.estimate_size(Some(arguments), None)?;
+    ///     match info.max_message_size {
+    ///         Some(max) if size > max => Err(WampError::PayloadTooLarge(size, max)),
+    ///         _ => Ok(()),
+    ///     }
+    /// }
+    /// ```
+    pub fn estimate_size(
+        &self,
+        arguments: Option<&WampArgs>,
+        arguments_kw: Option<&WampKwArgs>,
+    ) -> Result<usize, WampError> {
+        let msg = Msg::Yield {
+            // The actual request ID is never longer
+            request: WampId::max(),
+            options: WampDict::new(),
+            arguments: arguments.cloned(),
+            arguments_kw: arguments_kw.cloned(),
+        };
+        Ok(self.serializer.serializer().pack(&msg)?.len())
+    }
+}
+
+/// Details about the TLS session of a connection
+#[derive(Debug, Clone, Default)]
+pub struct TlsInfo {
+    /// DER encoded certificate presented by the server
+    pub peer_certificate: Option<Vec<u8>>,
+    /// `tls-server-end-point` channel binding data (RFC 5929) of the session
+    pub channel_binding: Option<Vec<u8>>,
+    /// Whether a client certificate was offered to the server, see
+    /// [`ClientConfig::set_tls_identity`](crate::ClientConfig::set_tls_identity)
+    pub client_certificate: bool,
+}
+
+/// The 4 octets sent by each side during a RawSocket handshake
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HandshakeBytes {
+    /// Octets sent by the client
+    pub client: [u8; 4],
+    /// Octets replied by the server
+    pub server: [u8; 4],
+}
+
+impl std::fmt::Display for HandshakeBytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "sent 0x{:02X}{:02X}{:02X}{:02X}, received 0x{:02X}{:02X}{:02X}{:02X}",
+            self.client[0],
+            self.client[1],
+            self.client[2],
+            self.client[3],
+            self.server[0],
+            self.server[1],
+            self.server[2],
+            self.server[3],
+        )
+    }
 }
 
 quick_error! {
-    #[derive(Debug)]
+    /// Failures of a transport, carrying the details given by the server when there are any
+    #[derive(Debug, Clone)]
     pub enum TransportError {
-        MaximumServerConn {
-            display("Server hit the maximum connection count")
+        /// The server refused the connection as it reached its maximum connection count
+        MaximumServerConn(handshake: HandshakeBytes) {
+            display("Server hit the maximum connection count ({})", handshake)
         }
+        /// The server replied with data that does not follow the transport protocol
         UnexpectedResponse {
             display("Server responded with unexpected data")
         }
-        SerializerNotSupported(e: String) {
-            display("The current serializer is not supported by the server (Requested : {})", e)
+        /// None of the requested serializers is supported by the server. The handshake is only
+        /// known for RawSocket.
+        SerializerNotSupported(requested: Vec<SerializerType>, handshake: Option<HandshakeBytes>) {
+            display(
+                "The current serializer is not supported by the server (Requested : {}{})",
+                requested.iter().map(|s| s.to_str()).collect::<Vec<&str>>().join(","),
+                match handshake {
+                    Some(h) => format!(", {}", h),
+                    None => String::new(),
+                }
+            )
+        }
+        /// The server did not accept the maximum message size we requested
+        InvalidMaximumMsgSize(requested: u32, handshake: HandshakeBytes) {
+            display("The server did not accept the maximum payload size (Requested : {}, {})", requested, handshake)
+        }
+        /// The server rejected the RawSocket handshake with an error code not covered by the
+        /// other variants (e.g. 3 when the reserved bits are used)
+        HandshakeRejected(code: u8, handshake: HandshakeBytes) {
+            display("The server rejected the RawSocket handshake with error code {} ({})", code, handshake)
         }
-        InvalidMaximumMsgSize(e: u32) {
-            display("The server did not accept the maximum payload size (Requested : {})", e)
+        /// The server replied to the RawSocket handshake with invalid octets
+        InvalidHandshakeReply(handshake: HandshakeBytes) {
+            display("The server replied to the RawSocket handshake with invalid data ({})", handshake)
         }
+        /// The server answered the WebSocket upgrade request with an HTTP error
+        UpgradeRejected(status: u16, body: String) {
+            display("The server rejected the WebSocket upgrade with HTTP status {} : {}", status, body)
+        }
+        /// A message is larger than the maximum size accepted by its receiver
+        MessageTooLarge(size: usize, max: usize) {
+            display("The message of {} bytes is larger than the maximum size of {} bytes", size, max)
+        }
+        /// The connection could not be established
         ConnectionFailed {
             display("Failed to negotiate connection with the server")
         }
+        /// The connection request built from the config is invalid
+        InvalidRequest(e: String) {
+            display("The connection request is invalid: {}", e)
+        }
+        /// Writing to the connection failed
         SendFailed {
             display("Failed to send message to peer")
         }
+        /// Reading from the connection failed
         ReceiveFailed {
             display("Failed to receive message from peer")
         }
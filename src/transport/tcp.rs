@@ -1,17 +1,27 @@
 use log::*;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
 use async_trait::async_trait;
+use futures::stream::{FuturesUnordered, StreamExt};
 use native_tls::TlsConnector;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::net::{lookup_host, TcpStream};
 use tokio_native_tls;
 
+use crate::log_targets::{PAYLOAD, TRANSPORT};
 use crate::serializer::SerializerType;
-use crate::transport::{Transport, TransportError};
-use crate::ClientConfig;
+use crate::transport::{
+    AsyncStream, HandshakeBytes, TlsInfo, Transport, TransportError, TransportMsg,
+};
+use crate::{ClientConfig, RawSocketHandshakeHook};
 
 pub const MAX_MSG_SZ: u32 = 1 << 24;
 pub const MIN_MSG_SZ: u32 = 1 << 9;
+pub const HANDSHAKE_MAGIC: u8 = 0x7F;
+/// Initial size of the receive buffer
+const RECV_BUF_SZ: usize = 8 * 1024;
 
 #[repr(u8)]
 #[derive(Debug)]
@@ -40,11 +50,92 @@ impl TcpMsg {
     }
 }
 
+/// Octets of the RawSocket handshake, for routers using them for vendor extensions
+///
+/// Set with [`ClientConfig::set_rawsocket_handshake`].
+///
+/// ```
+/// use wamp_async::transport::tcp::RawSocketHandshake;
+/// use wamp_async::{ClientConfig, TransportError};
+///
+/// let handshake = RawSocketHandshake::new()
+///     .with_reserved([0x00, 0x01])
+///     .with_hook(|_client, server| match server[2..] {
+///         [0x00, 0x00] | [0x00, 0x01] => Ok(()),
+///         _ => Err(TransportError::ConnectionFailed),
+///     });
+/// let config = ClientConfig::default().set_rawsocket_handshake(handshake);
+/// ```
+#[derive(Clone)]
+pub struct RawSocketHandshake {
+    magic: u8,
+    reserved: [u8; 2],
+    hook: Option<RawSocketHandshakeHook>,
+}
+
+impl Default for RawSocketHandshake {
+    fn default() -> Self {
+        Self {
+            magic: HANDSHAKE_MAGIC,
+            reserved: [0, 0],
+            hook: None,
+        }
+    }
+}
+
+impl RawSocketHandshake {
+    /// Creates the standard handshake : magic octet 0x7F and reserved octets set to 0
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Overrides the magic octet sent to the server. The server must echo it.
+    pub fn with_magic(mut self, magic: u8) -> Self {
+        self.magic = magic;
+        self
+    }
+
+    /// Sets the two reserved octets sent to the server
+    pub fn with_reserved(mut self, reserved: [u8; 2]) -> Self {
+        self.reserved = reserved;
+        self
+    }
+
+    /// Sets a hook that validates the reply of the server
+    ///
+    /// The hook receives the client and server handshake bytes and replaces the default
+    /// check that the reserved octets of the server are 0.
+    pub fn with_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&[u8; 4], &[u8; 4]) -> Result<(), TransportError> + Send + Sync + 'static,
+    {
+        self.hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Returns the magic octet sent to the server
+    pub fn get_magic(&self) -> u8 {
+        self.magic
+    }
+
+    /// Returns the reserved octets sent to the server
+    pub fn get_reserved(&self) -> [u8; 2] {
+        self.reserved
+    }
+
+    /// Returns the hook validating the reply of the server, if any
+    pub fn get_hook(&self) -> Option<&RawSocketHandshakeHook> {
+        self.hook.as_ref()
+    }
+}
+
 struct HandshakeCtx {
     msg_size: u32,
     serializer: SerializerType,
     client: [u8; 4],
     server: [u8; 4],
+    /// Validates the reply of the server instead of the default check of the reserved octets
+    hook: Option<RawSocketHandshakeHook>,
 }
 impl AsRef<[u8]> for HandshakeCtx {
     fn as_ref(&self) -> &[u8] {
@@ -70,46 +161,42 @@ impl std::fmt::Debug for HandshakeCtx {
     }
 }
 impl HandshakeCtx {
-    pub fn new() -> Self {
+    pub fn new(options: &RawSocketHandshake) -> Self {
         let client: [u8; 4] = [
-            0x7F, // Magic value
+            options.magic, // Magic value
             0xF0 & // Max msg length
             ((SerializerType::MsgPack as u8) & 0x0F), // Serialized
-            0,
-            0, // Reserved
+            options.reserved[0],
+            options.reserved[1], // Reserved
         ];
         HandshakeCtx {
             msg_size: 0,
             serializer: SerializerType::Json,
             client,
             server: [0, 0, 0, 0],
+            hook: options.hook.clone(),
         }
     }
 
     /// Sets the maximum message size to the next or equal power of two of msg_size
     pub fn set_msg_size(&mut self, msg_size: u32) {
         let req_size: u32 = match msg_size.checked_next_power_of_two() {
-            Some(p) => {
-                if p < MIN_MSG_SZ {
-                    MIN_MSG_SZ
-                } else if p > MAX_MSG_SZ {
-                    MAX_MSG_SZ
-                } else {
-                    p
-                }
-            }
+            Some(p) => p.clamp(MIN_MSG_SZ, MAX_MSG_SZ),
             None => MAX_MSG_SZ,
         };
 
         if msg_size != req_size {
             warn!(
+                target: TRANSPORT,
                 "Adjusted max TCP message size from {} to {}",
                 msg_size, req_size
             );
         }
 
         self.msg_size = req_size;
-        self.client[1] = (self.client[1] & 0x0F) | (0xF0);
+        // The length is sent as 2^(9 + x)
+        let exponent = (req_size.trailing_zeros() - 9) as u8;
+        self.client[1] = (self.client[1] & 0x0F) | (exponent << 4);
     }
 
     pub fn set_serializer(&mut self, serializer: SerializerType) {
@@ -117,30 +204,58 @@ impl HandshakeCtx {
         self.client[1] = (self.client[1] & 0xF0) | ((serializer as u8) & 0x0F);
     }
 
+    /// Returns the octets exchanged so far
+    pub fn bytes(&self) -> HandshakeBytes {
+        HandshakeBytes {
+            client: self.client,
+            server: self.server,
+        }
+    }
+
     pub fn srv_resp_bytes(&mut self) -> &mut [u8; 4] {
         &mut self.server
     }
 
+    /// Maximum message size the server accepts, as told by its reply
+    pub fn server_msg_size(&self) -> u32 {
+        1 << ((self.server[1] >> 4) + 9)
+    }
+
+    /// Validates the server reply. The reserved octets must be 0 unless a hook
+    /// was provided to handle vendor specific values.
     pub fn validate(&self) -> Result<(), TransportError> {
-        if self.server[0] != 0x7f || self.server[2] != 0 || self.server[3] != 0 {
-            return Err(TransportError::UnexpectedResponse);
+        if self.server[0] != self.client[0] {
+            return Err(TransportError::InvalidHandshakeReply(self.bytes()));
         }
 
-        if self.server[1] != self.client[1] {
-            // lower 4 bits should be 0 on error
-            if self.server[1] & 0x0F != 0 {
-                return Err(TransportError::UnexpectedResponse);
+        match &self.hook {
+            Some(hook) => hook(&self.client, &self.server)?,
+            None => {
+                if self.server[2] != 0 || self.server[3] != 0 {
+                    return Err(TransportError::InvalidHandshakeReply(self.bytes()));
+                }
             }
+        }
 
-            let server_error: u8 = (self.server[1] & 0xF0) >> 4 as u8;
+        // lower 4 bits are 0 on error
+        if self.server[1] & 0x0F == 0 {
+            let server_error: u8 = (self.server[1] & 0xF0) >> 4;
             return Err(match server_error {
-                1 => TransportError::SerializerNotSupported(self.serializer.to_str().to_string()),
-                2 => TransportError::InvalidMaximumMsgSize(self.msg_size),
-                4 => TransportError::MaximumServerConn,
-                _ => TransportError::UnexpectedResponse,
+                1 => TransportError::SerializerNotSupported(
+                    vec![self.serializer],
+                    Some(self.bytes()),
+                ),
+                2 => TransportError::InvalidMaximumMsgSize(self.msg_size, self.bytes()),
+                4 => TransportError::MaximumServerConn(self.bytes()),
+                code => TransportError::HandshakeRejected(code, self.bytes()),
             });
         }
 
+        // The server echoes the serializer but replies with its own maximum message size
+        if self.server[1] & 0x0F != self.client[1] & 0x0F {
+            return Err(TransportError::InvalidHandshakeReply(self.bytes()));
+        }
+
         Ok(())
     }
 }
@@ -201,213 +316,525 @@ impl MsgPrefix {
     }
 }
 
-enum SockWrapper {
-    Plain(TcpStream),
-    Tls(Box<tokio_native_tls::TlsStream<TcpStream>>),
+struct SockWrapper {
+    /// Taken once the stream is closed
+    stream: Option<Box<dyn AsyncStream>>,
 }
 impl SockWrapper {
-    pub fn close(&mut self) {
-        let sock = match self {
-            SockWrapper::Plain(ref mut s) => s,
-            SockWrapper::Tls(s) => s.get_mut().get_mut().get_mut(),
-        };
+    pub fn new<S: AsyncStream + 'static>(stream: S) -> Self {
+        SockWrapper {
+            stream: Some(Box::new(stream)),
+        }
+    }
 
-        match sock.shutdown() {
-            _ => {}
-        };
+    pub async fn close(&mut self) {
+        if let Some(mut stream) = self.stream.take() {
+            shutdown(&mut stream).await;
+        }
     }
-}
 
-impl SockWrapper {
-    pub async fn write_all(&mut self, bytes: &[u8]) -> Result<(), TransportError> {
-        let res = match self {
-            SockWrapper::Plain(ref mut s) => s.write_all(bytes).await,
-            SockWrapper::Tls(s) => s.write_all(bytes).await,
+    /// Shuts the stream down on the current runtime without waiting for it
+    pub fn close_in_background(&mut self) {
+        let mut stream = match self.stream.take() {
+            Some(s) => s,
+            None => return,
         };
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(async move { shutdown(&mut stream).await });
+        }
+    }
 
-        if let Err(e) = res {
-            debug!("Failed to send on RawSocket : {:?}", e);
+    pub async fn write_all(&mut self, bytes: &[u8]) -> Result<(), TransportError> {
+        let stream = self.stream.as_mut().ok_or(TransportError::SendFailed)?;
+        if let Err(e) = stream.write_all(bytes).await {
+            debug!(target: TRANSPORT, "Failed to send on RawSocket : {:?}", e);
             return Err(TransportError::SendFailed);
         }
 
         Ok(())
     }
 
-    pub async fn read_exact(&mut self, out_bytes: &mut [u8]) -> Result<(), TransportError> {
-        let res = match self {
-            SockWrapper::Plain(ref mut s) => s.read_exact(out_bytes).await,
-            SockWrapper::Tls(s) => s.read_exact(out_bytes).await,
-        };
+    /// Writes some of `bytes`, returning how many were written. Cancelling it writes nothing.
+    pub async fn write(&mut self, bytes: &[u8]) -> Result<usize, TransportError> {
+        let stream = self.stream.as_mut().ok_or(TransportError::SendFailed)?;
+        match stream.write(bytes).await {
+            Ok(0) => {
+                debug!(target: TRANSPORT, "RawSocket closed by the server");
+                Err(TransportError::SendFailed)
+            }
+            Ok(len) => Ok(len),
+            Err(e) => {
+                debug!(target: TRANSPORT, "Failed to send on RawSocket : {:?}", e);
+                Err(TransportError::SendFailed)
+            }
+        }
+    }
 
-        if let Err(e) = res {
-            debug!("Failed to recv on RawSocket : {:?}", e);
+    pub async fn read_exact(&mut self, out_bytes: &mut [u8]) -> Result<(), TransportError> {
+        let stream = self.stream.as_mut().ok_or(TransportError::ReceiveFailed)?;
+        if let Err(e) = stream.read_exact(out_bytes).await {
+            debug!(target: TRANSPORT, "Failed to recv on RawSocket : {:?}", e);
             return Err(TransportError::ReceiveFailed);
         }
 
         Ok(())
     }
+
+    /// Appends the bytes available to `buf`. Cancelling it does not lose data.
+    pub async fn read_buf(&mut self, buf: &mut Vec<u8>) -> Result<(), TransportError> {
+        let stream = self.stream.as_mut().ok_or(TransportError::ReceiveFailed)?;
+        if buf.capacity() == buf.len() {
+            buf.reserve(RECV_BUF_SZ);
+        }
+        match stream.read_buf(buf).await {
+            Ok(0) => {
+                debug!(target: TRANSPORT, "RawSocket closed by the server");
+                Err(TransportError::ReceiveFailed)
+            }
+            Ok(_) => Ok(()),
+            Err(e) => {
+                debug!(target: TRANSPORT, "Failed to recv on RawSocket : {:?}", e);
+                Err(TransportError::ReceiveFailed)
+            }
+        }
+    }
 }
+async fn shutdown(stream: &mut Box<dyn AsyncStream>) {
+    if let Err(e) = stream.shutdown().await {
+        debug!(target: TRANSPORT, "Failed to shutdown RawSocket : {:?}", e);
+    }
+}
+
 struct TcpTransport {
     sock: SockWrapper,
+    /// Maximum size of the messages we accept
+    max_recv_size: u32,
+    /// Maximum size of the messages the server accepts
+    max_send_size: u32,
+    /// Bytes received that may not form a complete frame yet. They are kept between calls to
+    /// `recv()` so that cancelling it (e.g. in a `select!`) does not lose part of a frame.
+    recv_buf: Vec<u8>,
+    /// Start of the first frame of `recv_buf` not returned yet
+    recv_pos: usize,
+    /// Frames queued by `recv()` (the pongs), written before anything else
+    queued: Vec<u8>,
+    /// Bytes of `queued` already written
+    queued_pos: usize,
+    tls_info: Option<TlsInfo>,
 }
+
 impl Drop for TcpTransport {
     fn drop(&mut self) {
-        self.sock.close();
+        // Nothing to do when the transport was closed
+        self.sock.close_in_background();
     }
 }
 
 #[async_trait]
 impl Transport for TcpTransport {
     async fn send(&mut self, data: &[u8]) -> Result<(), TransportError> {
-        let header: MsgPrefix = MsgPrefix::new_from(&TcpMsg::Regular, Some(data.len() as u32));
+        self.send_frame(&TcpMsg::Regular, data).await
+    }
 
-        trace!(
-            "Send[0x{:X}] : {:?} ({:?})",
-            std::mem::size_of_val(&header),
-            header.bytes,
-            header
-        );
-        self.sock.write_all(&header.bytes).await?;
+    async fn recv(&mut self) -> Result<TransportMsg, TransportError> {
+        loop {
+            while let Some((msg_type, payload)) = self.next_frame()? {
+                match msg_type {
+                    TcpMsg::Regular => return Ok(TransportMsg::Wamp(payload)),
+                    // Peer expects the ping payload to be echoed back
+                    TcpMsg::Ping => self.queue_frame(&TcpMsg::Pong, &payload),
+                    TcpMsg::Pong => return Ok(TransportMsg::Pong(payload)),
+                }
+            }
 
-        trace!("Send[0x{:X}] : {:?}", data.len(), data);
-        self.sock.write_all(data).await?;
+            // Answer the pings before waiting for more data
+            self.write_queued().await?;
 
-        Ok(())
+            // Drop the frames already returned before reading more
+            self.recv_buf.drain(..self.recv_pos);
+            self.recv_pos = 0;
+            self.sock.read_buf(&mut self.recv_buf).await?;
+        }
+    }
+
+    async fn ping(&mut self, payload: &[u8]) -> Result<(), TransportError> {
+        self.send_frame(&TcpMsg::Ping, payload).await
     }
 
-    async fn recv(&mut self) -> Result<Vec<u8>, TransportError> {
-        let mut payload: Vec<u8>;
-        let mut header: MsgPrefix = MsgPrefix::new();
+    async fn close(&mut self) {
+        self.sock.close().await;
+    }
 
-        loop {
-            self.sock.read_exact(&mut header.bytes).await?;
-            trace!(
-                "Recv[0x{:X}] : {:?} - ({:?})",
-                std::mem::size_of_val(&header),
-                header,
-                header
-            );
+    fn tls_info(&self) -> Option<&TlsInfo> {
+        self.tls_info.as_ref()
+    }
 
-            // Validate the 4 byte header
-            let msg_type = match header.msg_type() {
-                Some(m) => m,
-                None => {
-                    error!("RawSocket message had an invalid header");
-                    return Err(TransportError::ReceiveFailed);
-                }
-            };
+    fn max_send_size(&self) -> Option<usize> {
+        Some(self.max_send_size as usize)
+    }
+}
+
+impl TcpTransport {
+    /// Takes the next complete frame out of the receive buffer
+    fn next_frame(&mut self) -> Result<Option<(TcpMsg, Vec<u8>)>, TransportError> {
+        let buf = &self.recv_buf[self.recv_pos..];
+        if buf.len() < 4 {
+            return Ok(None);
+        }
 
-            payload = Vec::with_capacity(header.payload_len() as usize);
-            unsafe { payload.set_len(header.payload_len() as usize) };
-            self.sock.read_exact(&mut payload).await?;
-            trace!("Recv[0x{:X}] : {:?}", payload.len(), payload);
+        let mut header = MsgPrefix::new();
+        header.bytes.copy_from_slice(&buf[..4]);
+        trace!(target: TRANSPORT, "Recv header : {:?}", header);
 
-            match msg_type {
-                TcpMsg::Regular => break,
-                _ => continue, //TODO : Handle ping/pong
+        // Validate the 4 byte header
+        let msg_type = match header.msg_type() {
+            Some(m) => m,
+            None => {
+                error!(target: TRANSPORT, "RawSocket message had an invalid header");
+                return Err(TransportError::ReceiveFailed);
             }
+        };
+
+        if header.payload_len() > self.max_recv_size {
+            error!(
+                target: TRANSPORT,
+                "RawSocket message of {} bytes exceeds our maximum of {}",
+                header.payload_len(),
+                self.max_recv_size
+            );
+            return Err(TransportError::MessageTooLarge(
+                header.payload_len() as usize,
+                self.max_recv_size as usize,
+            ));
         }
 
-        Ok(payload)
+        let frame_len = 4 + header.payload_len() as usize;
+        if buf.len() < frame_len {
+            self.recv_buf.reserve(frame_len - buf.len());
+            return Ok(None);
+        }
+
+        let payload = buf[4..frame_len].to_vec();
+        self.recv_pos += frame_len;
+        trace!(target: PAYLOAD, "Recv[0x{:X}] : {:?}", payload.len(), payload);
+
+        Ok(Some((msg_type, payload)))
     }
 
-    async fn close(&mut self) {
-        self.sock.close();
+    /// Queues a frame to be written by `write_queued()`
+    fn queue_frame(&mut self, msg_type: &TcpMsg, data: &[u8]) {
+        let header = MsgPrefix::new_from(msg_type, Some(data.len() as u32));
+        trace!(target: TRANSPORT, "Queue : {:?}", header);
+        self.queued.extend_from_slice(&header.bytes);
+        self.queued.extend_from_slice(data);
+    }
+
+    /// Writes the queued frames. Cancelling it (e.g. along with `recv()`) does not corrupt
+    /// the stream : what was written is recorded, the rest is written by the next call.
+    async fn write_queued(&mut self) -> Result<(), TransportError> {
+        while self.queued_pos < self.queued.len() {
+            self.queued_pos += self.sock.write(&self.queued[self.queued_pos..]).await?;
+        }
+        self.queued.clear();
+        self.queued_pos = 0;
+        Ok(())
+    }
+
+    /// Sends a RawSocket frame of the given type
+    async fn send_frame(&mut self, msg_type: &TcpMsg, data: &[u8]) -> Result<(), TransportError> {
+        // Nothing is written so the connection stays usable
+        if data.len() > self.max_send_size as usize {
+            return Err(TransportError::MessageTooLarge(
+                data.len(),
+                self.max_send_size as usize,
+            ));
+        }
+        // Finish the frames queued by recv() so they are not interleaved with this one
+        self.write_queued().await?;
+
+        let header: MsgPrefix = MsgPrefix::new_from(msg_type, Some(data.len() as u32));
+
+        trace!(
+            target: TRANSPORT,
+            "Send[0x{:X}] : {:?} ({:?})",
+            std::mem::size_of_val(&header),
+            header.bytes,
+            header
+        );
+        self.sock.write_all(&header.bytes).await?;
+
+        trace!(target: PAYLOAD, "Send[0x{:X}] : {:?}", data.len(), data);
+        self.sock.write_all(data).await?;
+
+        Ok(())
     }
 }
 
-pub async fn connect(
+pub(crate) async fn connect(
     host_ip: &str,
     host_port: u16,
     is_tls: bool,
     config: &ClientConfig,
 ) -> Result<(Box<dyn Transport + Send>, SerializerType), TransportError> {
     let host_addr = format!("{}:{}", host_ip, host_port);
-    let mut handshake = HandshakeCtx::new();
-    let mut msg_size: u32 = MAX_MSG_SZ;
-    if let Some(m) = config.get_max_msg_size() {
-        msg_size = m;
-    }
-    handshake.set_msg_size(msg_size);
+    let mut last_err = TransportError::ConnectionFailed;
 
     for serializer in config.get_serializers() {
-        trace!("Connecting to host : {}", host_addr);
-        let mut stream = if is_tls {
-            SockWrapper::Tls(Box::new(connect_tls(host_ip, host_port, config).await?))
+        trace!(target: TRANSPORT, "Connecting to host : {}", host_addr);
+        let (stream, tls_info) = if is_tls {
+            let (stream, tls_info) = connect_tls(host_ip, host_port, config).await?;
+            (SockWrapper::new(stream), Some(tls_info))
         } else {
-            SockWrapper::Plain(connect_raw(host_ip, host_port).await?)
+            let stream = connect_raw(host_ip, host_port, config).await?;
+            (SockWrapper::new(stream), None)
         };
-        handshake.set_serializer(*serializer);
-        trace!("\tSending handshake : {:?}", handshake);
 
-        // Preform the WAMP handshake
-        if let Err(e) = stream.write_all(handshake.as_ref()).await {
-            error!("Failed to send on RawSocket handshake : {:?}", e);
+        match handshake(stream, *serializer, config, tls_info).await {
+            Ok(transport) => return Ok((transport, *serializer)),
+            // Try the next serializer on a new connection
+            Err(e @ TransportError::SerializerNotSupported(..)) => last_err = e,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(last_err)
+}
+
+/// Performs the RawSocket handshake over an already established stream (e.g. from a
+/// connection pool, a custom TLS stack or a tunnel).
+///
+/// As the stream cannot be re-opened, only the first serializer of the config is attempted.
+pub async fn from_stream<S: AsyncStream + 'static>(
+    stream: S,
+    config: &ClientConfig,
+) -> Result<(Box<dyn Transport + Send>, SerializerType), TransportError> {
+    let serializer = match config.get_serializers().first() {
+        Some(s) => *s,
+        None => {
+            error!(target: TRANSPORT, "No serializer specified in the client config");
             return Err(TransportError::ConnectionFailed);
         }
-        if let Err(e) = stream.read_exact(handshake.srv_resp_bytes()).await {
-            error!("RawSocket fail to receive handshake reply : {}", e);
+    };
+
+    let transport = handshake(SockWrapper::new(stream), serializer, config, None).await?;
+    Ok((transport, serializer))
+}
+
+/// Negotiates the serializer and maximum message size with the server
+async fn handshake(
+    mut stream: SockWrapper,
+    serializer: SerializerType,
+    config: &ClientConfig,
+    tls_info: Option<TlsInfo>,
+) -> Result<Box<dyn Transport + Send>, TransportError> {
+    let mut handshake = HandshakeCtx::new(config.get_rawsocket_handshake());
+    handshake.set_msg_size(config.get_max_msg_size().unwrap_or(MAX_MSG_SZ));
+    handshake.set_serializer(serializer);
+    trace!(target: TRANSPORT, "\tSending handshake : {:?}", handshake);
+
+    // Preform the WAMP handshake
+    if let Err(e) = stream.write_all(handshake.as_ref()).await {
+        error!(target: TRANSPORT, "Failed to send on RawSocket handshake : {:?}", e);
+        return Err(TransportError::ConnectionFailed);
+    }
+    if let Err(e) = stream.read_exact(handshake.srv_resp_bytes()).await {
+        error!(target: TRANSPORT, "RawSocket fail to receive handshake reply : {}", e);
+        return Err(TransportError::ConnectionFailed);
+    }
+
+    if let Err(e) = handshake.validate() {
+        match e {
+            TransportError::SerializerNotSupported(..) => warn!(target: TRANSPORT, "{:?}", e),
+            _ => error!(target: TRANSPORT, "{:?}", e),
+        };
+        stream.close().await;
+        return Err(e);
+    }
+
+    Ok(Box::new(TcpTransport {
+        sock: stream,
+        max_recv_size: handshake.msg_size,
+        max_send_size: handshake.server_msg_size(),
+        recv_buf: Vec::with_capacity(RECV_BUF_SZ),
+        recv_pos: 0,
+        queued: Vec::new(),
+        queued_pos: 0,
+        tls_info,
+    }))
+}
+
+/// Connects to the first address of the host that answers. A new attempt is started when the
+/// previous one fails or after the connection attempt delay of the config (RFC 8305).
+pub(crate) async fn connect_raw(
+    host_ip: &str,
+    host_port: u16,
+    cfg: &ClientConfig,
+) -> Result<TcpStream, TransportError> {
+    // IPv6 literals are bracketed in uris
+    let host = host_ip.trim_start_matches('[').trim_end_matches(']');
+    let addrs = match lookup_host((host, host_port)).await {
+        Ok(addrs) => interleave_families(addrs.collect()),
+        Err(e) => {
+            error!(target: TRANSPORT, "Failed to resolve {} : {:?}", host_ip, e);
             return Err(TransportError::ConnectionFailed);
         }
+    };
+    connect_first(addrs, cfg.get_connection_attempt_delay()).await
+}
 
-        if let Err(e) = handshake.validate() {
-            match e {
-                TransportError::SerializerNotSupported(_) => {
-                    warn!("{:?}", e);
-                    stream.close();
-                    continue;
-                }
-                TransportError::InvalidMaximumMsgSize(_) => {
-                    error!("{:?}", e);
-                    break;
-                }
-                _ => break,
-            };
+/// Connects to the first of `addrs` that answers, starting the next attempt when the previous
+/// one fails or after `attempt_delay`
+async fn connect_first(
+    addrs: Vec<SocketAddr>,
+    attempt_delay: Duration,
+) -> Result<TcpStream, TransportError> {
+    let mut addrs = addrs.into_iter();
+    let mut attempts = FuturesUnordered::new();
+    loop {
+        if let Some(addr) = addrs.next() {
+            trace!(target: TRANSPORT, "Connecting to {}", addr);
+            attempts.push(async move { (addr, TcpStream::connect(addr).await) });
+        } else if attempts.is_empty() {
+            break;
         }
 
-        return Ok((Box::new(TcpTransport { sock: stream }), *serializer));
+        // Wait for an attempt to complete, or start the next one after the delay
+        let res = if addrs.len() > 0 {
+            match tokio::time::timeout(attempt_delay, attempts.next()).await {
+                Ok(res) => res,
+                Err(_) => continue,
+            }
+        } else {
+            attempts.next().await
+        };
+
+        match res {
+            Some((_, Ok(s))) => return Ok(s),
+            Some((addr, Err(e))) => {
+                warn!(target: TRANSPORT, "Failed to connect to {} : {:?}", addr, e)
+            }
+            None => {}
+        }
     }
 
+    error!(target: TRANSPORT, "Failed to connect to server using raw tcp");
     Err(TransportError::ConnectionFailed)
 }
 
-pub async fn connect_raw(host_ip: &str, host_port: u16) -> Result<TcpStream, TransportError> {
-    let host_addr = format!("{}:{}", host_ip, host_port);
-
-    match TcpStream::connect(&host_addr).await {
-        Ok(s) => Ok(s),
-        Err(e) => {
-            error!("Failed to connect to server using raw tcp: {:?}", e);
-            Err(TransportError::ConnectionFailed)
+/// Alternates between IPv6 and IPv4 addresses, starting with the family of the first address
+/// returned by the resolver
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_v6 = matches!(addrs.first(), Some(a) if a.is_ipv6());
+    let (preferred, other): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        addrs.iter().partition(|a| a.is_ipv6() == first_is_v6);
+
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    let mut sorted = Vec::with_capacity(addrs.len());
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => break,
+            (a, b) => sorted.extend(a.into_iter().chain(b)),
         }
     }
+    sorted
 }
 
-pub async fn connect_tls(
+pub(crate) async fn connect_tls(
     host_url: &str,
     host_port: u16,
     cfg: &ClientConfig,
-) -> Result<tokio_native_tls::TlsStream<TcpStream>, TransportError> {
-    let stream = connect_raw(host_url, host_port).await?;
+) -> Result<(tokio_native_tls::TlsStream<TcpStream>, TlsInfo), TransportError> {
+    let stream = connect_raw(host_url, host_port, cfg).await?;
     let mut tls_cfg = TlsConnector::builder();
 
     if !cfg.get_ssl_verify() {
         tls_cfg.danger_accept_invalid_certs(true);
     }
+    if let Some(identity) = cfg.get_tls_identity() {
+        tls_cfg.identity(identity.clone());
+    }
 
     let cx = match tls_cfg.build() {
         Ok(c) => c,
         Err(e) => {
-            error!("Failed to create TLS context : {:?}", e);
+            error!(target: TRANSPORT, "Failed to create TLS context : {:?}", e);
             return Err(TransportError::ConnectionFailed);
         }
     };
     let cx = tokio_native_tls::TlsConnector::from(cx);
-    match cx.connect(host_url, stream).await {
-        Ok(s) => Ok(s),
+    let stream = match cx.connect(host_url, stream).await {
+        Ok(s) => s,
         Err(e) => {
-            error!("Failed to establish TLS handshake : {:?}", e);
-            Err(TransportError::ConnectionFailed)
+            error!(target: TRANSPORT, "Failed to establish TLS handshake : {:?}", e);
+            return Err(TransportError::ConnectionFailed);
+        }
+    };
+
+    let session = stream.get_ref();
+    let tls_info = TlsInfo {
+        peer_certificate: session
+            .peer_certificate()
+            .ok()
+            .flatten()
+            .and_then(|c| c.to_der().ok()),
+        channel_binding: session.tls_server_end_point().ok().flatten(),
+        client_certificate: cfg.get_tls_identity().is_some(),
+    };
+    Ok((stream, tls_info))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn addrs(addrs: &[&str]) -> Vec<SocketAddr> {
+        addrs.iter().map(|a| a.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn interleave_families_alternates() {
+        let resolved = addrs(&["[::1]:1", "[::2]:1", "[::3]:1", "1.0.0.1:1", "1.0.0.2:1"]);
+        assert_eq!(
+            interleave_families(resolved),
+            addrs(&["[::1]:1", "1.0.0.1:1", "[::2]:1", "1.0.0.2:1", "[::3]:1"])
+        );
+
+        // The family of the first address comes first
+        let resolved = addrs(&["1.0.0.1:1", "[::1]:1", "1.0.0.2:1", "1.0.0.3:1"]);
+        assert_eq!(
+            interleave_families(resolved),
+            addrs(&["1.0.0.1:1", "[::1]:1", "1.0.0.2:1", "1.0.0.3:1"])
+        );
+        assert!(interleave_families(Vec::new()).is_empty());
+    }
+
+    #[tokio::test]
+    async fn connect_first_falls_back() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let reachable = listener.local_addr().unwrap();
+        // Nothing listens on the port of a dropped listener. IPv6 may be unavailable.
+        let refused = match TcpListener::bind("[::1]:0").await {
+            Ok(l) => l,
+            Err(_) => TcpListener::bind("127.0.0.1:0").await.unwrap(),
         }
+        .local_addr()
+        .unwrap();
+
+        // The first address fails, the next one is tried without waiting for the delay
+        let stream = tokio::time::timeout(
+            Duration::from_secs(5),
+            connect_first(vec![refused, reachable], Duration::from_secs(60)),
+        )
+        .await
+        .expect("the next address was not tried")
+        .unwrap();
+        assert_eq!(stream.peer_addr().unwrap(), reachable);
+
+        assert!(matches!(
+            connect_first(vec![refused], Duration::from_millis(10)).await,
+            Err(TransportError::ConnectionFailed)
+        ));
     }
 }
@@ -2,81 +2,103 @@ use async_trait::async_trait;
 use futures::{SinkExt, StreamExt};
 use log::*;
 use std::str::FromStr;
-use tokio::net::TcpStream;
 use tokio_tungstenite::{
     client_async,
-    tungstenite::{handshake::client::Request, Message},
+    tungstenite::{
+        error::CapacityError,
+        handshake::client::Request,
+        http::{HeaderValue, Uri},
+        Error as WsError, Message,
+    },
     MaybeTlsStream, WebSocketStream,
 };
 
 use crate::client::ClientConfig;
+use crate::log_targets::{PAYLOAD, TRANSPORT};
 use crate::serializer::SerializerType;
-use crate::transport::{Transport, TransportError};
+use crate::transport::{AsyncStream, TlsInfo, Transport, TransportError, TransportMsg};
 
-struct WsCtx {
+struct WsCtx<S> {
     is_bin: bool,
-    client: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    client: WebSocketStream<S>,
+    response_headers: Vec<(String, String)>,
+    tls_info: Option<TlsInfo>,
 }
 
 #[async_trait]
-impl Transport for WsCtx {
+impl<S: AsyncStream> Transport for WsCtx<S> {
     async fn send(&mut self, data: &[u8]) -> Result<(), TransportError> {
-        trace!("Send[0x{:X}] : {:?}", data.len(), data);
+        trace!(target: PAYLOAD, "Send[0x{:X}] : {:?}", data.len(), data);
         let res = if self.is_bin {
             self.client.send(Message::Binary(Vec::from(data))).await
         } else {
             let str_payload = std::str::from_utf8(data).unwrap().to_owned();
-            trace!("Text('{}')", str_payload);
+            trace!(target: PAYLOAD, "Text('{}')", str_payload);
             self.client.send(Message::Text(str_payload)).await
         };
 
         if let Err(e) = res {
-            error!("Failed to send on websocket : {:?}", e);
+            error!(target: TRANSPORT, "Failed to send on websocket : {:?}", e);
             return Err(TransportError::SendFailed);
         }
 
         Ok(())
     }
 
-    async fn recv(&mut self) -> Result<Vec<u8>, TransportError> {
+    async fn recv(&mut self) -> Result<TransportMsg, TransportError> {
         let payload;
         // Receive a message
         loop {
             let msg: Message = match self.client.next().await {
                 Some(Ok(m)) => m,
+                Some(Err(WsError::Capacity(CapacityError::MessageTooLong { size, max_size }))) => {
+                    error!(
+                        target: TRANSPORT,
+                        "Websocket message of {} bytes exceeds our maximum of {}",
+                        size, max_size
+                    );
+                    return Err(TransportError::MessageTooLarge(size, max_size));
+                }
                 Some(Err(e)) => {
-                    error!("Failed to recv from websocket : {:?}", e);
+                    error!(target: TRANSPORT, "Failed to recv from websocket : {:?}", e);
                     return Err(TransportError::ReceiveFailed);
                 }
                 None => return Err(TransportError::ReceiveFailed),
             };
 
-            trace!("Recv[] : {:?}", msg);
+            trace!(target: PAYLOAD, "Recv[] : {:?}", msg);
 
             payload = match msg {
                 Message::Text(s) => {
                     if self.is_bin {
-                        error!("Got websocket Text message but only Binary is allowed");
+                        error!(
+                            target: TRANSPORT,
+                            "Got websocket Text message but only Binary is allowed"
+                        );
                         return Err(TransportError::UnexpectedResponse);
                     }
                     Vec::from(s.as_bytes())
                 }
                 Message::Binary(b) => {
                     if !self.is_bin {
-                        error!("Got websocket Binary message but only Text is allowed");
+                        error!(
+                            target: TRANSPORT,
+                            "Got websocket Binary message but only Text is allowed"
+                        );
                         return Err(TransportError::UnexpectedResponse);
                     }
                     b
                 }
                 Message::Ping(d) => {
                     if let Err(e) = self.client.send(Message::Pong(d)).await {
-                        error!("Failed to respond to websocket Ping : {:?}", e);
+                        error!(target: TRANSPORT, "Failed to respond to websocket Ping : {:?}", e);
                         return Err(TransportError::UnexpectedResponse);
                     }
                     continue;
                 }
-                _ => {
-                    error!("Unexpected websocket message type : {:?}", msg);
+                Message::Pong(d) => return Ok(TransportMsg::Pong(d)),
+                Message::Close(_) => {
+                    error!(target: TRANSPORT, "Unexpected websocket Close message");
                     return Err(TransportError::UnexpectedResponse);
                 }
             };
@@ -84,21 +106,92 @@ impl Transport for WsCtx {
             break;
         }
 
-        Ok(payload)
+        Ok(TransportMsg::Wamp(payload))
+    }
+
+    async fn ping(&mut self, payload: &[u8]) -> Result<(), TransportError> {
+        if let Err(e) = self.client.send(Message::Ping(Vec::from(payload))).await {
+            error!(target: TRANSPORT, "Failed to send websocket Ping : {:?}", e);
+            return Err(TransportError::SendFailed);
+        }
+
+        Ok(())
     }
 
     async fn close(&mut self) {
-        match self.client.close(None) {
-            _ => { /*ignore result*/ }
-        };
+        let _ = self.client.close(None).await;
+    }
+
+    fn response_headers(&self) -> &[(String, String)] {
+        &self.response_headers
+    }
+
+    fn tls_info(&self) -> Option<&TlsInfo> {
+        self.tls_info.as_ref()
     }
 }
 
-pub async fn connect(
+pub(crate) async fn connect(
     url: &url::Url,
     config: &ClientConfig,
 ) -> Result<(Box<dyn Transport + Send>, SerializerType), TransportError> {
-    let mut request = Request::builder().uri(url.as_ref());
+    let (sock, tls_info) = match url.scheme() {
+        "ws" => (
+            MaybeTlsStream::Plain(
+                crate::transport::tcp::connect_raw(
+                    url.host_str().unwrap(),
+                    url.port_or_known_default().unwrap(),
+                    config,
+                )
+                .await?,
+            ),
+            None,
+        ),
+        "wss" => {
+            let (stream, tls_info) = crate::transport::tcp::connect_tls(
+                url.host_str().unwrap(),
+                url.port_or_known_default().unwrap(),
+                config,
+            )
+            .await?;
+            (MaybeTlsStream::NativeTls(stream), Some(tls_info))
+        }
+        _ => panic!("ws::connect called but uri doesnt have websocket scheme"),
+    };
+
+    handshake(url.as_ref(), sock, config, tls_info).await
+}
+
+/// Performs the WebSocket upgrade over an already established stream (e.g. from a
+/// connection pool, a custom TLS stack or a tunnel).
+///
+/// `uri` is the WebSocket uri used to build the upgrade request (path, Host header).
+pub async fn from_stream<T: AsRef<str>, S: AsyncStream + 'static>(
+    uri: T,
+    stream: S,
+    config: &ClientConfig,
+) -> Result<(Box<dyn Transport + Send>, SerializerType), TransportError> {
+    handshake(uri.as_ref(), stream, config, None).await
+}
+
+/// Sends the upgrade request and picks the serializer from the server's response
+async fn handshake<S: AsyncStream + 'static>(
+    uri: &str,
+    sock: S,
+    config: &ClientConfig,
+    tls_info: Option<TlsInfo>,
+) -> Result<(Box<dyn Transport + Send>, SerializerType), TransportError> {
+    let mut request = Request::builder().uri(request_uri(uri, config)?);
+
+    if let Some(origin) = config.get_websocket_origin() {
+        if let Err(e) = HeaderValue::from_str(origin) {
+            return Err(TransportError::InvalidRequest(format!(
+                "Origin '{}' : {}",
+                origin, e
+            )));
+        }
+        request = request.header("Origin", origin);
+    }
 
     if !config.get_agent().is_empty() {
         request = request.header("User-Agent", config.get_agent());
@@ -116,51 +209,45 @@ pub async fn connect(
         request = request.header(key, value);
     }
 
-    let sock = match url.scheme() {
-        "ws" => MaybeTlsStream::Plain(
-            crate::transport::tcp::connect_raw(
-                url.host_str().unwrap(),
-                url.port_or_known_default().unwrap(),
-            )
-            .await?,
-        ),
-        "wss" => MaybeTlsStream::NativeTls(
-            crate::transport::tcp::connect_tls(
-                url.host_str().unwrap(),
-                url.port_or_known_default().unwrap(),
-                config,
-            )
-            .await?,
-        ),
-        _ => panic!("ws::connect called but uri doesnt have websocket scheme"),
+    let request = match request.body(()) {
+        Ok(r) => r,
+        Err(e) => return Err(TransportError::InvalidRequest(e.to_string())),
     };
 
-    let (client, resp) = match client_async(request.body(()).unwrap(), sock).await {
+    let (client, resp) = match client_async(request, sock).await {
         Ok(v) => v,
+        Err(WsError::Http(resp)) => {
+            error!(target: TRANSPORT, "Websocket upgrade rejected : {:?}", resp);
+            return Err(TransportError::UpgradeRejected(
+                resp.status().as_u16(),
+                resp.body().clone().unwrap_or_default(),
+            ));
+        }
         Err(e) => {
-            error!("Websocket failed to connect : {:?}", e);
+            error!(target: TRANSPORT, "Websocket failed to connect : {:?}", e);
             return Err(TransportError::ConnectionFailed);
         }
     };
 
     let mut picked_serializer: Option<SerializerType> = None;
+    let mut response_headers = Vec::with_capacity(resp.headers().len());
     for (key, value) in resp.headers().iter() {
         let val = match value.to_str() {
             Ok(v) => v,
             Err(_) => continue,
         };
-        trace!("Header '{}' = '{}'", key.as_str(), val);
-        if key.as_str().to_lowercase() == "sec-websocket-protocol" {
+        trace!(target: TRANSPORT, "Header '{}' = '{}'", key.as_str(), val);
+        response_headers.push((key.as_str().to_string(), val.to_string()));
+        if picked_serializer.is_none() && key.as_str().to_lowercase() == "sec-websocket-protocol" {
             let header_se = match SerializerType::from_str(val) {
                 Ok(s) => s,
                 Err(e) => {
                     //Hope that theres another serializer we support in the header
-                    warn!("{:?}", e);
+                    warn!(target: TRANSPORT, "{:?}", e);
                     continue;
                 }
             };
             picked_serializer = Some(header_se);
-            break;
         }
     }
 
@@ -168,19 +255,60 @@ pub async fn connect(
         Some(s) => s,
         None => {
             return Err(TransportError::SerializerNotSupported(
-                "<unknown>".to_string(),
+                config.get_serializers().clone(),
+                None,
             ))
         }
     };
 
     Ok((
         Box::new(WsCtx {
-            is_bin: match picked_serializer {
-                SerializerType::MsgPack => true,
-                _ => false,
-            },
+            is_bin: matches!(picked_serializer, SerializerType::MsgPack),
             client,
+            response_headers,
+            tls_info,
         }),
         picked_serializer,
     ))
 }
+
+/// Applies the configured query parameters and Host override to the websocket uri
+fn request_uri(uri: &str, config: &ClientConfig) -> Result<Uri, TransportError> {
+    let mut url = match url::Url::parse(uri) {
+        Ok(u) => u,
+        Err(e) => return Err(TransportError::InvalidRequest(format!("'{}' : {}", uri, e))),
+    };
+
+    if !config.get_websocket_query_params().is_empty() {
+        let mut query = url.query_pairs_mut();
+        for (key, value) in config.get_websocket_query_params() {
+            query.append_pair(key, value);
+        }
+    }
+
+    let uri = match Uri::from_str(url.as_str()) {
+        Ok(u) => u,
+        Err(e) => return Err(TransportError::InvalidRequest(format!("'{}' : {}", url, e))),
+    };
+
+    // The Host header is generated from the uri authority
+    let host = match config.get_websocket_host() {
+        Some(h) => h,
+        None => return Ok(uri),
+    };
+    let mut parts = uri.into_parts();
+    parts.authority = match host.parse() {
+        Ok(a) => Some(a),
+        Err(e) => {
+            return Err(TransportError::InvalidRequest(format!(
+                "Host '{}' : {}",
+                host, e
+            )))
+        }
+    };
+
+    match Uri::from_parts(parts) {
+        Ok(u) => Ok(u),
+        Err(e) => Err(TransportError::InvalidRequest(e.to_string())),
+    }
+}
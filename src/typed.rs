@@ -0,0 +1,123 @@
+use std::marker::PhantomData;
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::common::*;
+use crate::core::SubscriptionQueue;
+use crate::error::*;
+
+/// A topic URI bound to the type of its event payload
+///
+/// ```
+/// # use wamp_async::TypedTopic;
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// struct Heartbeat {
+///     uptime: u64,
+/// }
+///
+/// const HEARTBEAT: TypedTopic<Heartbeat> = TypedTopic::new("peer.heartbeat");
+/// ```
+///
+/// See [`crate::Client::publish_typed`] and [`crate::Client::subscribe_typed`].
+pub struct TypedTopic<T> {
+    uri: &'static str,
+    payload: PhantomData<fn() -> T>,
+}
+
+impl<T> TypedTopic<T> {
+    /// Binds `uri` to the payload type `T`
+    pub const fn new(uri: &'static str) -> Self {
+        Self {
+            uri,
+            payload: PhantomData,
+        }
+    }
+    /// Returns the topic URI
+    pub fn uri(&self) -> &'static str {
+        self.uri
+    }
+}
+
+/// A procedure URI bound to the types of its arguments (`A`) and result (`R`)
+///
+/// ```
+/// # use wamp_async::TypedProcedure;
+/// const ADD: TypedProcedure<(i64, i64), i64> = TypedProcedure::new("peer.add");
+/// ```
+///
+/// See [`crate::Client::call_typed`] and [`crate::Client::register_typed`].
+pub struct TypedProcedure<A, R> {
+    uri: &'static str,
+    payload: PhantomData<fn(A) -> R>,
+}
+
+impl<A, R> TypedProcedure<A, R> {
+    /// Binds `uri` to the argument type `A` and result type `R`
+    pub const fn new(uri: &'static str) -> Self {
+        Self {
+            uri,
+            payload: PhantomData,
+        }
+    }
+    /// Returns the procedure URI
+    pub fn uri(&self) -> &'static str {
+        self.uri
+    }
+}
+
+/// Receive end of a subscription to a [`TypedTopic`]
+pub struct TypedSubscription<T> {
+    queue: SubscriptionQueue,
+    payload: PhantomData<fn() -> T>,
+}
+
+impl<T: DeserializeOwned> TypedSubscription<T> {
+    pub(crate) fn new(queue: SubscriptionQueue) -> Self {
+        Self {
+            queue,
+            payload: PhantomData,
+        }
+    }
+
+    /// Waits for the next event and deserializes its payload.
+    ///
+    /// Returns `None` once the subscription is closed.
+    pub async fn recv(&mut self) -> Option<(WampId, Result<T, WampError>)> {
+        let (pub_id, arguments, arguments_kw, _) = self.queue.recv().await?;
+        Some((pub_id, decode_payload(arguments, arguments_kw)))
+    }
+
+    /// Returns the untyped event queue
+    pub fn into_inner(self) -> SubscriptionQueue {
+        self.queue
+    }
+}
+
+/// Converts a typed value into a WAMP payload.
+///
+/// Values serialized as a map are sent as keyword arguments, `None`/`()` as an empty payload,
+/// and anything else as a single positional argument.
+pub(crate) fn encode_payload<T: Serialize>(
+    value: &T,
+) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError> {
+    Ok(match try_into_any_value(value)? {
+        WampPayloadValue::Object(kwargs) => (None, Some(kwargs)),
+        WampPayloadValue::Null => (None, None),
+        value => (Some(vec![value]), None),
+    })
+}
+
+/// Converts a WAMP payload produced by [`encode_payload`] back into a typed value
+pub(crate) fn decode_payload<T: DeserializeOwned>(
+    arguments: Option<WampArgs>,
+    arguments_kw: Option<WampKwArgs>,
+) -> Result<T, WampError> {
+    let no_args = arguments.as_ref().map_or(true, |a| a.is_empty());
+    match arguments_kw {
+        Some(kwargs) if !kwargs.is_empty() || no_args => try_from_kwargs(kwargs),
+        _ => match arguments.and_then(|a| a.into_iter().next()) {
+            Some(value) => try_from_any_value(value),
+            None => try_from_any_value(WampPayloadValue::Null),
+        },
+    }
+}
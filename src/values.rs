@@ -0,0 +1,229 @@
+//! Commonly exchanged values, each behind the feature of the crate it relies on
+//!
+//! The types convert from and to payload values with `From`/`TryFrom` and implement serde so
+//! they can also be used in typed payloads.
+#[cfg(any(feature = "chrono", feature = "uuid", feature = "rust_decimal"))]
+use std::convert::TryFrom;
+
+#[cfg(any(feature = "chrono", feature = "uuid", feature = "rust_decimal"))]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::common::*;
+#[cfg(any(feature = "chrono", feature = "uuid", feature = "rust_decimal"))]
+use crate::error::*;
+
+/// Implements the conversions shared by all the value types
+#[cfg(any(feature = "chrono", feature = "uuid", feature = "rust_decimal"))]
+macro_rules! payload_value {
+    ($name:ident, $inner:ty) => {
+        impl From<$inner> for $name {
+            fn from(v: $inner) -> Self {
+                Self(v)
+            }
+        }
+
+        impl From<$name> for $inner {
+            fn from(v: $name) -> Self {
+                v.0
+            }
+        }
+
+        impl TryFrom<WampPayloadValue> for $name {
+            type Error = WampError;
+            fn try_from(value: WampPayloadValue) -> Result<Self, Self::Error> {
+                Self::try_from(&value)
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                WampPayloadValue::from(*self).serialize(serializer)
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let value = WampPayloadValue::deserialize(deserializer)?;
+                Self::try_from(&value).map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+/// UTC date and time of a payload
+///
+/// Sent as a MsgPack timestamp with the MsgPack serializer and as an RFC3339 string with JSON.
+/// Both representations are accepted when receiving.
+///
+/// ```
+/// # use std::convert::TryFrom;
+/// # use wamp_async::{WampDateTime, WampPayloadValue};
+/// let value: WampPayloadValue = "2020-09-13T12:26:40Z".into();
+/// let date = WampDateTime::try_from(value).unwrap();
+/// assert_eq!(date.0.timestamp(), 1_600_000_000);
+/// ```
+#[cfg(feature = "chrono")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WampDateTime(pub chrono::DateTime<chrono::Utc>);
+
+#[cfg(feature = "chrono")]
+payload_value!(WampDateTime, chrono::DateTime<chrono::Utc>);
+
+#[cfg(feature = "chrono")]
+impl From<WampDateTime> for WampPayloadValue {
+    fn from(v: WampDateTime) -> Self {
+        MsgPackExt::from_timestamp(v.0.timestamp(), v.0.timestamp_subsec_nanos()).into()
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl TryFrom<&WampPayloadValue> for WampDateTime {
+    type Error = WampError;
+    fn try_from(value: &WampPayloadValue) -> Result<Self, Self::Error> {
+        use chrono::TimeZone;
+
+        if let Some(s) = value.as_str() {
+            return chrono::DateTime::parse_from_rfc3339(s)
+                .map(|d| Self(d.with_timezone(&chrono::Utc)))
+                .map_err(|e| {
+                    WampError::InvalidArgument(format!("'{}' is not an RFC3339 date : {}", s, e))
+                });
+        }
+
+        MsgPackExt::from_value(value)
+            .and_then(|ext| ext.timestamp())
+            .and_then(|(secs, nanos)| chrono::Utc.timestamp_opt(secs, nanos).single())
+            .map(Self)
+            .ok_or_else(|| WampError::InvalidArgument(format!("{} is not a date", value)))
+    }
+}
+
+/// Formats a MsgPack timestamp as an RFC3339 string, for serializers without extension types
+///
+/// Done without `chrono` so the JSON encoding does not depend on the features enabled. The
+/// fraction of second has 3, 6 or 9 digits, as few as needed. Timestamps outside of the years
+/// 0 to 9999 have no RFC3339 representation.
+pub(crate) fn timestamp_to_rfc3339(ext: &MsgPackExt) -> Option<String> {
+    let (secs, nanos) = ext.timestamp()?;
+    if nanos >= 1_000_000_000 {
+        return None;
+    }
+    let (days, secs) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+
+    // Civil date of a count of days since 1970-01-01, from Howard Hinnant's `civil_from_days`
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    if !(0..=9999).contains(&year) {
+        return None;
+    }
+
+    let fraction = match nanos {
+        0 => String::new(),
+        n if n % 1_000_000 == 0 => format!(".{:03}", n / 1_000_000),
+        n if n % 1_000 == 0 => format!(".{:06}", n / 1_000),
+        n => format!(".{:09}", n),
+    };
+    Some(format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}Z",
+        year,
+        month,
+        day,
+        secs / 3_600,
+        secs % 3_600 / 60,
+        secs % 60,
+        fraction
+    ))
+}
+
+/// UUID of a payload
+///
+/// Sent as its hyphenated string with every serializer, as MsgPack has no standard UUID type.
+/// The simple, URN and braced forms are also accepted when receiving.
+///
+/// ```
+/// # use std::convert::TryFrom;
+/// # use wamp_async::{WampPayloadValue, WampUuid};
+/// let value: WampPayloadValue = "67e55044-10b1-426f-9247-bb680e5fe0c8".into();
+/// let id = WampUuid::try_from(value).unwrap();
+/// assert_eq!(WampPayloadValue::from(id), "67e55044-10b1-426f-9247-bb680e5fe0c8");
+/// ```
+#[cfg(feature = "uuid")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WampUuid(pub uuid::Uuid);
+
+#[cfg(feature = "uuid")]
+payload_value!(WampUuid, uuid::Uuid);
+
+#[cfg(feature = "uuid")]
+impl From<WampUuid> for WampPayloadValue {
+    fn from(v: WampUuid) -> Self {
+        v.0.hyphenated().to_string().into()
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl TryFrom<&WampPayloadValue> for WampUuid {
+    type Error = WampError;
+    fn try_from(value: &WampPayloadValue) -> Result<Self, Self::Error> {
+        let s = value
+            .as_str()
+            .ok_or_else(|| WampError::InvalidArgument(format!("{} is not a UUID", value)))?;
+        uuid::Uuid::parse_str(s)
+            .map(Self)
+            .map_err(|e| WampError::InvalidArgument(format!("'{}' is not a UUID : {}", s, e)))
+    }
+}
+
+/// Decimal number of a payload
+///
+/// Sent as a string with every serializer to keep it exact. Strings and numbers, including
+/// scientific notation, are accepted when receiving. Enable `arbitrary_precision` to receive
+/// JSON numbers without rounding them through `f64`.
+///
+/// ```
+/// # use std::convert::TryFrom;
+/// # use wamp_async::{WampDecimal, WampPayloadValue};
+/// let price = WampDecimal::try_from(WampPayloadValue::from("19.99")).unwrap();
+/// assert_eq!(WampPayloadValue::from(price), "19.99");
+/// ```
+#[cfg(feature = "rust_decimal")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WampDecimal(pub rust_decimal::Decimal);
+
+#[cfg(feature = "rust_decimal")]
+payload_value!(WampDecimal, rust_decimal::Decimal);
+
+#[cfg(feature = "rust_decimal")]
+impl From<WampDecimal> for WampPayloadValue {
+    fn from(v: WampDecimal) -> Self {
+        v.0.to_string().into()
+    }
+}
+
+#[cfg(feature = "rust_decimal")]
+impl TryFrom<&WampPayloadValue> for WampDecimal {
+    type Error = WampError;
+    fn try_from(value: &WampPayloadValue) -> Result<Self, Self::Error> {
+        let s = match value {
+            WampPayloadValue::String(s) => s.clone(),
+            WampPayloadValue::Number(n) => n.to_string(),
+            _ => {
+                return Err(WampError::InvalidArgument(format!(
+                    "{} is not a decimal",
+                    value
+                )))
+            }
+        };
+        s.parse::<rust_decimal::Decimal>()
+            .or_else(|_| rust_decimal::Decimal::from_scientific(&s))
+            .map(Self)
+            .map_err(|e| WampError::InvalidArgument(format!("'{}' is not a decimal : {}", s, e)))
+    }
+}
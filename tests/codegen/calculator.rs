@@ -0,0 +1,53 @@
+// Generated by wamp_async::codegen. Do not edit.
+
+pub const ADD: ::wamp_async::TypedProcedure<(i64, i64), i64> = ::wamp_async::TypedProcedure::new("peer.add");
+pub const TYPE: ::wamp_async::TypedProcedure<String, String> = ::wamp_async::TypedProcedure::new("peer.type");
+pub const HEARTBEAT: ::wamp_async::TypedTopic<u64> = ::wamp_async::TypedTopic::new("peer.heartbeat");
+
+/// Typed stubs for the `calculator` interface
+pub struct CalculatorCaller<'c, 'a> {
+    client: &'c ::wamp_async::Client<'a>,
+}
+
+impl<'c, 'a> CalculatorCaller<'c, 'a> {
+    pub fn new(client: &'c ::wamp_async::Client<'a>) -> Self {
+        Self { client }
+    }
+
+    /// Calls `peer.add`
+    pub async fn call_add(&self, args: &(i64, i64)) -> Result<i64, ::wamp_async::WampError> {
+        self.client.call_typed(&ADD, args).await
+    }
+
+    /// Calls `peer.type`
+    pub async fn call_type(&self, args: &String) -> Result<String, ::wamp_async::WampError> {
+        self.client.call_typed(&TYPE, args).await
+    }
+
+    /// Publishes on `peer.heartbeat`
+    pub async fn publish_heartbeat(&self, value: &u64, acknowledge: bool) -> Result<Option<::wamp_async::WampId>, ::wamp_async::WampError> {
+        self.client.publish_typed(&HEARTBEAT, value, acknowledge).await
+    }
+
+    /// Subscribes to `peer.heartbeat`
+    pub async fn subscribe_heartbeat(&self) -> Result<(::wamp_async::WampId, ::wamp_async::TypedSubscription<u64>), ::wamp_async::WampError> {
+        self.client.subscribe_typed(&HEARTBEAT).await
+    }
+}
+
+/// Implementation of the procedures of the `calculator` interface
+pub trait CalculatorCallee: Send + Sync + 'static {
+    fn add(&self, args: (i64, i64)) -> ::std::pin::Pin<Box<dyn ::std::future::Future<Output = Result<i64, ::wamp_async::WampError>> + Send>>;
+    fn r#type(&self, args: String) -> ::std::pin::Pin<Box<dyn ::std::future::Future<Output = Result<String, ::wamp_async::WampError>> + Send>>;
+}
+
+/// Registers every procedure of the `calculator` interface
+pub async fn register_calculator<T: CalculatorCallee>(client: &::wamp_async::Client<'_>, callee: ::std::sync::Arc<T>) -> Result<Vec<::wamp_async::WampId>, ::wamp_async::WampError> {
+    let mut rpc_ids = Vec::new();
+    let c = callee.clone();
+    rpc_ids.push(client.register_typed(&ADD, move |args| c.add(args)).await?);
+    let c = callee.clone();
+    rpc_ids.push(client.register_typed(&TYPE, move |args| c.r#type(args)).await?);
+    let _ = callee;
+    Ok(rpc_ids)
+}
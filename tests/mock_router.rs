@@ -0,0 +1,2655 @@
+//! Runs the example flows against an in-memory router, so they are verified by `cargo test`
+//!
+//! The router implements the subset of the WAMP basic profile the examples rely on (pub/sub,
+//! RPC, ticket authentication, prefix matching and `wamp.session.kill`) over the JSON
+//! serializer. The flows are run against real routers by `router_interop.rs`.
+
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::num::NonZeroU64;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde_json::{json, Value};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Semaphore;
+
+use wamp_async::transport::{Transport, TransportError, TransportMsg};
+use wamp_async::{
+    Arg, AuthenticationChallengeResponse, AuthenticationMethod, CallBatch, CallOptions, CallerPool,
+    CancelMode, Client, ClientConfig, ClientState, DispatchSource, DispatchTiming,
+    DynamicDispatcher, EventJournal, Extensions, IdempotencyCache, JsonTextCodec,
+    MalformedMessagePolicy, Manifest, MatchPolicy, MsgPackExt, OversizedResponsePolicy,
+    ProcedureManifest, PublishOptions, Quorum, RegisterOptions, RegistrationEvent, RouterDialect,
+    RpcEventPump, SerializerType, SessionCloseReason, ShutdownToken, SlowInlineHandlerPolicy,
+    SubscribeOptions, SubscriptionEvent, SubscriptionItem, TopicManifest, WampArgs, WampError,
+    WampKwArgs, IDEMPOTENCY_KEY_KWARG,
+};
+
+const REALM: &str = "realm1";
+/// `authid` and ticket accepted by the router
+const TICKET: (&str, &str) = ("joe", "secret!");
+
+/// Client side of an in-memory connection to the router
+struct MemoryTransport {
+    tx: UnboundedSender<Vec<u8>>,
+    rx: UnboundedReceiver<Vec<u8>>,
+    /// Payloads of the pings, echoed back as pongs
+    pongs: VecDeque<Vec<u8>>,
+    /// Largest message accepted by the router, like the limit negotiated by RawSocket
+    max_size: Option<usize>,
+}
+
+#[async_trait]
+impl Transport for MemoryTransport {
+    async fn send(&mut self, data: &[u8]) -> Result<(), TransportError> {
+        if let Some(max) = self.max_size.filter(|max| data.len() > *max) {
+            return Err(TransportError::MessageTooLarge(data.len(), max));
+        }
+        self.tx
+            .send(data.to_vec())
+            .map_err(|_| TransportError::SendFailed)
+    }
+
+    async fn recv(&mut self) -> Result<TransportMsg, TransportError> {
+        if let Some(payload) = self.pongs.pop_front() {
+            return Ok(TransportMsg::Pong(payload));
+        }
+        match self.rx.recv().await {
+            Some(data) => Ok(TransportMsg::Wamp(data)),
+            None => Err(TransportError::ReceiveFailed),
+        }
+    }
+
+    async fn ping(&mut self, payload: &[u8]) -> Result<(), TransportError> {
+        self.pongs.push_back(payload.to_vec());
+        Ok(())
+    }
+
+    async fn close(&mut self) {
+        self.rx.close();
+    }
+
+    fn max_send_size(&self) -> Option<usize> {
+        self.max_size
+    }
+}
+
+/// Whether `uri` matches the `pattern` registered or subscribed with the `policy` match option
+fn matches(policy: &str, pattern: &str, uri: &str) -> bool {
+    match policy {
+        "prefix" => uri.starts_with(pattern),
+        "wildcard" => {
+            let (pattern, uri): (Vec<_>, Vec<_>) =
+                (pattern.split('.').collect(), uri.split('.').collect());
+            pattern.len() == uri.len()
+                && pattern
+                    .iter()
+                    .zip(uri.iter())
+                    .all(|(p, u)| p.is_empty() || p == u)
+        }
+        _ => pattern == uri,
+    }
+}
+
+struct Entry {
+    session: u64,
+    uri: String,
+    policy: String,
+}
+
+#[derive(Default)]
+struct RouterState {
+    next_id: u64,
+    sessions: HashMap<u64, UnboundedSender<Vec<u8>>>,
+    /// `authid` and `authrole` of the joined sessions
+    auth: HashMap<u64, (String, String)>,
+    subscriptions: HashMap<u64, Entry>,
+    registrations: HashMap<u64, Entry>,
+    /// Invocation ID to the caller's session and CALL request ID
+    invocations: HashMap<u64, (u64, Value)>,
+}
+
+impl RouterState {
+    fn id(&mut self) -> u64 {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn send(&self, session: u64, mut msg: Value) {
+        // Optional args and kwargs that were not received are not forwarded
+        let parts = msg.as_array_mut().unwrap();
+        while parts.last() == Some(&Value::Null) {
+            parts.pop();
+        }
+        if let Some(tx) = self.sessions.get(&session) {
+            let _ = tx.send(serde_json::to_vec(&msg).unwrap());
+        }
+    }
+
+    /// Removes the subscriptions and registrations of a session leaving the realm
+    fn leave(&mut self, session: u64) {
+        self.subscriptions.retain(|_, e| e.session != session);
+        self.registrations.retain(|_, e| e.session != session);
+    }
+}
+
+#[derive(Clone, Default)]
+struct MockRouter {
+    state: Arc<Mutex<RouterState>>,
+}
+
+impl MockRouter {
+    /// Connects a client to the router, running its event loop and RPC handlers
+    fn connect(&self) -> Client<'static> {
+        self.connect_with_config(ClientConfig::default())
+    }
+
+    fn connect_with_config(&self, config: ClientConfig) -> Client<'static> {
+        self.connect_with_max_size(config, None)
+    }
+
+    /// Connects a client whose messages are refused by the transport above `max_size` bytes
+    fn connect_with_max_size(
+        &self,
+        config: ClientConfig,
+        max_size: Option<usize>,
+    ) -> Client<'static> {
+        let (client, (evt_loop, rpc_evt_queue)) = Client::from_transport(
+            self.transport_with_max_size(max_size),
+            SerializerType::Json,
+            Some(config),
+        );
+        tokio::spawn(evt_loop);
+        if let Some(rpc_evt_queue) = rpc_evt_queue {
+            RpcEventPump::spawn(rpc_evt_queue, 16, ShutdownToken::new());
+        }
+        client
+    }
+
+    /// Opens a new connection to the router
+    fn transport(&self) -> Box<dyn Transport + Send> {
+        self.transport_with_max_size(None)
+    }
+
+    fn transport_with_max_size(&self, max_size: Option<usize>) -> Box<dyn Transport + Send> {
+        let (client_tx, router_rx) = mpsc::unbounded_channel();
+        let (router_tx, client_rx) = mpsc::unbounded_channel();
+        tokio::spawn(self.clone().session(router_tx, router_rx));
+
+        Box::new(MemoryTransport {
+            tx: client_tx,
+            rx: client_rx,
+            pongs: VecDeque::new(),
+            max_size,
+        })
+    }
+
+    /// Closes the connection of `session`
+    fn drop_connection(&self, session: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.leave(session);
+        state.sessions.remove(&session);
+        state.auth.remove(&session);
+    }
+
+    /// Returns the joined sessions
+    fn joined_sessions(&self) -> Vec<u64> {
+        let mut sessions: Vec<u64> = self.state.lock().unwrap().auth.keys().copied().collect();
+        sessions.sort_unstable();
+        sessions
+    }
+
+    /// Returns the number of calls waiting for a result, per caller session
+    fn pending_calls(&self) -> HashMap<u64, usize> {
+        let mut calls = HashMap::new();
+        for (caller, _) in self.state.lock().unwrap().invocations.values() {
+            *calls.entry(*caller).or_insert(0) += 1;
+        }
+        calls
+    }
+
+    /// Sends a raw frame to `session`
+    fn inject(&self, session: u64, frame: &[u8]) {
+        if let Some(tx) = self.state.lock().unwrap().sessions.get(&session) {
+            let _ = tx.send(frame.to_vec());
+        }
+    }
+
+    /// Revokes the registration of `procedure` with an unsolicited UNREGISTERED
+    fn revoke_registration(&self, procedure: &str) {
+        let mut state = self.state.lock().unwrap();
+        let (reg_id, session) = state
+            .registrations
+            .iter()
+            .find(|(_, r)| r.uri == procedure)
+            .map(|(id, r)| (*id, r.session))
+            .unwrap();
+        state.registrations.remove(&reg_id);
+        state.send(
+            session,
+            json!([67, 0, {"registration": reg_id, "reason": "wamp.error.registration_revoked"}]),
+        );
+    }
+
+    /// Queues `count` events for the subscribers of `topic` at once
+    fn flood(&self, topic: &str, count: usize) {
+        let mut state = self.state.lock().unwrap();
+        let subscribers: Vec<(u64, u64)> = state
+            .subscriptions
+            .iter()
+            .filter(|(_, s)| s.uri == topic)
+            .map(|(id, s)| (*id, s.session))
+            .collect();
+        for _ in 0..count {
+            let publication = state.id();
+            for (sub_id, session) in &subscribers {
+                state.send(*session, json!([36, sub_id, publication, {}]));
+            }
+        }
+    }
+
+    /// Revokes the subscriptions to `topic` with an unsolicited UNSUBSCRIBED
+    fn revoke_subscription(&self, topic: &str) {
+        let mut state = self.state.lock().unwrap();
+        let revoked: Vec<(u64, u64)> = state
+            .subscriptions
+            .iter()
+            .filter(|(_, s)| s.uri == topic)
+            .map(|(id, s)| (*id, s.session))
+            .collect();
+        for (sub_id, session) in revoked {
+            state.subscriptions.remove(&sub_id);
+            state.send(
+                session,
+                json!([35, 0, {"subscription": sub_id, "reason": "wamp.error.subscription_revoked"}]),
+            );
+        }
+    }
+
+    async fn session(self, tx: UnboundedSender<Vec<u8>>, mut rx: UnboundedReceiver<Vec<u8>>) {
+        let session = {
+            let mut state = self.state.lock().unwrap();
+            let session = state.id();
+            state.sessions.insert(session, tx);
+            session
+        };
+
+        while let Some(data) = rx.recv().await {
+            let msg: Value = serde_json::from_slice(&data).unwrap();
+            self.handle(session, &msg);
+        }
+        let mut state = self.state.lock().unwrap();
+        state.leave(session);
+        state.sessions.remove(&session);
+        state.auth.remove(&session);
+    }
+
+    /// Handles a message of `session`
+    fn handle(&self, session: u64, msg: &Value) {
+        let mut state = self.state.lock().unwrap();
+        let welcome = json!([2, session, {"roles": {"broker": {}, "dealer": {}}}]);
+        match msg[0].as_u64().unwrap() {
+            // HELLO
+            1 => {
+                let ticket = msg[2]["authmethods"]
+                    .as_array()
+                    .is_some_and(|m| m.contains(&json!("ticket")));
+                if ticket {
+                    state.send(session, json!([4, "ticket", {}]));
+                } else {
+                    let auth = ("anonymous".to_string(), "anonymous".to_string());
+                    state.auth.insert(session, auth);
+                    state.send(session, welcome);
+                }
+            }
+            // AUTHENTICATE
+            5 => {
+                if msg[1] == TICKET.1 {
+                    let auth = (TICKET.0.to_string(), "user".to_string());
+                    state.auth.insert(session, auth);
+                    state.send(session, welcome);
+                } else {
+                    state.send(session, json!([3, {}, "wamp.error.not_authorized"]));
+                }
+            }
+            // GOODBYE, the connection can be used to join again
+            6 => {
+                state.send(session, json!([6, {}, "wamp.close.goodbye_and_out"]));
+                state.leave(session);
+            }
+            // PUBLISH
+            16 => {
+                let publication = state.id();
+                // Subscriber black/white-listing by session ID, authid and authrole
+                let listed = |key: &str, value: Value| {
+                    msg[2][key].as_array().map(|values| values.contains(&value))
+                };
+                for (sub_id, sub) in &state.subscriptions {
+                    let topic = msg[3].as_str().unwrap();
+                    let (authid, authrole) = state.auth[&sub.session].clone();
+                    let attributes = [
+                        ("", json!(sub.session)),
+                        ("_authid", json!(authid)),
+                        ("_authrole", json!(authrole)),
+                    ];
+                    if attributes.iter().any(|(suffix, value)| {
+                        listed(&format!("exclude{}", suffix), value.clone()) == Some(true)
+                            || listed(&format!("eligible{}", suffix), value.clone()) == Some(false)
+                    }) {
+                        continue;
+                    }
+                    let exclude_me = msg[2]["exclude_me"] != false;
+                    if (sub.session != session || !exclude_me)
+                        && matches(&sub.policy, &sub.uri, topic)
+                    {
+                        let details = match sub.policy.as_str() {
+                            "exact" => json!({}),
+                            _ => json!({ "topic": topic }),
+                        };
+                        state.send(
+                            sub.session,
+                            json!([36, sub_id, publication, details, msg[4], msg[5]]),
+                        );
+                    }
+                }
+                if msg[2]["acknowledge"] == true {
+                    state.send(session, json!([17, msg[1], publication]));
+                }
+            }
+            // SUBSCRIBE
+            32 => {
+                let sub_id = state.id();
+                let entry = Entry {
+                    session,
+                    uri: msg[3].as_str().unwrap().to_string(),
+                    policy: msg[2]["match"].as_str().unwrap_or("exact").to_string(),
+                };
+                state.subscriptions.insert(sub_id, entry);
+                state.send(session, json!([33, msg[1], sub_id]));
+            }
+            // UNSUBSCRIBE
+            34 => {
+                state.subscriptions.remove(&msg[2].as_u64().unwrap());
+                state.send(session, json!([35, msg[1]]));
+            }
+            // CALL of the session meta API
+            48 if msg[3] == "wamp.session.kill" => {
+                let reason = msg[5]["reason"].as_str().unwrap_or("wamp.close.killed");
+                let killed = msg[4][0].as_u64().unwrap();
+                state.send(killed, json!([6, {}, reason]));
+                state.send(session, json!([50, msg[1], {}]));
+            }
+            // CALL
+            48 => {
+                let procedure = msg[3].as_str().unwrap();
+                let registration = state
+                    .registrations
+                    .iter()
+                    .find(|(_, r)| matches(&r.policy, &r.uri, procedure))
+                    .map(|(reg_id, r)| (*reg_id, r.session, r.policy.clone()));
+                match registration {
+                    Some((reg_id, callee, policy)) => {
+                        let invocation = state.id();
+                        state
+                            .invocations
+                            .insert(invocation, (session, msg[1].clone()));
+                        let mut details = match policy.as_str() {
+                            "exact" => json!({}),
+                            _ => json!({ "procedure": procedure }),
+                        };
+                        if msg[2]["receive_progress"] == true {
+                            details["receive_progress"] = json!(true);
+                        }
+                        state.send(
+                            callee,
+                            json!([68, invocation, reg_id, details, msg[4], msg[5]]),
+                        );
+                    }
+                    None => state.send(
+                        session,
+                        json!([8, 48, msg[1], {}, "wamp.error.no_such_procedure"]),
+                    ),
+                }
+            }
+            // CANCEL, the callee is not interrupted and its result is discarded
+            49 => {
+                let invocation = state
+                    .invocations
+                    .iter()
+                    .find(|(_, (caller, request))| *caller == session && *request == msg[1])
+                    .map(|(invocation, _)| *invocation);
+                if let Some(invocation) = invocation {
+                    state.invocations.remove(&invocation);
+                    state.send(session, json!([8, 48, msg[1], {}, "wamp.error.canceled"]));
+                }
+            }
+            // REGISTER
+            64 => {
+                let procedure = msg[3].as_str().unwrap();
+                if state.registrations.values().any(|r| r.uri == procedure) {
+                    state.send(
+                        session,
+                        json!([8, 64, msg[1], {}, "wamp.error.procedure_already_exists"]),
+                    );
+                } else {
+                    let reg_id = state.id();
+                    let entry = Entry {
+                        session,
+                        uri: procedure.to_string(),
+                        policy: msg[2]["match"].as_str().unwrap_or("exact").to_string(),
+                    };
+                    state.registrations.insert(reg_id, entry);
+                    state.send(session, json!([65, msg[1], reg_id]));
+                }
+            }
+            // UNREGISTER
+            66 => {
+                state.registrations.remove(&msg[2].as_u64().unwrap());
+                state.send(session, json!([67, msg[1]]));
+            }
+            // Progressive YIELD, the call goes on
+            70 if msg[2]["progress"] == true => {
+                if let Some((caller, request)) = state.invocations.get(&msg[1].as_u64().unwrap()) {
+                    state.send(
+                        *caller,
+                        json!([50, request, {"progress": true}, msg[3], msg[4]]),
+                    );
+                }
+            }
+            // YIELD
+            70 => {
+                if let Some((caller, request)) = state.invocations.remove(&msg[1].as_u64().unwrap())
+                {
+                    state.send(caller, json!([50, request, {}, msg[3], msg[4]]));
+                }
+            }
+            // ERROR of an INVOCATION
+            8 if msg[1] == 68 => {
+                if let Some((caller, request)) = state.invocations.remove(&msg[2].as_u64().unwrap())
+                {
+                    state.send(
+                        caller,
+                        json!([8, 48, request, msg[3], msg[4], msg[5], msg[6]]),
+                    );
+                }
+            }
+            _ => panic!("Unexpected message : {}", msg),
+        }
+    }
+}
+
+async fn echo(
+    args: Option<WampArgs>,
+    kwargs: Option<WampKwArgs>,
+) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError> {
+    Ok((args, kwargs))
+}
+
+async fn joined(router: &MockRouter) -> Client<'static> {
+    let mut client = router.connect();
+    client.join_realm(REALM).await.unwrap();
+    client
+}
+
+#[tokio::test]
+async fn pubsub() {
+    let router = MockRouter::default();
+    let subscriber = joined(&router).await;
+    let publisher = joined(&router).await;
+
+    let (sub_id, mut events) = subscriber.subscribe("peer.heartbeat").await.unwrap();
+    for i in 0..3 {
+        let pub_id = publisher
+            .publish("peer.heartbeat", Some(vec![i.into()]), None, true)
+            .await
+            .unwrap();
+        assert!(pub_id.is_some());
+    }
+
+    for i in 0..3 {
+        let (_, args, kwargs, _) = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .expect("event never received")
+            .unwrap();
+        assert_eq!(args, Some(vec![i.into()]));
+        assert_eq!(kwargs, None);
+    }
+
+    subscriber.unsubscribe(sub_id).await.unwrap();
+    publisher.disconnect().await.unwrap();
+    subscriber.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn json_timestamps() {
+    let router = MockRouter::default();
+    let subscriber = joined(&router).await;
+    let publisher = joined(&router).await;
+
+    // MsgPack timestamps go through JSON as RFC3339 strings, whatever the features enabled
+    let (_, mut events) = subscriber.subscribe("peer.dates").await.unwrap();
+    let timestamps = [
+        (1_600_000_000, 0, "2020-09-13T12:26:40Z"),
+        (1_600_000_000, 5_000_000, "2020-09-13T12:26:40.005Z"),
+        (951_782_400, 123_456_789, "2000-02-29T00:00:00.123456789Z"),
+        (-1, 250_000, "1969-12-31T23:59:59.000250Z"),
+    ];
+    for (secs, nanos, _) in timestamps {
+        let date = MsgPackExt::from_timestamp(secs, nanos).into();
+        publisher
+            .publish("peer.dates", Some(vec![date]), None, true)
+            .await
+            .unwrap();
+    }
+    for (_, _, expected) in timestamps {
+        let (_, args, _, _) = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .expect("event never received")
+            .unwrap();
+        assert_eq!(args, Some(vec![expected.into()]));
+    }
+
+    publisher.disconnect().await.unwrap();
+    subscriber.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn rpc() {
+    let router = MockRouter::default();
+    let callee = joined(&router).await;
+    let caller = joined(&router).await;
+
+    let rpc_id = callee.register("peer.echo", echo).await.unwrap();
+    let mut kwargs = WampKwArgs::new();
+    kwargs.insert("name".to_string(), "wamp".into());
+    let (args, res_kwargs) = caller
+        .call("peer.echo", Some(vec![12.into()]), Some(kwargs.clone()))
+        .await
+        .unwrap();
+    assert_eq!(args, Some(vec![12.into()]));
+    assert_eq!(res_kwargs, Some(kwargs));
+
+    // The callee's error is forwarded to the caller
+    callee
+        .register("peer.fail", |_args, _kwargs| async {
+            Err::<(Option<WampArgs>, Option<WampKwArgs>), _>(WampError::InvalidArgument(
+                "nope".to_string(),
+            ))
+        })
+        .await
+        .unwrap();
+    match caller.call("peer.fail", None, None).await {
+        Err(WampError::ServerError(error, _)) => assert_eq!(error, "wamp.error.invalid_argument"),
+        res => panic!("Unexpected result : {:?}", res),
+    }
+
+    match caller.call("peer.missing", None, None).await {
+        Err(WampError::ServerError(error, _)) => assert_eq!(error, "wamp.error.no_such_procedure"),
+        res => panic!("Unexpected result : {:?}", res),
+    }
+    match callee.register("peer.echo", echo).await {
+        Err(WampError::ProcedureAlreadyExists(uri)) => assert_eq!(uri, "peer.echo"),
+        res => panic!("Unexpected result : {:?}", res),
+    }
+
+    callee.unregister(rpc_id).await.unwrap();
+    caller.disconnect().await.unwrap();
+    callee.disconnect().await.unwrap();
+}
+
+/// Stubs generated from `codegen/calculator.json`, kept up to date by the `codegen` test
+#[allow(dead_code)]
+mod calculator {
+    include!("codegen/calculator.rs");
+}
+
+struct Calculator;
+
+impl calculator::CalculatorCallee for Calculator {
+    fn add(
+        &self,
+        (a, b): (i64, i64),
+    ) -> Pin<Box<dyn Future<Output = Result<i64, WampError>> + Send>> {
+        Box::pin(async move { Ok(a + b) })
+    }
+
+    fn r#type(
+        &self,
+        args: String,
+    ) -> Pin<Box<dyn Future<Output = Result<String, WampError>> + Send>> {
+        Box::pin(async move { Ok(format!("{} : String", args)) })
+    }
+}
+
+#[tokio::test]
+async fn codegen() {
+    let generated = wamp_async::codegen::generate(include_str!("codegen/calculator.json")).unwrap();
+    assert_eq!(generated, include_str!("codegen/calculator.rs"));
+
+    // Keywords that cannot be raw identifiers and colliding constants are rejected
+    let invalid = [
+        r#"{ "procedures": [{ "name": "self", "uri": "peer.self" }] }"#,
+        r#"{ "name": "super" }"#,
+        r#"{ "procedures": [{ "name": "add", "uri": "peer.add" }, { "name": "add", "uri": "peer.sum" }] }"#,
+        r#"{ "procedures": [{ "name": "add", "uri": "peer.add" }], "topics": [{ "name": "Add", "uri": "peer.added" }] }"#,
+    ];
+    for idl in IntoIterator::into_iter(invalid) {
+        assert!(wamp_async::codegen::generate(idl).is_err(), "{}", idl);
+    }
+
+    let router = MockRouter::default();
+    let callee = joined(&router).await;
+    let caller = joined(&router).await;
+    calculator::register_calculator(&callee, Arc::new(Calculator))
+        .await
+        .unwrap();
+    let stubs = calculator::CalculatorCaller::new(&caller);
+    assert_eq!(stubs.call_add(&(1, 2)).await.unwrap(), 3);
+    assert_eq!(
+        stubs.call_type(&"name".to_string()).await.unwrap(),
+        "name : String"
+    );
+
+    caller.disconnect().await.unwrap();
+    callee.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn slow_inline_handlers() {
+    let router = MockRouter::default();
+    let caller = joined(&router).await;
+
+    for (policy, expected) in IntoIterator::into_iter([
+        (SlowInlineHandlerPolicy::Warn, 3),
+        // Only the first invocation runs inline
+        (SlowInlineHandlerPolicy::Demote, 1),
+    ]) {
+        let mut callee = router.connect_with_config(
+            ClientConfig::default()
+                .set_inline_handler_budget(Duration::from_millis(1))
+                .set_slow_inline_handler_policy(policy),
+        );
+        callee.join_realm(REALM).await.unwrap();
+        callee
+            .register_inline("peer.slow", |args, _kwargs| {
+                std::thread::sleep(Duration::from_millis(5));
+                Ok::<_, WampError>((args, None))
+            })
+            .await
+            .unwrap();
+
+        for i in 0..3 {
+            let (args, _) = caller
+                .call("peer.slow", Some(vec![i.into()]), None)
+                .await
+                .unwrap();
+            assert_eq!(args, Some(vec![i.into()]));
+        }
+        assert_eq!(callee.num_slow_inline_handlers(), expected, "{:?}", policy);
+        callee.disconnect().await.unwrap();
+    }
+
+    caller.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn streamed_rpc() {
+    let router = MockRouter::default();
+    let mut callee = joined(&router).await;
+    let caller = joined(&router).await;
+
+    let mut invocations = callee.invocations().unwrap();
+    callee
+        .register_streamed("peer.add", RegisterOptions::new())
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        while let Some(invocation) = invocations.next().await {
+            let args = invocation.arguments.clone().unwrap_or_default();
+            if args.is_empty() {
+                // Not answering fails the call
+                continue;
+            }
+            let sum: i64 = args.iter().filter_map(|v| v.as_i64()).sum();
+            invocation
+                .respond(Ok((Some(vec![sum.into()]), None)))
+                .await
+                .unwrap();
+        }
+    });
+
+    let (args, _) = caller
+        .call("peer.add", Some(vec![1.into(), 2.into()]), None)
+        .await
+        .unwrap();
+    assert_eq!(args, Some(vec![3.into()]));
+    match caller.call("peer.add", None, None).await {
+        Err(WampError::ServerError(error, _)) => assert_eq!(error, "wamp.async.rs.rpc.failed"),
+        res => panic!("Unexpected result : {:?}", res),
+    }
+
+    caller.disconnect().await.unwrap();
+    callee.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn session_kill() {
+    let router = MockRouter::default();
+    let mut killed = joined(&router).await;
+    let admin = joined(&router).await;
+
+    let session = killed.session_info().unwrap().session_id;
+    let mut kwargs = WampKwArgs::new();
+    kwargs.insert(
+        "reason".to_string(),
+        "wamp.error.authentication_expired".into(),
+    );
+    admin
+        .call(
+            "wamp.session.kill",
+            Some(vec![serde_json::to_value(session).unwrap()]),
+            Some(kwargs),
+        )
+        .await
+        .unwrap();
+
+    match tokio::time::timeout(Duration::from_secs(5), killed.block_until_disconnect())
+        .await
+        .expect("session never closed")
+    {
+        ClientState::Disconnected(Err(WampError::SessionClosed(reason, _))) => {
+            assert_eq!(*reason, SessionCloseReason::AuthenticationExpired);
+            assert!(reason.is_forced_logout());
+        }
+        res => panic!("Unexpected result : {:?}", res),
+    }
+    admin.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn malformed_messages() {
+    let router = MockRouter::default();
+    let config =
+        ClientConfig::default().set_malformed_message_policy(MalformedMessagePolicy::SkipN(2));
+    let mut client = router.connect_with_config(config);
+    client.join_realm(REALM).await.unwrap();
+    let session = NonZeroU64::from(client.session_info().unwrap().session_id).get();
+
+    // The first frames are dropped and the session goes on
+    let (_, mut events) = client.subscribe("peer.heartbeat").await.unwrap();
+    for _ in 0..2 {
+        router.inject(session, b"not a WAMP message");
+    }
+    let options = PublishOptions::new()
+        .with_acknowledge(true)
+        .with_exclude_me(false);
+    client
+        .publish("peer.heartbeat", Some(vec![1.into()]), None, options)
+        .await
+        .unwrap();
+    let (_, args, _, _) = events.try_recv().unwrap();
+    assert_eq!(args, Some(vec![1.into()]));
+
+    // One more closes the connection
+    router.inject(session, b"not a WAMP message");
+    match tokio::time::timeout(Duration::from_secs(5), client.block_until_disconnect())
+        .await
+        .expect("connection never closed")
+    {
+        ClientState::Disconnected(Err(WampError::SerializationError(_))) => {}
+        res => panic!("Unexpected result : {:?}", res),
+    }
+
+    // Strict closes it on the first one
+    let config =
+        ClientConfig::default().set_malformed_message_policy(MalformedMessagePolicy::Strict);
+    let mut client = router.connect_with_config(config);
+    client.join_realm(REALM).await.unwrap();
+    let session = NonZeroU64::from(client.session_info().unwrap().session_id).get();
+    router.inject(session, b"not a WAMP message");
+    match tokio::time::timeout(Duration::from_secs(5), client.block_until_disconnect())
+        .await
+        .expect("connection never closed")
+    {
+        ClientState::Disconnected(Err(WampError::SerializationError(_))) => {}
+        res => panic!("Unexpected result : {:?}", res),
+    }
+}
+
+async fn join_with_ticket(router: &MockRouter, ticket: &'static str) -> Result<(), WampError> {
+    let mut client = router.connect();
+    client
+        .join_realm_with_authentication(
+            REALM,
+            vec![AuthenticationMethod::Ticket],
+            TICKET.0,
+            move |_authentication_method, _extra| async move {
+                Ok(AuthenticationChallengeResponse::with_signature(
+                    ticket.to_string(),
+                ))
+            },
+        )
+        .await?;
+    assert_eq!(client.session_info().unwrap().realm, REALM);
+    client.leave_realm().await.unwrap();
+    client.disconnect().await.unwrap();
+    Ok(())
+}
+
+#[tokio::test]
+async fn ticket_auth() {
+    let router = MockRouter::default();
+    join_with_ticket(&router, TICKET.1).await.unwrap();
+    match join_with_ticket(&router, "wrong").await {
+        Err(WampError::ServerError(reason, _)) => assert_eq!(reason, "wamp.error.not_authorized"),
+        res => panic!("Unexpected result : {:?}", res),
+    }
+}
+
+/// Plays a JSON router, answering the next message with `msg`
+async fn reply(
+    rx: &mut UnboundedReceiver<Vec<u8>>,
+    tx: &UnboundedSender<Vec<u8>>,
+    msg: Value,
+) -> Value {
+    let received = serde_json::from_slice(&rx.recv().await.unwrap()).unwrap();
+    tx.send(serde_json::to_vec(&msg).unwrap()).unwrap();
+    received
+}
+
+/// Connects a client to a router played by the test over the JSON serializer
+fn scripted(
+    config: ClientConfig,
+) -> (
+    Client<'static>,
+    UnboundedReceiver<Vec<u8>>,
+    UnboundedSender<Vec<u8>>,
+) {
+    let (client_tx, router_rx) = mpsc::unbounded_channel();
+    let (router_tx, client_rx) = mpsc::unbounded_channel();
+    let transport = MemoryTransport {
+        tx: client_tx,
+        rx: client_rx,
+        pongs: VecDeque::new(),
+        max_size: None,
+    };
+    let (client, (evt_loop, _)) =
+        Client::from_transport(Box::new(transport), SerializerType::Json, Some(config));
+    tokio::spawn(evt_loop);
+    (client, router_rx, router_tx)
+}
+
+async fn join_with_ticket_auth(client: &mut Client<'static>) -> Result<(), WampError> {
+    client
+        .join_realm_with_authentication(
+            REALM,
+            vec![AuthenticationMethod::Ticket],
+            TICKET.0,
+            |_authentication_method, _extra| async {
+                Ok(AuthenticationChallengeResponse::with_signature(
+                    TICKET.1.to_string(),
+                ))
+            },
+        )
+        .await
+}
+
+/// Joins a scripted router with ticket authentication, then leaves while the router answers
+/// with a GOODBYE of its own. Returns the details of the HELLO and whether the client left
+/// the realm and stayed connected.
+async fn dialect_session(dialect: RouterDialect) -> (Value, bool) {
+    let config = ClientConfig::default()
+        .set_router_dialect(dialect)
+        .set_goodbye_timeout(Some(Duration::from_secs(5)));
+    let (mut client, mut router_rx, router_tx) = scripted(config);
+
+    let (joined, hello) = tokio::join!(
+        join_with_ticket_auth(&mut client),
+        reply(
+            &mut router_rx,
+            &router_tx,
+            json!([2, 1, {"roles": {"broker": {}}}])
+        )
+    );
+    joined.unwrap();
+    assert_eq!(hello[0], 1);
+
+    let (left, goodbye) = tokio::join!(
+        client.leave_realm(),
+        reply(
+            &mut router_rx,
+            &router_tx,
+            json!([6, {}, "wamp.close.close_realm"])
+        )
+    );
+    assert_eq!(goodbye[0], 6);
+    (hello[2].clone(), left.is_ok() && client.is_connected())
+}
+
+#[tokio::test]
+async fn goodbye_timeout() {
+    let timeout = Duration::from_millis(100);
+    let config = ClientConfig::default().set_goodbye_timeout(Some(timeout));
+    let (mut client, mut router_rx, router_tx) = scripted(config);
+    let welcome = json!([2, 1, {"roles": {"broker": {}}}]);
+    let (joined, _) = tokio::join!(
+        client.join_realm(REALM),
+        reply(&mut router_rx, &router_tx, welcome)
+    );
+    joined.unwrap();
+
+    // The router never answers our GOODBYE
+    let started = std::time::Instant::now();
+    let (left, goodbye) = tokio::join!(client.leave_realm(), router_rx.recv());
+    let goodbye: Value = serde_json::from_slice(&goodbye.unwrap()).unwrap();
+    assert_eq!(goodbye[0], 6);
+    match left {
+        Err(WampError::Timeout(_)) => assert!(started.elapsed() >= timeout),
+        res => panic!("Unexpected result : {:?}", res),
+    }
+}
+
+#[tokio::test]
+async fn router_dialects() {
+    let announces_features = |hello: &Value| {
+        hello["roles"]
+            .as_object()
+            .unwrap()
+            .values()
+            .all(|r| r["features"].is_object())
+    };
+
+    // The specification : features are announced, no authextra and only
+    // `wamp.close.goodbye_and_out` acknowledges our GOODBYE
+    for dialect in IntoIterator::into_iter([RouterDialect::Standard, RouterDialect::Crossbar]) {
+        let (hello, left) = dialect_session(dialect).await;
+        assert!(announces_features(&hello), "{:?}", dialect);
+        assert!(hello.get("authextra").is_none(), "{:?}", dialect);
+        assert!(!left, "{:?}", dialect);
+    }
+
+    // Nexus : roles without features, any GOODBYE acknowledges ours
+    let (hello, left) = dialect_session(RouterDialect::Nexus).await;
+    assert!(hello["roles"]
+        .as_object()
+        .unwrap()
+        .values()
+        .all(|r| r.get("features").is_none()));
+    assert!(hello.get("authextra").is_none());
+    assert!(left);
+
+    // Bondy : authextra is always sent when authenticating, any GOODBYE acknowledges ours
+    let (hello, left) = dialect_session(RouterDialect::Bondy).await;
+    assert!(announces_features(&hello));
+    assert_eq!(hello["authextra"], json!({}));
+    assert!(left);
+}
+
+#[tokio::test]
+async fn second_challenge() {
+    let (mut client, mut router_rx, router_tx) = scripted(ClientConfig::default());
+
+    // The router challenges the client again once it authenticated
+    let challenge = json!([4, "ticket", {}]);
+    let router = async {
+        let hello = reply(&mut router_rx, &router_tx, challenge.clone()).await;
+        let authenticate = reply(&mut router_rx, &router_tx, challenge.clone()).await;
+        let abort: Value = serde_json::from_slice(&router_rx.recv().await.unwrap()).unwrap();
+        (hello, authenticate, abort)
+    };
+    let (joined, (hello, authenticate, abort)) =
+        tokio::join!(join_with_ticket_auth(&mut client), router);
+    assert_eq!(hello[0], 1);
+    assert_eq!(authenticate, json!([5, TICKET.1, {}]));
+    assert_eq!(abort, json!([3, {}, "wamp.error.protocol_violation"]));
+    match joined {
+        Err(WampError::ProtocolError(_)) => {}
+        res => panic!("Unexpected result : {:?}", res),
+    }
+}
+
+#[tokio::test]
+async fn reauthenticate() {
+    let router = MockRouter::default();
+    let tokens = Arc::new(AtomicUsize::new(0));
+    let issued = tokens.clone();
+    let config = ClientConfig::default().set_token_provider(TICKET.0, move || {
+        issued.fetch_add(1, Ordering::SeqCst);
+        async { Ok(TICKET.1.to_string()) }
+    });
+    let mut subscriber = router.connect_with_config(config);
+    subscriber.join_realm(REALM).await.unwrap();
+    let publisher = joined(&router).await;
+    let (sub_id, mut events) = subscriber.subscribe("peer.heartbeat").await.unwrap();
+
+    // The subscription survives joining again with a new token
+    subscriber.reauthenticate().await.unwrap();
+    assert_eq!(tokens.load(Ordering::SeqCst), 2);
+    publisher
+        .publish("peer.heartbeat", Some(vec![1.into()]), None, true)
+        .await
+        .unwrap();
+    let (_, args, _, _) = tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("event never received")
+        .unwrap();
+    assert_eq!(args, Some(vec![1.into()]));
+
+    subscriber.unsubscribe(sub_id).await.unwrap();
+    publisher.disconnect().await.unwrap();
+    subscriber.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn prefix_registration() {
+    let router = MockRouter::default();
+    let callee = joined(&router).await;
+    let caller = joined(&router).await;
+
+    let plugins = DynamicDispatcher::new();
+    plugins.add("echo", echo);
+    callee
+        .register_dispatcher("peer.plugins", plugins.clone())
+        .await
+        .unwrap();
+
+    let (args, _) = caller
+        .call("peer.plugins.echo", Some(vec!["hi".into()]), None)
+        .await
+        .unwrap();
+    assert_eq!(args, Some(vec!["hi".into()]));
+
+    // The prefix ends at a component boundary and a single separator is stripped
+    plugins.add("x.echo", echo);
+    for procedure in ["peer.pluginsx.echo", "peer.plugins..echo"] {
+        match caller.call(procedure, None, None).await {
+            Err(WampError::ServerError(error, _)) => {
+                assert_eq!(error, "wamp.error.no_such_procedure")
+            }
+            res => panic!("Unexpected result for {} : {:?}", procedure, res),
+        }
+    }
+
+    plugins.remove("echo");
+    match caller.call("peer.plugins.echo", None, None).await {
+        Err(WampError::ServerError(error, _)) => assert_eq!(error, "wamp.error.no_such_procedure"),
+        res => panic!("Unexpected result : {:?}", res),
+    }
+
+    caller.disconnect().await.unwrap();
+    callee.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn extensions() {
+    struct Greeting(&'static str);
+    let router = MockRouter::default();
+    let extensions = Extensions::new();
+    extensions.insert(Greeting("hello"));
+    let mut callee = router.connect_with_config(ClientConfig::default().set_extensions(extensions));
+    callee.join_realm(REALM).await.unwrap();
+    let caller = joined(&router).await;
+
+    callee
+        .register("peer.greet", |_args, _kwargs| async {
+            let greeting = Extensions::current().unwrap().get::<Greeting>().unwrap();
+            Ok((Some(vec![greeting.0.into()]), None))
+        })
+        .await
+        .unwrap();
+    let (args, _) = caller.call("peer.greet", None, None).await.unwrap();
+    assert_eq!(args, Some(vec!["hello".into()]));
+
+    // Streamed procedures see the values inserted after connecting
+    callee.extensions().insert(Greeting("bonjour"));
+    let mut invocations = callee.invocations().unwrap();
+    callee
+        .register_streamed("peer.greet_streamed", RegisterOptions::new())
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        while let Some(invocation) = invocations.next().await {
+            let greeting = invocation.extensions().get::<Greeting>().unwrap();
+            let res = (Some(vec![greeting.0.into()]), None);
+            invocation.respond(Ok(res)).await.unwrap();
+        }
+    });
+    let (args, _) = caller
+        .call("peer.greet_streamed", None, None)
+        .await
+        .unwrap();
+    assert_eq!(args, Some(vec!["bonjour".into()]));
+    assert!(Extensions::current().is_none());
+
+    caller.disconnect().await.unwrap();
+    callee.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn call_batch() {
+    let router = MockRouter::default();
+    let callee = joined(&router).await;
+    let caller = joined(&router).await;
+
+    callee.register("peer.echo", echo).await.unwrap();
+    callee
+        .register("peer.slow", |args, kwargs| async move {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            echo(args, kwargs).await
+        })
+        .await
+        .unwrap();
+
+    let batch = CallBatch::new()
+        .add("peer.echo", Some(vec![1.into()]), None, CallOptions::new())
+        .add("peer.missing", None, None, CallOptions::new())
+        .add("peer.slow", None, None, CallOptions::new())
+        .add("peer.echo", Some(vec![2.into()]), None, CallOptions::new())
+        .with_timeout(Duration::from_millis(100));
+    let results = caller.call_batch(batch).await;
+
+    // Each call fails or succeeds on its own
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[0].as_ref().unwrap().0, Some(vec![1.into()]));
+    match &results[1] {
+        Err(WampError::ServerError(error, _)) => assert_eq!(error, "wamp.error.no_such_procedure"),
+        res => panic!("Unexpected result : {:?}", res),
+    }
+    assert!(matches!(results[2], Err(WampError::Timeout(_))));
+    assert_eq!(results[3].as_ref().unwrap().0, Some(vec![2.into()]));
+
+    caller.disconnect().await.unwrap();
+    callee.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn call_cancel() {
+    let router = MockRouter::default();
+    let callee = joined(&router).await;
+    let caller = joined(&router).await;
+
+    callee.register("peer.echo", echo).await.unwrap();
+    callee
+        .register("peer.slow", |args, kwargs| async move {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            echo(args, kwargs).await
+        })
+        .await
+        .unwrap();
+
+    // The call fails as soon as it is canceled, the late ERROR of the router is ignored
+    for mode in [CancelMode::Skip, CancelMode::KillNoWait, CancelMode::Kill] {
+        let call = caller
+            .call_with_handle("peer.slow", None, None, CallOptions::new())
+            .await
+            .unwrap();
+        call.cancel(mode).await.unwrap();
+        assert!(matches!(call.result().await, Err(WampError::CallCanceled)));
+    }
+
+    // A completed call cannot be canceled
+    let call = caller
+        .call_with_handle("peer.echo", Some(vec![1.into()]), None, CallOptions::new())
+        .await
+        .unwrap();
+    let request = call.request_id();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(call.cancel(CancelMode::Skip).await.is_err());
+    assert_eq!(call.request_id(), request);
+    assert_eq!(call.result().await.unwrap().0, Some(vec![1.into()]));
+
+    // The results of the canceled calls are discarded
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(caller.num_duplicate_replies(), 0);
+
+    caller.disconnect().await.unwrap();
+    callee.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn duplicate_replies() {
+    let router = MockRouter::default();
+    let callee = joined(&router).await;
+    let caller = joined(&router).await;
+    callee.register("peer.echo", echo).await.unwrap();
+
+    let call = caller
+        .call_with_handle("peer.echo", Some(vec![1.into()]), None, CallOptions::new())
+        .await
+        .unwrap();
+    let request = NonZeroU64::from(call.request_id()).get();
+    assert_eq!(call.result().await.unwrap().0, Some(vec![1.into()]));
+
+    // The router sends the RESULT again, it is counted and dropped
+    let session = NonZeroU64::from(caller.session_info().unwrap().session_id).get();
+    let duplicate = json!([50, request, {}, [2]]);
+    router.inject(session, &serde_json::to_vec(&duplicate).unwrap());
+    let (args, _) = caller
+        .call("peer.echo", Some(vec![3.into()]), None)
+        .await
+        .unwrap();
+    assert_eq!(args, Some(vec![3.into()]));
+    assert_eq!(caller.num_duplicate_replies(), 1);
+
+    caller.disconnect().await.unwrap();
+    callee.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn unknown_request_errors() {
+    let router = MockRouter::default();
+    let mut client = joined(&router).await;
+    let mut errors = client.protocol_errors().unwrap();
+
+    // An ERROR for a request the client never sent is reported once
+    let session = NonZeroU64::from(client.session_info().unwrap().session_id).get();
+    let error = json!([8, 48, 4242, {}, "wamp.error.runtime_error"]);
+    router.inject(session, &serde_json::to_vec(&error).unwrap());
+    let report = tokio::time::timeout(Duration::from_secs(5), errors.recv())
+        .await
+        .expect("error never reported")
+        .unwrap();
+    assert_eq!(report.message_type, 48);
+    assert_eq!(NonZeroU64::from(report.request).get(), 4242);
+    assert_eq!(report.error, "wamp.error.runtime_error");
+    assert!(errors.try_recv().is_err());
+
+    client.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn idempotency() {
+    let router = MockRouter::default();
+    let callee = joined(&router).await;
+    let subscriber = joined(&router).await;
+    let mut client = router.connect_with_config(
+        ClientConfig::default().set_idempotency_cache(IdempotencyCache::new(16)),
+    );
+    client.join_realm(REALM).await.unwrap();
+
+    let invocations = Arc::new(AtomicUsize::new(0));
+    let counter = invocations.clone();
+    callee
+        .register("peer.charge", move |_args, _kwargs| {
+            let counter = counter.clone();
+            async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                let num = counter.fetch_add(1, Ordering::SeqCst);
+                Ok::<_, WampError>((Some(vec![num.into()]), None))
+            }
+        })
+        .await
+        .unwrap();
+
+    // Duplicates sent while the call is in flight wait for its result
+    let options = || CallOptions::new().with_idempotency_key("order-1");
+    let (first, second) = tokio::join!(
+        client.call_with_options("peer.charge", None, None, options()),
+        client.call_with_options("peer.charge", None, None, options())
+    );
+    assert_eq!(first.unwrap(), second.unwrap());
+    let (args, _) = client
+        .call_with_options("peer.charge", None, None, options())
+        .await
+        .unwrap();
+    assert_eq!(args, Some(vec![0.into()]));
+    assert_eq!(invocations.load(Ordering::SeqCst), 1);
+
+    // Publications have their own keys
+    let (_, mut events) = subscriber.subscribe("peer.orders").await.unwrap();
+    let publish_options = PublishOptions::new()
+        .with_acknowledge(true)
+        .with_idempotency_key("order-1");
+    for i in 0..2 {
+        client
+            .publish_with_options(
+                "peer.orders",
+                Some(vec![i.into()]),
+                None,
+                publish_options.clone(),
+            )
+            .await
+            .unwrap();
+    }
+    client
+        .publish("peer.orders", Some(vec![2.into()]), None, true)
+        .await
+        .unwrap();
+    for expected in IntoIterator::into_iter([0, 2]) {
+        let (_, args, _, _) = tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .expect("event never received")
+            .unwrap();
+        assert_eq!(args, Some(vec![expected.into()]));
+    }
+    let (args, _) = client
+        .call_with_options("peer.charge", None, None, options())
+        .await
+        .unwrap();
+    assert_eq!(args, Some(vec![0.into()]));
+
+    // The key cannot be sent along with another one in the keyword arguments
+    let mut kwargs = WampKwArgs::new();
+    kwargs.insert(IDEMPOTENCY_KEY_KWARG.to_string(), "order-2".into());
+    let options = CallOptions::new().with_idempotency_key("order-2");
+    match client
+        .call_with_options("peer.charge", None, Some(kwargs), options)
+        .await
+    {
+        Err(WampError::InvalidArgument(_)) => {}
+        res => panic!("Unexpected result : {:?}", res),
+    }
+    assert_eq!(invocations.load(Ordering::SeqCst), 1);
+
+    client.disconnect().await.unwrap();
+    subscriber.disconnect().await.unwrap();
+    callee.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn scatter_gather() {
+    let router = MockRouter::default();
+    let callee = joined(&router).await;
+    let caller = joined(&router).await;
+
+    callee.register("peer.echo", echo).await.unwrap();
+    callee
+        .register("peer.slow", |args, kwargs| async move {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            echo(args, kwargs).await
+        })
+        .await
+        .unwrap();
+
+    let batch = || {
+        CallBatch::new()
+            .add("peer.echo", Some(vec![1.into()]), None, CallOptions::new())
+            .add("peer.missing", None, None, CallOptions::new())
+            .add("peer.slow", None, None, CallOptions::new())
+            .add("peer.echo", Some(vec![2.into()]), None, CallOptions::new())
+            .with_timeout(Duration::from_millis(100))
+    };
+
+    // The slow call is not waited for once two calls succeeded
+    let gathered = caller
+        .scatter_gather(batch(), Quorum::AtLeast(2))
+        .await
+        .unwrap();
+    let mut indexes: Vec<usize> = gathered.results.iter().map(|(i, _)| *i).collect();
+    indexes.sort_unstable();
+    assert_eq!(indexes, vec![0, 3]);
+
+    // Two calls fail, a majority of the four cannot succeed
+    match caller.scatter_gather(batch(), Quorum::Majority).await {
+        Err(WampError::QuorumNotReached(2, 3)) => {}
+        res => panic!("Unexpected result : {:?}", res.map(|g| g.results)),
+    }
+
+    caller.disconnect().await.unwrap();
+    callee.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn event_journal() {
+    let router = MockRouter::default();
+    let publisher = joined(&router).await;
+    let path = std::env::temp_dir().join(format!("wamp_async_journal_{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+
+    let journal = EventJournal::open(&path).unwrap();
+    let mut subscriber = router
+        .connect_with_config(ClientConfig::default().add_event_journal("peer.orders", journal));
+    subscriber.join_realm(REALM).await.unwrap();
+    let (_, mut events) = subscriber.subscribe("peer.orders").await.unwrap();
+    for i in 0..3 {
+        publisher
+            .publish("peer.orders", Some(vec![i.into()]), None, true)
+            .await
+            .unwrap();
+    }
+    for _ in 0..3 {
+        tokio::time::timeout(Duration::from_secs(5), events.recv())
+            .await
+            .expect("event never received")
+            .unwrap();
+    }
+    subscriber.disconnect().await.unwrap();
+
+    // The events are replayed after a restart, a truncated last line is skipped
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .and_then(|mut f| std::io::Write::write_all(&mut f, b"{\"publication\":"))
+        .unwrap();
+    let journal = EventJournal::open(&path).unwrap();
+    let replayed = journal.replay().unwrap();
+    let args: Vec<_> = replayed.into_iter().map(|e| e.arguments).collect();
+    assert_eq!(
+        args,
+        vec![
+            Some(vec![0.into()]),
+            Some(vec![1.into()]),
+            Some(vec![2.into()])
+        ]
+    );
+
+    journal.clear().unwrap();
+    assert!(journal.replay().unwrap().is_empty());
+
+    publisher.disconnect().await.unwrap();
+    let _ = std::fs::remove_file(&path);
+}
+
+#[tokio::test]
+async fn progressive_results() {
+    let router = MockRouter::default();
+    let mut callee = joined(&router).await;
+    let caller = joined(&router).await;
+
+    let mut invocations = callee.invocations().unwrap();
+    callee
+        .register_streamed("peer.count", RegisterOptions::new())
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        while let Some(invocation) = invocations.recv().await {
+            if invocation.receives_progress() {
+                for i in 0..3 {
+                    invocation
+                        .progress((Some(vec![i.into()]), None))
+                        .await
+                        .unwrap();
+                }
+            } else {
+                assert!(invocation.progress((None, None)).await.is_err());
+            }
+            invocation
+                .respond(Ok::<_, WampError>((Some(vec![3.into()]), None)))
+                .await
+                .unwrap();
+        }
+    });
+
+    let mut call = caller
+        .call_with_handle(
+            "peer.count",
+            None,
+            None,
+            CallOptions::new().with_receive_progress(true),
+        )
+        .await
+        .unwrap();
+    let mut progress = Vec::new();
+    while let Some((args, _)) = call.progress().await {
+        progress.push(args);
+    }
+    assert_eq!(
+        progress,
+        vec![
+            Some(vec![0.into()]),
+            Some(vec![1.into()]),
+            Some(vec![2.into()])
+        ]
+    );
+    assert_eq!(call.result().await.unwrap().0, Some(vec![3.into()]));
+
+    // Without asking for them, only the final result is received
+    let (args, _) = caller.call("peer.count", None, None).await.unwrap();
+    assert_eq!(args, Some(vec![3.into()]));
+
+    caller.disconnect().await.unwrap();
+    callee.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn manifest() {
+    let router = MockRouter::default();
+    let mut service = joined(&router).await;
+    let caller = joined(&router).await;
+
+    let manifest = Manifest::from_json(
+        r#"{
+            "procedures": [{ "uri": "peer.orders.", "match": "prefix" }],
+            "topics": [{ "uri": "peer.stock" }]
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(
+        Manifest::from_json(&manifest.to_json().unwrap()).unwrap(),
+        manifest
+    );
+
+    let mut invocations = service.invocations().unwrap();
+    let mut applied = service.apply_manifest(&manifest).await.unwrap();
+    assert!(applied.registrations.contains_key("peer.orders."));
+
+    tokio::spawn(async move {
+        while let Some(invocation) = invocations.recv().await {
+            let procedure = invocation.procedure.clone();
+            invocation
+                .respond(Ok::<_, WampError>((Some(vec![procedure.into()]), None)))
+                .await
+                .unwrap();
+        }
+    });
+    let (args, _) = caller.call("peer.orders.list", None, None).await.unwrap();
+    assert_eq!(args, Some(vec!["peer.orders.list".into()]));
+
+    caller
+        .publish("peer.stock", Some(vec![1.into()]), None, true)
+        .await
+        .unwrap();
+    let (_, events) = applied.subscriptions.get_mut("peer.stock").unwrap();
+    let (_, args, _, _) = tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("event never received")
+        .unwrap();
+    assert_eq!(args, Some(vec![1.into()]));
+    assert_eq!(service.export_manifest().await.unwrap(), manifest);
+
+    // Applying another manifest keeps the unchanged procedures and removes the others
+    let orders = applied.registrations["peer.orders."];
+    let updated = Manifest::new()
+        .with_procedure(ProcedureManifest {
+            match_policy: MatchPolicy::Prefix,
+            ..ProcedureManifest::new("peer.orders.")
+        })
+        .with_procedure(ProcedureManifest::new("peer.quotes"));
+    let reapplied = service.apply_manifest(&updated).await.unwrap();
+    assert_eq!(reapplied.registrations["peer.orders."], orders);
+    assert!(reapplied.subscriptions.is_empty());
+    assert_eq!(service.export_manifest().await.unwrap(), updated);
+    let (args, _) = caller.call("peer.quotes", None, None).await.unwrap();
+    assert_eq!(args, Some(vec!["peer.quotes".into()]));
+
+    // Nothing is left registered when a procedure cannot be registered
+    let conflicting = Manifest::new()
+        .with_topic(TopicManifest::new("peer.prices"))
+        .with_procedure(ProcedureManifest::new("peer.rates"))
+        .with_procedure(ProcedureManifest::new("peer.orders."));
+    assert!(caller.apply_manifest(&conflicting).await.is_err());
+    assert!(caller.call("peer.rates", None, None).await.is_err());
+    assert_eq!(caller.export_manifest().await.unwrap(), Manifest::new());
+
+    // The manifest of the configuration is applied when joining
+    let manifest = Manifest::new()
+        .with_procedure(ProcedureManifest::new("peer.invoices"))
+        .with_topic(TopicManifest::new("peer.stock"));
+    let mut configured =
+        router.connect_with_config(ClientConfig::default().set_manifest(manifest.clone()));
+    configured.join_realm(REALM).await.unwrap();
+    let applied = configured.take_applied_manifest().unwrap();
+    assert!(applied.registrations.contains_key("peer.invoices"));
+    assert!(applied.subscriptions.contains_key("peer.stock"));
+    assert_eq!(configured.export_manifest().await.unwrap(), manifest);
+
+    // The renewed subscriptions are kept when joining again
+    configured.reauthenticate().await.unwrap();
+    let reapplied = configured.take_applied_manifest().unwrap();
+    assert!(reapplied.registrations.contains_key("peer.invoices"));
+    assert!(reapplied.subscriptions.is_empty());
+    assert_eq!(configured.export_manifest().await.unwrap(), manifest);
+
+    configured.disconnect().await.unwrap();
+    caller.disconnect().await.unwrap();
+    service.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn strict_uris() {
+    let router = MockRouter::default();
+    let mut client = router.connect_with_config(ClientConfig::default().set_strict_uris(true));
+    client.join_realm(REALM).await.unwrap();
+
+    let cases = [
+        ("peer.echo", MatchPolicy::Exact, true),
+        ("peer..echo", MatchPolicy::Exact, false),
+        ("peer.echo.", MatchPolicy::Exact, false),
+        ("Peer.echo", MatchPolicy::Exact, false),
+        ("wamp.echo", MatchPolicy::Exact, false),
+        ("peer.plugins.", MatchPolicy::Prefix, true),
+        ("peer.plugins", MatchPolicy::Prefix, true),
+        ("peer..plugins", MatchPolicy::Prefix, false),
+        (".peer", MatchPolicy::Prefix, false),
+        ("peer..9", MatchPolicy::Wildcard, true),
+        ("peer.9.", MatchPolicy::Wildcard, true),
+        ("..", MatchPolicy::Wildcard, false),
+        ("peer.*.9", MatchPolicy::Wildcard, false),
+    ];
+    for (uri, policy, valid) in IntoIterator::into_iter(cases) {
+        let subscribed = client
+            .subscribe_with_options(uri, SubscribeOptions::new().with_match(policy))
+            .await;
+        let registered = client
+            .register_with_options(uri, RegisterOptions::new().with_match(policy), echo)
+            .await;
+        if valid {
+            client.unsubscribe(subscribed.unwrap().0).await.unwrap();
+            client.unregister(registered.unwrap()).await.unwrap();
+        } else {
+            // Invalid URIs are refused before reaching the router
+            for res in [subscribed.map(|(id, _)| id), registered] {
+                match res {
+                    Err(WampError::InvalidArgument(_)) => {}
+                    res => panic!("Unexpected result for {} {:?} : {:?}", uri, policy, res),
+                }
+            }
+        }
+    }
+    assert!(router.state.lock().unwrap().subscriptions.is_empty());
+    assert!(router.state.lock().unwrap().registrations.is_empty());
+
+    // Without strict URIs, the router decides
+    let lenient = joined(&router).await;
+    lenient.subscribe("Peer.echo").await.unwrap();
+
+    lenient.disconnect().await.unwrap();
+    client.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn tenant() {
+    let router = MockRouter::default();
+    let mut acme = router.connect_with_config(ClientConfig::default().set_tenant("acme"));
+    acme.join_realm("{tenant}.realm").await.unwrap();
+    let caller = joined(&router).await;
+
+    acme.register("app.{tenant}.echo", echo).await.unwrap();
+    let (args, _) = caller
+        .call("app.acme.echo", Some(vec![1.into()]), None)
+        .await
+        .unwrap();
+    assert_eq!(args, Some(vec![1.into()]));
+
+    // Dispatchers are scoped too, and route on the scoped prefix
+    let plugins = DynamicDispatcher::new();
+    plugins.add("echo", echo);
+    acme.register_dispatcher("app.{tenant}.plugins", plugins)
+        .await
+        .unwrap();
+    let (args, _) = caller
+        .call("app.acme.plugins.echo", Some(vec![2.into()]), None)
+        .await
+        .unwrap();
+    assert_eq!(args, Some(vec![2.into()]));
+
+    // A client without a tenant cannot use the placeholder
+    assert!(matches!(
+        caller.call("app.{tenant}.echo", None, None).await,
+        Err(WampError::InvalidArgument(_))
+    ));
+
+    caller.disconnect().await.unwrap();
+    acme.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn pattern_based_subscription() {
+    let router = MockRouter::default();
+    let subscriber = joined(&router).await;
+    let publisher = joined(&router).await;
+
+    let (_, mut events) = subscriber
+        .subscribe_with_options(
+            "peer.sensors.",
+            SubscribeOptions::new().with_match(MatchPolicy::Prefix),
+        )
+        .await
+        .unwrap();
+    publisher
+        .publish("peer.sensors.kitchen", Some(vec![21.into()]), None, true)
+        .await
+        .unwrap();
+
+    // The details tell which topic the event was published on
+    let (_, args, _, details) = tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("event never received")
+        .unwrap();
+    assert_eq!(args, Some(vec![21.into()]));
+    assert!(matches!(
+        details.get("topic"),
+        Some(Arg::Uri(topic)) | Some(Arg::String(topic)) if topic == "peer.sensors.kitchen"
+    ));
+
+    publisher.disconnect().await.unwrap();
+    subscriber.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn oversized_responses() {
+    const MAX_SIZE: usize = 2048;
+    let router = MockRouter::default();
+    let caller = joined(&router).await;
+
+    for policy in
+        IntoIterator::into_iter([OversizedResponsePolicy::Fail, OversizedResponsePolicy::Warn])
+    {
+        let config = ClientConfig::default().set_oversized_response_policy(policy);
+        let mut callee = router.connect_with_max_size(config, Some(MAX_SIZE));
+        callee.join_realm(REALM).await.unwrap();
+        let big = callee
+            .register("peer.big", |_args, _kwargs| async {
+                Ok((Some(vec!["x".repeat(MAX_SIZE).into()]), None))
+            })
+            .await
+            .unwrap();
+        let small = callee.register("peer.echo", echo).await.unwrap();
+
+        // The caller is answered with an ERROR instead of waiting for a result never sent
+        let res = tokio::time::timeout(Duration::from_secs(5), caller.call("peer.big", None, None))
+            .await
+            .expect("the caller was never answered");
+        match res {
+            Err(WampError::ServerError(error, _)) => {
+                assert_eq!(error, "wamp.error.payload_size_exceeded", "{:?}", policy)
+            }
+            res => panic!("Unexpected result with {:?} : {:?}", policy, res),
+        }
+        assert!(callee.largest_response_size() > MAX_SIZE);
+
+        // The connection is still usable
+        let (args, _) = caller
+            .call("peer.echo", Some(vec![12.into()]), None)
+            .await
+            .unwrap();
+        assert_eq!(args, Some(vec![12.into()]));
+
+        callee.unregister(big).await.unwrap();
+        callee.unregister(small).await.unwrap();
+        callee.disconnect().await.unwrap();
+    }
+    caller.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn handler_watchdog() {
+    let router = MockRouter::default();
+    let mut callee = router.connect_with_config(
+        ClientConfig::default().set_handler_watchdog(Duration::from_millis(50)),
+    );
+    callee.join_realm(REALM).await.unwrap();
+    let caller = joined(&router).await;
+
+    callee
+        .register("peer.stuck", |_args, _kwargs| async {
+            futures::future::pending::<()>().await;
+            Ok((None, None))
+        })
+        .await
+        .unwrap();
+    callee.register("peer.echo", echo).await.unwrap();
+
+    match caller.call("peer.stuck", None, None).await {
+        Err(WampError::ServerError(error, _)) => assert_eq!(error, "wamp.error.timeout"),
+        res => panic!("Unexpected result : {:?}", res),
+    }
+    assert_eq!(callee.num_expired_handlers(), 1);
+
+    // Handlers completing in time are not affected
+    let (args, _) = caller
+        .call("peer.echo", Some(vec![1.into()]), None)
+        .await
+        .unwrap();
+    assert_eq!(args, Some(vec![1.into()]));
+    assert_eq!(callee.num_expired_handlers(), 1);
+
+    caller.disconnect().await.unwrap();
+    callee.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn registration_revoked() {
+    let router = MockRouter::default();
+    let mut callee = joined(&router).await;
+    let caller = joined(&router).await;
+
+    let mut registration_events = callee.registration_events().unwrap();
+    let rpc_id = callee.register("peer.echo", echo).await.unwrap();
+    router.revoke_registration("peer.echo");
+
+    match registration_events.recv().await.unwrap() {
+        RegistrationEvent::Revoked {
+            registration,
+            procedure,
+            reason,
+        } => {
+            assert_eq!(registration, rpc_id);
+            assert_eq!(procedure, "peer.echo");
+            assert_eq!(reason.as_deref(), Some("wamp.error.registration_revoked"));
+        }
+    }
+    // The endpoint was removed from the client
+    assert!(callee.unregister(rpc_id).await.is_err());
+
+    // The procedure can be registered again
+    callee.register("peer.echo", echo).await.unwrap();
+    let (args, _) = caller
+        .call("peer.echo", Some(vec![1.into()]), None)
+        .await
+        .unwrap();
+    assert_eq!(args, Some(vec![1.into()]));
+
+    caller.disconnect().await.unwrap();
+    callee.disconnect().await.unwrap();
+}
+
+#[cfg(feature = "prometheus")]
+#[tokio::test]
+async fn prometheus_metrics() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let router = MockRouter::default();
+    let client = joined(&router).await;
+    let metrics = client.metrics();
+    assert!(metrics
+        .render()
+        .contains("\nwamp_client_session_joined 1\n"));
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+    tokio::spawn(metrics.clone().serve(addr));
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let mut stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("# TYPE wamp_client_congested_yields_total counter\n"));
+    assert!(response.contains("# TYPE wamp_client_dropped_events_total counter\n"));
+
+    // A scrape that never sends its request is closed
+    let mut silent = tokio::net::TcpStream::connect(addr).await.unwrap();
+    let mut buf = Vec::new();
+    let closed = tokio::time::timeout(
+        wamp_async::prometheus::REQUEST_TIMEOUT + Duration::from_secs(1),
+        silent.read_to_end(&mut buf),
+    )
+    .await;
+    assert!(matches!(closed, Ok(Ok(0))));
+
+    client.disconnect().await.unwrap();
+    assert!(metrics
+        .render()
+        .contains("\nwamp_client_session_joined 0\n"));
+}
+
+#[tokio::test]
+async fn dropped_events() {
+    let router = MockRouter::default();
+    let subscriber = joined(&router).await;
+    let publisher = joined(&router).await;
+
+    let (_, mut events) = subscriber
+        .subscribe_bounded("peer.heartbeat", 1)
+        .await
+        .unwrap();
+    let publish = |i: i64| publisher.publish("peer.heartbeat", Some(vec![i.into()]), None, true);
+    for i in 0..3 {
+        publish(i).await.unwrap();
+    }
+    // The router answers after sending the events
+    subscriber.subscribe("peer.sync").await.unwrap();
+    assert_eq!(subscriber.num_dropped_events(), 2);
+
+    // Reporting the drops with a Lagged item does not reset the total
+    assert!(matches!(
+        events.recv().await,
+        Some(SubscriptionItem::Event(..))
+    ));
+    publish(3).await.unwrap();
+    subscriber.subscribe("peer.sync").await.unwrap();
+    assert!(matches!(
+        events.recv().await,
+        Some(SubscriptionItem::Lagged { dropped: 2 })
+    ));
+    assert_eq!(subscriber.num_dropped_events(), 3);
+
+    publisher.disconnect().await.unwrap();
+    subscriber.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn subscription_revoked() {
+    let router = MockRouter::default();
+    let mut subscriber = joined(&router).await;
+
+    let mut subscription_events = subscriber.subscription_events().unwrap();
+    let (sub_id, mut events) = subscriber
+        .subscribe_bounded("peer.heartbeat", 8)
+        .await
+        .unwrap();
+    router.revoke_subscription("peer.heartbeat");
+
+    match subscription_events.recv().await.unwrap() {
+        SubscriptionEvent::Revoked {
+            subscription,
+            topic,
+            reason,
+        } => {
+            assert_eq!(subscription, sub_id);
+            assert_eq!(topic, "peer.heartbeat");
+            assert_eq!(reason.as_deref(), Some("wamp.error.subscription_revoked"));
+        }
+    }
+    // The queue ends with the reason of the revocation
+    match events.recv().await.unwrap() {
+        SubscriptionItem::Revoked { reason } => {
+            assert_eq!(reason.as_deref(), Some("wamp.error.subscription_revoked"))
+        }
+        item => panic!("Unexpected item : {:?}", item),
+    }
+    assert!(events.recv().await.is_none());
+
+    subscriber.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn queue_stats() {
+    let router = MockRouter::default();
+    let subscriber = joined(&router).await;
+    let publisher = joined(&router).await;
+
+    let (bounded_id, mut bounded) = subscriber
+        .subscribe_bounded("peer.heartbeat", 8)
+        .await
+        .unwrap();
+    let (unbounded_id, mut events) = subscriber.subscribe("peer.status").await.unwrap();
+    for i in 0..3 {
+        publisher
+            .publish("peer.heartbeat", Some(vec![i.into()]), None, true)
+            .await
+            .unwrap();
+        publisher
+            .publish("peer.status", Some(vec![i.into()]), None, true)
+            .await
+            .unwrap();
+    }
+    // The router answers after sending the events
+    subscriber.subscribe("peer.sync").await.unwrap();
+
+    let stats = subscriber.queue_stats();
+    assert_eq!(stats.control, 0);
+    let heartbeat = &stats.subscriptions[&bounded_id];
+    assert_eq!(heartbeat.topic, "peer.heartbeat");
+    assert_eq!(heartbeat.depth, 3);
+    assert_eq!(heartbeat.capacity, Some(8));
+    let status = &stats.subscriptions[&unbounded_id];
+    assert_eq!((status.depth, status.capacity), (3, None));
+    assert_eq!(events.len(), 3);
+
+    bounded.recv().await.unwrap();
+    events.recv().await.unwrap();
+    events.try_recv().unwrap();
+    let stats = subscriber.queue_stats();
+    assert_eq!(stats.subscriptions[&bounded_id].depth, 2);
+    assert_eq!(stats.subscriptions[&unbounded_id].depth, 1);
+
+    // Removed subscriptions are not reported anymore
+    subscriber.unsubscribe(bounded_id).await.unwrap();
+    assert!(!subscriber
+        .queue_stats()
+        .subscriptions
+        .contains_key(&bounded_id));
+
+    publisher.disconnect().await.unwrap();
+    subscriber.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn publish_black_white_listing() {
+    let router = MockRouter::default();
+    let publisher = joined(&router).await;
+    let mut subscribers = Vec::new();
+    for _ in 0..3 {
+        let client = joined(&router).await;
+        let session = client.session_info().unwrap().session_id;
+        let (_, events) = client.subscribe("peer.heartbeat").await.unwrap();
+        subscribers.push((client, session, events));
+    }
+    let sessions: Vec<_> = subscribers.iter().map(|(_, s, _)| *s).collect();
+
+    let options = PublishOptions::new()
+        .with_acknowledge(true)
+        .with_exclude([sessions[0]]);
+    publisher
+        .publish("peer.heartbeat", Some(vec![1.into()]), None, options)
+        .await
+        .unwrap();
+    let options = PublishOptions::new()
+        .with_acknowledge(true)
+        .with_eligible([sessions[2]]);
+    publisher
+        .publish("peer.heartbeat", Some(vec![2.into()]), None, options)
+        .await
+        .unwrap();
+    publisher
+        .publish("peer.heartbeat", Some(vec![3.into()]), None, true)
+        .await
+        .unwrap();
+
+    let expected: [&[i64]; 3] = [&[3], &[1, 3], &[1, 2, 3]];
+    for ((client, _, mut events), expected) in subscribers.into_iter().zip(expected) {
+        for value in expected {
+            let (_, args, _, _) = events.recv().await.unwrap();
+            assert_eq!(args, Some(vec![(*value).into()]));
+        }
+        client.disconnect().await.unwrap();
+    }
+    publisher.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn publish_black_white_listing_by_auth() {
+    let router = MockRouter::default();
+    let publisher = joined(&router).await;
+    let anonymous = joined(&router).await;
+    let mut user = router.connect();
+    user.join_realm_with_authentication(
+        REALM,
+        vec![AuthenticationMethod::Ticket],
+        TICKET.0,
+        |_authentication_method, _extra| async move {
+            Ok(AuthenticationChallengeResponse::with_signature(
+                TICKET.1.to_string(),
+            ))
+        },
+    )
+    .await
+    .unwrap();
+    let (_, mut anonymous_events) = anonymous.subscribe("peer.heartbeat").await.unwrap();
+    let (_, mut user_events) = user.subscribe("peer.heartbeat").await.unwrap();
+
+    let publications = vec![
+        PublishOptions::new().with_eligible_authid([TICKET.0]),
+        PublishOptions::new().with_exclude_authid([TICKET.0]),
+        PublishOptions::new().with_eligible_authrole(["anonymous"]),
+        PublishOptions::new().with_exclude_authrole(["anonymous"]),
+        // Empty white-lists deliver the event to no one
+        PublishOptions::new().with_eligible(Vec::new()),
+        PublishOptions::new().with_eligible_authid(Vec::<String>::new()),
+        PublishOptions::new().with_eligible_authrole(Vec::<String>::new()),
+        PublishOptions::new(),
+    ];
+    for (i, options) in publications.into_iter().enumerate() {
+        publisher
+            .publish(
+                "peer.heartbeat",
+                Some(vec![i.into()]),
+                None,
+                options.with_acknowledge(true),
+            )
+            .await
+            .unwrap();
+    }
+
+    for (events, expected) in IntoIterator::into_iter([
+        (&mut anonymous_events, [1, 2, 7]),
+        (&mut user_events, [0, 3, 7]),
+    ]) {
+        for value in expected {
+            let (_, args, _, _) = events.recv().await.unwrap();
+            assert_eq!(args, Some(vec![value.into()]));
+        }
+        assert!(events.try_recv().is_err());
+    }
+
+    user.disconnect().await.unwrap();
+    anonymous.disconnect().await.unwrap();
+    publisher.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn payload_codecs() {
+    let router = MockRouter::default();
+    let config = || {
+        ClientConfig::default()
+            .add_payload_codec("peer.echo", JsonTextCodec)
+            .add_payload_codec("peer.heartbeat", JsonTextCodec)
+    };
+    let mut callee = router.connect_with_config(config());
+    callee.join_realm(REALM).await.unwrap();
+    let mut caller = router.connect_with_config(config());
+    caller.join_realm(REALM).await.unwrap();
+    let plain = joined(&router).await;
+
+    callee.register("peer.echo", echo).await.unwrap();
+    let mut kwargs = WampKwArgs::new();
+    kwargs.insert("name".to_string(), "wamp".into());
+    let (args, res_kwargs) = caller
+        .call("peer.echo", Some(vec![12.into()]), Some(kwargs.clone()))
+        .await
+        .unwrap();
+    assert_eq!(args, Some(vec![12.into()]));
+    assert_eq!(res_kwargs, Some(kwargs));
+
+    // Peers without the codec see the encoded payload
+    let (args, _) = plain
+        .call("peer.echo", Some(vec![r#"{"args":[1]}"#.into()]), None)
+        .await
+        .unwrap();
+    assert_eq!(args, Some(vec![r#"{"args":[1]}"#.into()]));
+    match plain.call("peer.echo", Some(vec![1.into()]), None).await {
+        Err(WampError::ServerError(error, _)) => assert_eq!(error, "wamp.error.invalid_argument"),
+        res => panic!("Unexpected result : {:?}", res),
+    }
+
+    let (_, mut events) = callee.subscribe("peer.heartbeat").await.unwrap();
+    let (_, mut plain_events) = plain.subscribe("peer.heartbeat").await.unwrap();
+    caller
+        .publish("peer.heartbeat", Some(vec![7.into()]), None, true)
+        .await
+        .unwrap();
+    let (_, args, kwargs, _) = events.recv().await.unwrap();
+    assert_eq!(args, Some(vec![7.into()]));
+    assert_eq!(kwargs, None);
+    let (_, args, _, _) = plain_events.recv().await.unwrap();
+    assert_eq!(args, Some(vec![r#"{"args":[7]}"#.into()]));
+
+    plain.disconnect().await.unwrap();
+    caller.disconnect().await.unwrap();
+    callee.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn local_echo() {
+    let router = MockRouter::default();
+    let client = joined(&router).await;
+    let session = client.session_info().unwrap().session_id;
+
+    let (_, mut events) = client.subscribe("peer.heartbeat").await.unwrap();
+    let echo = || {
+        PublishOptions::new()
+            .with_acknowledge(true)
+            .with_local_echo(true)
+    };
+    for (i, options) in IntoIterator::into_iter([
+        // Echoed, the broker excludes the publisher
+        echo(),
+        // Received from the broker only
+        echo().with_exclude_me(false),
+        // Neither echoed nor received when the session is excluded, or for a shard
+        echo().with_exclude([session]),
+        echo().with_rkey("customer-42"),
+    ])
+    .enumerate()
+    {
+        client
+            .publish("peer.heartbeat", Some(vec![i.into()]), None, options)
+            .await
+            .unwrap();
+    }
+
+    // The acknowledgements follow the events of the broker, so every event was queued
+    let (_, args, _, _) = events.try_recv().unwrap();
+    assert_eq!(args, Some(vec![0.into()]));
+    let (_, args, _, _) = events.try_recv().unwrap();
+    assert_eq!(args, Some(vec![1.into()]));
+    assert!(events.try_recv().is_err());
+
+    client.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn publisher_exclusion() {
+    let router = MockRouter::default();
+    let client = joined(&router).await;
+
+    let (_, mut events) = client.subscribe("peer.heartbeat").await.unwrap();
+    client
+        .publish("peer.heartbeat", Some(vec![1.into()]), None, true)
+        .await
+        .unwrap();
+    let options = PublishOptions::new()
+        .with_acknowledge(true)
+        .with_exclude_me(false);
+    client
+        .publish("peer.heartbeat", Some(vec![2.into()]), None, options)
+        .await
+        .unwrap();
+
+    // Only the event published without publisher exclusion is received
+    let (_, args, _, _) = tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("event never received")
+        .unwrap();
+    assert_eq!(args, Some(vec![2.into()]));
+    assert!(events.try_recv().is_err());
+
+    client.disconnect().await.unwrap();
+}
+
+#[cfg(feature = "ffi")]
+#[test]
+fn ffi_errors() {
+    use std::ffi::{CStr, CString};
+    use std::ptr;
+    use wamp_async::ffi::*;
+
+    let last_error = || {
+        unsafe { CStr::from_ptr(wamp_last_error()) }
+            .to_str()
+            .unwrap()
+    };
+    let realm = CString::new(REALM).unwrap();
+
+    assert!(unsafe { wamp_connect(ptr::null(), realm.as_ptr()) }.is_null());
+    assert_eq!(last_error(), "uri is NULL");
+
+    let topic = CString::new("peer.heartbeat").unwrap();
+    let sub_id = unsafe { wamp_subscribe(ptr::null_mut(), topic.as_ptr(), None, ptr::null_mut()) };
+    assert_eq!(sub_id, 0);
+    assert_eq!(last_error(), "callback is NULL");
+
+    // Strings handed to C are given back to be freed
+    unsafe {
+        wamp_string_free(CString::new("{}").unwrap().into_raw());
+        wamp_string_free(ptr::null_mut());
+    }
+}
+
+#[cfg(feature = "protobuf")]
+#[tokio::test]
+async fn protobuf_payloads() {
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Add {
+        #[prost(int64, repeated, tag = "1")]
+        values: Vec<i64>,
+    }
+    #[derive(Clone, PartialEq, prost::Message)]
+    struct Sum {
+        #[prost(int64, tag = "1")]
+        total: i64,
+    }
+
+    let router = MockRouter::default();
+    let callee = joined(&router).await;
+    let caller = joined(&router).await;
+
+    callee
+        .register_proto("peer.add", |add: Add| async move {
+            Ok(Sum {
+                total: add.values.iter().sum(),
+            })
+        })
+        .await
+        .unwrap();
+    let sum: Sum = caller
+        .call_proto(
+            "peer.add",
+            &Add {
+                values: vec![1, 2, 3],
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(sum.total, 6);
+
+    // The message is carried in a single binary argument
+    let args = wamp_async::try_into_args_proto(&Add { values: vec![1] }).unwrap();
+    assert_eq!(args, vec![json!("\u{0}CgEB")]);
+    assert!(caller
+        .call("peer.add", Some(vec![1.into()]), None)
+        .await
+        .is_err());
+
+    caller.disconnect().await.unwrap();
+    callee.disconnect().await.unwrap();
+}
+
+#[cfg(feature = "auth")]
+#[test]
+fn wampcra_key_derivation_bounds() {
+    use wamp_async::auth::{derive_key, sign_challenge, MAX_ITERATIONS, MAX_KEY_LEN};
+    use wamp_async::WampDict;
+
+    let extra = |iterations: Value, keylen: Value| -> WampDict {
+        serde_json::from_value(json!({
+            "challenge": "{}",
+            "salt": "salt",
+            "iterations": iterations,
+            "keylen": keylen,
+        }))
+        .unwrap()
+    };
+
+    assert!(sign_challenge("secret", &extra(json!(1000), json!(32))).is_ok());
+    assert!(derive_key("secret", "salt", MAX_ITERATIONS + 1, 32).is_err());
+    assert!(derive_key("secret", "salt", 1, MAX_KEY_LEN + 1).is_err());
+    for (iterations, keylen) in IntoIterator::into_iter([
+        (json!(0), json!(32)),
+        (json!(1000), json!(0)),
+        (json!(1000), json!(MAX_KEY_LEN + 1)),
+        (json!(MAX_ITERATIONS + 1), json!(32)),
+        // Would be 1 iteration if truncated to 32 bits
+        (json!((1u64 << 32) + 1), json!(32)),
+    ]) {
+        let res = sign_challenge("secret", &extra(iterations.clone(), keylen.clone()));
+        assert!(
+            matches!(res, Err(WampError::InvalidArgument(_))),
+            "iterations {} keylen {}",
+            iterations,
+            keylen
+        );
+    }
+
+    // Negative integers are not valid in a CHALLENGE
+    assert!(serde_json::from_value::<WampDict>(json!({ "iterations": -1 })).is_err());
+}
+
+#[tokio::test]
+async fn rawsocket_handshake() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use wamp_async::transport::tcp;
+    use wamp_async::RawSocketHandshake;
+
+    let (client, mut server) = tokio::io::duplex(64);
+    let router = tokio::spawn(async move {
+        let mut hello = [0u8; 4];
+        server.read_exact(&mut hello).await.unwrap();
+        // Echo the handshake with a vendor specific reserved octet
+        server
+            .write_all(&[hello[0], hello[1], 0x00, 0x02])
+            .await
+            .unwrap();
+        let mut rest = Vec::new();
+        server.read_to_end(&mut rest).await.unwrap();
+        hello
+    });
+
+    let seen = Arc::new(Mutex::new(None));
+    let hook_seen = seen.clone();
+    let config = ClientConfig::default()
+        .set_serializers(vec![SerializerType::Json])
+        .set_rawsocket_handshake(
+            RawSocketHandshake::new()
+                .with_magic(0x7E)
+                .with_reserved([0x00, 0x01])
+                .with_hook(move |_client, server| {
+                    *hook_seen.lock().unwrap() = Some(*server);
+                    Ok(())
+                }),
+        );
+    let (transport, _) = tcp::from_stream(client, &config).await.unwrap();
+
+    // Dropping the transport without closing it ends the stream
+    drop(transport);
+    let hello = tokio::time::timeout(Duration::from_secs(1), router)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(hello[0], 0x7E);
+    assert_eq!(hello[2..], [0x00, 0x01]);
+    assert_eq!(seen.lock().unwrap().unwrap()[2..], [0x00, 0x02]);
+}
+
+/// Waits until `condition` holds
+async fn until(condition: impl Fn() -> bool) {
+    let polled = async {
+        while !condition() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    };
+    tokio::time::timeout(Duration::from_secs(5), polled)
+        .await
+        .expect("condition never met");
+}
+
+/// Sends `count` calls of `peer.hold` through the pool and returns how many were waiting for
+/// a result on each session once they all reached the callee
+async fn hold_calls(
+    router: &MockRouter,
+    pool: &CallerPool,
+    release: &Semaphore,
+    count: usize,
+) -> HashMap<u64, usize> {
+    let calls = futures::future::join_all(
+        (0..count).map(|i| pool.call("peer.hold", Some(vec![i.into()]), None)),
+    );
+    let held = async {
+        until(|| router.pending_calls().values().sum::<usize>() == count).await;
+        let pending = router.pending_calls();
+        release.add_permits(count);
+        pending
+    };
+    let (results, pending) = tokio::join!(calls, held);
+    for (i, res) in results.into_iter().enumerate() {
+        assert_eq!(res.unwrap().0, Some(vec![i.into()]));
+    }
+    pending
+}
+
+#[tokio::test]
+async fn caller_pool() {
+    let router = MockRouter::default();
+    let callee = joined(&router).await;
+    // Invocations are held until released
+    let release = Arc::new(Semaphore::new(0));
+    let held = release.clone();
+    callee
+        .register("peer.hold", move |args, kwargs| {
+            let held = held.clone();
+            async move {
+                held.acquire().await.unwrap().forget();
+                Ok((args, kwargs))
+            }
+        })
+        .await
+        .unwrap();
+
+    let others = router.joined_sessions();
+    let members = || -> Vec<u64> {
+        let joined = router.joined_sessions();
+        joined.into_iter().filter(|s| !others.contains(s)).collect()
+    };
+    let transports = router.clone();
+    let (pool, sessions) = CallerPool::from_transports(
+        move || {
+            let transport = transports.transport();
+            async move { Ok(transport) }
+        },
+        SerializerType::Json,
+        REALM,
+        ClientConfig::default(),
+        2,
+    )
+    .await
+    .unwrap();
+    tokio::spawn(sessions);
+    assert_eq!(pool.num_sessions(), 2);
+    let first = members();
+    assert_eq!(first.len(), 2);
+
+    // Calls are spread over the sessions with the fewest calls in flight
+    let pending = hold_calls(&router, &pool, &release, 4).await;
+    assert_eq!(pending, first.iter().map(|s| (*s, 2)).collect());
+
+    // A member that lost its connection is replaced by a new session
+    router.drop_connection(first[0]);
+    until(|| members().len() == 2 && !members().contains(&first[0]) && pool.num_sessions() == 2)
+        .await;
+    let second = members();
+    assert!(second.contains(&first[1]));
+    let pending = hold_calls(&router, &pool, &release, 2).await;
+    assert_eq!(pending, second.iter().map(|s| (*s, 1)).collect());
+
+    drop(pool);
+    callee.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn fair_dispatch() {
+    const FLOOD: usize = 10_000;
+
+    let router = MockRouter::default();
+    let order = Arc::new(Mutex::new(Vec::new()));
+    let hook_order = order.clone();
+    let mut client = router.connect_with_config(ClientConfig::default().set_dispatch_timing_hook(
+        move |t: &DispatchTiming| hook_order.lock().unwrap().push((t.source, t.kind)),
+    ));
+    client.join_realm(REALM).await.unwrap();
+    let (_, mut events) = client.subscribe("peer.flood").await.unwrap();
+    // Number of dispatches of `source` before the first one of `kind`
+    let dispatched_before = |source: DispatchSource, kind: &str| {
+        let order = order.lock().unwrap();
+        let pos = order.iter().position(|d| d.1 == kind).unwrap();
+        order[..pos].iter().filter(|d| d.0 == source).count()
+    };
+
+    // Inbound flood : a request of the client does not wait for the messages received before
+    order.lock().unwrap().clear();
+    router.flood("peer.flood", FLOOD);
+    client
+        .publish("peer.other", None, None, false)
+        .await
+        .unwrap();
+    for _ in 0..FLOOD {
+        events.recv().await.unwrap();
+    }
+    let waited = dispatched_before(DispatchSource::Peer, "publish");
+    assert!(waited < FLOOD / 10, "publish waited for {} events", waited);
+
+    // Local flood : a message of the router does not wait for the requests queued before
+    order.lock().unwrap().clear();
+    for _ in 0..FLOOD {
+        client
+            .publish("peer.other", None, None, false)
+            .await
+            .unwrap();
+    }
+    router.flood("peer.flood", 1);
+    events.recv().await.unwrap();
+    let waited = dispatched_before(DispatchSource::Local, "EVENT");
+    assert!(waited < FLOOD / 10, "EVENT waited for {} requests", waited);
+
+    client.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn rawsocket_ping() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use wamp_async::transport::{tcp, TransportMsg};
+
+    let (client, mut server) = tokio::io::duplex(4);
+    let config = ClientConfig::default().set_serializers(vec![SerializerType::Json]);
+    let (handshake, transport) = tokio::join!(
+        async {
+            let mut hello = [0u8; 4];
+            server.read_exact(&mut hello).await.unwrap();
+            server.write_all(&[hello[0], hello[1], 0, 0]).await.unwrap();
+        },
+        tcp::from_stream(client, &config)
+    );
+    let ((), (mut transport, _)) = (handshake, transport.unwrap());
+
+    // The ping is answered while the client only waits for messages. The pong does not fit in
+    // the stream, and the receive is cancelled halfway through writing it.
+    let (written, recv) = tokio::join!(
+        server.write_all(&[1, 0, 0, 3, b'a', b'b', b'c']),
+        tokio::time::timeout(Duration::from_millis(50), transport.recv())
+    );
+    written.unwrap();
+    assert!(recv.is_err());
+
+    // The rest of the pong is written before the next frame
+    let mut frames = [0u8; 14];
+    let (sent, read) = tokio::time::timeout(Duration::from_secs(1), async {
+        tokio::join!(transport.send(b"[1]"), server.read_exact(&mut frames))
+    })
+    .await
+    .unwrap();
+    sent.unwrap();
+    read.unwrap();
+    assert_eq!(
+        frames,
+        [2, 0, 0, 3, b'a', b'b', b'c', 0, 0, 0, 3, b'[', b'1', b']']
+    );
+    let (written, recv) = tokio::join!(
+        server.write_all(&[0, 0, 0, 2, b'[', b']']),
+        transport.recv()
+    );
+    written.unwrap();
+    match recv.unwrap() {
+        TransportMsg::Wamp(payload) => assert_eq!(payload, b"[]"),
+        msg => panic!("Unexpected message : {:?}", msg),
+    }
+}
+
+/// Binary value of a MsgPack frame
+struct Bytes(&'static [u8]);
+
+impl serde::Serialize for Bytes {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+/// Plays a MsgPack router, answering the next message with `reply` built from its request ID
+async fn answer(
+    rx: &mut UnboundedReceiver<Vec<u8>>,
+    tx: &UnboundedSender<Vec<u8>>,
+    reply: impl Fn(u64) -> Vec<u8>,
+) -> Vec<u8> {
+    let frame = rx.recv().await.unwrap();
+    // Array header and message type, followed by the request ID if the message has one
+    let request = rmp_serde::decode::from_read(&frame[2..]).unwrap_or(0);
+    tx.send(reply(request)).unwrap();
+    frame
+}
+
+#[tokio::test]
+async fn msgpack_binary() {
+    let (client_tx, mut router_rx) = mpsc::unbounded_channel();
+    let (router_tx, client_rx) = mpsc::unbounded_channel();
+    let transport = MemoryTransport {
+        tx: client_tx,
+        rx: client_rx,
+        pongs: VecDeque::new(),
+        max_size: None,
+    };
+    let (mut client, (evt_loop, _)) =
+        Client::from_transport(Box::new(transport), SerializerType::MsgPack, None);
+    tokio::spawn(evt_loop);
+
+    let welcome = |_| rmp_serde::to_vec(&(2, 1, json!({"roles": {"broker": {}}}))).unwrap();
+    let (joined, _) = tokio::join!(
+        client.join_realm(REALM),
+        answer(&mut router_rx, &router_tx, welcome)
+    );
+    joined.unwrap();
+    let subscribed = |request| rmp_serde::to_vec(&(33, request, 7)).unwrap();
+    let (subscribed, _) = tokio::join!(
+        client.subscribe("peer.blobs"),
+        answer(&mut router_rx, &router_tx, subscribed)
+    );
+    let (_, mut events) = subscribed.unwrap();
+
+    // Binary strings are sent as MsgPack binary values, and received back as strings
+    let published = |request| rmp_serde::to_vec(&(17, request, 8)).unwrap();
+    let (res, publish) = tokio::join!(
+        client.publish("peer.blobs", Some(vec!["\0AQID".into()]), None, true),
+        answer(&mut router_rx, &router_tx, published)
+    );
+    res.unwrap();
+    assert!(publish.windows(5).any(|w| w == [0xc4, 3, 1, 2, 3]));
+
+    let event = (36, 7, 8, json!({}), (Bytes(&[1, 2, 3]),));
+    router_tx.send(rmp_serde::to_vec(&event).unwrap()).unwrap();
+    let (_, args, _, _) = tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .expect("event never received")
+        .unwrap();
+    assert_eq!(args, Some(vec!["\0AQID".into()]));
+
+    client.disconnect().await.unwrap();
+}
@@ -0,0 +1,185 @@
+//! Runs the example flows against a real router for each transport and serializer
+//!
+//! These tests are skipped unless `WAMP_ASYNC_ROUTER_URLS` is set :
+//!
+//! * `WAMP_ASYNC_ROUTER_URLS` : Comma separated router uris (e.g. `ws://127.0.0.1:8080/ws,tcp://127.0.0.1:8081`)
+//! * `WAMP_ASYNC_ROUTER_CMD` : Shell command spawning the router for the duration of the tests (optional)
+//! * `WAMP_ASYNC_ROUTER_REALM` : Realm to join (`realm1` by default)
+//! * `WAMP_ASYNC_ROUTER_TICKET` : `authid:ticket` accepted by the realm to test ticket authentication (optional)
+//!
+//! ```text
+//! WAMP_ASYNC_ROUTER_CMD="crossbar start --cbdir .crossbar" \
+//! WAMP_ASYNC_ROUTER_URLS="ws://127.0.0.1:8080/ws,tcp://127.0.0.1:8081" \
+//! cargo test --test router_interop -- --nocapture
+//! ```
+
+use std::env;
+use std::process::{Child, Command};
+use std::time::Duration;
+
+use wamp_async::{
+    AuthenticationChallengeResponse, AuthenticationMethod, Client, ClientConfig, RpcEventPump,
+    SerializerType, ShutdownToken, WampArgs, WampError, WampKwArgs,
+};
+
+/// Kills the spawned router once the tests are done
+struct Router(Option<Child>);
+
+impl Router {
+    fn spawn() -> Self {
+        let child = env::var("WAMP_ASYNC_ROUTER_CMD").ok().map(|cmd| {
+            Command::new("sh")
+                .arg("-c")
+                .arg(&cmd)
+                .spawn()
+                .unwrap_or_else(|e| panic!("Failed to spawn router '{}' : {}", cmd, e))
+        });
+        Router(child)
+    }
+}
+
+impl Drop for Router {
+    fn drop(&mut self) {
+        if let Some(child) = self.0.as_mut() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+async fn echo(
+    args: Option<WampArgs>,
+    kwargs: Option<WampKwArgs>,
+) -> Result<(Option<WampArgs>, Option<WampKwArgs>), WampError> {
+    Ok((args, kwargs))
+}
+
+fn config(serializer: SerializerType) -> ClientConfig {
+    ClientConfig::default()
+        .set_ssl_verify(false)
+        .set_serializers(vec![serializer])
+}
+
+/// Connects and joins the realm, retrying while the router is starting
+async fn join(uri: &str, serializer: SerializerType, realm: &str) -> Client<'static> {
+    let mut attempts = 0;
+    let (mut client, (evt_loop, rpc_evt_queue)) = loop {
+        match Client::connect(uri, Some(config(serializer))).await {
+            Ok(c) => break c,
+            Err(e) if attempts < 50 => {
+                attempts += 1;
+                log::debug!("Router not ready ({}), retrying...", e);
+                tokio::time::sleep(Duration::from_millis(200)).await;
+            }
+            Err(e) => panic!("{} {:?} : failed to connect : {}", uri, serializer, e),
+        }
+    };
+    tokio::spawn(evt_loop);
+    if let Some(rpc_evt_queue) = rpc_evt_queue {
+        RpcEventPump::spawn(rpc_evt_queue, 16, ShutdownToken::new());
+    }
+
+    client
+        .join_realm(realm)
+        .await
+        .unwrap_or_else(|e| panic!("{} {:?} : failed to join : {}", uri, serializer, e));
+    client
+}
+
+async fn pubsub(uri: &str, serializer: SerializerType, realm: &str) {
+    let topic = format!("wamp_async.test.pubsub.{}", serializer.to_str());
+    let subscriber = join(uri, serializer, realm).await;
+    let publisher = join(uri, serializer, realm).await;
+
+    let (sub_id, mut events) = subscriber.subscribe(&topic).await.unwrap();
+    let pub_id = publisher
+        .publish(&topic, Some(vec![1.into(), "two".into()]), None, true)
+        .await
+        .unwrap();
+    assert!(
+        pub_id.is_some(),
+        "{} {:?} : no publication id",
+        uri,
+        serializer
+    );
+
+    let (_, args, _, _) = tokio::time::timeout(Duration::from_secs(5), events.recv())
+        .await
+        .unwrap_or_else(|_| panic!("{} {:?} : event never received", uri, serializer))
+        .unwrap();
+    assert_eq!(args, Some(vec![1.into(), "two".into()]));
+
+    subscriber.unsubscribe(sub_id).await.unwrap();
+    publisher.disconnect().await.unwrap();
+    subscriber.disconnect().await.unwrap();
+}
+
+async fn rpc(uri: &str, serializer: SerializerType, realm: &str) {
+    let procedure = format!("wamp_async.test.echo.{}", serializer.to_str());
+    let callee = join(uri, serializer, realm).await;
+    let caller = join(uri, serializer, realm).await;
+
+    let rpc_id = callee.register(&procedure, echo).await.unwrap();
+    let mut kwargs = WampKwArgs::new();
+    kwargs.insert("name".to_string(), "wamp".into());
+    let (args, res_kwargs) = caller
+        .call(&procedure, Some(vec![12.into()]), Some(kwargs.clone()))
+        .await
+        .unwrap_or_else(|e| panic!("{} {:?} : call failed : {}", uri, serializer, e));
+    assert_eq!(args, Some(vec![12.into()]));
+    assert_eq!(res_kwargs, Some(kwargs));
+
+    callee.unregister(rpc_id).await.unwrap();
+    caller.disconnect().await.unwrap();
+    callee.disconnect().await.unwrap();
+}
+
+async fn ticket_auth(uri: &str, serializer: SerializerType, realm: &str, ticket: &str) {
+    let (authid, ticket) = ticket
+        .split_once(':')
+        .expect("WAMP_ASYNC_ROUTER_TICKET must be 'authid:ticket'");
+    let ticket = ticket.to_string();
+    let (mut client, (evt_loop, _)) = Client::connect(uri, Some(config(serializer)))
+        .await
+        .unwrap();
+    tokio::spawn(evt_loop);
+
+    client
+        .join_realm_with_authentication(
+            realm,
+            vec![AuthenticationMethod::Ticket],
+            authid,
+            move |_authentication_method, _extra| {
+                let ticket = ticket.clone();
+                async move { Ok(AuthenticationChallengeResponse::with_signature(ticket)) }
+            },
+        )
+        .await
+        .unwrap_or_else(|e| panic!("{} {:?} : authentication failed : {}", uri, serializer, e));
+    client.leave_realm().await.unwrap();
+    client.disconnect().await.unwrap();
+}
+
+#[tokio::test]
+async fn router_interop() {
+    let uris = match env::var("WAMP_ASYNC_ROUTER_URLS") {
+        Ok(u) => u,
+        Err(_) => {
+            eprintln!("WAMP_ASYNC_ROUTER_URLS is not set, skipping router interop tests");
+            return;
+        }
+    };
+    let realm = env::var("WAMP_ASYNC_ROUTER_REALM").unwrap_or_else(|_| "realm1".to_string());
+    let ticket = env::var("WAMP_ASYNC_ROUTER_TICKET").ok();
+    let _router = Router::spawn();
+
+    for uri in uris.split(',').map(str::trim).filter(|u| !u.is_empty()) {
+        for serializer in [SerializerType::Json, SerializerType::MsgPack] {
+            pubsub(uri, serializer, &realm).await;
+            rpc(uri, serializer, &realm).await;
+            if let Some(ticket) = ticket.as_deref() {
+                ticket_auth(uri, serializer, &realm, ticket).await;
+            }
+        }
+    }
+}